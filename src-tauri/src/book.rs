@@ -0,0 +1,221 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, EnPassantMode, Move, Role, Square,
+};
+use specta::Type;
+
+use crate::error::Error;
+
+/// Size of a Polyglot entry: the key, the move, its weight and learn data
+const ENTRY_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct BookMove {
+    /// In UCI notation, with castling written as the move of the king
+    #[serde(rename = "move")]
+    pub uci: String,
+    pub san: String,
+    pub weight: u16,
+}
+
+struct BookEntry {
+    key: u64,
+    raw_move: u16,
+    weight: u16,
+}
+
+fn read_entry(file: &mut File, index: u64) -> Result<BookEntry, Error> {
+    let mut buf = [0; ENTRY_SIZE as usize];
+    file.seek(SeekFrom::Start(index * ENTRY_SIZE))?;
+    file.read_exact(&mut buf)?;
+    Ok(BookEntry {
+        key: u64::from_be_bytes(buf[..8].try_into().unwrap()),
+        raw_move: u16::from_be_bytes([buf[8], buf[9]]),
+        weight: u16::from_be_bytes([buf[10], buf[11]]),
+    })
+}
+
+/// Polyglot hashes the en passant square whenever a pawn stands next to the
+/// one that was pushed, even if taking it would leave the king in check
+fn book_key(position: &Chess) -> u64 {
+    position
+        .zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal)
+        .0
+}
+
+/// Books are sorted by key, so the entries of a position are found with a
+/// binary search instead of reading the whole file
+fn entries_for(file: &mut File, key: u64) -> Result<Vec<BookEntry>, Error> {
+    let count = file.metadata()?.len() / ENTRY_SIZE;
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if read_entry(file, mid)?.key < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let mut entries = Vec::new();
+    for index in low..count {
+        let entry = read_entry(file, index)?;
+        if entry.key != key {
+            break;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Castling is written as the king taking its own rook, like Chess960 UCI
+/// does, which `to_move` understands
+fn decode_move(position: &Chess, raw: u16) -> Option<Move> {
+    let to = Square::new(u32::from(raw & 0x3f));
+    let from = Square::new(u32::from((raw >> 6) & 0x3f));
+    let promotion = match (raw >> 12) & 0x7 {
+        0 => None,
+        1 => Some(Role::Knight),
+        2 => Some(Role::Bishop),
+        3 => Some(Role::Rook),
+        4 => Some(Role::Queen),
+        _ => return None,
+    };
+    UciMove::Normal {
+        from,
+        to,
+        promotion,
+    }
+    .to_move(position)
+    .ok()
+}
+
+/// The moves a Polyglot opening book has for the position of `fen`, with the
+/// most weighted first. Entries that aren't legal in the position are left
+/// out.
+#[tauri::command]
+#[specta::specta]
+pub fn probe_book(path: PathBuf, fen: String) -> Result<Vec<BookMove>, Error> {
+    let fen = Fen::from_ascii(fen.as_bytes())?;
+    let position: Chess = fen.into_position(CastlingMode::Chess960)?;
+    let mut file = File::open(path)?;
+
+    let mut moves: Vec<BookMove> = entries_for(&mut file, book_key(&position))?
+        .into_iter()
+        .filter_map(|entry| {
+            let m = decode_move(&position, entry.raw_move)?;
+            Some(BookMove {
+                uci: m.to_uci(CastlingMode::Standard).to_string(),
+                san: SanPlus::from_move(position.clone(), &m).to_string(),
+                weight: entry.weight,
+            })
+        })
+        .collect();
+    moves.sort_by(|a, b| b.weight.cmp(&a.weight));
+    Ok(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::Position;
+    use std::io::Write;
+
+    const CASTLING_FEN: &str = "r3k2r/pppq1ppp/2npbn2/4p3/4P3/2NPBN2/PPPQ1PPP/R3K2R w KQkq - 0 1";
+
+    fn position(fen: &str) -> Chess {
+        fen.parse::<Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    fn raw_move(from: Square, to: Square) -> u16 {
+        ((from as u16) << 6) | to as u16
+    }
+
+    fn write_book(entries: &mut [(u64, u16, u16)]) -> tempfile::NamedTempFile {
+        entries.sort_by_key(|&(key, _, _)| key);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for &(key, m, weight) in entries.iter() {
+            file.write_all(&key.to_be_bytes()).unwrap();
+            file.write_all(&m.to_be_bytes()).unwrap();
+            file.write_all(&weight.to_be_bytes()).unwrap();
+            file.write_all(&[0; 4]).unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn keys_match_polyglot() {
+        let mut pos = Chess::default();
+        assert_eq!(book_key(&pos), 0x463b96181691fc9c);
+        for (uci, key) in [
+            ("e2e4", 0x823c9b50fd114196),
+            ("d7d5", 0x0756b94461c50fb0),
+            ("e4e5", 0x662fafb965db29d4),
+            // a pawn can take on f6, so the en passant square counts
+            ("f7f5", 0x22a48b5a8e47ff78),
+        ] {
+            let m = UciMove::from_ascii(uci.as_bytes())
+                .unwrap()
+                .to_move(&pos)
+                .unwrap();
+            pos.play_unchecked(&m);
+            assert_eq!(book_key(&pos), key, "after {uci}");
+        }
+    }
+
+    #[test]
+    fn probes_a_book() {
+        let start = book_key(&Chess::default());
+        let castling = book_key(&position(CASTLING_FEN));
+        let book = write_book(&mut [
+            (start, raw_move(Square::E2, Square::E4), 10),
+            (start, raw_move(Square::D2, Square::D4), 30),
+            (start, raw_move(Square::G1, Square::F3), 5),
+            // not legal in the start position
+            (start, raw_move(Square::E2, Square::E5), 50),
+            (castling, raw_move(Square::E1, Square::H1), 3),
+            (castling, raw_move(Square::E1, Square::A1), 1),
+            (start.wrapping_add(1), raw_move(Square::C2, Square::C4), 40),
+        ]);
+
+        let moves = probe_book(
+            book.path().to_path_buf(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+        )
+        .unwrap();
+        let moves: Vec<_> = moves
+            .iter()
+            .map(|m| (m.uci.as_str(), m.san.as_str(), m.weight))
+            .collect();
+        assert_eq!(
+            moves,
+            vec![("d2d4", "d4", 30), ("e2e4", "e4", 10), ("g1f3", "Nf3", 5)]
+        );
+
+        let moves = probe_book(book.path().to_path_buf(), CASTLING_FEN.to_string()).unwrap();
+        let moves: Vec<_> = moves
+            .iter()
+            .map(|m| (m.uci.as_str(), m.san.as_str()))
+            .collect();
+        assert_eq!(moves, vec![("e1g1", "O-O"), ("e1c1", "O-O-O")]);
+
+        // positions the book doesn't know
+        let fen = "4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string();
+        assert!(probe_book(book.path().to_path_buf(), fen)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,4 +1,15 @@
-use std::{fmt::Display, path::PathBuf, process::Stdio, sync::Arc, time::Instant};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use derivative::Derivative;
 use governor::{Quota, RateLimiter};
@@ -6,15 +17,21 @@ use log::{error, info};
 use nonzero_ext::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, san::SanPlus, uci::UciMove, ByColor, CastlingMode, Chess, Color, EnPassantMode,
-    Position, Role,
+    fen::Fen,
+    san::SanPlus,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    ByColor, CastlingMode, Chess, Color, EnPassantMode, Move, Position, Role,
 };
+use shakmaty_syzygy::{Dtz, Tablebase};
 use specta::Type;
+use sysinfo::{System, SystemExt};
+use tauri::{path::BaseDirectory, Manager};
 use tauri_specta::Event;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
     process::{Child, ChildStdin, ChildStdout, Command},
-    sync::Mutex,
+    sync::{Mutex, OwnedSemaphorePermit, Semaphore},
 };
 use vampirc_uci::{
     parse_one,
@@ -25,14 +42,89 @@ use vampirc_uci::{
 use crate::{
     db::{is_position_in_db, GameQueryJs, PositionQueryJs},
     error::Error,
+    tree::{Eval, MoveAnnotation},
     AppState,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum LogDirection {
+    /// Sent to the engine
+    Gui,
+    /// Received from the engine
+    Engine,
+}
+
 #[derive(Debug, Clone, Serialize, Type)]
-#[serde(tag = "type", content = "value", rename_all = "camelCase")]
-pub enum EngineLog {
-    Gui(String),
-    Engine(String),
+#[serde(rename_all = "camelCase")]
+pub struct EngineLog {
+    #[serde(rename = "type")]
+    pub direction: LogDirection,
+    pub value: String,
+    /// Milliseconds since the engine was started
+    pub elapsed_ms: u64,
+}
+
+/// Lines kept per engine, older ones are dropped first
+const MAX_ENGINE_LOGS: usize = 5000;
+
+/// Log lines sent with an `EngineCrashed` event
+const CRASH_LOG_LINES: usize = 20;
+
+/// Time an engine has to exit once it closed its output
+const ENGINE_EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time an engine has to answer `uci` with `uciok`
+const UCI_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Engines get this fraction of the total memory for their hash by default
+const HASH_MEMORY_FRACTION: u32 = 8;
+
+/// Smallest default hash, which is what most engines start with anyway
+const MIN_DEFAULT_HASH_MB: u32 = 16;
+
+#[derive(Debug)]
+struct EngineLogs {
+    lines: VecDeque<EngineLog>,
+    start: Instant,
+}
+
+impl EngineLogs {
+    fn new() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            start: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, direction: LogDirection, value: String) {
+        if self.lines.len() == MAX_ENGINE_LOGS {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(EngineLog {
+            direction,
+            value,
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+        });
+    }
+
+    fn filtered(&self, filter: &EngineLogFilter) -> Vec<EngineLog> {
+        self.lines
+            .iter()
+            .filter(|log| filter.direction.is_none() || filter.direction == Some(log.direction))
+            .filter(|log| match &filter.contains {
+                Some(text) => log.value.contains(text.as_str()),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Type)]
+pub struct EngineLogFilter {
+    pub direction: Option<LogDirection>,
+    pub contains: Option<String>,
 }
 
 #[derive(Debug)]
@@ -46,12 +138,80 @@ pub struct EngineProcess {
     go_mode: GoMode,
     running: bool,
     real_multipv: u16,
-    logs: Vec<EngineLog>,
+    advertised_options: Vec<UciOption>,
+    logs: EngineLogs,
     start: Instant,
+    child: Child,
+    /// Set once the engine was told to quit
+    quitting: bool,
+    /// Set once the engine's output ended
+    output_closed: bool,
+    ponder: PonderState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+enum PonderState {
+    #[default]
+    Idle,
+    /// Searching the position after the reply the engine expects
+    Pondering { fen: String, moves: Vec<String> },
+    /// The reply was another move, the ponder search is being stopped
+    Missed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PonderOutcome {
+    /// Not pondering
+    None,
+    /// The expected reply was played, the ponder search goes on
+    Hit,
+    /// Another reply was played, the ponder search has to be stopped
+    Miss,
+}
+
+impl PonderState {
+    /// Updates the state once a new position is to be searched
+    fn on_position(&mut self, fen: &str, moves: &[String]) -> PonderOutcome {
+        match self {
+            PonderState::Pondering {
+                fen: ponder_fen,
+                moves: ponder_moves,
+            } => {
+                if ponder_fen == fen && ponder_moves == moves {
+                    *self = PonderState::Idle;
+                    PonderOutcome::Hit
+                } else {
+                    *self = PonderState::Missed;
+                    PonderOutcome::Miss
+                }
+            }
+            _ => PonderOutcome::None,
+        }
+    }
+
+    /// Updates the state once the engine sent `bestmove`, returning whether
+    /// it ended a ponder search that shouldn't be reported
+    fn on_best_move(&mut self) -> bool {
+        let ponder_search = *self != PonderState::Idle;
+        *self = PonderState::Idle;
+        ponder_search
+    }
+}
+
+/// How an engine that stopped by itself exited
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EngineExit {
+    /// `None` if the engine was killed by a signal
+    exit_code: Option<i32>,
+    logs: Vec<String>,
 }
 
 impl EngineProcess {
-    async fn new(path: PathBuf) -> Result<(Self, Lines<BufReader<ChildStdout>>), Error> {
+    pub(crate) async fn new(
+        path: PathBuf,
+        tablebases: Option<&Path>,
+    ) -> Result<(Self, Lines<BufReader<ChildStdout>>), Error> {
+        check_engine_file(&path)?;
         let mut command = Command::new(&path);
         command.current_dir(path.parent().unwrap());
         command
@@ -64,28 +224,70 @@ impl EngineProcess {
 
         let mut child = command.spawn()?;
 
-        let mut logs = Vec::new();
+        let mut logs = EngineLogs::new();
 
         let mut stdin = child.stdin.take().ok_or(Error::NoStdin)?;
 
+        let stderr = child.stderr.take().unwrap();
         tokio::spawn(async move {
-            let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
-            while let Some(line) = stderr.next_line().await.unwrap() {
+            let mut stderr = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = stderr.next_line().await {
                 error!("{}", &line);
             }
         });
 
         let mut lines = BufReader::new(child.stdout.take().ok_or(Error::NoStdout)?).lines();
 
+        let mut advertised_options = Vec::new();
+
         let _ = stdin.write_all("uci\n".as_bytes()).await;
-        logs.push(EngineLog::Gui("uci\n".to_string()));
-        while let Some(line) = lines.next_line().await? {
-            logs.push(EngineLog::Engine(line.clone()));
+        logs.push(LogDirection::Gui, "uci\n".to_string());
+        loop {
+            let line = match tokio::time::timeout(UCI_HANDSHAKE_TIMEOUT, lines.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                Ok(Err(e)) => return Err(e.into()),
+                // whatever this is, it isn't talking UCI
+                Ok(Ok(None)) | Err(_) => {
+                    let _ = child.start_kill();
+                    return Err(Error::NotUciEngine);
+                }
+            };
+            logs.push(LogDirection::Engine, line.clone());
+            if let UciMessage::Option(option) = parse_one(&line) {
+                advertised_options.push(UciOption::from(option));
+            }
             if line == "uciok" {
+                let supports_syzygy = advertised_options
+                    .iter()
+                    .any(|o: &UciOption| o.name.eq_ignore_ascii_case("SyzygyPath"));
+                if let (Some(dir), true) = (tablebases, supports_syzygy) {
+                    let msg = format!("setoption name SyzygyPath value {}\n", dir.display());
+                    let _ = stdin.write_all(msg.as_bytes()).await;
+                    logs.push(LogDirection::Gui, msg);
+                }
+                // win/draw/loss stats come with every score when enabled, the
+                // engine settings can still turn them off
+                let supports_wdl = advertised_options
+                    .iter()
+                    .any(|o: &UciOption| o.name.eq_ignore_ascii_case("UCI_ShowWDL"));
+                if supports_wdl {
+                    let msg = "setoption name UCI_ShowWDL value true\n".to_string();
+                    let _ = stdin.write_all(msg.as_bytes()).await;
+                    logs.push(LogDirection::Gui, msg);
+                }
+                // a Hash from the engine settings replaces this one later
+                if let Some(hash) = advertised_options.iter().find(|o: &&UciOption| {
+                    o.name.eq_ignore_ascii_case("Hash") && o.option_type == UciOptionType::Spin
+                }) {
+                    let size = suggested_hash(total_memory_mb(), hash.min, hash.max);
+                    let msg = format!("setoption name Hash value {}\n", size);
+                    let _ = stdin.write_all(msg.as_bytes()).await;
+                    logs.push(LogDirection::Gui, msg);
+                }
                 let _ = stdin.write_all("isready\n".as_bytes()).await;
-                logs.push(EngineLog::Gui("isready\n".to_string()));
+                logs.push(LogDirection::Gui, "isready\n".to_string());
                 while let Some(line_is_ready) = lines.next_line().await? {
-                    logs.push(EngineLog::Engine(line_is_ready.clone()));
+                    logs.push(LogDirection::Engine, line_is_ready.clone());
                     if line_is_ready == "readyok" {
                         break;
                     }
@@ -104,9 +306,14 @@ impl EngineProcess {
                 logs,
                 options: EngineOptions::default(),
                 real_multipv: 0,
+                advertised_options,
                 go_mode: GoMode::Infinite,
                 running: false,
                 start: Instant::now(),
+                child,
+                quitting: false,
+                output_closed: false,
+                ponder: PonderState::Idle,
             },
             lines,
         ))
@@ -118,11 +325,85 @@ impl EngineProcess {
     {
         let msg = format!("setoption name {} value {}\n", name, value);
         self.stdin.write_all(msg.as_bytes()).await?;
-        self.logs.push(EngineLog::Gui(msg));
+        self.logs.push(LogDirection::Gui, msg);
+
+        Ok(())
+    }
+
+    /// Sets an option the engine advertised, checking that the value fits it
+    pub async fn apply_option(&mut self, name: String, value: String) -> Result<(), Error> {
+        let option = self
+            .advertised_option(&name)
+            .ok_or_else(|| Error::UnknownEngineOption(name.clone()))?;
+        if !option.is_valid_value(&value) {
+            return Err(Error::InvalidEngineOptionValue { name, value });
+        }
+        let is_button = option.option_type == UciOptionType::Button;
+        let name = option.name.clone();
+
+        if is_button {
+            let msg = format!("setoption name {}\n", name);
+            self.stdin.write_all(msg.as_bytes()).await?;
+            self.logs.push(LogDirection::Gui, msg);
+        } else {
+            self.set_option(&name, &value).await?;
+        }
+        Ok(())
+    }
+
+    /// Sets each option with `apply_option`, stopping at the first invalid one
+    pub async fn apply_options(&mut self, options: &[EngineOption]) -> Result<(), Error> {
+        for option in options {
+            self.apply_option(option.name.clone(), option.value.clone())
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn advertised_option(&self, name: &str) -> Option<&UciOption> {
+        self.advertised_options
+            .iter()
+            .find(|option| option.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The `UCI_Elo` closest to `target` that the engine accepts, if it can
+    /// limit its strength at all
+    fn strength_elo(&self, target: u16) -> Option<i64> {
+        self.advertised_option("UCI_LimitStrength")?;
+        let elo = self
+            .advertised_option("UCI_Elo")
+            .filter(|option| option.option_type == UciOptionType::Spin)?;
+        let target = i64::from(target);
+        Some(
+            target
+                .max(elo.min.unwrap_or(target))
+                .min(elo.max.unwrap_or(target)),
+        )
+    }
 
+    /// Limits the strength of the engine to `target_elo`, or gives it back
+    /// its full strength
+    async fn set_strength(&mut self, target_elo: Option<u16>) -> Result<(), Error> {
+        if self.advertised_option("UCI_LimitStrength").is_none() {
+            return Ok(());
+        }
+        match target_elo.and_then(|target| self.strength_elo(target)) {
+            Some(elo) => {
+                self.set_option("UCI_LimitStrength", true).await?;
+                self.set_option("UCI_Elo", elo).await?;
+            }
+            None => self.set_option("UCI_LimitStrength", false).await?,
+        }
         Ok(())
     }
 
+    fn max_multipv(&self) -> Option<u16> {
+        self.advertised_option("MultiPV")
+            .filter(|option| option.option_type == UciOptionType::Spin)
+            .and_then(|option| option.max)
+            .map(|max| max.clamp(1, u16::MAX as i64) as u16)
+    }
+
     async fn set_options(&mut self, options: EngineOptions) -> Result<(), Error> {
         let fen: Fen = options.fen.parse()?;
         let mut pos: Chess = match fen.into_position(CastlingMode::Chess960) {
@@ -141,7 +422,9 @@ impl EngineProcess {
             .map(|x| x.value.parse().unwrap_or(1))
             .unwrap_or(1);
 
-        self.real_multipv = multipv.min(pos.legal_moves().len() as u16);
+        self.real_multipv = multipv
+            .min(pos.legal_moves().len() as u16)
+            .min(self.max_multipv().unwrap_or(u16::MAX));
 
         for option in &options.extra_options {
             if !self.options.extra_options.contains(option) {
@@ -149,6 +432,40 @@ impl EngineProcess {
             }
         }
 
+        // engines want to be told before the first `go ponder`
+        let ponder_set = options
+            .extra_options
+            .iter()
+            .any(|x| x.name.eq_ignore_ascii_case("Ponder"));
+        if options.ponder != self.options.ponder
+            && !ponder_set
+            && self.advertised_option("Ponder").is_some()
+        {
+            self.set_option("Ponder", options.ponder).await?;
+        }
+
+        // an explicit UCI_Chess960 option from the caller takes precedence
+        let chess960_set = options
+            .extra_options
+            .iter()
+            .any(|x| x.name.eq_ignore_ascii_case("UCI_Chess960"));
+        if options.variant != self.options.variant
+            && !chess960_set
+            && self.advertised_option("UCI_Chess960").is_some()
+        {
+            self.set_option("UCI_Chess960", options.variant == Variant::Chess960)
+                .await?;
+        }
+
+        // and so do its strength options
+        let strength_set = options.extra_options.iter().any(|x| {
+            x.name.eq_ignore_ascii_case("UCI_LimitStrength")
+                || x.name.eq_ignore_ascii_case("UCI_Elo")
+        });
+        if options.target_elo != self.options.target_elo && !strength_set {
+            self.set_strength(options.target_elo).await?;
+        }
+
         if options.fen != self.options.fen || options.moves != self.options.moves {
             self.set_position(&options.fen, &options.moves).await?;
         }
@@ -169,31 +486,71 @@ impl EngineProcess {
         self.stdin.write_all(msg.as_bytes()).await?;
         self.options.fen = fen.to_string();
         self.options.moves = moves.clone();
-        self.logs.push(EngineLog::Gui(msg));
+        self.logs.push(LogDirection::Gui, msg);
         Ok(())
     }
 
     async fn go(&mut self, mode: &GoMode) -> Result<(), Error> {
         self.go_mode = mode.clone();
-        let msg = match mode {
-            GoMode::Depth(depth) => format!("go depth {}\n", depth),
-            GoMode::Time(time) => format!("go movetime {}\n", time),
-            GoMode::Nodes(nodes) => format!("go nodes {}\n", nodes),
-            GoMode::PlayersTime(PlayersTime {
-                white,
-                black,
-                winc,
-                binc,
-            }) => {
-                format!(
-                    "go wtime {} btime {} winc {} binc {}\n",
-                    white, black, winc, binc
-                )
-            }
-            GoMode::Infinite => "go infinite\n".to_string(),
-        };
+        let msg = mode.command();
+        self.stdin.write_all(msg.as_bytes()).await?;
+        self.logs.push(LogDirection::Gui, msg);
+        self.running = true;
+        self.start = Instant::now();
+        Ok(())
+    }
+
+    /// Collects the lines of the current search iteration, returning all of
+    /// them, ordered by rank, once the last expected line arrives.
+    fn push_line(&mut self, line: BestMoves) -> Option<Vec<BestMoves>> {
+        let mut complete = None;
+        let new_iteration = self
+            .best_moves
+            .first()
+            .is_some_and(|first| line.multipv == 1 && line.depth > first.depth);
+        if new_iteration {
+            // The engine started a new iteration without sending all the lines
+            // we asked for, so it caps MultiPV below the requested value
+            self.real_multipv = self.best_moves.len() as u16;
+            complete = Some(std::mem::take(&mut self.best_moves));
+        }
+        if line.multipv as usize != self.best_moves.len() + 1 {
+            return complete;
+        }
+        self.best_moves.push(line);
+        if self.best_moves.len() >= self.real_multipv as usize {
+            return Some(std::mem::take(&mut self.best_moves));
+        }
+        complete
+    }
+
+    /// Starts searching the position after the reply the engine expects to
+    /// `best_move`, on the same terms as the search that found it
+    async fn start_ponder(&mut self, best_move: String, reply: String) -> Result<(), Error> {
+        let fen = self.options.fen.clone();
+        let mut moves = self.options.moves.clone();
+        moves.push(best_move);
+        moves.push(reply);
+        self.set_position(&fen, &moves).await?;
+
+        let msg = self.go_mode.ponder_command();
         self.stdin.write_all(msg.as_bytes()).await?;
-        self.logs.push(EngineLog::Gui(msg));
+        self.logs.push(LogDirection::Gui, msg);
+        self.ponder = PonderState::Pondering { fen, moves };
+        self.last_depth = 0;
+        self.best_moves.clear();
+        self.last_best_moves.clear();
+        self.running = true;
+        self.start = Instant::now();
+        Ok(())
+    }
+
+    /// Turns the ponder search into the search of the new position
+    async fn ponder_hit(&mut self, options: EngineOptions, mode: &GoMode) -> Result<(), Error> {
+        self.stdin.write_all(b"ponderhit\n").await?;
+        self.logs.push(LogDirection::Gui, "ponderhit\n".to_string());
+        self.options = options;
+        self.go_mode = mode.clone();
         self.running = true;
         self.start = Instant::now();
         Ok(())
@@ -201,17 +558,39 @@ impl EngineProcess {
 
     async fn stop(&mut self) -> Result<(), Error> {
         self.stdin.write_all(b"stop\n").await?;
-        self.logs.push(EngineLog::Gui("stop\n".to_string()));
+        self.logs.push(LogDirection::Gui, "stop\n".to_string());
         self.running = false;
         Ok(())
     }
 
     async fn kill(&mut self) -> Result<(), Error> {
+        self.quitting = true;
         self.stdin.write_all(b"quit\n").await?;
-        self.logs.push(EngineLog::Gui("quit\n".to_string()));
+        self.logs.push(LogDirection::Gui, "quit\n".to_string());
         self.running = false;
         Ok(())
     }
+
+    /// Tells how the engine exited, if its output ended without it being
+    /// told to quit
+    async fn crash(&mut self) -> Option<EngineExit> {
+        if self.quitting || !self.output_closed {
+            return None;
+        }
+        let exit_code = match tokio::time::timeout(ENGINE_EXIT_TIMEOUT, self.child.wait()).await {
+            Ok(Ok(status)) => status.code(),
+            _ => None,
+        };
+        let skip = self.logs.lines.len().saturating_sub(CRASH_LOG_LINES);
+        let logs = self
+            .logs
+            .lines
+            .iter()
+            .skip(skip)
+            .map(|log| log.value.clone())
+            .collect();
+        Some(EngineExit { exit_code, logs })
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -230,7 +609,11 @@ pub struct AnalysisCacheKey {
 pub struct BestMoves {
     nodes: u32,
     depth: u32,
+    /// Score from White's perspective, whoever is to move
     score: Score,
+    /// Score as the engine sent it, from the perspective of the side to move
+    #[serde(rename = "rawScore")]
+    raw_score: Score,
     #[serde(rename = "uciMoves")]
     uci_moves: Vec<String>,
     #[serde(rename = "sanMoves")]
@@ -238,6 +621,12 @@ pub struct BestMoves {
     #[derivative(Default(value = "1"))]
     multipv: u16,
     nps: u32,
+    /// How full the hash table is, in permille
+    #[specta(optional)]
+    hashfull: Option<u16>,
+    /// Positions found in the tablebases
+    #[specta(optional)]
+    tbhits: Option<u32>,
 }
 
 #[derive(Serialize, Debug, Clone, Type, Event)]
@@ -249,6 +638,9 @@ pub struct BestMovesPayload {
     pub fen: String,
     pub moves: Vec<String>,
     pub progress: f64,
+    pub depth: u32,
+    /// Set on the last payload of a search, once the engine sent `bestmove`
+    pub complete: bool,
 }
 
 fn invert_score(score: Score) -> Score {
@@ -299,6 +691,12 @@ fn parse_uci_attrs(
             UciInfoAttribute::Nodes(nodes) => {
                 best_moves.nodes = nodes as u32;
             }
+            UciInfoAttribute::HashFull(hashfull) => {
+                best_moves.hashfull = Some(hashfull);
+            }
+            UciInfoAttribute::TbHits(tbhits) => {
+                best_moves.tbhits = Some(tbhits as u32);
+            }
             UciInfoAttribute::Depth(depth) => {
                 best_moves.depth = depth;
             }
@@ -316,6 +714,7 @@ fn parse_uci_attrs(
         return Err(Error::NoMovesFound);
     }
 
+    best_moves.raw_score = best_moves.score.clone();
     if turn == Color::Black {
         best_moves.score = invert_score(best_moves.score);
     }
@@ -334,6 +733,8 @@ fn start_engine(path: PathBuf) -> Result<Child, Error> {
     #[cfg(target_os = "windows")]
     command.creation_flags(CREATE_NO_WINDOW);
 
+    command.kill_on_drop(true);
+
     let child = command.spawn()?;
 
     Ok(child)
@@ -346,6 +747,34 @@ fn get_handles(child: &mut Child) -> Result<(ChildStdin, Lines<BufReader<ChildSt
     Ok((stdin, stdout))
 }
 
+fn total_memory_mb() -> u32 {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    (sys.total_memory() / 1024 / 1024) as u32
+}
+
+/// Default hash size for a machine with `total_mb` of memory, within the
+/// `min..=max` range an engine advertises for its `Hash` option
+fn suggested_hash(total_mb: u32, min: Option<i64>, max: Option<i64>) -> u32 {
+    let hash = (total_mb / HASH_MEMORY_FRACTION).max(MIN_DEFAULT_HASH_MB) as i64;
+    let min = min.unwrap_or(1).max(1);
+    let max = max.unwrap_or(u32::MAX as i64).max(min);
+    hash.clamp(min, max) as u32
+}
+
+/// The hash size engines get when their settings don't have one, in MB
+#[tauri::command]
+#[specta::specta]
+pub fn suggested_hash_mb() -> u32 {
+    suggested_hash(total_memory_mb(), None, None)
+}
+
+/// Runs the `uci` handshake and quits the engine, failing if it doesn't
+/// answer `uciok` within `timeout`
+pub async fn engine_handshake(path: PathBuf, timeout: Duration) -> Result<(), Error> {
+    read_engine_config(path, timeout).await.map(|_| ())
+}
+
 async fn send_command(stdin: &mut ChildStdin, command: impl AsRef<str>) {
     stdin
         .write_all(command.as_ref().as_bytes())
@@ -353,6 +782,83 @@ async fn send_command(stdin: &mut ChildStdin, command: impl AsRef<str>) {
         .expect("Failed to write command");
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum UciOptionType {
+    Check,
+    Spin,
+    Combo,
+    Button,
+    String,
+}
+
+/// An option advertised by the engine with an `option` line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UciOption {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub option_type: UciOptionType,
+    pub default: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub vars: Vec<String>,
+}
+
+impl From<UciOptionConfig> for UciOption {
+    fn from(config: UciOptionConfig) -> Self {
+        let option = |name, option_type| UciOption {
+            name,
+            option_type,
+            default: None,
+            min: None,
+            max: None,
+            vars: Vec::new(),
+        };
+        match config {
+            UciOptionConfig::Check { name, default } => UciOption {
+                default: default.map(|d| d.to_string()),
+                ..option(name, UciOptionType::Check)
+            },
+            UciOptionConfig::Spin {
+                name,
+                default,
+                min,
+                max,
+            } => UciOption {
+                default: default.map(|d| d.to_string()),
+                min,
+                max,
+                ..option(name, UciOptionType::Spin)
+            },
+            UciOptionConfig::Combo { name, default, var } => UciOption {
+                default,
+                vars: var,
+                ..option(name, UciOptionType::Combo)
+            },
+            UciOptionConfig::Button { name } => option(name, UciOptionType::Button),
+            UciOptionConfig::String { name, default } => UciOption {
+                default,
+                ..option(name, UciOptionType::String)
+            },
+        }
+    }
+}
+
+impl UciOption {
+    /// Checks that `value` is acceptable for this option
+    fn is_valid_value(&self, value: &str) -> bool {
+        match self.option_type {
+            UciOptionType::Check => value == "true" || value == "false",
+            UciOptionType::Spin => value.parse::<i64>().is_ok_and(|v| {
+                self.min.map_or(true, |min| v >= min) && self.max.map_or(true, |max| v <= max)
+            }),
+            UciOptionType::Combo => self.vars.iter().any(|x| x.eq_ignore_ascii_case(value)),
+            UciOptionType::Button | UciOptionType::String => true,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Type, Derivative, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[derivative(Default)]
@@ -360,12 +866,36 @@ pub struct EngineOptions {
     pub fen: String,
     pub moves: Vec<String>,
     pub extra_options: Vec<EngineOption>,
+    #[serde(default)]
+    pub variant: Variant,
+    /// Starts the engine again, once, if it crashes during the search
+    #[serde(default)]
+    #[specta(optional)]
+    pub auto_restart: bool,
+    /// Keeps searching on the expected reply once a search is done, see
+    /// `get_best_moves`
+    #[serde(default)]
+    #[specta(optional)]
+    pub ponder: bool,
+    /// Weakens the engine to about this rating with `UCI_LimitStrength`,
+    /// kept within the `UCI_Elo` range the engine advertises
+    #[serde(default)]
+    #[specta(optional)]
+    pub target_elo: Option<u16>,
 }
 
-#[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, Copy, Default, Type, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
 pub struct EngineOption {
-    name: String,
-    value: String,
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
@@ -375,6 +905,7 @@ pub enum GoMode {
     Depth(u32),
     Time(u32),
     Nodes(u32),
+    Mate(u32),
     Infinite,
 }
 
@@ -386,6 +917,37 @@ pub struct PlayersTime {
     binc: u32,
 }
 
+impl GoMode {
+    /// The `go` command starting a search limited by this mode. Infinite
+    /// searches run until `stop_engine`.
+    fn command(&self) -> String {
+        match self {
+            GoMode::Depth(depth) => format!("go depth {}\n", depth),
+            GoMode::Time(time) => format!("go movetime {}\n", time),
+            GoMode::Nodes(nodes) => format!("go nodes {}\n", nodes),
+            GoMode::Mate(moves) => format!("go mate {}\n", moves),
+            GoMode::PlayersTime(PlayersTime {
+                white,
+                black,
+                winc,
+                binc,
+            }) => {
+                format!(
+                    "go wtime {} btime {} winc {} binc {}\n",
+                    white, black, winc, binc
+                )
+            }
+            GoMode::Infinite => "go infinite\n".to_string(),
+        }
+    }
+
+    /// The `go ponder` command searching the expected reply with the same
+    /// limits
+    fn ponder_command(&self) -> String {
+        self.command().replacen("go ", "go ponder ", 1)
+    }
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn kill_engines(tab: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
@@ -428,6 +990,12 @@ pub async fn stop_engine(
     tab: String,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), Error> {
+    if let Some(cancelled) = state
+        .analysis_cancellations
+        .get(&best_moves_request_id(&tab, &engine))
+    {
+        cancelled.store(true, Ordering::Relaxed);
+    }
     let key = (tab, engine);
     if let Some(process) = state.engine_processes.get(&key) {
         let mut process = process.lock().await;
@@ -436,17 +1004,56 @@ pub async fn stop_engine(
     Ok(())
 }
 
+/// Sends a `setoption` to a running engine, if the engine advertised an
+/// option with that name during the `uci` handshake
+#[tauri::command]
+#[specta::specta]
+pub async fn set_engine_option(
+    engine: String,
+    tab: String,
+    name: String,
+    value: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let key = (tab, engine);
+    let process = state
+        .engine_processes
+        .get(&key)
+        .map(|p| p.clone())
+        .ok_or(Error::EngineNotRunning)?;
+    let mut process = process.lock().await;
+    process.apply_option(name, value).await
+}
+
+/// Returns the options the engine advertised when it was started
+#[tauri::command]
+#[specta::specta]
+pub async fn get_engine_options(
+    engine: String,
+    tab: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<UciOption>, Error> {
+    let key = (tab, engine);
+    if let Some(process) = state.engine_processes.get(&key) {
+        let process = process.lock().await;
+        Ok(process.advertised_options.clone())
+    } else {
+        Err(Error::EngineNotRunning)
+    }
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn get_engine_logs(
     engine: String,
     tab: String,
+    filter: Option<EngineLogFilter>,
     state: tauri::State<'_, AppState>,
 ) -> Result<Vec<EngineLog>, Error> {
     let key = (tab, engine);
     if let Some(process) = state.engine_processes.get(&key) {
         let process = process.lock().await;
-        Ok(process.logs.clone())
+        Ok(process.logs.filtered(&filter.unwrap_or_default()))
     } else {
         Ok(Vec::new())
     }
@@ -459,18 +1066,33 @@ pub async fn get_best_moves(
     engine: String,
     tab: String,
     go_mode: GoMode,
-    options: EngineOptions,
+    mut options: EngineOptions,
+    multipv: Option<u8>,
     app: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
 ) -> Result<Option<(f32, Vec<BestMoves>)>, Error> {
     let path = PathBuf::from(&engine);
 
+    // an explicit value, even 1, replaces the one of the options
+    if let Some(multipv) = multipv {
+        options.extra_options.retain(|x| x.name != "MultiPV");
+        options.extra_options.push(EngineOption {
+            name: "MultiPV".to_string(),
+            value: multipv.to_string(),
+        });
+    }
+
     let key = (tab.clone(), engine.clone());
 
     if state.engine_processes.contains_key(&key) {
         {
             let process = state.engine_processes.get_mut(&key).unwrap();
             let mut process = process.lock().await;
+            // a miss stops the ponder search like any other search below
+            if process.ponder.on_position(&options.fen, &options.moves) == PonderOutcome::Hit {
+                process.ponder_hit(options, &go_mode).await?;
+                return Ok(None);
+            }
             if options == process.options && go_mode == process.go_mode && process.running {
                 return Ok(Some((
                     process.last_progress,
@@ -490,7 +1112,23 @@ pub async fn get_best_moves(
         return Ok(None);
     }
 
-    let (mut process, mut reader) = EngineProcess::new(path).await?;
+    // the slot is held until the engine exits
+    let request_id = best_moves_request_id(&tab, &engine);
+    let cancelled = register_analysis(&request_id, &state);
+    let slot = state
+        .analysis_slots
+        .acquire(&state.new_request, &cancelled)
+        .await;
+    state.analysis_cancellations.remove(&request_id);
+    let _slot = match slot {
+        Ok(slot) => slot,
+        // stopped with `stop_engine` while waiting
+        Err(Error::AnalysisCancelled) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let tablebases = state.tablebase_path.lock().unwrap().clone();
+    let (mut process, mut reader) = EngineProcess::new(path, tablebases.as_deref()).await?;
     process.set_options(options.clone()).await?;
     process.go(&go_mode).await?;
 
@@ -498,83 +1136,562 @@ pub async fn get_best_moves(
 
     state.engine_processes.insert(key.clone(), process.clone());
 
+    run_engine(&key, process, reader, &id, true, &app, &state).await?;
+
+    info!("Engine process finished: tab: {}, engine: {}", tab, engine);
+    state.engine_processes.remove(&key);
+    Ok(None)
+}
+
+/// Whether two sets of lines only differ in their node counts
+fn same_lines(a: &[BestMoves], b: &[BestMoves]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.depth == b.depth
+                && a.uci_moves == b.uci_moves
+                && normalized_cp(&a.score) == normalized_cp(&b.score)
+        })
+}
+
+fn search_progress(proc: &EngineProcess, depth: u32, nodes: u32) -> f64 {
+    match proc.go_mode {
+        GoMode::Depth(target) => (depth as f64 / target as f64) * 100.0,
+        GoMode::Time(time) => (proc.start.elapsed().as_millis() as f64 / time as f64) * 100.0,
+        GoMode::Nodes(target) => (nodes as f64 / target as f64) * 100.0,
+        GoMode::Mate(_) => 99.99,
+        GoMode::PlayersTime(_) => 99.99,
+        GoMode::Infinite => 99.99,
+    }
+}
+
+/// Emits a `BestMovesPayload` for every new complete set of lines until the
+/// engine exits, or unless `keep_alive` is set, until it is stopped
+async fn stream_best_moves(
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+    id: &str,
+    tab: &str,
+    keep_alive: bool,
+    app: &tauri::AppHandle,
+) -> Result<(), Error> {
     let lim = RateLimiter::direct(Quota::per_second(nonzero!(5u32)));
 
-    while let Some(line) = reader.next_line().await? {
+    loop {
+        let Some(line) = reader.next_line().await? else {
+            process.lock().await.output_closed = true;
+            break;
+        };
         let mut proc = process.lock().await;
+        let mut finished = false;
         match parse_one(&line) {
+            // lines of a ponder search are for a position the user isn't on
+            UciMessage::Info(_) if proc.ponder != PonderState::Idle => {}
             UciMessage::Info(attrs) => {
                 if let Ok(best_moves) =
                     parse_uci_attrs(attrs, &proc.options.fen.parse()?, &proc.options.moves)
                 {
-                    let multipv = best_moves.multipv;
-                    let cur_depth = best_moves.depth;
-                    let cur_nodes = best_moves.nodes;
-                    if multipv as usize == proc.best_moves.len() + 1 {
-                        proc.best_moves.push(best_moves);
-                        if multipv == proc.real_multipv {
-                            if proc.best_moves.iter().all(|x| x.depth == cur_depth)
-                                && cur_depth >= proc.last_depth
-                                && lim.check().is_ok()
-                            {
-                                let progress = match proc.go_mode {
-                                    GoMode::Depth(depth) => {
-                                        (cur_depth as f64 / depth as f64) * 100.0
-                                    }
-                                    GoMode::Time(time) => {
-                                        (proc.start.elapsed().as_millis() as f64 / time as f64)
-                                            * 100.0
-                                    }
-                                    GoMode::Nodes(nodes) => {
-                                        (cur_nodes as f64 / nodes as f64) * 100.0
-                                    }
-                                    GoMode::PlayersTime(_) => 99.99,
-                                    GoMode::Infinite => 99.99,
-                                };
-                                BestMovesPayload {
-                                    best_lines: proc.best_moves.clone(),
-                                    engine: id.clone(),
-                                    tab: tab.clone(),
-                                    fen: proc.options.fen.clone(),
-                                    moves: proc.options.moves.clone(),
-                                    progress,
-                                }
-                                .emit(&app)?;
-                                proc.last_depth = cur_depth;
-                                proc.last_best_moves = proc.best_moves.clone();
-                                proc.last_progress = progress as f32;
+                    if let Some(lines) = proc.push_line(best_moves) {
+                        let cur_depth = lines.last().map(|x| x.depth).unwrap_or_default();
+                        let cur_nodes = lines.last().map(|x| x.nodes).unwrap_or_default();
+                        if lines.iter().all(|x| x.depth == cur_depth)
+                            && cur_depth >= proc.last_depth
+                            && !same_lines(&lines, &proc.last_best_moves)
+                            && lim.check().is_ok()
+                        {
+                            let progress = search_progress(&proc, cur_depth, cur_nodes);
+                            BestMovesPayload {
+                                best_lines: lines.clone(),
+                                engine: id.to_string(),
+                                tab: tab.to_string(),
+                                fen: proc.options.fen.clone(),
+                                moves: proc.options.moves.clone(),
+                                progress,
+                                depth: cur_depth,
+                                complete: false,
                             }
-                            proc.best_moves.clear();
+                            .emit(app)?;
+                            proc.last_depth = cur_depth;
+                            proc.last_best_moves = lines;
+                            proc.last_progress = progress as f32;
                         }
                     }
                 }
             }
-            UciMessage::BestMove { .. } => {
+            UciMessage::BestMove { .. } if proc.ponder.on_best_move() => {}
+            UciMessage::BestMove { best_move, ponder } => {
                 BestMovesPayload {
                     best_lines: proc.last_best_moves.clone(),
-                    engine: id.clone(),
-                    tab: tab.clone(),
+                    engine: id.to_string(),
+                    tab: tab.to_string(),
                     fen: proc.options.fen.clone(),
                     moves: proc.options.moves.clone(),
                     progress: 100.0,
+                    depth: proc.last_depth,
+                    complete: true,
                 }
-                .emit(&app)?;
+                .emit(app)?;
                 proc.last_progress = 100.0;
+                if let (true, Some(reply)) = (proc.options.ponder, ponder) {
+                    // pondering during an infinite search would never end
+                    if proc.go_mode != GoMode::Infinite {
+                        proc.start_ponder(best_move.to_string(), reply.to_string())
+                            .await?;
+                    }
+                }
+                // a new search may already have been started on the process
+                finished = !keep_alive && !proc.running;
             }
             _ => {}
         }
-        proc.logs.push(EngineLog::Engine(line));
+        proc.logs.push(LogDirection::Engine, line);
+        if finished {
+            break;
+        }
     }
-    info!("Engine process finished: tab: {}, engine: {}", tab, engine);
-    state.engine_processes.remove(&key);
-    Ok(None)
+    Ok(())
 }
 
-#[derive(Serialize, Debug, Default, Type)]
-pub struct MoveAnalysis {
-    best: Vec<BestMoves>,
-    novelty: bool,
-    is_sacrifice: bool,
+/// Sent when an engine exits without being told to
+#[derive(Clone, Serialize, Debug, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineCrashed {
+    pub engine: String,
+    pub tab: String,
+    /// `None` if the engine was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Last lines exchanged with the engine
+    pub logs: Vec<String>,
+    /// Whether the engine is started again, see `EngineOptions::auto_restart`
+    pub restarting: bool,
+}
+
+/// Checks whether an engine exited by itself, in which case it is removed
+/// from the running engines and the `EngineCrashed` event to send is
+/// returned, with the options and search to start it again with
+async fn engine_crash(
+    key: &(String, String),
+    process: &Mutex<EngineProcess>,
+    restarted: bool,
+    state: &AppState,
+) -> Option<(EngineCrashed, EngineOptions, GoMode)> {
+    let (tab, engine) = key;
+    let (crash, options, go_mode) = {
+        let mut proc = process.lock().await;
+        let crash = proc.crash().await;
+        (crash, proc.options.clone(), proc.go_mode.clone())
+    };
+    let crash = crash?;
+
+    error!(
+        "Engine {} crashed with exit code {:?}",
+        engine, crash.exit_code
+    );
+    state.engine_processes.remove(key);
+    let event = EngineCrashed {
+        engine: engine.clone(),
+        tab: tab.clone(),
+        exit_code: crash.exit_code,
+        logs: crash.logs,
+        restarting: options.auto_restart && !restarted,
+    };
+    Some((event, options, go_mode))
+}
+
+/// Streams the search of an engine with `stream_best_moves`, emitting
+/// `EngineCrashed` if the engine exits by itself. If its options ask for it,
+/// a crashed engine is started again once, on the same search.
+async fn run_engine(
+    key: &(String, String),
+    mut process: Arc<Mutex<EngineProcess>>,
+    mut reader: Lines<BufReader<ChildStdout>>,
+    id: &str,
+    keep_alive: bool,
+    app: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<(), Error> {
+    let (tab, engine) = key;
+    let mut restarted = false;
+    loop {
+        let result = stream_best_moves(&process, &mut reader, id, tab, keep_alive, app).await;
+        let Some((event, options, go_mode)) = engine_crash(key, &process, restarted, state).await
+        else {
+            return result;
+        };
+        let restarting = event.restarting;
+        event.emit(app)?;
+        if !restarting {
+            return result;
+        }
+
+        restarted = true;
+        let tablebases = state.tablebase_path.lock().unwrap().clone();
+        let (mut new_process, new_reader) =
+            EngineProcess::new(PathBuf::from(engine), tablebases.as_deref()).await?;
+        new_process.set_options(options).await?;
+        new_process.go(&go_mode).await?;
+        process = Arc::new(Mutex::new(new_process));
+        reader = new_reader;
+        state.engine_processes.insert(key.clone(), process.clone());
+    }
+}
+
+/// Starts an infinite search streaming `BestMovesPayload` events, returning
+/// once the search is stopped with `stop_engine`. If the engine is already
+/// running for this tab, it is restarted on the new position instead.
+#[tauri::command]
+#[specta::specta]
+pub async fn start_analysis(
+    id: String,
+    engine: String,
+    tab: String,
+    options: EngineOptions,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let key = (tab.clone(), engine.clone());
+
+    if let Some(process) = state.engine_processes.get(&key) {
+        // the task that started the engine keeps reading its output
+        let mut process = process.lock().await;
+        if process.running {
+            process.stop().await?;
+        }
+        process.set_options(options).await?;
+        process.go(&GoMode::Infinite).await?;
+        return Ok(());
+    }
+
+    let tablebases = state.tablebase_path.lock().unwrap().clone();
+    let (mut process, mut reader) =
+        EngineProcess::new(PathBuf::from(&engine), tablebases.as_deref()).await?;
+    process.set_options(options).await?;
+    process.go(&GoMode::Infinite).await?;
+
+    let process = Arc::new(Mutex::new(process));
+    state.engine_processes.insert(key.clone(), process.clone());
+
+    let result = run_engine(&key, process, reader, &id, false, &app, &state).await;
+
+    // a restarted engine replaces the one started here
+    if let Some((_, process)) = state.engine_processes.remove(&key) {
+        let _ = process.lock().await.kill().await;
+    }
+    result
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum MoveClassification {
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl MoveClassification {
+    /// The NAG marking the move in a PGN, only for the bad ones
+    pub fn nag(self) -> Option<&'static str> {
+        match self {
+            MoveClassification::Best | MoveClassification::Good => None,
+            MoveClassification::Inaccuracy => Some("$6"),
+            MoveClassification::Mistake => Some("$2"),
+            MoveClassification::Blunder => Some("$4"),
+        }
+    }
+}
+
+/// Centipawn losses from which a move is classified as inaccuracy, mistake
+/// or blunder
+#[derive(Deserialize, Debug, Clone, Copy, Type, Derivative)]
+#[derivative(Default)]
+pub struct ClassificationThresholds {
+    #[derivative(Default(value = "50"))]
+    pub inaccuracy: i32,
+    #[derivative(Default(value = "100"))]
+    pub mistake: i32,
+    #[derivative(Default(value = "300"))]
+    pub blunder: i32,
+}
+
+/// Centipawn value of a mate, mates in fewer moves score higher
+const MATE_SCORE: i32 = 100_000;
+
+/// Converts a score to centipawns, with mates worth more than any material
+fn normalized_cp(score: &Score) -> i32 {
+    match score.value {
+        ScoreValue::Cp(x) => x.clamp(-MATE_SCORE / 2, MATE_SCORE / 2),
+        ScoreValue::Mate(x) if x > 0 => MATE_SCORE - x as i32,
+        ScoreValue::Mate(x) if x < 0 => -MATE_SCORE - x as i32,
+        ScoreValue::Mate(_) => 0,
+    }
+}
+
+/// Centipawns lost by the side that moved, given the white relative scores of
+/// the best move and the move that was played
+fn centipawn_loss(best: &Score, played: &Score, mover: Color) -> i32 {
+    let sign = if mover == Color::White { 1 } else { -1 };
+    (sign * (normalized_cp(best) - normalized_cp(played))).max(0)
+}
+
+fn is_mate_for(score: &Score, color: Color) -> bool {
+    match score.value {
+        ScoreValue::Mate(x) => (x > 0) == (color == Color::White),
+        ScoreValue::Cp(_) => false,
+    }
+}
+
+fn classify_move(
+    best: &Score,
+    played: &Score,
+    mover: Color,
+    thresholds: &ClassificationThresholds,
+) -> MoveClassification {
+    // missing a forced mate is a blunder no matter what the engine thinks
+    // of the position afterwards
+    if is_mate_for(best, mover) && !is_mate_for(played, mover) {
+        return MoveClassification::Blunder;
+    }
+    let loss = centipawn_loss(best, played, mover);
+    if loss >= thresholds.blunder {
+        MoveClassification::Blunder
+    } else if loss >= thresholds.mistake {
+        MoveClassification::Mistake
+    } else if loss >= thresholds.inaccuracy {
+        MoveClassification::Inaccuracy
+    } else {
+        MoveClassification::Good
+    }
+}
+
+/// Steepness of the logistic curve mapping centipawns to an expected score,
+/// fitted by Lichess on its own games
+const WIN_PROBABILITY_K: f64 = 0.00368208;
+
+/// Expected score, between 0 and 1, of the side a score is relative to
+pub fn win_probability(score: &Score) -> f64 {
+    match score.value {
+        ScoreValue::Mate(x) if x > 0 => 1.0,
+        ScoreValue::Mate(x) if x < 0 => 0.0,
+        ScoreValue::Mate(_) => 0.5,
+        ScoreValue::Cp(x) => {
+            let cp = x.clamp(-1000, 1000) as f64;
+            1.0 / (1.0 + (-WIN_PROBABILITY_K * cp).exp())
+        }
+    }
+}
+
+/// Accuracy of a single move, from the winning chances of the mover (in
+/// percent) before and after it
+fn move_accuracy(win_before: f64, win_after: f64) -> f64 {
+    let delta = (win_before - win_after).max(0.0);
+    (103.1668 * (-0.04354 * delta).exp() - 3.1669).clamp(0.0, 100.0)
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Type)]
+pub struct PlayerStats {
+    /// Average centipawn loss, with evaluations capped at ±1000
+    pub acpl: f64,
+    /// Lichess style accuracy percentage
+    pub accuracy: f64,
+}
+
+#[derive(Default)]
+struct StatsAccumulator {
+    losses: Vec<i32>,
+    accuracies: Vec<f64>,
+}
+
+impl StatsAccumulator {
+    fn push(&mut self, best: &Score, played: &Score, mover: Color) {
+        let capped = |score: &Score| Score {
+            value: ScoreValue::Cp(normalized_cp(score).clamp(-1000, 1000)),
+            ..Default::default()
+        };
+        self.losses
+            .push(centipawn_loss(&capped(best), &capped(played), mover));
+
+        let for_mover = |score: &Score| {
+            let p = win_probability(score);
+            if mover == Color::White {
+                p * 100.0
+            } else {
+                (1.0 - p) * 100.0
+            }
+        };
+        self.accuracies
+            .push(move_accuracy(for_mover(best), for_mover(played)));
+    }
+
+    fn finish(&self) -> Option<PlayerStats> {
+        if self.losses.is_empty() {
+            return None;
+        }
+        let n = self.losses.len() as f64;
+        let acpl = self.losses.iter().sum::<i32>() as f64 / n;
+        // like Lichess, average the arithmetic and harmonic means so a single
+        // blunder weighs more than a few slightly imprecise moves
+        let mean = self.accuracies.iter().sum::<f64>() / n;
+        let harmonic = n / self
+            .accuracies
+            .iter()
+            .map(|a| 1.0 / a.max(1.0))
+            .sum::<f64>();
+        Some(PlayerStats {
+            acpl,
+            accuracy: (mean + harmonic) / 2.0,
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, Type)]
+pub struct GameAnalysis {
+    pub moves: Vec<MoveAnalysis>,
+    /// One entry per move, ready to be written into the game with
+    /// `write_game_analysis`
+    pub annotations: Vec<MoveAnnotation>,
+    pub white: Option<PlayerStats>,
+    pub black: Option<PlayerStats>,
+    /// How the game ended on the board, if it did
+    pub terminal_state: Option<TerminalState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TerminalState {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    ThreefoldRepetition,
+    FiftyMoves,
+}
+
+/// Plays the moves of a game to find out whether its final position ends
+/// it. Repetitions count every earlier occurrence of the final position.
+fn terminal_state(options: &AnalysisOptions) -> Result<Option<TerminalState>, Error> {
+    let fen = Fen::from_ascii(options.fen.as_bytes())?;
+    let mut position: Chess = fen.into_position(CastlingMode::Chess960)?;
+    let mut seen: HashMap<Zobrist64, u32> = HashMap::new();
+    *seen
+        .entry(position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal))
+        .or_default() += 1;
+    for m in &options.moves {
+        let m = UciMove::from_ascii(m.as_bytes())?.to_move(&position)?;
+        position.play_unchecked(&m);
+        *seen
+            .entry(position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal))
+            .or_default() += 1;
+    }
+
+    let repetitions = seen[&position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal)];
+    Ok(if position.is_checkmate() {
+        Some(TerminalState::Checkmate)
+    } else if position.is_stalemate() {
+        Some(TerminalState::Stalemate)
+    } else if position.is_insufficient_material() {
+        Some(TerminalState::InsufficientMaterial)
+    } else if repetitions >= 3 {
+        Some(TerminalState::ThreefoldRepetition)
+    } else if position.halfmoves() >= 100 {
+        Some(TerminalState::FiftyMoves)
+    } else {
+        None
+    })
+}
+
+/// Largest number of pieces covered by Syzygy tablebases
+const MAX_TABLEBASE_PIECES: usize = 7;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TablebaseWdl {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// Exact result of a position found in the tablebases, from White's point of
+/// view like the engine scores
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+pub struct TablebaseResult {
+    pub wdl: TablebaseWdl,
+    /// Plies until the next capture or pawn move that keeps the result
+    pub dtz: u32,
+}
+
+fn count_tablebase_files(dir: &Path) -> Result<usize, Error> {
+    if !dir.is_dir() {
+        return Err(Error::InvalidTablebasePath(dir.display().to_string()));
+    }
+    Ok(fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "rtbw" || ext == "rtbz")
+        })
+        .count())
+}
+
+fn open_tablebases(dir: &Path) -> Result<Tablebase<Chess>, Error> {
+    let mut tables = Tablebase::new();
+    tables.add_directory(dir)?;
+    Ok(tables)
+}
+
+/// Probes the tablebases, returning `None` if the position has too many
+/// pieces or castling rights, or if the table is missing
+fn probe_tablebase(tables: &Tablebase<Chess>, pos: &Chess) -> Option<TablebaseResult> {
+    if pos.board().occupied().count() > MAX_TABLEBASE_PIECES {
+        return None;
+    }
+    let Dtz(dtz) = tables.probe_dtz(pos).ok()?.ignore_rounding();
+    // wins that take too long are drawn by the fifty move rule
+    let wdl = if dtz == 0 || dtz.unsigned_abs() + pos.halfmoves() > 100 {
+        TablebaseWdl::Draw
+    } else if (dtz > 0) == (pos.turn() == Color::White) {
+        TablebaseWdl::Win
+    } else {
+        TablebaseWdl::Loss
+    };
+    Some(TablebaseResult {
+        wdl,
+        dtz: dtz.unsigned_abs(),
+    })
+}
+
+/// Checks a tablebase directory, returning the number of tablebase files in it
+#[tauri::command]
+#[specta::specta]
+pub fn validate_tablebase_path(path: PathBuf) -> Result<u32, Error> {
+    Ok(count_tablebase_files(&path)? as u32)
+}
+
+/// Sets the tablebase directory given to engines started from now on, `None`
+/// disables tablebases
+#[tauri::command]
+#[specta::specta]
+pub fn set_tablebase_path(
+    path: Option<PathBuf>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    if let Some(dir) = &path {
+        if count_tablebase_files(dir)? == 0 {
+            return Err(Error::InvalidTablebasePath(dir.display().to_string()));
+        }
+    }
+    *state.tablebase_path.lock().unwrap() = path;
+    Ok(())
+}
+
+#[derive(Serialize, Debug, Default, Clone, Type)]
+pub struct MoveAnalysis {
+    best: Vec<BestMoves>,
+    novelty: bool,
+    is_sacrifice: bool,
+    classification: Option<MoveClassification>,
+    tablebase: Option<TablebaseResult>,
 }
 
 #[derive(Deserialize, Debug, Default, Type)]
@@ -585,6 +1702,68 @@ pub struct AnalysisOptions {
     pub annotate_novelties: bool,
     pub reference_db: Option<PathBuf>,
     pub reversed: bool,
+    #[serde(default)]
+    pub thresholds: ClassificationThresholds,
+}
+
+fn score_eval(score: &Score) -> Eval {
+    match score.value {
+        ScoreValue::Cp(x) => Eval::Cp(x),
+        ScoreValue::Mate(x) => Eval::Mate(x as i32),
+    }
+}
+
+/// The evaluation and classification of each move, from the analysis of the
+/// position it leads to. The first analysis is the one of the starting
+/// position, which no move leads to.
+fn move_annotations(analysis: &[MoveAnalysis]) -> Vec<MoveAnnotation> {
+    analysis
+        .iter()
+        .skip(1)
+        .map(|position| MoveAnnotation {
+            eval: position.best.first().map(|best| score_eval(&best.score)),
+            classification: position.classification,
+        })
+        .collect()
+}
+
+/// Classifies the move leading to each position, by comparing the evaluation
+/// of the previous position with the one after the move was played, and
+/// returns the statistics of both players
+fn classify_moves(
+    analysis: &mut [MoveAnalysis],
+    fens: &[(Fen, Vec<String>, bool)],
+    thresholds: &ClassificationThresholds,
+) -> (Option<PlayerStats>, Option<PlayerStats>) {
+    let mut white = StatsAccumulator::default();
+    let mut black = StatsAccumulator::default();
+    for i in 1..analysis.len().min(fens.len()) {
+        let (previous_fen, previous_moves, _) = &fens[i - 1];
+        let (_, moves, _) = &fens[i];
+        if moves.len() != previous_moves.len() + 1 {
+            continue;
+        }
+        let (Some(best), Some(played)) = (analysis[i - 1].best.first(), analysis[i].best.first())
+        else {
+            continue;
+        };
+        let mover = previous_fen.as_setup().turn;
+        let is_best = best.uci_moves.first() == moves.last();
+        let classification = if is_best {
+            MoveClassification::Best
+        } else {
+            classify_move(&best.score, &played.score, mover, thresholds)
+        };
+        // the engine's choice loses nothing, even if the following search
+        // evaluates it a bit differently
+        let played_score = if is_best { &best.score } else { &played.score };
+        match mover {
+            Color::White => white.push(&best.score, played_score, mover),
+            Color::Black => black.push(&best.score, played_score, mover),
+        }
+        analysis[i].classification = Some(classification);
+    }
+    (white.finish(), black.finish())
 }
 
 #[derive(Clone, Type, serde::Serialize, Event)]
@@ -592,32 +1771,22 @@ pub struct ReportProgress {
     pub progress: f64,
     pub id: String,
     pub finished: bool,
+    pub cancelled: bool,
+    pub game_index: u32,
+    pub game_total: u32,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn analyze_game(
-    id: String,
-    engine: String,
-    go_mode: GoMode,
-    options: AnalysisOptions,
-    uci_options: Vec<EngineOption>,
-    state: tauri::State<'_, AppState>,
-    app: tauri::AppHandle,
-) -> Result<Vec<MoveAnalysis>, Error> {
-    let path = PathBuf::from(&engine);
-    let mut analysis: Vec<MoveAnalysis> = Vec::new();
-
-    let (mut proc, mut reader) = EngineProcess::new(path).await?;
-
+/// Returns the positions to analyze, with the moves leading to them and
+/// whether the last move looks like a sacrifice
+fn analysis_positions(options: &AnalysisOptions) -> Result<Vec<(Fen, Vec<String>, bool)>, Error> {
     let fen = Fen::from_ascii(options.fen.as_bytes())?;
 
     let mut chess: Chess = fen.clone().into_position(CastlingMode::Chess960)?;
     let mut fens: Vec<(Fen, Vec<String>, bool)> = vec![(fen, vec![], false)];
 
-    options.moves.iter().enumerate().for_each(|(i, m)| {
-        let uci = UciMove::from_ascii(m.as_bytes()).unwrap();
-        let m = uci.to_move(&chess).unwrap();
+    for (i, m) in options.moves.iter().enumerate() {
+        let uci = UciMove::from_ascii(m.as_bytes())?;
+        let m = uci.to_move(&chess)?;
         let previous_pos = chess.clone();
         chess.play_unchecked(&m);
         let current_pos = chess.clone();
@@ -626,27 +1795,43 @@ pub async fn analyze_game(
             let cur_eval = -naive_eval(&current_pos);
             fens.push((
                 Fen::from_position(current_pos, EnPassantMode::Legal),
-                options.moves.clone().into_iter().take(i + 1).collect(),
+                options.moves.iter().take(i + 1).cloned().collect(),
                 prev_eval > cur_eval + 100,
             ));
         }
-    });
+    }
 
     if options.reversed {
         fens.reverse();
     }
+    Ok(fens)
+}
+
+/// Analyzes every position of a game with an already started engine,
+/// calling `report` with the progress before each position
+#[allow(clippy::too_many_arguments)]
+async fn run_analysis(
+    proc: &mut EngineProcess,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+    go_mode: &GoMode,
+    options: &AnalysisOptions,
+    uci_options: &[EngineOption],
+    cancelled: &AtomicBool,
+    state: &tauri::State<'_, AppState>,
+    mut report: impl FnMut(f64) -> Result<(), Error>,
+) -> Result<GameAnalysis, Error> {
+    let mut fens = analysis_positions(options)?;
+    let mut analysis: Vec<MoveAnalysis> = Vec::with_capacity(fens.len());
 
     let mut novelty_found = false;
 
     for (i, (_, moves, _)) in fens.iter().enumerate() {
-        ReportProgress {
-            progress: (i as f64 / fens.len() as f64) * 100.0,
-            id: id.clone(),
-            finished: false,
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(Error::AnalysisCancelled);
         }
-        .emit(&app)?;
+        report((i as f64 / fens.len() as f64) * 100.0)?;
 
-        let mut extra_options = uci_options.clone();
+        let mut extra_options = uci_options.to_vec();
         if !extra_options.iter().any(|x| x.name == "MultiPV") {
             extra_options.push(EngineOption {
                 name: "MultiPV".to_string(),
@@ -664,41 +1849,45 @@ pub async fn analyze_game(
             fen: options.fen.clone(),
             moves: moves.clone(),
             extra_options,
+            ..Default::default()
         })
         .await?;
 
-        proc.go(&go_mode).await?;
+        proc.go(go_mode).await?;
 
         let mut current_analysis = MoveAnalysis::default();
-        while let Ok(Some(line)) = reader.next_line().await {
+        let mut search_finished = false;
+        while let Some(line) = reader.next_line().await? {
+            if proc.running && cancelled.load(Ordering::Relaxed) {
+                // cut the search short, the loop stops before the next position
+                proc.stop().await?;
+            }
             match parse_one(&line) {
                 UciMessage::Info(attrs) => {
                     if let Ok(best_moves) =
                         parse_uci_attrs(attrs, &proc.options.fen.parse()?, moves)
                     {
-                        let multipv = best_moves.multipv;
-                        let cur_depth = best_moves.depth;
-                        if multipv as usize == proc.best_moves.len() + 1 {
-                            proc.best_moves.push(best_moves);
-                            if multipv == proc.real_multipv {
-                                if proc.best_moves.iter().all(|x| x.depth == cur_depth)
-                                    && cur_depth >= proc.last_depth
-                                {
-                                    current_analysis.best = proc.best_moves.clone();
-                                    proc.last_depth = cur_depth;
-                                }
-                                assert_eq!(proc.best_moves.len(), proc.real_multipv as usize);
-                                proc.best_moves.clear();
+                        if let Some(lines) = proc.push_line(best_moves) {
+                            let cur_depth = lines.last().map(|x| x.depth).unwrap_or_default();
+                            if lines.iter().all(|x| x.depth == cur_depth)
+                                && cur_depth >= proc.last_depth
+                            {
+                                current_analysis.best = lines;
+                                proc.last_depth = cur_depth;
                             }
                         }
                     }
                 }
                 UciMessage::BestMove { .. } => {
+                    search_finished = true;
                     break;
                 }
                 _ => {}
             }
         }
+        if !search_finished {
+            return Err(Error::EngineCrashed);
+        }
         analysis.push(current_analysis);
     }
 
@@ -707,6 +1896,11 @@ pub async fn analyze_game(
         fens.reverse();
     }
 
+    let (white, black) = classify_moves(&mut analysis, &fens, &options.thresholds);
+
+    let tablebase_path = state.tablebase_path.lock().unwrap().clone();
+    let tablebases = tablebase_path.as_deref().map(open_tablebases).transpose()?;
+
     for (i, analysis) in analysis.iter_mut().enumerate() {
         let fen = &fens[i].0;
         // let query = PositionQuery::exact_from_fen(&fen.to_string())?;
@@ -716,6 +1910,11 @@ pub async fn analyze_game(
         };
 
         analysis.is_sacrifice = fens[i].2;
+        if let Some(tables) = &tablebases {
+            if let Ok(pos) = fen.clone().into_position(CastlingMode::Chess960) {
+                analysis.tablebase = probe_tablebase(tables, &pos);
+            }
+        }
         if options.annotate_novelties && !novelty_found {
             if let Some(reference) = options.reference_db.clone() {
                 analysis.novelty = !is_position_in_db(
@@ -732,13 +1931,428 @@ pub async fn analyze_game(
             }
         }
     }
+    Ok(GameAnalysis {
+        annotations: move_annotations(&analysis),
+        moves: analysis,
+        white,
+        black,
+        terminal_state: terminal_state(options)?,
+    })
+}
+
+/// How many engine searches run at once, unless the user picked another
+/// limit. `AppState::new_request` starts with this many permits.
+pub const ANALYSIS_SLOTS: usize = 2;
+
+/// Where the analysis limit picked by the user is kept, in the app data
+/// directory
+const ANALYSIS_SETTINGS_FILE: &str = "analysis.json";
+
+/// How long a search waits for a slot by default before giving up
+const DEFAULT_ANALYSIS_SLOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a waiting search checks whether it was cancelled
+const ANALYSIS_SLOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The limit on searches, and how many of the running ones have to give up
+/// their slot when they finish because the limit was lowered
+struct SlotLimit {
+    limit: usize,
+    debt: usize,
+}
+
+/// How many permits of `AppState::new_request` there are and how long a
+/// search waits for one of them
+pub struct AnalysisSlots {
+    /// Permits are only taken away or given back with this held, so that the
+    /// debt always matches the slots in use
+    limit: Arc<std::sync::Mutex<SlotLimit>>,
+    timeout_ms: AtomicU64,
+}
+
+impl Default for AnalysisSlots {
+    fn default() -> Self {
+        Self {
+            limit: Arc::new(std::sync::Mutex::new(SlotLimit {
+                limit: ANALYSIS_SLOTS,
+                debt: 0,
+            })),
+            timeout_ms: AtomicU64::new(DEFAULT_ANALYSIS_SLOT_TIMEOUT.as_millis() as u64),
+        }
+    }
+}
+
+/// The slot of a running search, given back when it is dropped
+struct AnalysisSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    limit: Arc<std::sync::Mutex<SlotLimit>>,
+}
+
+impl Drop for AnalysisSlot {
+    fn drop(&mut self) {
+        let mut limit = self.limit.lock().unwrap();
+        // a permit that isn't forgotten goes back before the lock is released
+        if let Some(permit) = self.permit.take() {
+            if limit.debt > 0 {
+                limit.debt -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl AnalysisSlots {
+    /// Searches that are already running keep their slot, the ones a lower
+    /// limit takes away are given up as they finish
+    fn set_limit(&self, slots: &Semaphore, new_limit: usize) {
+        let mut limit = self.limit.lock().unwrap();
+        if new_limit > limit.limit {
+            let raise = new_limit - limit.limit;
+            let paid = raise.min(limit.debt);
+            limit.debt -= paid;
+            slots.add_permits(raise - paid);
+        } else {
+            let mut extra = limit.limit - new_limit;
+            while extra > 0 {
+                match slots.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => break,
+                }
+                extra -= 1;
+            }
+            limit.debt += extra;
+        }
+        limit.limit = new_limit;
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Waits for a free slot, unless the search is cancelled or no slot
+    /// frees up before the timeout
+    async fn acquire(
+        &self,
+        slots: &Arc<Semaphore>,
+        cancelled: &AtomicBool,
+    ) -> Result<AnalysisSlot, Error> {
+        let timeout = Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed));
+        let deadline = Instant::now() + timeout;
+        let permit = slots.clone().acquire_owned();
+        tokio::pin!(permit);
+        loop {
+            tokio::select! {
+                permit = &mut permit => {
+                    return Ok(AnalysisSlot {
+                        permit: Some(permit?),
+                        limit: self.limit.clone(),
+                    })
+                }
+                _ = tokio::time::sleep(ANALYSIS_SLOT_POLL_INTERVAL) => {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return Err(Error::AnalysisCancelled);
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(Error::AnalysisSlotsBusy);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sets how long `get_best_moves` waits for another search to finish before
+/// failing with `AnalysisSlotsBusy`
+#[tauri::command]
+#[specta::specta]
+pub fn set_analysis_slot_timeout(seconds: u32, state: tauri::State<'_, AppState>) {
+    state
+        .analysis_slots
+        .set_timeout(Duration::from_secs(seconds.into()));
+}
+
+/// Each analysis runs its own engine, so there is no point in running more
+/// of them than there are cores
+fn clamp_analysis_limit(limit: u32, cores: usize) -> usize {
+    (limit as usize).clamp(1, cores.max(1))
+}
+
+fn available_cores() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct AnalysisSettings {
+    max_concurrent_analyses: Option<u32>,
+}
+
+fn analysis_settings_path(app: &tauri::AppHandle) -> Result<PathBuf, Error> {
+    Ok(app
+        .path()
+        .resolve(ANALYSIS_SETTINGS_FILE, BaseDirectory::AppData)?)
+}
+
+/// Applies the analysis limit saved by `set_max_concurrent_analyses`
+pub fn restore_analysis_settings(app: &tauri::AppHandle, state: &AppState) {
+    let Ok(path) = analysis_settings_path(app) else {
+        return;
+    };
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return;
+    };
+    match serde_json::from_str::<AnalysisSettings>(&contents) {
+        Ok(settings) => {
+            if let Some(limit) = settings.max_concurrent_analyses {
+                state.analysis_slots.set_limit(
+                    &state.new_request,
+                    clamp_analysis_limit(limit, available_cores()),
+                );
+            }
+        }
+        Err(e) => error!("Invalid {}: {}", ANALYSIS_SETTINGS_FILE, e),
+    }
+}
+
+/// Sets how many engine searches can run at once and saves it for the next
+/// launch. Returns the limit that was applied, which is at most the number
+/// of cores.
+#[tauri::command]
+#[specta::specta]
+pub async fn set_max_concurrent_analyses(
+    limit: u32,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32, Error> {
+    let limit = clamp_analysis_limit(limit, available_cores());
+    state.analysis_slots.set_limit(&state.new_request, limit);
+    let settings = AnalysisSettings {
+        max_concurrent_analyses: Some(limit as u32),
+    };
+    std::fs::write(
+        analysis_settings_path(&app)?,
+        serde_json::to_string(&settings)?,
+    )?;
+    Ok(limit as u32)
+}
+
+/// Where a `get_best_moves` waiting for a slot is registered in
+/// `analysis_cancellations`, so that `stop_engine` can stop it
+fn best_moves_request_id(tab: &str, engine: &str) -> String {
+    format!("best_moves:{tab}:{engine}")
+}
+
+fn register_analysis(id: &str, state: &tauri::State<'_, AppState>) -> Arc<AtomicBool> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .analysis_cancellations
+        .insert(id.to_string(), cancelled.clone());
+    cancelled
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn analyze_game(
+    id: String,
+    engine: String,
+    go_mode: GoMode,
+    options: AnalysisOptions,
+    uci_options: Vec<EngineOption>,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<GameAnalysis, Error> {
+    let cancelled = register_analysis(&id, &state);
+
+    let tablebases = state.tablebase_path.lock().unwrap().clone();
+    let (mut proc, mut reader) =
+        EngineProcess::new(PathBuf::from(&engine), tablebases.as_deref()).await?;
+
+    let result = run_analysis(
+        &mut proc,
+        &mut reader,
+        &go_mode,
+        &options,
+        &uci_options,
+        &cancelled,
+        &state,
+        |progress| {
+            ReportProgress {
+                progress,
+                id: id.clone(),
+                finished: false,
+                cancelled: false,
+                game_index: 0,
+                game_total: 1,
+            }
+            .emit(&app)?;
+            Ok(())
+        },
+    )
+    .await;
+
+    state.analysis_cancellations.remove(&id);
+    let _ = proc.kill().await;
+
+    let was_cancelled = matches!(result, Err(Error::AnalysisCancelled));
+    if result.is_ok() || was_cancelled {
+        ReportProgress {
+            progress: 100.0,
+            id: id.clone(),
+            finished: true,
+            cancelled: was_cancelled,
+            game_index: 0,
+            game_total: 1,
+        }
+        .emit(&app)?;
+    }
+    result
+}
+
+/// Sent by `analyze_games` as soon as each game is done, so the results don't
+/// have to be kept until the whole batch finishes
+#[derive(Clone, Serialize, Debug, Type, Event)]
+pub struct GameAnalysisResult {
+    pub id: String,
+    pub game_index: u32,
+    pub analysis: Option<GameAnalysis>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, Type)]
+pub struct BatchAnalysisSummary {
+    pub analyzed: u32,
+    pub failed: Vec<u32>,
+}
+
+/// Analyzes several games with the same engine process. Games that fail,
+/// for example because the engine crashed, are reported and skipped.
+#[tauri::command]
+#[specta::specta]
+pub async fn analyze_games(
+    id: String,
+    engine: String,
+    go_mode: GoMode,
+    games: Vec<AnalysisOptions>,
+    uci_options: Vec<EngineOption>,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<BatchAnalysisSummary, Error> {
+    let cancelled = register_analysis(&id, &state);
+
+    let path = PathBuf::from(&engine);
+    let tablebases = state.tablebase_path.lock().unwrap().clone();
+    let game_total = games.len() as u32;
+    let mut summary = BatchAnalysisSummary::default();
+    let mut engine_process = None;
+
+    for (game_index, options) in games.iter().enumerate() {
+        let game_index = game_index as u32;
+
+        if engine_process.is_none() {
+            match EngineProcess::new(path.clone(), tablebases.as_deref()).await {
+                Ok(process) => engine_process = Some(process),
+                Err(e) => {
+                    state.analysis_cancellations.remove(&id);
+                    return Err(e);
+                }
+            }
+        }
+        let (proc, reader) = engine_process.as_mut().unwrap();
+
+        let result = run_analysis(
+            proc,
+            reader,
+            &go_mode,
+            options,
+            &uci_options,
+            &cancelled,
+            &state,
+            |progress| {
+                ReportProgress {
+                    progress,
+                    id: id.clone(),
+                    finished: false,
+                    cancelled: false,
+                    game_index,
+                    game_total,
+                }
+                .emit(&app)?;
+                Ok(())
+            },
+        )
+        .await;
+
+        match result {
+            Ok(analysis) => {
+                summary.analyzed += 1;
+                GameAnalysisResult {
+                    id: id.clone(),
+                    game_index,
+                    analysis: Some(analysis),
+                    error: None,
+                }
+                .emit(&app)?;
+            }
+            Err(Error::AnalysisCancelled) => {
+                let _ = proc.kill().await;
+                state.analysis_cancellations.remove(&id);
+                ReportProgress {
+                    progress: 100.0,
+                    id: id.clone(),
+                    finished: true,
+                    cancelled: true,
+                    game_index,
+                    game_total,
+                }
+                .emit(&app)?;
+                return Err(Error::AnalysisCancelled);
+            }
+            Err(e) => {
+                error!("Analysis of game {} failed: {}", game_index, e);
+                summary.failed.push(game_index);
+                GameAnalysisResult {
+                    id: id.clone(),
+                    game_index,
+                    analysis: None,
+                    error: Some(e.to_string()),
+                }
+                .emit(&app)?;
+                if matches!(e, Error::EngineCrashed | Error::Io(_)) {
+                    // start a new engine for the remaining games
+                    engine_process = None;
+                }
+            }
+        }
+    }
+
+    if let Some((mut proc, _)) = engine_process {
+        let _ = proc.kill().await;
+    }
+    state.analysis_cancellations.remove(&id);
+
     ReportProgress {
         progress: 100.0,
         id: id.clone(),
         finished: true,
+        cancelled: false,
+        game_index: game_total.saturating_sub(1),
+        game_total,
+    }
+    .emit(&app)?;
+    Ok(summary)
+}
+
+/// Stops a running `analyze_game` or `analyze_games` before its next move
+#[tauri::command]
+#[specta::specta]
+pub async fn cancel_analysis(id: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    if let Some(cancelled) = state.analysis_cancellations.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
     }
-    .emit(&app)?;
-    Ok(analysis)
+    Ok(())
 }
 
 fn count_material(position: &Chess) -> i32 {
@@ -815,6 +2429,295 @@ fn naive_eval(pos: &Chess) -> i32 {
         .unwrap_or(i32::MIN)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum GamePhase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+/// Phase weight of all the pieces in the starting position
+const FULL_PHASE_WEIGHT: u32 = 24;
+/// Positions with at most this much phase weight left are endgames
+const ENDGAME_PHASE_WEIGHT: u32 = 8;
+/// Full material is only considered an opening for this many moves
+const OPENING_MAX_FULLMOVES: u32 = 12;
+
+/// Classifies a position by its remaining non-pawn material: knights and
+/// bishops weigh 1, rooks 2 and queens 4.
+pub fn position_phase(position: &Chess) -> GamePhase {
+    let material = position.board().material();
+    let weight: u32 = [material.white, material.black]
+        .iter()
+        .map(|m| m.knight as u32 + m.bishop as u32 + 2 * m.rook as u32 + 4 * m.queen as u32)
+        .sum();
+
+    if weight <= ENDGAME_PHASE_WEIGHT {
+        GamePhase::Endgame
+    } else if weight >= FULL_PHASE_WEIGHT - 2
+        && position.fullmoves().get() <= OPENING_MAX_FULLMOVES
+    {
+        GamePhase::Opening
+    } else {
+        GamePhase::Middlegame
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_position_phase(fen: String) -> Result<GamePhase, Error> {
+    let parsed: Fen = fen.parse()?;
+    let position: Chess = match parsed.into_position(CastlingMode::Chess960) {
+        Ok(p) => p,
+        Err(e) => e.ignore_too_much_material()?,
+    };
+    Ok(position_phase(&position))
+}
+
+/// Plays `san` on the position of `fen` and returns the FEN of the resulting
+/// position, with its castling rights, en passant square and clocks updated
+#[tauri::command]
+#[specta::specta]
+pub fn apply_san(fen: String, san: String) -> Result<String, Error> {
+    let fen = Fen::from_ascii(fen.as_bytes())?;
+    let mut position: Chess = fen.into_position(CastlingMode::Chess960)?;
+    let san = SanPlus::from_ascii(san.as_bytes())?;
+    let m = san.san.to_move(&position)?;
+    position.play_unchecked(&m);
+    Ok(Fen::from_position(position, EnPassantMode::Legal).to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct LegalMove {
+    pub from: String,
+    /// Where the king lands when castling, not the square of the rook
+    pub to: String,
+    #[specta(optional)]
+    pub promotion: Option<String>,
+    pub san: String,
+}
+
+/// Every legal move of the position of `fen`
+#[tauri::command]
+#[specta::specta]
+pub fn legal_moves(fen: String) -> Result<Vec<LegalMove>, Error> {
+    let fen = Fen::from_ascii(fen.as_bytes())?;
+    let position: Chess = fen.into_position(CastlingMode::Chess960)?;
+    let turn = position.turn();
+    Ok(position
+        .legal_moves()
+        .iter()
+        .filter_map(|m| {
+            let to = match m.castling_side() {
+                Some(side) => side.king_to(turn),
+                None => m.to(),
+            };
+            Some(LegalMove {
+                from: m.from()?.to_string(),
+                to: to.to_string(),
+                promotion: m.promotion().map(|role| role.char().to_string()),
+                san: SanPlus::from_move(position.clone(), m).to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Longest mate the built-in search looks for
+const MAX_BUILTIN_MATE: u32 = 3;
+/// Longest mate an engine is asked to look for
+const MAX_ENGINE_MATE: u32 = 20;
+/// How long an engine gets to find a mate before the built-in search is used
+const ENGINE_MATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Finds a forced mate in exactly `n` moves or less for the side to move.
+/// The returned line follows the longest defence.
+fn mate_in(pos: &Chess, n: u32) -> Option<Vec<Move>> {
+    if n == 0 {
+        return None;
+    }
+    for m in pos.legal_moves() {
+        let mut after = pos.clone();
+        after.play_unchecked(&m);
+        if after.is_checkmate() {
+            return Some(vec![m]);
+        }
+        if n == 1 || after.is_game_over() {
+            continue;
+        }
+
+        let mut longest: Option<Vec<Move>> = None;
+        let mut forced = true;
+        for reply in after.legal_moves() {
+            let mut next = after.clone();
+            next.play_unchecked(&reply);
+            match mate_in(&next, n - 1) {
+                Some(line) => {
+                    if longest.as_ref().map_or(true, |l| line.len() + 1 > l.len()) {
+                        longest = Some([vec![reply], line].concat());
+                    }
+                }
+                None => {
+                    forced = false;
+                    break;
+                }
+            }
+        }
+        if let (true, Some(rest)) = (forced, longest) {
+            return Some([vec![m], rest].concat());
+        }
+    }
+    None
+}
+
+/// Finds the shortest forced mate of at most `max_n` moves
+fn find_forced_mate(pos: &Chess, max_n: u32) -> Option<Vec<Move>> {
+    (1..=max_n).find_map(|n| mate_in(pos, n))
+}
+
+/// Checks that `line` is legal and ends in checkmate. Lines short enough to
+/// search must also be forced, returns whether the search could check that.
+fn verify_mate_line(pos: &Chess, line: &[Move]) -> Option<bool> {
+    if line.len() % 2 == 0 {
+        return None;
+    }
+    let mut after = pos.clone();
+    for m in line {
+        if !after.is_legal(m) {
+            return None;
+        }
+        after.play_unchecked(m);
+    }
+    if !after.is_checkmate() {
+        return None;
+    }
+    let moves = (line.len() as u32 + 1) / 2;
+    if moves > MAX_BUILTIN_MATE {
+        return Some(false);
+    }
+    mate_in(pos, moves).map(|_| true)
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MateSolution {
+    pub moves: u32,
+    pub uci_moves: Vec<String>,
+    pub san_moves: Vec<String>,
+    /// Whether the built-in search proved that the mate can't be avoided.
+    /// The engine's longer mates are only checked to be legal and to end in
+    /// mate.
+    pub verified: bool,
+}
+
+impl MateSolution {
+    fn new(pos: &Chess, line: &[Move], verified: bool) -> Self {
+        let mut pos = pos.clone();
+        let mut uci_moves = Vec::with_capacity(line.len());
+        let mut san_moves = Vec::with_capacity(line.len());
+        for m in line {
+            uci_moves.push(m.to_uci(CastlingMode::Standard).to_string());
+            san_moves.push(SanPlus::from_move_and_play_unchecked(&mut pos, m).to_string());
+        }
+        MateSolution {
+            moves: (line.len() as u32 + 1) / 2,
+            uci_moves,
+            san_moves,
+            verified,
+        }
+    }
+}
+
+/// Asks the engine for a mate with `go mate`, returning its principal
+/// variation if it reports a mate for the side to move. An engine that doesn't
+/// finish in time is stopped, with the last mate it reported.
+async fn engine_mate(engine: PathBuf, fen: &str, pos: &Chess, n: u32) -> Result<Vec<Move>, Error> {
+    let (mut proc, mut reader) = EngineProcess::new(engine, None).await?;
+    proc.set_position(fen, &Vec::new()).await?;
+    proc.go(&GoMode::Mate(n)).await?;
+
+    let fen: Fen = fen.parse()?;
+    let mut line = Vec::new();
+    let deadline = tokio::time::Instant::now() + ENGINE_MATE_TIMEOUT;
+    loop {
+        let Ok(output) = tokio::time::timeout_at(deadline, reader.next_line()).await else {
+            info!("Engine didn't finish the mate search in time");
+            break;
+        };
+        let Some(output) = output? else {
+            break;
+        };
+        match parse_one(&output) {
+            UciMessage::Info(attrs) => {
+                if let Ok(best_moves) = parse_uci_attrs(attrs, &fen, &Vec::new()) {
+                    // scores are relative to white
+                    let mating = match best_moves.score.value {
+                        ScoreValue::Mate(x) => (x > 0) == (pos.turn() == Color::White),
+                        ScoreValue::Cp(_) => false,
+                    };
+                    if best_moves.multipv == 1 {
+                        line = if mating {
+                            best_moves.uci_moves
+                        } else {
+                            Vec::new()
+                        };
+                    }
+                }
+            }
+            UciMessage::BestMove { .. } => break,
+            _ => {}
+        }
+    }
+    proc.kill().await?;
+
+    let mut after = pos.clone();
+    let mut moves = Vec::with_capacity(line.len());
+    for uci in line {
+        let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&after)?;
+        after.play_unchecked(&m);
+        moves.push(m);
+    }
+    Ok(moves)
+}
+
+/// Looks for a forced mate in at most `max_n` moves. Uses `go mate` when an
+/// engine is given, and the built-in search otherwise or when the engine
+/// doesn't find one. Returns `None` when there's no forced mate.
+#[tauri::command]
+#[specta::specta]
+pub async fn solve_mate(
+    fen: String,
+    max_n: u32,
+    engine: Option<PathBuf>,
+) -> Result<Option<MateSolution>, Error> {
+    let max_mate = if engine.is_some() {
+        MAX_ENGINE_MATE
+    } else {
+        MAX_BUILTIN_MATE
+    };
+    if max_n == 0 || max_n > max_mate {
+        return Err(Error::InvalidMateLength(max_mate));
+    }
+
+    let parsed: Fen = fen.parse()?;
+    let pos: Chess = match parsed.into_position(CastlingMode::Chess960) {
+        Ok(p) => p,
+        Err(e) => e.ignore_too_much_material()?,
+    };
+
+    if let Some(engine) = engine {
+        let line = engine_mate(engine, &fen, &pos, max_n).await?;
+        if !line.is_empty() && line.len() as u32 <= 2 * max_n - 1 {
+            if let Some(verified) = verify_mate_line(&pos, &line) {
+                return Ok(Some(MateSolution::new(&pos, &line, verified)));
+            }
+        }
+    }
+
+    Ok(find_forced_mate(&pos, max_n.min(MAX_BUILTIN_MATE))
+        .map(|line| MateSolution::new(&pos, &line, true)))
+}
+
 #[cfg(test)]
 mod tests {
     use shakmaty::FromSetup;
@@ -878,41 +2781,901 @@ mod tests {
         let position = pos("4kb1r/p2rqppp/5n2/1B2p1B1/4P3/1Q6/PPP2PPP/2KR4 b k - 1 14");
         assert_eq!(naive_eval(&position), 0);
     }
+
+    #[test]
+    fn phase_start_pos() {
+        assert_eq!(position_phase(&Chess::default()), GamePhase::Opening);
+    }
+
+    #[test]
+    fn phase_full_material_late() {
+        let position =
+            pos("r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQK2R w KQkq - 6 20");
+        assert_eq!(position_phase(&position), GamePhase::Middlegame);
+    }
+
+    #[test]
+    fn phase_rook_endgame() {
+        let position = pos("8/5pk1/6p1/8/3R4/6P1/r4PK1/8 w - - 0 41");
+        assert_eq!(position_phase(&position), GamePhase::Endgame);
+    }
+
+    fn cp(x: i32) -> Score {
+        Score {
+            value: ScoreValue::Cp(x),
+            ..Default::default()
+        }
+    }
+
+    fn mate(x: i8) -> Score {
+        Score {
+            value: ScoreValue::Mate(x),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn classify_blunder() {
+        let thresholds = ClassificationThresholds::default();
+        assert_eq!(
+            classify_move(&cp(50), &cp(-300), Color::White, &thresholds),
+            MoveClassification::Blunder
+        );
+        assert_eq!(
+            classify_move(&cp(-50), &cp(300), Color::Black, &thresholds),
+            MoveClassification::Blunder
+        );
+        assert_eq!(
+            classify_move(&cp(50), &cp(-100), Color::White, &thresholds),
+            MoveClassification::Mistake
+        );
+        assert_eq!(
+            classify_move(&cp(50), &cp(-10), Color::White, &thresholds),
+            MoveClassification::Inaccuracy
+        );
+        assert_eq!(
+            classify_move(&cp(50), &cp(40), Color::White, &thresholds),
+            MoveClassification::Good
+        );
+    }
+
+    #[test]
+    fn classify_mates() {
+        let thresholds = ClassificationThresholds::default();
+        // missing a mate, even when still winning
+        assert_eq!(
+            classify_move(&mate(2), &cp(900), Color::White, &thresholds),
+            MoveClassification::Blunder
+        );
+        // a slower mate is still fine
+        assert_eq!(
+            classify_move(&mate(2), &mate(3), Color::White, &thresholds),
+            MoveClassification::Good
+        );
+        // allowing a mate
+        assert_eq!(
+            classify_move(&cp(0), &mate(4), Color::Black, &thresholds),
+            MoveClassification::Blunder
+        );
+    }
+
+    #[test]
+    fn annotates_the_moves_of_an_analysis() {
+        let analyzed = |score, raw_score, classification| MoveAnalysis {
+            best: vec![BestMoves {
+                score,
+                raw_score,
+                ..Default::default()
+            }],
+            classification,
+            ..Default::default()
+        };
+        let analysis = vec![
+            // the starting position, no move leads to it
+            analyzed(cp(20), cp(20), None),
+            // black to move, the engine scores it for black
+            analyzed(cp(35), cp(-35), Some(MoveClassification::Good)),
+            analyzed(mate(3), mate(-3), Some(MoveClassification::Blunder)),
+            MoveAnalysis::default(),
+        ];
+        assert_eq!(
+            move_annotations(&analysis),
+            vec![
+                MoveAnnotation {
+                    eval: Some(Eval::Cp(35)),
+                    classification: Some(MoveClassification::Good),
+                },
+                MoveAnnotation {
+                    eval: Some(Eval::Mate(3)),
+                    classification: Some(MoveClassification::Blunder),
+                },
+                MoveAnnotation::default(),
+            ]
+        );
+    }
+
+    #[test]
+    fn win_probability_reference_values() {
+        assert!((win_probability(&cp(0)) - 0.5).abs() < 1e-9);
+        assert!((win_probability(&cp(100)) - 0.591).abs() < 1e-3);
+        assert!((win_probability(&cp(300)) - 0.751).abs() < 1e-3);
+        assert!((win_probability(&cp(-100)) - 0.409).abs() < 1e-3);
+        assert_eq!(win_probability(&mate(3)), 1.0);
+        assert_eq!(win_probability(&mate(-1)), 0.0);
+        assert!((win_probability(&cp(5000)) - win_probability(&cp(1000))).abs() < 1e-9);
+    }
+
+    #[test]
+    fn player_stats() {
+        let mut white = StatsAccumulator::default();
+        white.push(&cp(30), &cp(30), Color::White);
+        white.push(&cp(30), &cp(-270), Color::White);
+        let stats = white.finish().unwrap();
+        assert_eq!(stats.acpl, 150.0);
+        assert!(stats.accuracy > 0.0 && stats.accuracy < 100.0);
+
+        let mut black = StatsAccumulator::default();
+        black.push(&cp(-20), &cp(-20), Color::Black);
+        let stats = black.finish().unwrap();
+        assert_eq!(stats.acpl, 0.0);
+        assert!(stats.accuracy > 99.9);
+
+        // a missed mate is capped instead of costing the whole mate score
+        let mut missed = StatsAccumulator::default();
+        missed.push(&mate(1), &cp(0), Color::White);
+        assert_eq!(missed.finish().unwrap().acpl, 1000.0);
+
+        assert!(StatsAccumulator::default().finish().is_none());
+    }
+
+    #[test]
+    fn applies_san_moves() {
+        let apply_san = |fen: &str, san: &str| apply_san(fen.to_string(), san.to_string());
+        assert_eq!(
+            apply_san(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "e4"
+            )
+            .unwrap(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+        );
+        assert!(matches!(
+            apply_san(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "e5"
+            ),
+            Err(Error::IllegalSan(_))
+        ));
+
+        assert_eq!(
+            apply_san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "O-O").unwrap(),
+            "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1"
+        );
+
+        // the en passant square is only set when the capture is possible
+        let fen = apply_san(
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+            "f5",
+        )
+        .unwrap();
+        assert_eq!(
+            fen,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"
+        );
+        assert_eq!(
+            apply_san(&fen, "exf6").unwrap(),
+            "rnbqkbnr/ppp1p1pp/5P2/3p4/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3"
+        );
+    }
+
+    #[test]
+    fn labels_finished_games() {
+        let game = |fen: &str, moves: &[&str]| {
+            terminal_state(&AnalysisOptions {
+                fen: fen.to_string(),
+                moves: moves.iter().map(|m| m.to_string()).collect(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+        assert_eq!(
+            game("7k/8/5QK1/8/8/8/8/8 w - - 0 1", &["f6f7"]),
+            Some(TerminalState::Stalemate)
+        );
+        assert_eq!(
+            game(start, &["f2f3", "e7e5", "g2g4", "d8h4"]),
+            Some(TerminalState::Checkmate)
+        );
+
+        let knights = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        assert_eq!(game(start, &knights), None);
+        assert_eq!(
+            game(start, &knights.repeat(2)),
+            Some(TerminalState::ThreefoldRepetition)
+        );
+        assert_eq!(
+            game("8/8/4k3/8/8/4K3/8/8 w - - 0 1", &["e3e4"]),
+            Some(TerminalState::InsufficientMaterial)
+        );
+        assert_eq!(
+            game("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80", &["a1a2"]),
+            Some(TerminalState::FiftyMoves)
+        );
+    }
+
+    #[test]
+    fn lists_legal_moves() {
+        let moves = |fen: &str| legal_moves(fen.to_string()).unwrap();
+        let legal_move = |from: &str, to: &str, promotion: Option<&str>, san: &str| LegalMove {
+            from: from.to_string(),
+            to: to.to_string(),
+            promotion: promotion.map(str::to_string),
+            san: san.to_string(),
+        };
+
+        assert_eq!(moves(&Fen::default().to_string()).len(), 20);
+
+        let en_passant = moves("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3");
+        assert!(en_passant.contains(&legal_move("e5", "f6", None, "exf6")));
+
+        let castling = moves("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert!(castling.contains(&legal_move("e1", "g1", None, "O-O")));
+        assert!(castling.contains(&legal_move("e1", "c1", None, "O-O-O")));
+
+        let promotions = moves("8/P7/8/8/8/8/8/k6K w - - 0 1");
+        assert!(promotions.contains(&legal_move("a7", "a8", Some("q"), "a8=Q")));
+        assert_eq!(
+            promotions.iter().filter(|m| m.promotion.is_some()).count(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn waiting_for_an_analysis_slot_times_out() {
+        let requests = Arc::new(Semaphore::new(ANALYSIS_SLOTS));
+        let slots = AnalysisSlots::default();
+        slots.set_timeout(Duration::from_millis(300));
+        let cancelled = AtomicBool::new(false);
+        let _first = slots.acquire(&requests, &cancelled).await.unwrap();
+        let second = slots.acquire(&requests, &cancelled).await.unwrap();
+
+        let start = Instant::now();
+        assert!(matches!(
+            slots.acquire(&requests, &cancelled).await,
+            Err(Error::AnalysisSlotsBusy)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        cancelled.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            slots.acquire(&requests, &cancelled).await,
+            Err(Error::AnalysisCancelled)
+        ));
+
+        drop(second);
+        assert!(slots
+            .acquire(&requests, &AtomicBool::new(false))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn raising_the_analysis_limit_frees_a_slot() {
+        let requests = Arc::new(Semaphore::new(ANALYSIS_SLOTS));
+        let slots = AnalysisSlots::default();
+        slots.set_timeout(Duration::from_millis(200));
+        let cancelled = AtomicBool::new(false);
+        let first = slots.acquire(&requests, &cancelled).await.unwrap();
+        let second = slots.acquire(&requests, &cancelled).await.unwrap();
+        assert!(matches!(
+            slots.acquire(&requests, &cancelled).await,
+            Err(Error::AnalysisSlotsBusy)
+        ));
+
+        slots.set_limit(&requests, 3);
+        let third = slots.acquire(&requests, &cancelled).await.unwrap();
+
+        // lowering the limit doesn't stop the running analyses
+        slots.set_limit(&requests, 1);
+        drop((first, second));
+        assert_eq!(requests.available_permits(), 0);
+        drop(third);
+        assert_eq!(requests.available_permits(), 1);
+
+        // a raise first pays off the slots still owed
+        let first = slots.acquire(&requests, &cancelled).await.unwrap();
+        slots.set_limit(&requests, 0);
+        slots.set_limit(&requests, 2);
+        drop(first);
+        assert_eq!(requests.available_permits(), 2);
+
+        assert_eq!(clamp_analysis_limit(0, 8), 1);
+        assert_eq!(clamp_analysis_limit(4, 8), 4);
+        assert_eq!(clamp_analysis_limit(32, 8), 8);
+        assert_eq!(clamp_analysis_limit(2, 0), 1);
+    }
+
+    #[test]
+    fn counts_tablebase_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["KQvK.rtbw", "KQvK.rtbz", "KRvK.rtbw", "README.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        assert_eq!(count_tablebase_files(dir.path()).unwrap(), 3);
+        assert!(matches!(
+            count_tablebase_files(&dir.path().join("missing")),
+            Err(Error::InvalidTablebasePath(_))
+        ));
+    }
+
+    #[test]
+    fn engine_logs_are_bounded_and_filtered() {
+        let mut logs = EngineLogs::new();
+        for i in 0..MAX_ENGINE_LOGS + 10 {
+            logs.push(LogDirection::Engine, format!("info depth {}", i));
+        }
+        logs.push(LogDirection::Gui, "stop\n".to_string());
+        assert_eq!(logs.lines.len(), MAX_ENGINE_LOGS);
+        assert_eq!(logs.lines[0].value, "info depth 11");
+
+        let sent = logs.filtered(&EngineLogFilter {
+            direction: Some(LogDirection::Gui),
+            contains: None,
+        });
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].value, "stop\n");
+
+        let matching = logs.filtered(&EngineLogFilter {
+            direction: None,
+            contains: Some("depth 500".to_string()),
+        });
+        assert_eq!(matching.len(), 11);
+        assert!(logs
+            .lines
+            .iter()
+            .zip(logs.lines.iter().skip(1))
+            .all(|(a, b)| a.elapsed_ms <= b.elapsed_ms));
+    }
+
+    #[test]
+    fn identical_lines_are_deduplicated() {
+        let line = |depth: u32, score: Score, nodes: u32| BestMoves {
+            depth,
+            score,
+            nodes,
+            uci_moves: vec!["e2e4".to_string(), "e7e5".to_string()],
+            ..Default::default()
+        };
+        assert!(same_lines(
+            &[line(20, cp(30), 1000)],
+            &[line(20, cp(30), 5000)]
+        ));
+        assert!(!same_lines(
+            &[line(20, cp(30), 1000)],
+            &[line(21, cp(30), 1000)]
+        ));
+        assert!(!same_lines(
+            &[line(20, cp(30), 1000)],
+            &[line(20, cp(35), 1000)]
+        ));
+        assert!(!same_lines(&[line(20, cp(30), 1000)], &[]));
+    }
+
+    #[test]
+    fn option_values() {
+        let spin = UciOption::from(UciOptionConfig::Spin {
+            name: "Threads".to_string(),
+            default: Some(1),
+            min: Some(1),
+            max: Some(512),
+        });
+        assert!(spin.is_valid_value("8"));
+        assert!(!spin.is_valid_value("0"));
+        assert!(!spin.is_valid_value("many"));
+
+        let check = UciOption::from(UciOptionConfig::Check {
+            name: "Ponder".to_string(),
+            default: Some(false),
+        });
+        assert!(check.is_valid_value("true"));
+        assert!(!check.is_valid_value("yes"));
+    }
+
+    #[test]
+    fn parses_wdl_from_info_lines() {
+        let line = "info depth 20 seldepth 28 multipv 1 score cp 35 wdl 120 820 60 nodes 1000 nps 500000 pv e7e5";
+        let UciMessage::Info(attrs) = parse_one(line) else {
+            panic!("not an info line");
+        };
+        // the score is from the side to move, black here
+        let fen: Fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            .parse()
+            .unwrap();
+        let best_moves = parse_uci_attrs(attrs, &fen, &Vec::new()).unwrap();
+        assert_eq!(best_moves.score.value, ScoreValue::Cp(-35));
+        assert_eq!(best_moves.score.wdl, Some((60, 820, 120)));
+
+        let UciMessage::Info(attrs) = parse_one("info depth 1 score cp 35 pv e2e4") else {
+            panic!("not an info line");
+        };
+        let best_moves = parse_uci_attrs(attrs, &Fen::default(), &Vec::new()).unwrap();
+        assert_eq!(best_moves.score.wdl, None);
+    }
+
+    #[test]
+    fn scores_are_from_whites_perspective() {
+        let black_to_move: Fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            .parse()
+            .unwrap();
+        let score = |line: &str, fen: &Fen| {
+            let UciMessage::Info(attrs) = parse_one(line) else {
+                panic!("not an info line");
+            };
+            let best_moves = parse_uci_attrs(attrs, fen, &Vec::new()).unwrap();
+            (best_moves.score.value, best_moves.raw_score.value)
+        };
+
+        assert_eq!(
+            score("info depth 10 score cp 50 pv e7e5", &black_to_move),
+            (ScoreValue::Cp(-50), ScoreValue::Cp(50))
+        );
+        assert_eq!(
+            score("info depth 10 score mate 3 pv e7e5", &black_to_move),
+            (ScoreValue::Mate(-3), ScoreValue::Mate(3))
+        );
+        assert_eq!(
+            score("info depth 10 score cp 50 pv e2e4", &Fen::default()),
+            (ScoreValue::Cp(50), ScoreValue::Cp(50))
+        );
+    }
+
+    #[test]
+    fn parses_search_stats_from_info_lines() {
+        let UciMessage::Info(attrs) = parse_one(
+            "info depth 20 seldepth 31 multipv 1 score cp 28 nodes 12345678 nps 3000000 hashfull 850 tbhits 42 time 4115 pv e2e4 e7e5",
+        ) else {
+            panic!("not an info line");
+        };
+        let best_moves = parse_uci_attrs(attrs, &Fen::default(), &Vec::new()).unwrap();
+        assert_eq!(best_moves.depth, 20);
+        assert_eq!(best_moves.nodes, 12345678);
+        assert_eq!(best_moves.nps, 3000000);
+        assert_eq!(best_moves.hashfull, Some(850));
+        assert_eq!(best_moves.tbhits, Some(42));
+
+        let UciMessage::Info(attrs) = parse_one("info depth 1 score cp 35 pv e2e4") else {
+            panic!("not an info line");
+        };
+        let best_moves = parse_uci_attrs(attrs, &Fen::default(), &Vec::new()).unwrap();
+        assert_eq!((best_moves.hashfull, best_moves.tbhits), (None, None));
+    }
+
+    #[test]
+    fn ponder_hits_and_misses() {
+        let fen = Fen::default().to_string();
+        let moves =
+            |moves: &[&str]| -> Vec<String> { moves.iter().map(|m| m.to_string()).collect() };
+
+        let mut state = PonderState::Idle;
+        assert_eq!(
+            state.on_position(&fen, &moves(&["e2e4"])),
+            PonderOutcome::None
+        );
+        assert!(!state.on_best_move());
+
+        // the expected reply was played
+        state = PonderState::Pondering {
+            fen: fen.clone(),
+            moves: moves(&["e2e4", "e7e5"]),
+        };
+        assert_eq!(
+            state.on_position(&fen, &moves(&["e2e4", "e7e5"])),
+            PonderOutcome::Hit
+        );
+        assert_eq!(state, PonderState::Idle);
+        // the search goes on and its result is reported
+        assert!(!state.on_best_move());
+
+        // another reply was played
+        state = PonderState::Pondering {
+            fen: fen.clone(),
+            moves: moves(&["e2e4", "e7e5"]),
+        };
+        assert_eq!(
+            state.on_position(&fen, &moves(&["e2e4", "c7c5"])),
+            PonderOutcome::Miss
+        );
+        assert_eq!(state, PonderState::Missed);
+        // the stopped ponder search isn't reported, the next one is
+        assert!(state.on_best_move());
+        assert!(!state.on_best_move());
+
+        assert_eq!(
+            GoMode::PlayersTime(PlayersTime {
+                white: 60000,
+                black: 55000,
+                winc: 1000,
+                binc: 1000,
+            })
+            .ponder_command(),
+            "go ponder wtime 60000 btime 55000 winc 1000 binc 1000\n"
+        );
+    }
+
+    #[test]
+    fn go_commands() {
+        assert_eq!(GoMode::Depth(25).command(), "go depth 25\n");
+        assert_eq!(GoMode::Time(5000).command(), "go movetime 5000\n");
+        assert_eq!(GoMode::Nodes(1000000).command(), "go nodes 1000000\n");
+        assert_eq!(GoMode::Mate(3).command(), "go mate 3\n");
+        assert_eq!(GoMode::Infinite.command(), "go infinite\n");
+        assert_eq!(
+            GoMode::PlayersTime(PlayersTime {
+                white: 60000,
+                black: 55000,
+                winc: 1000,
+                binc: 1000,
+            })
+            .command(),
+            "go wtime 60000 btime 55000 winc 1000 binc 1000\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn detects_engines_exiting_by_themselves() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crashing-engine");
+        fs::write(
+            &path,
+            "#!/bin/sh\nread line\necho uciok\nread line\necho readyok\nexit 3\n",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (mut process, mut reader) = EngineProcess::new(path, None).await.unwrap();
+        while reader.next_line().await.unwrap().is_some() {}
+        // not crashed until its output is known to have ended
+        assert_eq!(process.crash().await, None);
+        process.output_closed = true;
+
+        let crash = process.crash().await.unwrap();
+        assert_eq!(crash.exit_code, Some(3));
+        assert_eq!(crash.logs, vec!["uci\n", "uciok", "isready\n", "readyok"]);
+
+        // engines told to quit didn't crash
+        process.quitting = true;
+        assert_eq!(process.crash().await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reports_crashed_engines() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crashing-engine");
+        fs::write(
+            &path,
+            "#!/bin/sh\nread line\necho uciok\nread line\necho readyok\nexit 3\n",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (mut process, mut reader) = EngineProcess::new(path.clone(), None).await.unwrap();
+        while reader.next_line().await.unwrap().is_some() {}
+        process.output_closed = true;
+        process.options.auto_restart = true;
+
+        let state = AppState::default();
+        let key = ("tab".to_string(), path.to_string_lossy().to_string());
+        let process = Arc::new(Mutex::new(process));
+        state.engine_processes.insert(key.clone(), process.clone());
+
+        let (event, options, _) = engine_crash(&key, &process, false, &state).await.unwrap();
+        assert_eq!(event.engine, key.1);
+        assert_eq!(event.tab, "tab");
+        assert_eq!(event.exit_code, Some(3));
+        assert_eq!(event.logs, vec!["uci\n", "uciok", "isready\n", "readyok"]);
+        assert!(event.restarting);
+        assert!(options.auto_restart);
+        assert!(!state.engine_processes.contains_key(&key));
+
+        // engines are only started again once
+        let (event, _, _) = engine_crash(&key, &process, true, &state).await.unwrap();
+        assert!(!event.restarting);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rejects_files_that_arent_engines() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            read_engine_config(missing, Duration::from_secs(1)).await,
+            Err(Error::EngineNotFound(_))
+        ));
+
+        let archive = dir.path().join("stockfish.zip");
+        fs::write(&archive, b"PK\x03\x04").unwrap();
+        assert!(matches!(
+            read_engine_config(archive, Duration::from_secs(1)).await,
+            Err(Error::EngineNotExecutable(_))
+        ));
+
+        let silent = dir.path().join("silent");
+        fs::write(&silent, "#!/bin/sh\nsleep 30\n").unwrap();
+        fs::set_permissions(&silent, fs::Permissions::from_mode(0o755)).unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            read_engine_config(silent, Duration::from_millis(300)).await,
+            Err(Error::NotUciEngine)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let engine = dir.path().join("engine");
+        fs::write(
+            &engine,
+            "#!/bin/sh\nread line\necho \"id name Dummy 1.0\"\necho \"id author Someone\"\necho \"option name Hash type spin default 16 min 1 max 1024\"\necho uciok\n",
+        )
+        .unwrap();
+        fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = read_engine_config(engine, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(config.name, "Dummy 1.0");
+        assert_eq!(config.author.as_deref(), Some("Someone"));
+        assert_eq!(config.options.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn checks_the_engine_handshake() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = dir.path().join("engine");
+        fs::write(&engine, "#!/bin/sh\nread line\necho uciok\nread line\n").unwrap();
+        fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(engine_handshake(engine, Duration::from_secs(5))
+            .await
+            .is_ok());
+
+        let quitter = dir.path().join("quitter");
+        fs::write(&quitter, "#!/bin/sh\nread line\necho \"id name Quitter\"\n").unwrap();
+        fs::set_permissions(&quitter, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(
+            engine_handshake(quitter, Duration::from_secs(5)).await,
+            Err(Error::NotUciEngine)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn limits_the_engine_strength() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine");
+        fs::write(
+            &path,
+            "#!/bin/sh\nread line\n\
+            echo \"option name UCI_LimitStrength type check default false\"\n\
+            echo \"option name UCI_Elo type spin default 1320 min 1320 max 3190\"\n\
+            echo uciok\nread line\necho readyok\ncat > /dev/null\n",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (mut process, _reader) = EngineProcess::new(path, None).await.unwrap();
+        let sent_options = |process: &EngineProcess| -> Vec<String> {
+            process
+                .logs
+                .filtered(&EngineLogFilter {
+                    direction: Some(LogDirection::Gui),
+                    contains: Some("setoption".to_string()),
+                })
+                .into_iter()
+                .map(|log| log.value)
+                .collect()
+        };
+        let options = |target_elo| EngineOptions {
+            fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+            target_elo,
+            ..Default::default()
+        };
+
+        process.set_options(options(Some(1800))).await.unwrap();
+        assert_eq!(
+            sent_options(&process),
+            vec![
+                "setoption name UCI_LimitStrength value true\n",
+                "setoption name UCI_Elo value 1800\n",
+            ]
+        );
+
+        // below what the engine can play at
+        process.set_options(options(Some(800))).await.unwrap();
+        assert_eq!(
+            sent_options(&process)[2..],
+            [
+                "setoption name UCI_LimitStrength value true\n",
+                "setoption name UCI_Elo value 1320\n",
+            ]
+        );
+
+        process.set_options(options(None)).await.unwrap();
+        assert_eq!(
+            sent_options(&process)[4..],
+            ["setoption name UCI_LimitStrength value false\n"]
+        );
+        let _ = process.kill().await;
+    }
+
+    #[test]
+    fn parse_option_lines() {
+        let UciMessage::Option(config) =
+            parse_one("option name Style type combo default Normal var Solid var Normal var Risky")
+        else {
+            panic!("not an option line");
+        };
+        assert_eq!(
+            UciOption::from(config),
+            UciOption {
+                name: "Style".to_string(),
+                option_type: UciOptionType::Combo,
+                default: Some("Normal".to_string()),
+                min: None,
+                max: None,
+                vars: vec![
+                    "Solid".to_string(),
+                    "Normal".to_string(),
+                    "Risky".to_string()
+                ],
+            }
+        );
+
+        let UciMessage::Option(config) =
+            parse_one("option name Hash type spin default 16 min 1 max 33554432")
+        else {
+            panic!("not an option line");
+        };
+        let hash = UciOption::from(config);
+        assert_eq!(hash.option_type, UciOptionType::Spin);
+        assert_eq!(hash.default.as_deref(), Some("16"));
+        assert_eq!(hash.max, Some(33554432));
+    }
+
+    #[test]
+    fn hash_suggestions_are_clamped() {
+        assert_eq!(suggested_hash(8 * 1024, None, None), 1024);
+        // small machines still get the usual default
+        assert_eq!(suggested_hash(64, None, None), MIN_DEFAULT_HASH_MB);
+        assert_eq!(suggested_hash(64, Some(32), Some(1024)), 32);
+        // and large ones don't go over what the engine takes
+        assert_eq!(suggested_hash(256 * 1024, Some(1), Some(2048)), 2048);
+        assert_eq!(
+            suggested_hash(256 * 1024, Some(1), Some(33554432)),
+            32 * 1024
+        );
+    }
+
+    #[test]
+    fn mate_in_two() {
+        let position = pos("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        assert_eq!(find_forced_mate(&position, 1), None);
+        let line = find_forced_mate(&position, 2).unwrap();
+        assert_eq!(line.len(), 3);
+        assert_eq!(verify_mate_line(&position, &line), Some(true));
+    }
+
+    #[test]
+    fn no_forced_mate() {
+        let position = pos("8/8/8/3k4/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(find_forced_mate(&position, 2), None);
+    }
+
+    #[test]
+    fn mate_line_must_end_in_mate() {
+        let position = pos("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let rh8 = "h1h8"
+            .parse::<UciMove>()
+            .unwrap()
+            .to_move(&position)
+            .unwrap();
+        assert_eq!(verify_mate_line(&position, &[rh8]), None);
+
+        // too long for the search, so only its moves are checked
+        let mut after = position.clone();
+        let long: Vec<Move> = ["h1h2", "a8b8", "h2h1", "b8a8", "c6b6", "a8b8", "h1h8"]
+            .iter()
+            .map(|uci| {
+                let m = uci.parse::<UciMove>().unwrap().to_move(&after).unwrap();
+                after.play_unchecked(&m);
+                m
+            })
+            .collect();
+        assert_eq!(verify_mate_line(&position, &long), Some(false));
+        assert_eq!(verify_mate_line(&position, &long[..5]), None);
+    }
 }
 
 #[derive(Type, Default, Serialize, Debug)]
 pub struct EngineConfig {
     pub name: String,
+    pub author: Option<String>,
     pub options: Vec<UciOptionConfig>,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
+/// Checks that an engine path is an executable file, before trying to run it
+fn check_engine_file(path: &Path) -> Result<(), Error> {
+    if !path.is_file() {
+        return Err(Error::EngineNotFound(path.display().to_string()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path)?.permissions().mode() & 0o111 == 0 {
+            return Err(Error::EngineNotExecutable(path.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `uci` handshake, collecting what the engine says about itself
+async fn read_engine_config(path: PathBuf, timeout: Duration) -> Result<EngineConfig, Error> {
+    check_engine_file(&path)?;
     let mut child = start_engine(path)?;
     let (mut stdin, mut stdout) = get_handles(&mut child)?;
 
     send_command(&mut stdin, "uci\n").await;
 
-    let mut config = EngineConfig::default();
-
-    loop {
-        if let Some(line) = stdout.next_line().await? {
-            if let UciMessage::Id {
-                name: Some(name),
-                author: _,
-            } = parse_one(&line)
-            {
-                config.name = name;
-            }
-            if let UciMessage::Option(opt) = parse_one(&line) {
-                config.options.push(opt);
-            }
-            if let UciMessage::UciOk = parse_one(&line) {
-                break;
+    let handshake = async {
+        let mut config = EngineConfig::default();
+        while let Some(line) = stdout.next_line().await? {
+            match parse_one(&line) {
+                UciMessage::Id { name, author } => {
+                    if let Some(name) = name {
+                        config.name = name;
+                    }
+                    if author.is_some() {
+                        config.author = author;
+                    }
+                }
+                UciMessage::Option(opt) => config.options.push(opt),
+                UciMessage::UciOk => return Ok(Some(config)),
+                _ => {}
             }
         }
+        Ok::<_, Error>(None)
+    };
+    let config = tokio::time::timeout(timeout, handshake).await;
+    let _ = stdin.write_all(b"quit\n").await;
+    // the engine is killed when dropped, in case it doesn't quit
+    match config {
+        Ok(Ok(Some(config))) => Ok(config),
+        Ok(Err(e)) => Err(e),
+        Ok(Ok(None)) | Err(_) => Err(Error::NotUciEngine),
     }
-    println!("{:?}", config);
-    Ok(config)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
+    read_engine_config(path, UCI_HANDSHAKE_TIMEOUT).await
+}
+
+/// Checks that a file is a UCI engine, returning its name, author and
+/// options. Fails with a clear error for files that aren't engines instead
+/// of waiting for them forever.
+#[tauri::command]
+#[specta::specta]
+pub async fn test_engine(path: PathBuf) -> Result<EngineConfig, Error> {
+    get_engine_config(path).await
 }
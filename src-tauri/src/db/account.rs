@@ -0,0 +1,340 @@
+use std::{path::PathBuf, time::Duration};
+
+use chrono::Utc;
+use diesel::{connection::SimpleConnection, Connection, SqliteConnection};
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use specta::Type;
+use tauri_specta::Event as _;
+
+use crate::{error::Error, oauth::refresh_token_if_needed, AppState};
+
+use super::{
+    get_db_or_create, invalidate_caches, is_blank, parse_games, update_info_counts,
+    ConnectionOptions, DatabaseProgress, COMMENTS_INDEX_SQL, IMPORT_BATCH_SIZE,
+};
+
+/// How many times a rate limited request is retried before giving up
+const MAX_RETRIES: u32 = 3;
+
+/// How long to wait after being rate limited, when the server doesn't say
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountSource {
+    Lichess,
+    Chesscom,
+}
+
+/// Sends a request, waiting and trying again when rate limited
+async fn send_with_retries(request: RequestBuilder) -> Result<Response, Error> {
+    let mut attempts = 0;
+    loop {
+        let response = request
+            .try_clone()
+            .expect("request has no streaming body")
+            .send()
+            .await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS || attempts >= MAX_RETRIES {
+            return Ok(response.error_for_status()?);
+        }
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+        tokio::time::sleep(retry_after).await;
+        attempts += 1;
+    }
+}
+
+#[derive(Deserialize)]
+struct LichessGame {
+    pgn: String,
+}
+
+/// Splits an NDJSON stream of Lichess games into their PGN as chunks arrive
+#[derive(Default)]
+struct NdjsonGames {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonGames {
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        self.buffer.extend_from_slice(chunk);
+        let mut games = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            games.extend(Self::parse_line(&line)?);
+        }
+        Ok(games)
+    }
+
+    /// Parses what is left after the last newline
+    fn finish(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Self::parse_line(&std::mem::take(&mut self.buffer))
+    }
+
+    fn parse_line(line: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if is_blank(line) {
+            return Ok(None);
+        }
+        let game: LichessGame = serde_json::from_slice(line)?;
+        Ok(Some(game.pgn.into_bytes()))
+    }
+}
+
+/// Collects games and inserts them into the database a batch at a time
+struct AccountImporter<'a> {
+    db: &'a mut SqliteConnection,
+    since: Option<i64>,
+    batch: Vec<Vec<u8>>,
+    read: usize,
+    imported: usize,
+}
+
+impl<'a> AccountImporter<'a> {
+    fn new(db: &'a mut SqliteConnection, since: Option<i64>) -> Self {
+        Self {
+            db,
+            since,
+            batch: Vec::with_capacity(IMPORT_BATCH_SIZE),
+            read: 0,
+            imported: 0,
+        }
+    }
+
+    fn add(&mut self, game: Vec<u8>) -> Result<(), Error> {
+        self.batch.push(game);
+        self.read += 1;
+        if self.batch.len() >= IMPORT_BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let games = parse_games(&self.batch, self.since);
+        self.db.transaction::<_, Error, _>(|db| {
+            for game in &games {
+                game.insert_to_db(db)?;
+            }
+            Ok(())
+        })?;
+        self.imported += games.len();
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+async fn import_lichess(
+    importer: &mut AccountImporter<'_>,
+    username: &str,
+    token: Option<String>,
+    max: Option<u32>,
+    on_progress: impl Fn(usize),
+) -> Result<(), Error> {
+    let mut query = vec![
+        ("pgnInJson", "true".to_string()),
+        ("clocks", "true".to_string()),
+    ];
+    if let Some(since) = importer.since {
+        query.push(("since", (since * 1000).to_string()));
+    }
+    if let Some(max) = max {
+        query.push(("max", max.to_string()));
+    }
+
+    let mut request = Client::new()
+        .get(format!("https://lichess.org/api/games/user/{username}"))
+        .header(header::ACCEPT, "application/x-ndjson")
+        .query(&query);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    let mut response = send_with_retries(request).await?;
+    let mut games = NdjsonGames::default();
+    while let Some(chunk) = response.chunk().await? {
+        for game in games.push(&chunk)? {
+            importer.add(game)?;
+            on_progress(importer.read);
+        }
+    }
+    if let Some(game) = games.finish()? {
+        importer.add(game)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChesscomArchives {
+    archives: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChesscomArchive {
+    games: Vec<ChesscomGame>,
+}
+
+#[derive(Deserialize)]
+struct ChesscomGame {
+    #[serde(default)]
+    pgn: String,
+    end_time: i64,
+}
+
+/// Chess.com only has monthly archives, so they are read from the most
+/// recent one until `since` or `max` is reached
+async fn import_chesscom(
+    importer: &mut AccountImporter<'_>,
+    username: &str,
+    max: Option<u32>,
+    on_progress: impl Fn(usize),
+) -> Result<(), Error> {
+    // Chess.com rejects requests without a user agent
+    let client = Client::builder().user_agent("En Croissant").build()?;
+    let archives: ChesscomArchives = send_with_retries(client.get(format!(
+        "https://api.chess.com/pub/player/{}/games/archives",
+        username.to_lowercase()
+    )))
+    .await?
+    .json()
+    .await?;
+
+    let max = max.map_or(usize::MAX, |max| max as usize);
+    for url in archives.archives.iter().rev() {
+        // requests are made one at a time, as Chess.com asks
+        let archive: ChesscomArchive = send_with_retries(client.get(url)).await?.json().await?;
+        let mut reached_since = false;
+        for game in archive.games.into_iter().rev() {
+            if importer.since.is_some_and(|since| game.end_time <= since) {
+                reached_since = true;
+                continue;
+            }
+            if importer.read >= max {
+                return Ok(());
+            }
+            if !game.pgn.is_empty() {
+                importer.add(game.pgn.into_bytes())?;
+            }
+        }
+        on_progress(importer.read);
+        if reached_since {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Imports the games of a Lichess or Chess.com account into an existing
+/// database, skipping the ones played before `since` (a unix timestamp in
+/// seconds). Returns the number of games imported.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_account_games(
+    source: AccountSource,
+    username: String,
+    since: Option<i32>,
+    max: Option<u32>,
+    db_path: PathBuf,
+    id: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32, Error> {
+    let db = &mut get_db_or_create(
+        &state,
+        db_path.to_str().unwrap(),
+        ConnectionOptions::default(),
+    )?;
+
+    db.batch_execute(COMMENTS_INDEX_SQL)?;
+
+    let on_progress = |read: usize| {
+        // the total is only known when there is a maximum
+        let progress = max.map_or(0.0, |max| read as f64 / max.max(1) as f64 * 100.0);
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress: progress.min(100.0),
+        }
+        .emit(&app);
+    };
+
+    let mut importer = AccountImporter::new(db, since.map(|t| t as i64));
+    match source {
+        AccountSource::Lichess => {
+            // logged in users get a higher rate limit, but anyone's games
+            // can be downloaded without a token
+            let token = refresh_token_if_needed(&state.auth, &app, || Utc::now().timestamp())
+                .await
+                .ok();
+            import_lichess(&mut importer, &username, token, max, on_progress).await?
+        }
+        AccountSource::Chesscom => {
+            import_chesscom(&mut importer, &username, max, on_progress).await?
+        }
+    }
+    importer.flush()?;
+    let imported = importer.imported;
+
+    update_info_counts(db)?;
+    invalidate_caches(&state, &db_path);
+
+    DatabaseProgress {
+        id,
+        progress: 100.0,
+    }
+    .emit(&app)?;
+
+    Ok(imported as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{schema::games, tests::test_db};
+    use diesel::{QueryDsl, RunQueryDsl};
+
+    const LICHESS_GAMES: &str = include_str!("fixtures/lichess_games.ndjson");
+
+    #[test]
+    fn imports_lichess_ndjson() {
+        let mut db = test_db("");
+        let mut importer = AccountImporter::new(&mut db, None);
+        let mut stream = NdjsonGames::default();
+        // chunks don't line up with the lines of the stream
+        for chunk in LICHESS_GAMES.as_bytes().chunks(37) {
+            for game in stream.push(chunk).unwrap() {
+                importer.add(game).unwrap();
+            }
+        }
+        assert!(stream.finish().unwrap().is_none());
+        importer.flush().unwrap();
+        assert_eq!(importer.read, 3);
+        assert_eq!(importer.imported, 3);
+
+        let count: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(count, 3);
+
+        // games played before `since` are left out
+        let mut db = test_db("");
+        let since = chrono::NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        let mut importer = AccountImporter::new(&mut db, Some(since));
+        for game in NdjsonGames::default()
+            .push(LICHESS_GAMES.as_bytes())
+            .unwrap()
+        {
+            importer.add(game).unwrap();
+        }
+        importer.flush().unwrap();
+        assert_eq!(importer.imported, 2);
+    }
+}
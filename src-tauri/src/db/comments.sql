@@ -0,0 +1,5 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS GameComments USING fts5(Comments);
+
+CREATE TRIGGER IF NOT EXISTS games_comments_delete AFTER DELETE ON Games BEGIN
+    DELETE FROM GameComments WHERE rowid = old.ID;
+END;
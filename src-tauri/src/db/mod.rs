@@ -1,3 +1,4 @@
+mod account;
 mod encoding;
 mod models;
 mod ops;
@@ -12,59 +13,91 @@ use crate::{
         schema::*,
     },
     error::Error,
-    opening::get_opening_from_setup,
+    opening::{
+        classify_game_opening, classify_position, get_opening_from_setup, max_opening_plies,
+    },
+    pgn::{game_clock_issues, ClockIssue, GameClockReport, PgnValidationReport},
+    tree::extract_annotations,
     AppState,
 };
 use chrono::{NaiveDate, NaiveTime};
 use dashmap::DashMap;
 use diesel::{
     connection::{DefaultLoadingMode, SimpleConnection},
+    dsl::sql,
+    expression::is_aggregate,
     insert_into,
     prelude::*,
+    query_builder::{BoxedSqlQuery, SqlQuery},
     r2d2::{ConnectionManager, Pool},
     sql_query,
-    sql_types::Text,
+    sql_types::{Bool, Integer, Nullable, Text},
+    sqlite::Sqlite,
 };
-use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, Board, ByColor, Chess, EnPassantMode, FromSetup, Piece, Position, PositionError,
+    fen::Fen,
+    zobrist::{Zobrist64, ZobristHash},
+    Board, ByColor, CastlingMode, Chess, EnPassantMode, FromSetup, Piece, Position, PositionError,
 };
 use specta::Type;
 use std::{
+    collections::{HashMap, HashSet},
     fs::{remove_file, File, OpenOptions},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicI32, AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
 use std::{
-    io::{BufWriter, Write},
+    io::{self, BufRead, BufReader, BufWriter, Write},
     str::FromStr,
 };
 use tauri::{path::BaseDirectory, Manager};
-use tauri::{Emitter, State};
+use tauri::State;
 
 use log::info;
 use tauri_specta::Event as _;
 
 use self::encoding::encode_move;
 
+pub use self::account::import_account_games;
 pub use self::models::NormalizedGame;
 pub use self::models::Puzzle;
 pub use self::schema::puzzles;
 pub use self::search::{
-    is_position_in_db, search_position, PositionQuery, PositionQueryJs, PositionStats,
+    get_opening_explorer, is_position_in_db, position_hash, search_position, GameDataCache,
+    PositionQuery, PositionQueryJs, PositionStats,
 };
 
 const DATABASE_VERSION: &str = "1.0.0";
 
 const INDEXES_SQL: &str = include_str!("indexes.sql");
 
+/// Names of the indexes made by indexes.sql, which `get_index_status`
+/// reports on
+const GAME_INDEXES: &[&str] = &[
+    "games_date_idx",
+    "games_white_idx",
+    "games_black_idx",
+    "games_event_idx",
+    "games_result_idx",
+    "games_white_elo_idx",
+    "games_black_elo_idx",
+    "games_plycount_idx",
+    "games_eco_idx",
+    "games_position_idx",
+];
+
 const DELETE_INDEXES_SQL: &str = include_str!("delete_indexes.sql");
 
 const CREATE_TABLES_SQL: &str = include_str!("create.sql");
 
+/// Full-text index of the comments of each game, missing from databases
+/// created before it was added
+const COMMENTS_INDEX_SQL: &str = include_str!("comments.sql");
+
 const WHITE_PAWN: Piece = Piece {
     color: shakmaty::Color::White,
     role: shakmaty::Role::Pawn,
@@ -156,6 +189,7 @@ fn get_db_or_create(
                 .max_size(16)
                 .connection_customizer(Box::new(options))
                 .build(ConnectionManager::<SqliteConnection>::new(db_path))?;
+            add_missing_columns(&mut pool.get()?)?;
             state
                 .connection_pool
                 .insert(db_path.to_string(), pool.clone());
@@ -166,6 +200,18 @@ fn get_db_or_create(
     Ok(pool.get()?)
 }
 
+/// Adds the columns introduced after the first version of the schema to the
+/// Games table of older databases. Databases without games tables yet are
+/// left alone, `CREATE_TABLES_SQL` already has every column.
+fn add_missing_columns(db: &mut SqliteConnection) -> Result<(), Error> {
+    let columns: Vec<VirtualTable> =
+        sql_query("SELECT name FROM pragma_table_info('Games')").load(db)?;
+    if !columns.is_empty() && !columns.iter().any(|column| column.name == "Termination") {
+        db.batch_execute("ALTER TABLE Games ADD COLUMN Termination TEXT;")?;
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct MaterialColor {
     white: u8,
@@ -200,10 +246,12 @@ pub struct TempGame {
     pub black_name: Option<String>,
     pub black_elo: Option<i32>,
     pub result: Option<String>,
+    pub termination: Option<Termination>,
     pub time_control: Option<String>,
     pub eco: Option<String>,
     pub fen: Option<String>,
     pub moves: Vec<u8>,
+    pub comments: Vec<String>,
     pub position: Chess,
     pub material_count: MaterialColor,
 }
@@ -260,9 +308,16 @@ impl TempGame {
             result: self.result.as_deref(),
             moves: self.moves.as_slice(),
             pawn_home: pawn_home as i32,
+            termination: self.termination.map(|termination| termination.as_str()),
         };
 
-        create_game(db, new_game)?;
+        let game = create_game(db, new_game)?;
+        if !self.comments.is_empty() {
+            sql_query("INSERT INTO GameComments (rowid, Comments) VALUES (?, ?)")
+                .bind::<Integer, _>(game.id)
+                .bind::<Text, _>(self.comments.join("\n"))
+                .execute(db)?;
+        }
         Ok(())
     }
 }
@@ -271,6 +326,8 @@ struct Importer {
     game: TempGame,
     timestamp: Option<i64>,
     skip: bool,
+    /// ECO of the deepest known opening reached so far
+    opening_eco: Option<String>,
 }
 
 impl Importer {
@@ -279,6 +336,7 @@ impl Importer {
             game: TempGame::default(),
             timestamp,
             skip: false,
+            opening_eco: None,
         }
     }
 }
@@ -288,6 +346,7 @@ impl Visitor for Importer {
 
     fn begin_game(&mut self) {
         self.skip = false;
+        self.opening_eco = None;
     }
 
     fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
@@ -314,7 +373,10 @@ impl Visitor for Importer {
         } else if key == b"Event" {
             self.game.event_name = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
         } else if key == b"Result" {
-            self.game.result = Some(String::from_utf8_lossy(value.as_bytes()).to_string());
+            let result = Outcome::from_pgn(&value.decode_utf8_lossy());
+            self.game.result = Some(result.as_str().to_string());
+        } else if key == b"Termination" {
+            self.game.termination = Termination::from_pgn(&value.decode_utf8_lossy());
         } else if key == b"FEN" {
             if value.as_bytes() == b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" {
                 self.game.fen = None;
@@ -376,11 +438,27 @@ impl Visitor for Importer {
                 .moves
                 .push(encode_move(&m, &self.game.position).unwrap());
             self.game.position.play_unchecked(&m);
+
+            if self.game.fen.is_none() && self.game.moves.len() <= max_opening_plies() {
+                let setup = self.game.position.clone().into_setup(EnPassantMode::Legal);
+                if let Some(opening) = classify_position(setup) {
+                    self.opening_eco = Some(opening.eco);
+                }
+            }
         } else {
             self.skip = true;
         }
     }
 
+    fn comment(&mut self, comment: RawComment<'_>) {
+        // clocks and evals are noise for a text search
+        let (text, _, _) = extract_annotations(&String::from_utf8_lossy(comment.as_bytes()));
+        let text = text.trim();
+        if !text.is_empty() {
+            self.game.comments.push(text.to_string());
+        }
+    }
+
     fn begin_variation(&mut self) -> Skip {
         Skip(true) // stay in the mainline
     }
@@ -390,11 +468,215 @@ impl Visitor for Importer {
             self.game = TempGame::default();
             None
         } else {
+            if self.game.eco.is_none() {
+                self.game.eco = self.opening_eco.take();
+            }
             Some(std::mem::take(&mut self.game))
         }
     }
 }
 
+/// Number of games parsed in parallel before being written to the database
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Splits a PGN stream into the raw bytes of each game
+struct RawGameReader<R> {
+    reader: R,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> RawGameReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+        }
+    }
+
+    /// Returns the next game, or `None` at the end of the stream
+    fn next_game(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut game = Vec::new();
+        let mut in_moves = false;
+        loop {
+            if self.line.is_empty() && self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+            let is_header = self.line.starts_with(b"[");
+            if is_header && in_moves && !is_blank(&game) {
+                // keep the line for the next game
+                break;
+            }
+            in_moves |= !is_header;
+            game.append(&mut self.line);
+        }
+        Ok((!is_blank(&game)).then_some(game))
+    }
+}
+
+fn is_blank(bytes: &[u8]) -> bool {
+    bytes.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Parses the raw games in parallel, keeping their order
+fn parse_games(raw_games: &[Vec<u8>], timestamp: Option<i64>) -> Vec<TempGame> {
+    raw_games
+        .par_iter()
+        .map(|raw| {
+            let mut importer = Importer::new(timestamp);
+            BufferedReader::new(raw.as_slice())
+                .into_iter(&mut importer)
+                .flatten()
+                .flatten()
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// How many parsed batches can wait for the writer
+const PARSED_BATCH_QUEUE: usize = 2;
+
+/// Progress is reported after this many games, or this much time, whichever
+/// comes first
+const PROGRESS_EVERY_GAMES: usize = 100;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far an import has gone
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ImportStats {
+    /// Games read, whether they were imported or not
+    processed: usize,
+    /// Games that couldn't be parsed or were older than the timestamp
+    skipped: usize,
+    current_event: Option<String>,
+}
+
+/// Keeps progress events from flooding the frontend
+struct ProgressThrottle {
+    reported: usize,
+    at: Instant,
+}
+
+impl ProgressThrottle {
+    fn new() -> Self {
+        Self {
+            reported: 0,
+            at: Instant::now(),
+        }
+    }
+
+    fn ready(&mut self, processed: usize) -> bool {
+        let ready = processed > self.reported
+            && (processed - self.reported >= PROGRESS_EVERY_GAMES
+                || self.at.elapsed() >= PROGRESS_INTERVAL);
+        if ready {
+            self.reported = processed;
+            self.at = Instant::now();
+        }
+        ready
+    }
+}
+
+/// Imports the games of a PGN stream in a single transaction. Batches of
+/// games are read and parsed in parallel on another thread while the previous
+/// ones are inserted in order on this one. Returns how many games were read
+/// and the ones with inconsistent clocks, which are imported all the same.
+fn import_pgn_games(
+    db: &mut SqliteConnection,
+    reader: impl BufRead + Send,
+    timestamp: Option<i64>,
+    mut on_progress: impl FnMut(&ImportStats) -> Result<(), Error>,
+) -> Result<PgnValidationReport, Error> {
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(PARSED_BATCH_QUEUE);
+        scope.spawn(move || {
+            let mut reader = RawGameReader::new(reader);
+            loop {
+                let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+                while batch.len() < IMPORT_BATCH_SIZE {
+                    match reader.next_game() {
+                        Ok(Some(game)) => batch.push(game),
+                        Ok(None) => break,
+                        Err(e) => {
+                            let _ = sender.send(Err(e));
+                            return;
+                        }
+                    }
+                }
+                if batch.is_empty() {
+                    return;
+                }
+                let parsed = parse_games(&batch, timestamp);
+                let clock_issues: Vec<Vec<ClockIssue>> =
+                    batch.par_iter().map(|raw| game_clock_issues(raw)).collect();
+                // the writer stops listening when it fails
+                if sender
+                    .send(Ok((batch.len(), parsed, clock_issues)))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        let mut stats = ImportStats::default();
+        let mut throttle = ProgressThrottle::new();
+        let mut report = PgnValidationReport::default();
+        db.transaction::<_, Error, _>(|db| {
+            for parsed in &receiver {
+                let (read, games, clock_issues) = parsed?;
+                let before = stats.processed;
+                for (i, issues) in clock_issues.into_iter().enumerate() {
+                    if !issues.is_empty() {
+                        report.flagged.push(GameClockReport {
+                            index: (before + i) as u32,
+                            issues,
+                        });
+                    }
+                }
+                for (i, game) in games.iter().enumerate() {
+                    game.insert_to_db(db)?;
+                    // which of the batch were skipped isn't known until its end
+                    if throttle.ready(before + i + 1) {
+                        stats.processed = before + i + 1;
+                        stats.current_event.clone_from(&game.event_name);
+                        on_progress(&stats)?;
+                    }
+                }
+                stats.processed = before + read;
+                stats.skipped += read.saturating_sub(games.len());
+                if let Some(game) = games.last() {
+                    stats.current_event.clone_from(&game.event_name);
+                }
+                if throttle.ready(stats.processed) {
+                    on_progress(&stats)?;
+                }
+            }
+            // the last games are always reported
+            if stats.processed > throttle.reported {
+                on_progress(&stats)?;
+            }
+            Ok(())
+        })?;
+        report.games = stats.processed as u32;
+        Ok(report)
+    })
+}
+
+#[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
+pub struct ImportProgress {
+    pub games_processed: u32,
+    /// Only known for uncompressed files
+    pub games_total: Option<u32>,
+    pub games_skipped: u32,
+    pub current_event: Option<String>,
+    pub elapsed_ms: u32,
+}
+
+/// Imports a PGN file into a database, creating it if needed. The games with
+/// inconsistent clocks are imported too, and returned to warn about them.
 #[tauri::command]
 #[specta::specta]
 pub async fn convert_pgn(
@@ -405,7 +687,7 @@ pub async fn convert_pgn(
     title: String,
     description: Option<String>,
     state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
+) -> Result<PgnValidationReport, Error> {
     let description = description.unwrap_or_default();
     let extension = file.extension();
 
@@ -433,13 +715,27 @@ pub async fn convert_pgn(
             .as_str(),
         )?;
     }
+    db.batch_execute(COMMENTS_INDEX_SQL)?;
 
-    let file = File::open(&file)?;
+    let compressed = extension == Some("bz2".as_ref()) || extension == Some("zst".as_ref());
+    // counting the games means reading the file twice, but the index is
+    // usually there already from when the file was listed
+    let games_total = if compressed {
+        None
+    } else {
+        crate::pgn::game_count(&file, &state.pgn_offsets)
+            .ok()
+            .map(|count| count as u32)
+    };
+
+    let mut file = File::open(&file)?;
 
     let uncompressed: Box<dyn std::io::Read + Send> = if extension == Some("bz2".as_ref()) {
         Box::new(bzip2::read::MultiBzDecoder::new(file))
     } else if extension == Some("zst".as_ref()) {
         Box::new(zstd::Decoder::new(file)?)
+    } else if crate::pgn::is_gzip(&mut file)? {
+        Box::new(flate2::read::MultiGzDecoder::new(file))
     } else {
         Box::new(file)
     };
@@ -447,20 +743,16 @@ pub async fn convert_pgn(
     // start counting time
     let start = Instant::now();
 
-    let mut importer = Importer::new(timestamp.map(|t| t as i64));
-    db.transaction::<_, diesel::result::Error, _>(|db| {
-        for (i, game) in BufferedReader::new(uncompressed)
-            .into_iter(&mut importer)
-            .flatten()
-            .flatten()
-            .enumerate()
-        {
-            if i % 1000 == 0 {
-                let elapsed = start.elapsed().as_millis() as u32;
-                app.emit("convert_progress", (i, elapsed)).unwrap();
-            }
-            game.insert_to_db(db)?;
+    let timestamp = timestamp.map(|t| t as i64);
+    let report = import_pgn_games(db, BufReader::new(uncompressed), timestamp, |stats| {
+        ImportProgress {
+            games_processed: stats.processed as u32,
+            games_total,
+            games_skipped: stats.skipped as u32,
+            current_event: stats.current_event.clone(),
+            elapsed_ms: start.elapsed().as_millis() as u32,
         }
+        .emit(&app)?;
         Ok(())
     })?;
 
@@ -469,7 +761,14 @@ pub async fn convert_pgn(
         db.batch_execute(INDEXES_SQL)?;
     }
 
-    // get game, player, event and site counts and to the info table
+    update_info_counts(db)?;
+    invalidate_caches(&state, &db_path);
+
+    Ok(report)
+}
+
+/// Stores the game, player, event and site counts in the info table
+fn update_info_counts(db: &mut SqliteConnection) -> Result<(), Error> {
     let game_count: i64 = games::table.count().get_result(db)?;
     let player_count: i64 = players::table.count().get_result(db)?;
     let event_count: i64 = events::table.count().get_result(db)?;
@@ -509,7 +808,7 @@ pub struct DatabaseInfo {
 #[derive(QueryableByName, Debug, Serialize)]
 struct IndexInfo {
     #[diesel(sql_type = Text, column_name = "name")]
-    _name: String,
+    name: String,
 }
 
 fn check_index_exists(conn: &mut SqliteConnection) -> Result<bool, Error> {
@@ -518,6 +817,25 @@ fn check_index_exists(conn: &mut SqliteConnection) -> Result<bool, Error> {
     Ok(!indexes.is_empty())
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct IndexStatus {
+    pub name: String,
+    pub exists: bool,
+}
+
+/// Tells which of the indexes made by `create_indexes` are in the database
+fn index_status(conn: &mut SqliteConnection) -> Result<Vec<IndexStatus>, Error> {
+    let existing: Vec<IndexInfo> =
+        sql_query("SELECT name FROM pragma_index_list('Games');").load(conn)?;
+    Ok(GAME_INDEXES
+        .iter()
+        .map(|&name| IndexStatus {
+            name: name.to_string(),
+            exists: existing.iter().any(|index| index.name == name),
+        })
+        .collect())
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn get_db_info(
@@ -571,16 +889,65 @@ pub async fn get_db_info(
     })
 }
 
+/// Fills in the ECO code of games imported without one, so that the ECO
+/// index covers them too. Returns the number of games classified.
+fn classify_missing_openings(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let games: Vec<(i32, Vec<u8>)> = games::table
+        .filter(games::eco.is_null().and(games::fen.is_null()))
+        .select((games::id, games::moves))
+        .load(db)?;
+
+    let classified: Vec<(i32, String)> = games
+        .into_par_iter()
+        .filter_map(|(id, moves)| {
+            let mut chess = Chess::default();
+            let mut eco = None;
+            for byte in moves.into_iter().take(max_opening_plies()) {
+                let m = decode_move(byte, &chess)?;
+                chess.play_unchecked(&m);
+                if let Some(opening) =
+                    classify_position(chess.clone().into_setup(EnPassantMode::Legal))
+                {
+                    eco = Some(opening.eco);
+                }
+            }
+            Some((id, eco?))
+        })
+        .collect();
+
+    db.transaction(|db| {
+        for (id, eco) in &classified {
+            diesel::update(games::table.filter(games::id.eq(id)))
+                .set(games::eco.eq(eco))
+                .execute(db)?;
+        }
+        Ok::<_, Error>(())
+    })?;
+    Ok(classified.len())
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn create_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
+    classify_missing_openings(db)?;
     db.batch_execute(INDEXES_SQL)?;
 
     Ok(())
 }
 
+#[tauri::command]
+#[specta::specta]
+pub async fn get_index_status(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<IndexStatus>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    index_status(db)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn delete_indexes(file: PathBuf, state: tauri::State<'_, AppState>) -> Result<(), Error> {
@@ -666,6 +1033,24 @@ pub struct QueryOptions<SortT> {
     pub page_size: Option<i32>,
     pub sort: SortT,
     pub direction: SortDirection,
+    /// Pages by cursor instead of `page` when set, only supported by
+    /// `get_games`
+    #[specta(optional)]
+    pub pagination: Option<Pagination>,
+    /// `next_cursor` of the previous page, to get the one after it
+    #[specta(optional)]
+    pub after: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, Deserialize, PartialEq, Eq, Hash, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum Pagination {
+    /// Skips `page - 1` pages, which gets slower the further the page is
+    #[default]
+    Offset,
+    /// Continues after the last game of the previous page, which is as fast
+    /// for every page
+    Cursor,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
@@ -704,7 +1089,17 @@ pub struct GameQueryJs {
     #[specta(optional)]
     pub sides: Option<Sides>,
     #[specta(optional)]
-    pub outcome: Option<String>,
+    pub outcome: Option<Outcome>,
+    #[specta(optional)]
+    pub termination: Option<Termination>,
+    /// Inclusive range of ECO codes, like `("B90", "B99")`
+    #[specta(optional)]
+    pub eco_range: Option<(String, String)>,
+    /// Range both players' ratings have to be in
+    #[specta(optional)]
+    pub rating: Option<(i32, i32)>,
+    #[specta(optional)]
+    pub time_control: Option<String>,
     #[specta(optional)]
     pub position: Option<PositionQueryJs>,
 }
@@ -723,165 +1118,317 @@ impl GameQueryJs {
 pub struct QueryResponse<T> {
     pub data: T,
     pub count: Option<i32>,
+    /// Cursor of the next page, when paging by cursor and there are more
+    pub next_cursor: Option<String>,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn get_games(
-    file: PathBuf,
-    query: GameQueryJs,
-    state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+/// Where a page of games ends: the sort key and id of its last game
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GameCursor {
+    sort: GameSort,
+    direction: SortDirection,
+    keys: Vec<Option<CursorKey>>,
+    id: i32,
+}
 
-    let mut count: Option<i64> = None;
-    let query_options = query.options.unwrap_or_default();
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CursorKey {
+    Int(i32),
+    Text(String),
+}
 
-    let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    let mut sql_query = games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .into_boxed();
-    let mut count_query = games::table.into_boxed();
+/// Part of the condition of a cursor. It is made of SQL literals, which
+/// work on any query, with the values of the cursor bound to them.
+type CursorCondition<QS> =
+    Box<dyn BoxableExpression<QS, Sqlite, (), is_aggregate::Never, SqlType = Bool>>;
 
-    // if let Some(speed) = query.speed {
-    //     sql_query = sql_query.filter(games::speed.eq(speed as i32));
-    //     count_query = count_query.filter(games::speed.eq(speed as i32));
-    // }
+fn cursor_literal<QS>(condition: String) -> CursorCondition<QS> {
+    Box::new(sql::<Bool>(&condition))
+}
+
+/// Compares `column` to the value of a key, like `Games.WhiteElo > ?`
+fn cursor_compare<QS>(column: &str, operator: &str, key: &CursorKey) -> CursorCondition<QS> {
+    let condition = sql::<Bool>(&format!("{column} {operator} "));
+    match key {
+        CursorKey::Int(n) => Box::new(condition.bind::<Integer, _>(*n)),
+        CursorKey::Text(s) => Box::new(condition.bind::<Text, _>(s.clone())),
+    }
+}
+
+impl GameCursor {
+    fn columns(sort: &GameSort) -> &'static [&'static str] {
+        match sort {
+            GameSort::Id => &[],
+            GameSort::Date => &["Games.Date", "Games.UTCTime"],
+            GameSort::WhiteElo => &["Games.WhiteElo"],
+            GameSort::BlackElo => &["Games.BlackElo"],
+            GameSort::PlyCount => &["Games.PlyCount"],
+        }
+    }
+
+    fn from_game(sort: &GameSort, direction: &SortDirection, game: &Game) -> Self {
+        let keys = match sort {
+            GameSort::Id => vec![],
+            GameSort::Date => vec![
+                game.date.clone().map(CursorKey::Text),
+                game.time.clone().map(CursorKey::Text),
+            ],
+            GameSort::WhiteElo => vec![game.white_elo.map(CursorKey::Int)],
+            GameSort::BlackElo => vec![game.black_elo.map(CursorKey::Int)],
+            GameSort::PlyCount => vec![game.ply_count.map(CursorKey::Int)],
+        };
+        Self {
+            sort: sort.clone(),
+            direction: direction.clone(),
+            keys,
+            id: game.id,
+        }
+    }
+
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("serialize cursor"))
+    }
+
+    fn decode(cursor: &str) -> Result<Self, Error> {
+        let bytes = hex::decode(cursor).map_err(|_| Error::InvalidCursor)?;
+        serde_json::from_slice(&bytes).map_err(|_| Error::InvalidCursor)
+    }
+
+    /// Condition for the games sorted after this one. NULLs come first in
+    /// ascending order and last in descending order, like SQLite sorts them.
+    fn condition<QS: 'static>(&self) -> CursorCondition<QS> {
+        let asc = self.direction == SortDirection::Asc;
+        let id = CursorKey::Int(self.id);
+        let id = cursor_compare("Games.ID", if asc { ">" } else { "<" }, &id);
+        Self::columns(&self.sort).iter().zip(&self.keys).rev().fold(
+            id,
+            |rest, (column, key)| -> CursorCondition<QS> {
+                let is_null = || cursor_literal(format!("{column} IS NULL"));
+                let (after, equal): (CursorCondition<QS>, CursorCondition<QS>) = match (key, asc) {
+                    (None, true) => (cursor_literal(format!("{column} IS NOT NULL")), is_null()),
+                    (None, false) => (cursor_literal("0".to_string()), is_null()),
+                    (Some(key), true) => (
+                        cursor_compare(column, ">", key),
+                        cursor_compare(column, "=", key),
+                    ),
+                    (Some(key), false) => (
+                        Box::new(cursor_compare(column, "<", key).or(is_null())),
+                        cursor_compare(column, "=", key),
+                    ),
+                };
+                Box::new(after.or(equal.and(rest)))
+            },
+        )
+    }
+}
+
+/// A filter on the games, for any query the games table is part of
+type GameFilter<QS> = Box<dyn BoxableExpression<QS, Sqlite, SqlType = Nullable<Bool>>>;
+
+/// The filters of `query`, ignoring its paging and sorting options. They
+/// apply to the games table on its own as well as to the games joined with
+/// their players, events and sites.
+fn game_filters<QS>(query: &GameQueryJs) -> Vec<GameFilter<QS>>
+where
+    games::result: SelectableExpression<QS>,
+    games::termination: SelectableExpression<QS>,
+    games::date: SelectableExpression<QS>,
+    games::event_id: SelectableExpression<QS>,
+    games::eco: SelectableExpression<QS>,
+    games::time_control: SelectableExpression<QS>,
+    games::white_id: SelectableExpression<QS>,
+    games::black_id: SelectableExpression<QS>,
+    games::white_elo: SelectableExpression<QS>,
+    games::black_elo: SelectableExpression<QS>,
+{
+    let mut filters: Vec<GameFilter<QS>> = Vec::new();
 
     if let Some(outcome) = query.outcome {
-        sql_query = sql_query.filter(games::result.eq(outcome.clone()));
-        count_query = count_query.filter(games::result.eq(outcome));
+        filters.push(Box::new(games::result.eq(outcome.as_str())));
+    }
+
+    if let Some(termination) = query.termination {
+        filters.push(Box::new(games::termination.eq(termination.as_str())));
     }
 
-    if let Some(start_date) = query.start_date {
-        sql_query = sql_query.filter(games::date.ge(start_date.clone()));
-        count_query = count_query.filter(games::date.ge(start_date));
+    if let Some(start_date) = &query.start_date {
+        filters.push(Box::new(games::date.ge(start_date.clone())));
     }
 
-    if let Some(end_date) = query.end_date {
-        sql_query = sql_query.filter(games::date.le(end_date.clone()));
-        count_query = count_query.filter(games::date.le(end_date));
+    if let Some(end_date) = &query.end_date {
+        filters.push(Box::new(games::date.le(end_date.clone())));
     }
 
     if let Some(tournament_id) = query.tournament_id {
-        sql_query = sql_query.filter(games::event_id.eq(tournament_id));
-        count_query = count_query.filter(games::event_id.eq(tournament_id));
+        filters.push(Box::new(games::event_id.eq(tournament_id).nullable()));
     }
 
-    if let Some(limit) = query_options.page_size {
-        sql_query = sql_query.limit(limit as i64);
+    if let Some((from, to)) = &query.eco_range {
+        filters.push(Box::new(games::eco.between(from.clone(), to.clone())));
     }
 
-    if let Some(page) = query_options.page {
-        sql_query = sql_query.offset(((page - 1) * query_options.page_size.unwrap_or(10)) as i64);
+    if let Some((min, max)) = query.rating {
+        filters.push(Box::new(
+            games::white_elo
+                .between(min, max)
+                .and(games::black_elo.between(min, max)),
+        ));
+    }
+
+    if let Some(time_control) = &query.time_control {
+        filters.push(Box::new(games::time_control.eq(time_control.clone())));
     }
 
     match query.sides {
         Some(Sides::BlackWhite) => {
             if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::black_id.eq(player1));
-                count_query = count_query.filter(games::black_id.eq(player1));
+                filters.push(Box::new(games::black_id.eq(player1).nullable()));
             }
             if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::white_id.eq(player2));
-                count_query = count_query.filter(games::white_id.eq(player2));
+                filters.push(Box::new(games::white_id.eq(player2).nullable()));
             }
 
             if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::black_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::black_elo.between(range1.0, range1.1));
+                filters.push(Box::new(games::black_elo.between(range1.0, range1.1)));
             }
 
             if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::white_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::white_elo.between(range2.0, range2.1));
+                filters.push(Box::new(games::white_elo.between(range2.0, range2.1)));
             }
         }
         Some(Sides::WhiteBlack) => {
             if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::white_id.eq(player1));
-                count_query = count_query.filter(games::white_id.eq(player1));
+                filters.push(Box::new(games::white_id.eq(player1).nullable()));
             }
             if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::black_id.eq(player2));
-                count_query = count_query.filter(games::black_id.eq(player2));
+                filters.push(Box::new(games::black_id.eq(player2).nullable()));
             }
 
             if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::white_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::white_elo.between(range1.0, range1.1));
+                filters.push(Box::new(games::white_elo.between(range1.0, range1.1)));
             }
 
             if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::black_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::black_elo.between(range2.0, range2.1));
+                filters.push(Box::new(games::black_elo.between(range2.0, range2.1)));
             }
         }
         Some(Sides::Any) => {
             if let Some(player1) = query.player1 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
+                filters.push(Box::new(
+                    games::white_id
+                        .eq(player1)
+                        .or(games::black_id.eq(player1))
+                        .nullable(),
+                ));
             }
             if let Some(player2) = query.player2 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
+                filters.push(Box::new(
+                    games::white_id
+                        .eq(player2)
+                        .or(games::black_id.eq(player2))
+                        .nullable(),
+                ));
             }
 
             if let (Some(range1), Some(range2)) = (query.range1, query.range2) {
-                sql_query = sql_query.filter(
-                    games::white_elo
-                        .between(range1.0, range1.1)
-                        .or(games::black_elo.between(range1.0, range1.1))
-                        .or(games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1))),
-                );
-                count_query = count_query.filter(
+                filters.push(Box::new(
                     games::white_elo
                         .between(range1.0, range1.1)
                         .or(games::black_elo.between(range1.0, range1.1))
                         .or(games::white_elo
                             .between(range2.0, range2.1)
                             .or(games::black_elo.between(range2.0, range2.1))),
-                );
+                ));
             } else {
                 if let Some(range1) = query.range1 {
-                    sql_query = sql_query.filter(
+                    filters.push(Box::new(
                         games::white_elo
                             .between(range1.0, range1.1)
                             .or(games::black_elo.between(range1.0, range1.1)),
-                    );
-                    count_query = count_query.filter(
-                        games::white_elo
-                            .between(range1.0, range1.1)
-                            .or(games::black_elo.between(range1.0, range1.1)),
-                    );
+                    ));
                 }
 
                 if let Some(range2) = query.range2 {
-                    sql_query = sql_query.filter(
-                        games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1)),
-                    );
-                    count_query = count_query.filter(
+                    filters.push(Box::new(
                         games::white_elo
                             .between(range2.0, range2.1)
                             .or(games::black_elo.between(range2.0, range2.1)),
-                    );
+                    ));
                 }
             }
         }
         None => {}
     }
 
+    filters
+}
+
+/// Games of the database matching the filters of `query`
+fn filter_games(query: &GameQueryJs) -> games::BoxedQuery<'static, Sqlite> {
+    let mut filtered = games::table.into_boxed();
+    for filter in game_filters(query) {
+        filtered = filtered.filter(filter);
+    }
+    filtered
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_games(
+    file: PathBuf,
+    query: GameQueryJs,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    query_games(db, query)
+}
+
+fn query_games(
+    db: &mut SqliteConnection,
+    query: GameQueryJs,
+) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
+    let mut count: Option<i64> = None;
+    let count_query = filter_games(&query);
+    let query_options = query.options.clone().unwrap_or_default();
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut sql_query = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .into_boxed();
+
+    // if let Some(speed) = query.speed {
+    //     sql_query = sql_query.filter(games::speed.eq(speed as i32));
+    //     count_query = count_query.filter(games::speed.eq(speed as i32));
+    // }
+
+    // the same filters as the count, on the joined query itself so that
+    // SQLite can seek to the cursor instead of collecting every matching id
+    for filter in game_filters(&query) {
+        sql_query = sql_query.filter(filter);
+    }
+
+    if let Some(limit) = query_options.page_size {
+        sql_query = sql_query.limit(limit as i64);
+    }
+
+    let by_cursor = query_options.pagination == Some(Pagination::Cursor);
+    if by_cursor {
+        if let Some(after) = &query_options.after {
+            let cursor = GameCursor::decode(after)?;
+            if cursor.sort != query_options.sort || cursor.direction != query_options.direction {
+                return Err(Error::InvalidCursor);
+            }
+            sql_query = sql_query.filter(cursor.condition());
+        }
+    } else if let Some(page) = query_options.page {
+        sql_query = sql_query.offset(((page - 1) * query_options.page_size.unwrap_or(10)) as i64);
+    }
+
     sql_query = match query_options.sort {
         GameSort::Id => match query_options.direction {
             SortDirection::Asc => sql_query.order(games::id.asc()),
@@ -904,6 +1451,13 @@ pub async fn get_games(
             SortDirection::Desc => sql_query.order(games::ply_count.desc()),
         },
     };
+    // ties are broken by id, so that pages don't overlap
+    if query_options.sort != GameSort::Id {
+        sql_query = match query_options.direction {
+            SortDirection::Asc => sql_query.then_order_by(games::id.asc()),
+            SortDirection::Desc => sql_query.then_order_by(games::id.desc()),
+        };
+    }
 
     if !query_options.skip_count {
         count = Some(
@@ -919,11 +1473,20 @@ pub async fn get_games(
     // );
 
     let games: Vec<(Game, Player, Player, Event, Site)> = sql_query.load(db)?;
+    let next_cursor = match (games.last(), query_options.page_size) {
+        (Some((last, ..)), Some(page_size)) if by_cursor && games.len() == page_size as usize => {
+            Some(
+                GameCursor::from_game(&query_options.sort, &query_options.direction, last).encode(),
+            )
+        }
+        _ => None,
+    };
     let normalized_games = normalize_games(games);
 
     Ok(QueryResponse {
         data: normalized_games,
         count: count.map(|c| c as i32),
+        next_cursor,
     })
 }
 
@@ -931,10 +1494,16 @@ fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Vec<Norma
     games
         .into_iter()
         .map(|(game, white, black, event, site)| {
+            let from_start = game.fen.is_none();
             let fen: Fen = game
                 .fen
                 .map(|f| Fen::from_ascii(f.as_bytes()).unwrap())
                 .unwrap_or_default();
+            let moves = decode_moves(game.moves, fen.clone()).unwrap_or_default();
+            let opening = from_start
+                .then(|| classify_game_opening(&moves))
+                .flatten()
+                .map(|opening| opening.name);
 
             NormalizedGame {
                 id: game.id,
@@ -951,12 +1520,14 @@ fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Vec<Norma
                 black: black.name.unwrap_or_default(),
                 black_id: game.black_id,
                 black_elo: game.black_elo,
-                result: Outcome::from_str(&game.result.unwrap_or_default()).unwrap_or_default(),
+                result: Outcome::from_pgn(&game.result.unwrap_or_default()),
+                termination: game.termination.as_deref().and_then(Termination::from_pgn),
                 time_control: game.time_control,
                 eco: game.eco,
                 ply_count: game.ply_count,
                 fen: fen.to_string(),
-                moves: decode_moves(game.moves, fen).unwrap_or_default().join(" "),
+                opening,
+                moves: moves.join(" "),
             }
         })
         .collect()
@@ -1053,6 +1624,7 @@ pub async fn get_players(
     Ok(QueryResponse {
         data: players,
         count: count.map(|c| c as i32),
+        next_cursor: None,
     })
 }
 
@@ -1062,65 +1634,140 @@ pub enum TournamentSort {
     Id,
     #[serde(rename = "name")]
     Name,
+    #[serde(rename = "date")]
+    Date,
+    #[serde(rename = "games")]
+    Games,
 }
 
 #[derive(Debug, Clone, Deserialize, Type)]
 pub struct TournamentQuery {
     pub options: QueryOptions<TournamentSort>,
     pub name: Option<String>,
+    /// Keeps the tournaments with games between these dates, as `YYYY.MM.DD`
+    #[specta(optional)]
+    #[serde(default)]
+    pub start_date: Option<String>,
+    #[specta(optional)]
+    #[serde(default)]
+    pub end_date: Option<String>,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn get_tournaments(
-    file: PathBuf,
-    query: TournamentQuery,
-    state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<Event>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-    let mut count: Option<i64> = None;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, QueryableByName, Type)]
+pub struct Tournament {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub name: Option<String>,
+    #[diesel(sql_type = Integer)]
+    pub games: i32,
+    /// Dates of the first and last games
+    #[diesel(sql_type = Nullable<Text>)]
+    pub date: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub end_date: Option<String>,
+}
 
-    let mut sql_query = events::table.into_boxed();
-    let mut count_query = events::table.into_boxed();
-    sql_query = sql_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
-    count_query = count_query.filter(events::name.is_not("Unknown").and(events::name.is_not("")));
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = Integer)]
+    count: i32,
+}
 
-    if let Some(name) = query.name {
-        sql_query = sql_query.filter(events::name.like(format!("%{}%", name)));
-        count_query = count_query.filter(events::name.like(format!("%{}%", name)));
+/// The filtered and grouped tournaments, after `select` so that it can also
+/// be wrapped to count them
+fn tournaments_sql(
+    select: &str,
+    query: &TournamentQuery,
+) -> BoxedSqlQuery<'static, Sqlite, SqlQuery> {
+    let mut sql = sql_query(format!(
+        "{select} FROM Events LEFT JOIN Games ON Games.EventID = Events.ID \
+        WHERE Events.Name IS NOT 'Unknown' AND Events.Name IS NOT ''"
+    ))
+    .into_boxed();
+    if let Some(name) = &query.name {
+        sql = sql
+            .sql(" AND Events.Name LIKE ?")
+            .bind::<Text, _>(format!("%{name}%"));
     }
-
-    if !query.options.skip_count {
-        count = Some(count_query.count().get_result(db)?);
+    sql = sql.sql(" GROUP BY Events.ID HAVING 1");
+    if let Some(start_date) = &query.start_date {
+        sql = sql
+            .sql(" AND MAX(Games.Date) >= ?")
+            .bind::<Text, _>(start_date.clone());
     }
-
-    if let Some(limit) = query.options.page_size {
-        sql_query = sql_query.limit(limit as i64);
+    if let Some(end_date) = &query.end_date {
+        sql = sql
+            .sql(" AND MIN(Games.Date) <= ?")
+            .bind::<Text, _>(end_date.clone());
     }
+    sql
+}
 
-    if let Some(page) = query.options.page {
-        sql_query = sql_query.offset(((page - 1) * query.options.page_size.unwrap_or(10)) as i64);
+fn query_tournaments(
+    db: &mut SqliteConnection,
+    query: &TournamentQuery,
+) -> Result<QueryResponse<Vec<Tournament>>, Error> {
+    let mut count: Option<i32> = None;
+    if !query.options.skip_count {
+        count = Some(
+            tournaments_sql("SELECT COUNT(*) AS count FROM (SELECT Events.ID", query)
+                .sql(")")
+                .get_result::<Count>(db)?
+                .count,
+        );
     }
 
-    sql_query = match query.options.sort {
-        TournamentSort::Id => match query.options.direction {
-            SortDirection::Asc => sql_query.order(events::id.asc()),
-            SortDirection::Desc => sql_query.order(events::id.desc()),
-        },
-        TournamentSort::Name => match query.options.direction {
-            SortDirection::Asc => sql_query.order(events::name.asc()),
-            SortDirection::Desc => sql_query.order(events::name.desc()),
-        },
+    let mut sql = tournaments_sql(
+        "SELECT Events.ID AS id, Events.Name AS name, COUNT(Games.ID) AS games, \
+        MIN(Games.Date) AS date, MAX(Games.Date) AS end_date",
+        query,
+    );
+    let column = match query.options.sort {
+        TournamentSort::Id => "id",
+        TournamentSort::Name => "name",
+        TournamentSort::Date => "date",
+        TournamentSort::Games => "games",
+    };
+    let direction = match query.options.direction {
+        SortDirection::Asc => "ASC",
+        SortDirection::Desc => "DESC",
     };
+    sql = sql.sql(format!(" ORDER BY {column} {direction}, id {direction}"));
 
-    let events = sql_query.load::<Event>(db)?;
+    if let Some(limit) = query.options.page_size {
+        sql = sql.sql(" LIMIT ?").bind::<Integer, _>(limit);
+    } else if query.options.page.is_some() {
+        // SQLite only takes an offset after a limit
+        sql = sql.sql(" LIMIT -1");
+    }
+    if let Some(page) = query.options.page {
+        let page_size = query.options.page_size.unwrap_or(10);
+        sql = sql
+            .sql(" OFFSET ?")
+            .bind::<Integer, _>((page - 1) * page_size);
+    }
 
     Ok(QueryResponse {
-        data: events,
-        count: count.map(|c| c as i32),
+        data: sql.load(db)?,
+        count,
+        next_cursor: None,
     })
 }
 
+/// Returns the tournaments with how many games each has, filtered by name
+/// and by the dates of their games
+#[tauri::command]
+#[specta::specta]
+pub async fn get_tournaments(
+    file: PathBuf,
+    query: TournamentQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<Tournament>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    query_tournaments(db, &query)
+}
+
 #[derive(Debug, Clone, Serialize, Type, Default)]
 pub struct PlayerGameInfo {
     pub won: i32,
@@ -1342,6 +1989,73 @@ pub async fn get_players_game_info(
     Ok(game_info)
 }
 
+/// A player's rating on a day, as given by the Elo headers of their games
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct RatingPoint {
+    pub date: String,
+    pub rating: i32,
+    /// Site of the game the rating was taken from, like `lichess.org`
+    pub source: String,
+}
+
+fn rating_history(db: &mut SqliteConnection, player_id: i32) -> Result<Vec<RatingPoint>, Error> {
+    let games: Vec<(i32, String, Option<i32>, Option<i32>, Option<String>)> = games::table
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .select((
+            games::white_id,
+            games::date.assume_not_null(),
+            games::white_elo,
+            games::black_elo,
+            sites::name,
+        ))
+        .filter(
+            games::white_id
+                .eq(player_id)
+                .or(games::black_id.eq(player_id)),
+        )
+        .filter(games::date.is_not_null())
+        .order((games::date, games::time, games::id))
+        .load(db)?;
+
+    let mut history: Vec<RatingPoint> = Vec::new();
+    for (white_id, date, white_elo, black_elo, site) in games {
+        let rating = if white_id == player_id {
+            white_elo
+        } else {
+            black_elo
+        };
+        // partial dates like `2023.??.??` can't be placed on a graph
+        let Some(rating) = rating.filter(|_| !date.contains('?')) else {
+            continue;
+        };
+        let point = RatingPoint {
+            date,
+            rating,
+            source: site.unwrap_or_default(),
+        };
+        // the last game of the day is the closest to the rating after it
+        match history.last_mut() {
+            Some(last) if last.date == point.date => *last = point,
+            _ => history.push(point),
+        }
+    }
+    Ok(history)
+}
+
+/// Returns the rating of a player over time, with a point for every day
+/// they played a rated game, sorted by date
+#[tauri::command]
+#[specta::specta]
+pub async fn get_player_rating_history(
+    file: PathBuf,
+    player_id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<RatingPoint>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    rating_history(db, player_id)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn delete_database(
@@ -1357,15 +2071,16 @@ pub async fn delete_database(
     Ok(())
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn delete_duplicated_games(
-    file: PathBuf,
-    state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Type)]
+pub struct DuplicateReport {
+    /// Exact copies that were removed
+    pub deleted: i32,
+    /// Near duplicates folded into the most complete version of the game
+    pub merged: i32,
+}
 
-    db.batch_execute(
+fn delete_exact_duplicates(db: &mut SqliteConnection) -> Result<i32, Error> {
+    let deleted = sql_query(
         "
         DELETE FROM Games
         WHERE ID IN (
@@ -1378,9 +2093,146 @@ pub async fn delete_duplicated_games(
             WHERE RowNum > 1
         );
         ",
-    )?;
+    )
+    .execute(db)?;
+    Ok(deleted as i32)
+}
 
-    Ok(())
+struct DuplicateCandidate {
+    id: i32,
+    /// Hash of the position after each ply
+    positions: Vec<Zobrist64>,
+}
+
+impl DuplicateCandidate {
+    fn new(id: i32, fen: Option<&str>, moves: &[u8]) -> Option<Self> {
+        let mut chess = match fen {
+            Some(fen) => Chess::from_setup(Fen::from_str(fen).ok()?.into(), CastlingMode::Chess960)
+                .or_else(PositionError::ignore_too_much_material)
+                .ok()?,
+            None => Chess::default(),
+        };
+        let mut positions = Vec::with_capacity(moves.len());
+        for &byte in moves {
+            let m = decode_move(byte, &chess)?;
+            chess.play_unchecked(&m);
+            positions.push(chess.zobrist_hash(EnPassantMode::Legal));
+        }
+        Some(Self { id, positions })
+    }
+
+    /// Whether both games share their first `min_plies` plies, in any move
+    /// order, which is when they reach the same position after them. What
+    /// comes after may differ. A game ending earlier has to reach the
+    /// position the other one has at the same ply.
+    fn same_game(&self, other: &Self, min_plies: usize) -> bool {
+        let ply = min_plies
+            .min(self.positions.len())
+            .min(other.positions.len());
+        ply > 0 && self.positions[ply - 1] == other.positions[ply - 1]
+    }
+}
+
+/// Groups games into clusters of duplicates, returning `(kept, removed)`
+/// pairs. The longest game of a cluster is the one kept.
+fn find_fuzzy_duplicates(mut games: Vec<DuplicateCandidate>, min_plies: usize) -> Vec<(i32, i32)> {
+    games.sort_by(|a, b| {
+        b.positions
+            .len()
+            .cmp(&a.positions.len())
+            .then(a.id.cmp(&b.id))
+    });
+
+    let mut kept: Vec<DuplicateCandidate> = Vec::new();
+    let mut removed = Vec::new();
+    for game in games {
+        match kept.iter().find(|k| k.same_game(&game, min_plies)) {
+            Some(k) => removed.push((k.id, game.id)),
+            None => kept.push(game),
+        }
+    }
+    removed
+}
+
+/// Merges games with the same players and date which share their first
+/// `min_plies` plies, keeping the most complete one. Metadata missing
+/// from the kept game is filled in from its duplicates.
+fn merge_fuzzy_duplicates(db: &mut SqliteConnection, min_plies: usize) -> Result<i32, Error> {
+    let rows: Vec<(i32, i32, i32, Option<String>, Option<String>, Vec<u8>)> = games::table
+        .select((
+            games::id,
+            games::white_id,
+            games::black_id,
+            games::date,
+            games::fen,
+            games::moves,
+        ))
+        .order(games::id)
+        .load(db)?;
+
+    let mut groups: HashMap<(i32, i32, Option<String>), Vec<DuplicateCandidate>> = HashMap::new();
+    for (id, white_id, black_id, date, fen, moves) in rows {
+        if let Some(candidate) = DuplicateCandidate::new(id, fen.as_deref(), &moves) {
+            groups
+                .entry((white_id, black_id, date))
+                .or_default()
+                .push(candidate);
+        }
+    }
+
+    let duplicates: Vec<(i32, i32)> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .flat_map(|group| find_fuzzy_duplicates(group, min_plies))
+        .collect();
+
+    db.transaction(|db| {
+        for (kept, removed) in &duplicates {
+            sql_query(
+                "
+                UPDATE Games SET
+                    WhiteElo = COALESCE(WhiteElo, (SELECT WhiteElo FROM Games WHERE ID = ?2)),
+                    BlackElo = COALESCE(BlackElo, (SELECT BlackElo FROM Games WHERE ID = ?2)),
+                    Round = COALESCE(Round, (SELECT Round FROM Games WHERE ID = ?2)),
+                    UTCTime = COALESCE(UTCTime, (SELECT UTCTime FROM Games WHERE ID = ?2)),
+                    TimeControl = COALESCE(TimeControl, (SELECT TimeControl FROM Games WHERE ID = ?2)),
+                    ECO = COALESCE(ECO, (SELECT ECO FROM Games WHERE ID = ?2)),
+                    Termination = COALESCE(Termination, (SELECT Termination FROM Games WHERE ID = ?2))
+                WHERE ID = ?1
+                ",
+            )
+            .bind::<Integer, _>(*kept)
+            .bind::<Integer, _>(*removed)
+            .execute(db)?;
+            diesel::delete(games::table.filter(games::id.eq(*removed))).execute(db)?;
+        }
+        Ok::<_, Error>(())
+    })?;
+
+    Ok(duplicates.len() as i32)
+}
+
+/// Deletes exact duplicates and, when `min_plies` is set, also merges games
+/// that share their first `min_plies` plies, even if they were transposed or
+/// one of them has fewer moves
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_duplicated_games(
+    file: PathBuf,
+    min_plies: Option<u32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<DuplicateReport, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let deleted = delete_exact_duplicates(db)?;
+    let merged = match min_plies {
+        Some(min_plies) => merge_fuzzy_duplicates(db, min_plies as usize)?,
+        None => 0,
+    };
+    update_info_counts(db)?;
+    invalidate_caches(&state, &file);
+
+    Ok(DuplicateReport { deleted, merged })
 }
 
 #[tauri::command]
@@ -1392,10 +2244,298 @@ pub async fn delete_empty_games(
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
     diesel::delete(games::table.filter(games::ply_count.eq(0))).execute(db)?;
+    invalidate_caches(&state, &file);
 
     Ok(())
 }
 
+/// Size of the database file in bytes, before and after optimizing it
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizeReport {
+    pub size_before: i64,
+    pub size_after: i64,
+}
+
+#[derive(QueryableByName)]
+struct VirtualTable {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+/// Reclaims the space left by deleted games and refreshes the statistics of
+/// the query planner. `VACUUM` can't report its own progress, so
+/// `on_progress` is only called between the steps.
+fn optimize(
+    db: &mut SqliteConnection,
+    file: &Path,
+    mut on_progress: impl FnMut(f64),
+) -> Result<OptimizeReport, Error> {
+    let size_before = file.metadata()?.len() as i64;
+
+    let fts_tables: Vec<VirtualTable> = sql_query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND sql LIKE '%USING fts5%'",
+    )
+    .load(db)?;
+    for (i, table) in fts_tables.iter().enumerate() {
+        db.batch_execute(&format!(
+            "INSERT INTO \"{0}\"(\"{0}\") VALUES ('rebuild')",
+            table.name
+        ))?;
+        on_progress(10.0 * (i + 1) as f64 / fts_tables.len() as f64);
+    }
+
+    on_progress(10.0);
+    db.batch_execute("VACUUM")?;
+    on_progress(80.0);
+    db.batch_execute("ANALYZE")?;
+    on_progress(100.0);
+
+    Ok(OptimizeReport {
+        size_before,
+        size_after: file.metadata()?.len() as i64,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn optimize_database(
+    file: PathBuf,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<OptimizeReport, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let id = format!("optimize_{}", file.display());
+    optimize(db, &file, |progress| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress,
+        }
+        .emit(&app);
+    })
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Type)]
+pub struct MergeReport {
+    /// Games copied into the target database
+    pub added: i32,
+    /// Games the target database already had
+    pub skipped: i32,
+}
+
+#[derive(QueryableByName)]
+struct GameCommentText {
+    #[diesel(sql_type = Text, column_name = "Comments")]
+    comments: String,
+}
+
+fn database_version(db: &mut SqliteConnection) -> Result<Option<String>, Error> {
+    Ok(info::table
+        .filter(info::name.eq("Version"))
+        .select(info::value)
+        .first::<Option<String>>(db)
+        .optional()?
+        .flatten())
+}
+
+/// Calls `f` with every game of the database, loading them a batch at a time
+fn for_each_game(
+    db: &mut SqliteConnection,
+    mut f: impl FnMut(&mut SqliteConnection, Game) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut last_id = i32::MIN;
+    loop {
+        let batch: Vec<Game> = games::table
+            .filter(games::id.gt(last_id))
+            .order(games::id)
+            .limit(IMPORT_BATCH_SIZE as i64)
+            .load(db)?;
+        let Some(last) = batch.last() else {
+            return Ok(());
+        };
+        last_id = last.id;
+        for game in batch {
+            f(db, game)?;
+        }
+    }
+}
+
+/// Identifies a game the way `delete_exact_duplicates` does
+type GameKey = (
+    i32,
+    i32,
+    Option<String>,
+    i32,
+    i32,
+    Vec<u8>,
+    Option<String>,
+    Option<String>,
+);
+
+fn game_key(game: &NewGame) -> GameKey {
+    (
+        game.event_id,
+        game.site_id,
+        game.round.map(str::to_string),
+        game.white_id,
+        game.black_id,
+        game.moves.to_vec(),
+        game.date.map(str::to_string),
+        game.time.map(str::to_string),
+    )
+}
+
+fn new_game(game: &Game) -> NewGame<'_> {
+    NewGame {
+        event_id: game.event_id,
+        site_id: game.site_id,
+        date: game.date.as_deref(),
+        time: game.time.as_deref(),
+        round: game.round.as_deref(),
+        white_id: game.white_id,
+        white_elo: game.white_elo,
+        black_id: game.black_id,
+        black_elo: game.black_elo,
+        white_material: game.white_material,
+        black_material: game.black_material,
+        result: game.result.as_deref(),
+        time_control: game.time_control.as_deref(),
+        eco: game.eco.as_deref(),
+        ply_count: game.ply_count.unwrap_or_default(),
+        fen: game.fen.as_deref(),
+        moves: &game.moves,
+        pawn_home: game.pawn_home,
+        termination: game.termination.as_deref(),
+    }
+}
+
+/// Copies the games of `source` that `target` doesn't have yet. Players,
+/// events and sites are matched by name, like when importing games.
+fn merge_into(
+    source: &mut SqliteConnection,
+    target: &mut SqliteConnection,
+    on_progress: impl Fn(f64),
+) -> Result<MergeReport, Error> {
+    let (from, into) = (database_version(source)?, database_version(target)?);
+    if from != into {
+        let unknown = || "unknown".to_string();
+        return Err(Error::DatabaseVersionMismatch {
+            from: from.unwrap_or_else(unknown),
+            into: into.unwrap_or_else(unknown),
+        });
+    }
+
+    let has_comments = has_comment_index(source)?;
+    let total: i64 = games::table.count().get_result(source)?;
+    target.batch_execute(COMMENTS_INDEX_SQL)?;
+
+    let mut report = MergeReport::default();
+    target.transaction::<_, Error, _>(|target| {
+        let mut player_ids = HashMap::new();
+        for player in players::table.load::<Player>(source)? {
+            let id = match &player.name {
+                Some(name) => create_player(target, name)?.id,
+                None => 0,
+            };
+            player_ids.insert(player.id, id);
+        }
+        let mut event_ids = HashMap::new();
+        for event in events::table.load::<Event>(source)? {
+            let id = match &event.name {
+                Some(name) => create_event(target, name)?.id,
+                None => 0,
+            };
+            event_ids.insert(event.id, id);
+        }
+        let mut site_ids = HashMap::new();
+        for site in sites::table.load::<Site>(source)? {
+            let id = match &site.name {
+                Some(name) => create_site(target, name)?.id,
+                None => 0,
+            };
+            site_ids.insert(site.id, id);
+        }
+
+        let mut known = HashSet::new();
+        for_each_game(target, |_, game| {
+            known.insert(game_key(&new_game(&game)));
+            Ok(())
+        })?;
+
+        for_each_game(source, |source, mut game| {
+            game.white_id = player_ids.get(&game.white_id).copied().unwrap_or(0);
+            game.black_id = player_ids.get(&game.black_id).copied().unwrap_or(0);
+            game.event_id = event_ids.get(&game.event_id).copied().unwrap_or(0);
+            game.site_id = site_ids.get(&game.site_id).copied().unwrap_or(0);
+
+            let copy = new_game(&game);
+            if !known.insert(game_key(&copy)) {
+                report.skipped += 1;
+            } else {
+                let added = create_game(target, copy)?;
+                if has_comments {
+                    let comments: Vec<GameCommentText> =
+                        sql_query("SELECT Comments FROM GameComments WHERE rowid = ?")
+                            .bind::<Integer, _>(game.id)
+                            .load(source)?;
+                    for comment in comments {
+                        sql_query("INSERT INTO GameComments (rowid, Comments) VALUES (?, ?)")
+                            .bind::<Integer, _>(added.id)
+                            .bind::<Text, _>(comment.comments)
+                            .execute(target)?;
+                    }
+                }
+                report.added += 1;
+            }
+
+            let done = report.added + report.skipped;
+            if done as usize % IMPORT_BATCH_SIZE == 0 {
+                on_progress(done as f64 / total.max(1) as f64 * 100.0);
+            }
+            Ok(())
+        })?;
+        Ok(())
+    })?;
+
+    update_info_counts(target)?;
+    on_progress(100.0);
+    Ok(report)
+}
+
+/// Copies the games of the `source` database into `target`, skipping the
+/// ones `target` already has
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_databases(
+    source: PathBuf,
+    target: PathBuf,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<MergeReport, Error> {
+    let source_db = &mut get_db_or_create(
+        &state,
+        source.to_str().unwrap(),
+        ConnectionOptions::default(),
+    )?;
+    let target_db = &mut get_db_or_create(
+        &state,
+        target.to_str().unwrap(),
+        ConnectionOptions::default(),
+    )?;
+
+    let id = format!("merge_{}", target.display());
+    let report = merge_into(source_db, target_db, |progress| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress,
+        }
+        .emit(&app);
+    })?;
+    invalidate_caches(&state, &target);
+    Ok(report)
+}
+
 struct PgnGame {
     event: Option<String>,
     site: Option<String>,
@@ -1404,6 +2544,7 @@ struct PgnGame {
     white: Option<String>,
     black: Option<String>,
     result: Option<String>,
+    termination: Option<String>,
     time_control: Option<String>,
     eco: Option<String>,
     white_elo: Option<String>,
@@ -1411,10 +2552,48 @@ struct PgnGame {
     ply_count: Option<String>,
     fen: Option<String>,
     moves: Option<Vec<String>>,
+    comments: Vec<String>,
+}
+
+type PgnGameRow = (Game, Player, Player, Event, Site);
+
+impl From<PgnGameRow> for PgnGame {
+    fn from((game, white, black, event, site): PgnGameRow) -> Self {
+        PgnGame {
+            event: event.name,
+            site: site.name,
+            date: game.date,
+            round: game.round,
+            white: white.name,
+            black: black.name,
+            result: game.result,
+            termination: game.termination,
+            time_control: game.time_control,
+            eco: game.eco,
+            white_elo: game.white_elo.map(|e| e.to_string()),
+            black_elo: game.black_elo.map(|e| e.to_string()),
+            ply_count: game.ply_count.map(|e| e.to_string()),
+            fen: game.fen.clone(),
+            moves: decode_moves(
+                game.moves,
+                if let Some(fen) = game.fen {
+                    Fen::from_ascii(fen.as_bytes()).unwrap_or_default()
+                } else {
+                    Fen::default()
+                },
+            )
+            .ok(),
+            comments: Vec::new(),
+        }
+    }
 }
 
 impl PgnGame {
-    fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+    /// Writes the game as PGN. With `minimal_headers` only the seven tag
+    /// roster and the starting position are written. Where the comments were
+    /// in the game isn't stored, so they are all written before the first
+    /// move.
+    fn write(&self, writer: &mut impl Write, minimal_headers: bool) -> Result<(), Error> {
         writeln!(
             writer,
             "[Event \"{}\"]",
@@ -1442,26 +2621,35 @@ impl PgnGame {
             "[Result \"{}\"]",
             self.result.as_deref().unwrap_or("*")
         )?;
-        if let Some(time_control) = self.time_control.as_deref() {
-            writeln!(writer, "[TimeControl \"{}\"]", time_control)?;
-        }
-        if let Some(eco) = self.eco.as_deref() {
-            writeln!(writer, "[ECO \"{}\"]", eco)?;
-        }
-        if let Some(white_elo) = self.white_elo.as_deref() {
-            writeln!(writer, "[WhiteElo \"{}\"]", white_elo)?;
-        }
-        if let Some(black_elo) = self.black_elo.as_deref() {
-            writeln!(writer, "[BlackElo \"{}\"]", black_elo)?;
-        }
-        if let Some(ply_count) = self.ply_count.as_deref() {
-            writeln!(writer, "[PlyCount \"{}\"]", ply_count)?;
+        if !minimal_headers {
+            if let Some(termination) = self.termination.as_deref() {
+                writeln!(writer, "[Termination \"{}\"]", termination)?;
+            }
+            if let Some(time_control) = self.time_control.as_deref() {
+                writeln!(writer, "[TimeControl \"{}\"]", time_control)?;
+            }
+            if let Some(eco) = self.eco.as_deref() {
+                writeln!(writer, "[ECO \"{}\"]", eco)?;
+            }
+            if let Some(white_elo) = self.white_elo.as_deref() {
+                writeln!(writer, "[WhiteElo \"{}\"]", white_elo)?;
+            }
+            if let Some(black_elo) = self.black_elo.as_deref() {
+                writeln!(writer, "[BlackElo \"{}\"]", black_elo)?;
+            }
+            if let Some(ply_count) = self.ply_count.as_deref() {
+                writeln!(writer, "[PlyCount \"{}\"]", ply_count)?;
+            }
         }
         if let Some(fen) = self.fen.as_deref() {
             writeln!(writer, "[SetUp \"1\"]")?;
             writeln!(writer, "[FEN \"{}\"]", fen)?;
         }
         writeln!(writer)?;
+        for comment in &self.comments {
+            // a comment ends at the first closing brace
+            write!(writer, "{{ {} }} ", comment.replace('}', ""))?;
+        }
         for (i, move_) in self.moves.as_ref().unwrap().iter().enumerate() {
             if i % 2 == 0 {
                 write!(writer, "{}. ", i / 2 + 1)?;
@@ -1484,8 +2672,10 @@ impl PgnGame {
 pub async fn export_to_pgn(
     file: PathBuf,
     dest_file: PathBuf,
+    query: Option<GameQueryJs>,
+    options: PgnExportOptions,
     state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
+) -> Result<i32, Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
     let file = OpenOptions::new()
@@ -1496,46 +2686,222 @@ pub async fn export_to_pgn(
 
     let mut writer = BufWriter::new(file);
 
+    let count = write_pgn_games(
+        db,
+        GameSelection::Filter(query.unwrap_or_default()),
+        &options,
+        &mut writer,
+    )?;
+    writer.flush()?;
+    Ok(count as i32)
+}
+
+/// Games picked from a database, to export or delete them
+enum GameSelection<'a> {
+    Ids(&'a [i32]),
+    Filter(GameQueryJs),
+}
+
+/// Streams the selected games of the database into `writer`, returning the
+/// number of games written
+fn write_pgn_games(
+    db: &mut SqliteConnection,
+    selection: GameSelection,
+    options: &PgnExportOptions,
+    writer: &mut impl Write,
+) -> Result<usize, Error> {
+    // loaded before the games, the connection is busy while they stream
+    let mut comments = if options.include_comments && has_comment_index(db)? {
+        load_game_comments(db, &selection)?
+    } else {
+        HashMap::new()
+    };
+
     let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    games::table
+    let mut query = games::table
         .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
         .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
         .inner_join(events::table.on(games::event_id.eq(events::id)))
         .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
+        .order(games::id)
+        .into_boxed();
+
+    query = match selection {
+        GameSelection::Ids(game_ids) => query.filter(games::id.eq_any(game_ids)),
+        GameSelection::Filter(filter) => {
+            query.filter(games::id.eq_any(filter_games(&filter).select(games::id)))
+        }
+    };
+
+    let mut count = 0;
+    for row in query
+        .load_iter::<PgnGameRow, DefaultLoadingMode>(db)?
         .flatten()
-        .map(|(game, white, black, event, site)| {
-            let pgn = PgnGame {
-                event: event.name,
-                site: site.name,
-                date: game.date,
-                round: game.round,
-                white: white.name,
-                black: black.name,
-                result: game.result,
-                time_control: game.time_control,
-                eco: game.eco,
-                white_elo: game.white_elo.map(|e| e.to_string()),
-                black_elo: game.black_elo.map(|e| e.to_string()),
-                ply_count: game.ply_count.map(|e| e.to_string()),
-                fen: game.fen.clone(),
-                moves: decode_moves(
-                    game.moves,
-                    if let Some(fen) = game.fen {
-                        Fen::from_ascii(fen.as_bytes()).unwrap_or_default()
-                    } else {
-                        Fen::default()
-                    },
-                )
-                .ok(),
-            };
+    {
+        let id = row.0.id;
+        let mut game = PgnGame::from(row);
+        if let Some(text) = comments.remove(&id) {
+            game.comments = text.lines().map(str::to_string).collect();
+        }
+        game.write(writer, options.minimal_headers)?;
+        count += 1;
+    }
+    Ok(count)
+}
 
-            pgn.write(&mut writer)?;
+#[derive(QueryableByName)]
+struct GameCommentRow {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text, column_name = "Comments")]
+    comments: String,
+}
 
-            Ok(())
+/// The comments of the selected games, one per line, by game id. Only games
+/// with comments are in the full text index, so filtered exports take all of
+/// them instead of filtering the games twice.
+fn load_game_comments(
+    db: &mut SqliteConnection,
+    selection: &GameSelection,
+) -> Result<HashMap<i32, String>, Error> {
+    let rows: Vec<GameCommentRow> = match selection {
+        GameSelection::Ids(game_ids) => game_ids
+            .iter()
+            .map(|id| {
+                sql_query("SELECT rowid AS id, Comments FROM GameComments WHERE rowid = ?")
+                    .bind::<Integer, _>(*id)
+                    .load::<GameCommentRow>(db)
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect(),
+        GameSelection::Filter(_) => {
+            sql_query("SELECT rowid AS id, Comments FROM GameComments").load(db)?
+        }
+    };
+    Ok(rows.into_iter().map(|row| (row.id, row.comments)).collect())
+}
+
+/// Maximum number of games [`export_games_to_string`] will export
+const MAX_STRING_EXPORT_GAMES: usize = 1000;
+
+/// Databases only store the mainline of each game, so exports never contain
+/// variations
+#[derive(Debug, Clone, Default, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PgnExportOptions {
+    #[serde(default)]
+    pub minimal_headers: bool,
+    /// Writes the comments kept in the full text index of the database,
+    /// without their clocks and evals
+    #[serde(default)]
+    pub include_comments: bool,
+}
+
+/// Exports the selected games as a PGN string, e.g. to copy them to the
+/// clipboard. Large selections should be exported to a file instead.
+#[tauri::command]
+#[specta::specta]
+pub async fn export_games_to_string(
+    file: PathBuf,
+    game_ids: Vec<i32>,
+    options: PgnExportOptions,
+    state: tauri::State<'_, AppState>,
+) -> Result<String, Error> {
+    if game_ids.len() > MAX_STRING_EXPORT_GAMES {
+        return Err(Error::TooManyGamesToExport(MAX_STRING_EXPORT_GAMES));
+    }
+    if game_ids.is_empty() {
+        return Ok(String::new());
+    }
+
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let mut buffer = Vec::new();
+    write_pgn_games(db, GameSelection::Ids(&game_ids), &options, &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+#[derive(Debug, Serialize, Type)]
+pub struct CommentMatch {
+    pub game: NormalizedGame,
+    /// Part of the comments around the match, with the matching words
+    /// between `<mark>` and `</mark>`
+    pub snippet: String,
+}
+
+#[derive(QueryableByName)]
+struct CommentHit {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+}
+
+/// Whether the database has the full text index of the game comments
+fn has_comment_index(db: &mut SqliteConnection) -> Result<bool, Error> {
+    let index: Vec<VirtualTable> =
+        sql_query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'GameComments'")
+            .load(db)?;
+    Ok(!index.is_empty())
+}
+
+/// Finds the games whose comments contain `phrase`, best matches first
+fn search_game_comments(
+    db: &mut SqliteConnection,
+    phrase: &str,
+    limit: i32,
+) -> Result<Vec<CommentMatch>, Error> {
+    if !has_comment_index(db)? {
+        return Err(Error::NoCommentIndex);
+    }
+
+    // searched as a single phrase, so that FTS syntax in it is taken literally
+    let query = format!("\"{}\"", phrase.replace('"', "\"\""));
+    let hits: Vec<CommentHit> = sql_query(
+        "SELECT rowid AS id, snippet(GameComments, 0, '<mark>', '</mark>', '…', 16) AS snippet
+        FROM GameComments WHERE GameComments MATCH ? ORDER BY rank LIMIT ?",
+    )
+    .bind::<Text, _>(query)
+    .bind::<Integer, _>(limit)
+    .load(db)?;
+
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let games: Vec<(Game, Player, Player, Event, Site)> = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .filter(games::id.eq_any(hits.iter().map(|hit| hit.id).collect::<Vec<_>>()))
+        .load(db)?;
+    let mut games: HashMap<i32, NormalizedGame> = normalize_games(games)
+        .into_iter()
+        .map(|game| (game.id, game))
+        .collect();
+
+    Ok(hits
+        .into_iter()
+        .filter_map(|hit| {
+            Some(CommentMatch {
+                game: games.remove(&hit.id)?,
+                snippet: hit.snippet,
+            })
         })
-        .collect::<Result<Vec<_>, Error>>()?;
-    Ok(())
+        .collect())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn search_comments(
+    file: PathBuf,
+    query: String,
+    limit: Option<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<CommentMatch>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    search_game_comments(db, &query, limit.unwrap_or(50))
 }
 
 #[tauri::command]
@@ -1548,10 +2914,65 @@ pub async fn delete_db_game(
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
     diesel::delete(games::table.filter(games::id.eq(game_id))).execute(db)?;
+    invalidate_caches(&state, &file);
 
     Ok(())
 }
 
+/// Forgets the cached games and position searches of a database, which are
+/// out of date once its games change
+pub(crate) fn invalidate_caches(state: &AppState, file: &Path) {
+    state.line_cache.retain(|(_, path), _| path != file);
+    state.db_cache.lock().unwrap().invalidate(file);
+}
+
+/// Deletes the selected games in one transaction and updates the counts of
+/// the info table, returning the number of games deleted
+fn delete_games(db: &mut SqliteConnection, selection: GameSelection) -> Result<usize, Error> {
+    db.transaction::<_, Error, _>(|db| {
+        let deleted = match selection {
+            GameSelection::Ids(game_ids) => {
+                diesel::delete(games::table.filter(games::id.eq_any(game_ids))).execute(db)?
+            }
+            GameSelection::Filter(filter) => diesel::delete(
+                games::table.filter(games::id.eq_any(filter_games(&filter).select(games::id))),
+            )
+            .execute(db)?,
+        };
+        update_info_counts(db)?;
+        Ok(deleted)
+    })
+}
+
+/// Deletes the games with the given ids, or none of them if it fails
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_db_games(
+    file: PathBuf,
+    game_ids: Vec<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let deleted = delete_games(db, GameSelection::Ids(&game_ids))?;
+    invalidate_caches(&state, &file);
+    Ok(deleted as u32)
+}
+
+/// Deletes every game matching the filters of `query`, like the ones
+/// `get_games` lists. Its position and pagination are ignored.
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_games_matching(
+    file: PathBuf,
+    query: GameQueryJs,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let deleted = delete_games(db, GameSelection::Filter(query))?;
+    invalidate_caches(&state, &file);
+    Ok(deleted as u32)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn merge_players(
@@ -1593,21 +3014,436 @@ pub async fn merge_players(
         .do_update()
         .set(info::value.eq(player_count.to_string()))
         .execute(db)?;
+    invalidate_caches(&state, &file);
 
     Ok(())
 }
 
+/// Forgets the games and position searches cached for every database
 #[tauri::command]
 #[specta::specta]
 pub fn clear_games(state: tauri::State<'_, AppState>) {
-    let mut state = state.db_cache.lock().unwrap();
-    state.clear();
+    state.db_cache.lock().unwrap().clear();
+    state.line_cache.clear();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    pub(super) fn test_db(pgn: &str) -> SqliteConnection {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        db.batch_execute(COMMENTS_INDEX_SQL).unwrap();
+        for game in parse_games_sequential(pgn) {
+            game.insert_to_db(&mut db).unwrap();
+        }
+        db
+    }
+
+    fn parse_games_sequential(pgn: &str) -> Vec<TempGame> {
+        let mut importer = Importer::new(None);
+        BufferedReader::new(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect()
+    }
+
+    const TEST_GAMES: &str = r#"[Event "Test"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[Event "Test"]
+[White "Carol"]
+[Black "Dave"]
+[Result "1/2-1/2"]
+
+1. d4 d5 2. c4 e6 1/2-1/2
+
+[Event "Test"]
+[White "Erin"]
+[Black "Frank"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+"#;
+
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let pgn = TEST_GAMES.repeat(500);
+
+        let sequential = parse_games_sequential(&pgn);
+
+        let mut reader = RawGameReader::new(pgn.as_bytes());
+        let mut parallel = Vec::new();
+        loop {
+            let mut batch = Vec::new();
+            while batch.len() < 64 {
+                match reader.next_game().unwrap() {
+                    Some(game) => batch.push(game),
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+            parallel.extend(parse_games(&batch, None));
+        }
+
+        assert_eq!(parallel.len(), 1500);
+        assert_eq!(parallel.len(), sequential.len());
+        for (a, b) in parallel.iter().zip(sequential.iter()) {
+            assert_eq!(a.white_name, b.white_name);
+            assert_eq!(a.black_name, b.black_name);
+            assert_eq!(a.result, b.result);
+            assert_eq!(a.moves, b.moves);
+        }
+    }
+
+    #[test]
+    fn export_selected_games() {
+        let mut db = test_db(TEST_GAMES);
+        let mut buffer = Vec::new();
+        let count = write_pgn_games(
+            &mut db,
+            GameSelection::Ids(&[1, 3]),
+            &PgnExportOptions {
+                minimal_headers: true,
+                ..Default::default()
+            },
+            &mut buffer,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+
+        let games = parse_games_sequential(&String::from_utf8(buffer).unwrap());
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].white_name.as_deref(), Some("Alice"));
+        assert_eq!(games[0].result.as_deref(), Some("1-0"));
+        assert_eq!(games[0].moves.len(), 7);
+        assert_eq!(games[1].black_name.as_deref(), Some("Frank"));
+        assert_eq!(games[1].moves.len(), 4);
+    }
+
+    const DUPLICATE_GAMES: &str = r#"[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 1-0
+
+[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[Result "1-0"]
+
+1. Nf3 Nc6 2. e4 e5 3. Bb5 a6 4. Ba4 Nf6 1-0
+
+[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[WhiteElo "2100"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0
+
+[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.02"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 1-0
+
+[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[Result "0-1"]
+
+1. d4 d5 2. c4 e6 3. Nc3 Nf6 0-1
+"#;
+
+    #[test]
+    fn searches_comments() {
+        let mut db = test_db(
+            r#"[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 { [%clk 0:03:00] A classic } e5 2. Qh5 { Black is already in zugzwang, sort of } Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[White "Carol"]
+[Black "Dave"]
+[Result "1/2-1/2"]
+
+{ Annotated by Carol } 1. d4 d5 2. c4 e6 { Mutual Zugzwang, sort of } 1/2-1/2
+
+[White "Erin"]
+[Black "Frank"]
+[Result "0-1"]
+
+1. f3 { zugzwang } e5 2. g4 Qh4# 0-1
+"#,
+        );
+
+        let matches = search_game_comments(&mut db, "zugzwang, sort of", 10).unwrap();
+        let mut white: Vec<&str> = matches.iter().map(|m| m.game.white.as_str()).collect();
+        white.sort();
+        assert_eq!(white, vec!["Alice", "Carol"]);
+        assert!(matches[0].snippet.contains("<mark>"));
+
+        // clock annotations aren't indexed
+        assert!(search_game_comments(&mut db, "clk", 10).unwrap().is_empty());
+
+        // deleted games leave the index
+        diesel::delete(games::table.filter(games::white_id.eq(matches[0].game.white_id)))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(
+            search_game_comments(&mut db, "zugzwang, sort of", 10)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let mut old = SqliteConnection::establish(":memory:").unwrap();
+        old.batch_execute(CREATE_TABLES_SQL).unwrap();
+        assert!(matches!(
+            search_game_comments(&mut old, "zugzwang", 10),
+            Err(Error::NoCommentIndex)
+        ));
+    }
+
+    #[test]
+    fn vacuum_shrinks_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("games.db3");
+        let mut db = SqliteConnection::establish(file.to_str().unwrap()).unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        let parsed = parse_games_sequential(&format!("{TEST_GAMES}\n").repeat(500));
+        db.transaction::<_, Error, _>(|db| {
+            for game in &parsed {
+                game.insert_to_db(db)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        diesel::delete(games::table).execute(&mut db).unwrap();
+
+        let mut steps = Vec::new();
+        let report = optimize(&mut db, &file, |progress| steps.push(progress)).unwrap();
+        assert!(report.size_after < report.size_before);
+        assert_eq!(report.size_after, file.metadata().unwrap().len() as i64);
+        assert_eq!(steps.last(), Some(&100.0));
+    }
+
+    #[test]
+    fn merges_fuzzy_duplicates() {
+        let mut db = test_db(DUPLICATE_GAMES);
+        assert_eq!(delete_exact_duplicates(&mut db).unwrap(), 0);
+
+        // the transposition and the shorter copy go into the first game
+        assert_eq!(merge_fuzzy_duplicates(&mut db, 6).unwrap(), 2);
+
+        let remaining: Vec<(i32, Option<i32>)> = games::table
+            .select((games::id, games::white_elo))
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(remaining, vec![(1, Some(2100)), (4, None), (5, None)]);
+    }
+
+    #[test]
+    fn keeps_the_longer_of_games_sharing_their_first_plies() {
+        let mut db = test_db(
+            r#"[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0
+
+[White "Alice"]
+[Black "Bob"]
+[Date "2024.01.01"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. c3 1-0
+"#,
+        );
+        // not the same game anymore by the fifth ply
+        assert_eq!(merge_fuzzy_duplicates(&mut db, 5).unwrap(), 0);
+
+        assert_eq!(merge_fuzzy_duplicates(&mut db, 4).unwrap(), 1);
+        let remaining: Vec<(i32, Option<i32>)> = games::table
+            .select((games::id, games::ply_count))
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(remaining, vec![(2, Some(7))]);
+    }
+
+    #[test]
+    fn transpositions_need_to_rejoin_by_the_threshold() {
+        let mut db = test_db(DUPLICATE_GAMES);
+        // at ply 2 the transposed game is still in a different position
+        assert_eq!(merge_fuzzy_duplicates(&mut db, 2).unwrap(), 1);
+        assert_eq!(games::table.count().get_result::<i64>(&mut db).unwrap(), 4);
+    }
+
+    #[test]
+    fn export_filtered_games() {
+        let games = TEST_GAMES.to_string()
+            + r#"
+[Event "Test"]
+[White "Alice"]
+[Black "Dave"]
+[Result "0-1"]
+
+1. e4 e5 2. Ke2 Qh4 0-1
+
+[Event "Test"]
+[White "Bob"]
+[Black "Alice"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+"#;
+        let mut db = test_db(&games);
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        let query = GameQueryJs {
+            player1: Some(alice),
+            sides: Some(Sides::WhiteBlack),
+            outcome: Some(Outcome::WhiteWin),
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        let count = write_pgn_games(
+            &mut db,
+            GameSelection::Filter(query),
+            &PgnExportOptions::default(),
+            &mut buffer,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+
+        let exported = parse_games_sequential(&String::from_utf8(buffer).unwrap());
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].white_name.as_deref(), Some("Alice"));
+        assert_eq!(exported[0].black_name.as_deref(), Some("Bob"));
+        assert_eq!(exported[0].result.as_deref(), Some("1-0"));
+    }
+
+    #[test]
+    fn exports_comments() {
+        let mut db = test_db(
+            r#"[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 { [%clk 0:03:00] A classic } e5 2. Qh5 { Black is already in zugzwang } Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+"#,
+        );
+        let export = |db: &mut SqliteConnection, include_comments| {
+            let mut buffer = Vec::new();
+            let options = PgnExportOptions {
+                include_comments,
+                ..Default::default()
+            };
+            write_pgn_games(db, GameSelection::Ids(&[1]), &options, &mut buffer).unwrap();
+            parse_games_sequential(&String::from_utf8(buffer).unwrap()).remove(0)
+        };
+
+        let game = export(&mut db, true);
+        assert_eq!(
+            game.comments,
+            vec!["A classic", "Black is already in zugzwang"]
+        );
+        assert_eq!(game.moves.len(), 7);
+        assert!(export(&mut db, false).comments.is_empty());
+    }
+
+    #[test]
+    fn deletes_selected_games() {
+        let games = TEST_GAMES.to_string()
+            + r#"
+[Event "Test"]
+[White "Bob"]
+[Black "Alice"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+"#;
+        let mut db = test_db(&games);
+        let query = GameQueryJs {
+            outcome: Some(Outcome::WhiteWin),
+            ..Default::default()
+        };
+        assert_eq!(
+            delete_games(&mut db, GameSelection::Filter(query)).unwrap(),
+            2
+        );
+        let results: Vec<Option<String>> = games::table
+            .order(games::id)
+            .select(games::result)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![Some("1/2-1/2".to_string()), Some("0-1".to_string())]
+        );
+
+        let ids: Vec<i32> = games::table.select(games::id).load(&mut db).unwrap();
+        // ids that don't exist anymore are skipped
+        let selection = [ids[0], ids[0] + 100];
+        assert_eq!(
+            delete_games(&mut db, GameSelection::Ids(&selection)).unwrap(),
+            1
+        );
+        let game_count: Option<String> = info::table
+            .filter(info::name.eq("GameCount"))
+            .select(info::value)
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(game_count.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn imported_games_get_an_eco() {
+        let mut db = test_db(
+            r#"[White "Alice"]
+[Black "Bob"]
+[Result "*"]
+
+1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6 6. a3 *
+
+[White "Alice"]
+[Black "Bob"]
+[ECO "C20"]
+[Result "*"]
+
+1. e4 c5 2. Nf3 d6 *
+"#,
+        );
+        let ecos: Vec<Option<String>> = games::table.select(games::eco).load(&mut db).unwrap();
+        assert_eq!(ecos, vec![Some("B90".to_string()), Some("C20".to_string())]);
+
+        diesel::update(games::table)
+            .set(games::eco.eq(None::<String>))
+            .execute(&mut db)
+            .unwrap();
+        assert_eq!(classify_missing_openings(&mut db).unwrap(), 2);
+        let ecos: Vec<Option<String>> = games::table.select(games::eco).load(&mut db).unwrap();
+        assert_eq!(ecos, vec![Some("B90".to_string()), Some("B50".to_string())]);
+    }
+
     #[test]
     fn home_row() {
         use shakmaty::Board;
@@ -1623,4 +3459,596 @@ mod tests {
         let pawn_home = get_pawn_home(&Board::from_ascii_board_fen(b"8/8/8/8/8/8/8/8").unwrap());
         assert_eq!(pawn_home, 0b0000000000000000);
     }
+
+    #[derive(QueryableByName)]
+    struct PlanStep {
+        #[diesel(sql_type = Text)]
+        detail: String,
+    }
+
+    #[test]
+    fn parses_results_and_terminations() {
+        let mut db = test_db(
+            r#"[Result "1-0"]
+[Termination "Time forfeit"]
+
+1. e4 e5 1-0
+
+[Result "0-1"]
+[Termination "Normal"]
+
+1. e4 e5 0-1
+
+[Result "1/2-1/2"]
+
+1. e4 e5 1/2-1/2
+
+[Result "½-½"]
+[Termination "Game drawn by agreement"]
+
+1. e4 e5 1/2-1/2
+
+[Result "*"]
+[Termination "unterminated"]
+
+1. e4 e5 *
+"#,
+        );
+        let games = |db: &mut SqliteConnection, query: GameQueryJs| {
+            let mut games = query_games(db, query).unwrap().data;
+            games.sort_by_key(|game| game.id);
+            games
+                .into_iter()
+                .map(|game| (game.result, game.termination))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            games(&mut db, GameQueryJs::default()),
+            vec![
+                (Outcome::WhiteWin, Some(Termination::TimeForfeit)),
+                (Outcome::BlackWin, Some(Termination::Normal)),
+                (Outcome::Draw, None),
+                (Outcome::Draw, Some(Termination::Normal)),
+                (Outcome::Unknown, Some(Termination::Unterminated)),
+            ]
+        );
+
+        let timeouts = GameQueryJs {
+            termination: Some(Termination::TimeForfeit),
+            ..Default::default()
+        };
+        assert_eq!(games(&mut db, timeouts).len(), 1);
+        let draws = GameQueryJs {
+            outcome: Some(Outcome::Draw),
+            ..Default::default()
+        };
+        assert_eq!(games(&mut db, draws).len(), 2);
+
+        // databases from before the column existed get it when opened
+        let mut old = SqliteConnection::establish(":memory:").unwrap();
+        old.batch_execute(&CREATE_TABLES_SQL.replace("    Termination TEXT,\n", ""))
+            .unwrap();
+        add_missing_columns(&mut old).unwrap();
+        add_missing_columns(&mut old).unwrap();
+        assert!(games(&mut old, GameQueryJs::default()).is_empty());
+        add_missing_columns(&mut SqliteConnection::establish(":memory:").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn cursor_pages_match_offset_pages() {
+        // repeated and missing ratings and dates, so that ties and NULLs
+        // have to be paged through
+        let pgn: String = (0..157)
+            .map(|i| {
+                let elo = if i % 7 == 0 {
+                    String::new()
+                } else {
+                    format!("[WhiteElo \"{}\"]\n", 2000 + i % 13)
+                };
+                let date = if i % 5 == 0 {
+                    String::new()
+                } else {
+                    format!("[Date \"2024.01.{:02}\"]\n", 1 + i % 9)
+                };
+                format!(
+                    "[White \"W{i}\"]\n[Black \"B{i}\"]\n{elo}{date}[Result \"*\"]\n\n1. e4 *\n\n"
+                )
+            })
+            .collect();
+        let mut db = test_db(&pgn);
+
+        let page_ids = |db: &mut SqliteConnection, options: QueryOptions<GameSort>| {
+            let response = query_games(
+                db,
+                GameQueryJs {
+                    options: Some(options),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let ids: Vec<i32> = response.data.iter().map(|game| game.id).collect();
+            (ids, response.next_cursor)
+        };
+
+        for sort in [GameSort::Id, GameSort::Date, GameSort::WhiteElo] {
+            for direction in [SortDirection::Asc, SortDirection::Desc] {
+                let options = QueryOptions {
+                    skip_count: true,
+                    page_size: Some(10),
+                    sort: sort.clone(),
+                    direction: direction.clone(),
+                    ..Default::default()
+                };
+
+                let mut by_offset = Vec::new();
+                for page in 1..=16 {
+                    let (ids, cursor) = page_ids(
+                        &mut db,
+                        QueryOptions {
+                            page: Some(page),
+                            ..options.clone()
+                        },
+                    );
+                    assert!(cursor.is_none());
+                    by_offset.extend(ids);
+                }
+
+                let mut by_cursor = Vec::new();
+                let mut after = None;
+                loop {
+                    let (ids, cursor) = page_ids(
+                        &mut db,
+                        QueryOptions {
+                            pagination: Some(Pagination::Cursor),
+                            after: after.take(),
+                            ..options.clone()
+                        },
+                    );
+                    by_cursor.extend(ids);
+                    match cursor {
+                        Some(cursor) => after = Some(cursor),
+                        None => break,
+                    }
+                }
+
+                assert_eq!(by_offset.len(), 157, "{sort:?} {direction:?}");
+                assert_eq!(by_cursor, by_offset, "{sort:?} {direction:?}");
+            }
+        }
+
+        // a cursor only works for the order it was made for
+        let (_, cursor) = page_ids(
+            &mut db,
+            QueryOptions {
+                page_size: Some(10),
+                pagination: Some(Pagination::Cursor),
+                ..Default::default()
+            },
+        );
+        let response = query_games(
+            &mut db,
+            GameQueryJs {
+                options: Some(QueryOptions {
+                    sort: GameSort::WhiteElo,
+                    pagination: Some(Pagination::Cursor),
+                    after: cursor,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(response, Err(Error::InvalidCursor)));
+
+        // deep pages seek to the cursor instead of stepping over every
+        // game before them
+        let cursor = GameCursor {
+            sort: GameSort::Id,
+            direction: SortDirection::Asc,
+            keys: vec![],
+            id: 150,
+        };
+        let page = games::table
+            .select(games::id)
+            .filter(cursor.condition::<games::table>())
+            .order(games::id)
+            .limit(10);
+        let page = diesel::debug_query::<Sqlite, _>(&page).to_string();
+        // the values of the cursor are bound, not written into the SQL
+        let (page, binds) = page.split_once(" -- binds: ").unwrap();
+        assert_eq!(binds, "[150, 10]");
+        let plan: Vec<PlanStep> = sql_query(format!("EXPLAIN QUERY PLAN {page}"))
+            .load(&mut db)
+            .unwrap();
+        assert!(
+            plan.iter()
+                .any(|step| step.detail.starts_with("SEARCH") && step.detail.contains("rowid>?")),
+            "{:?}",
+            plan.iter().map(|step| &step.detail).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cursor_pages_through_equal_keys() {
+        // every game has the same rating and date, so only the ids tell
+        // the pages apart
+        let pgn: String = (0..23)
+            .map(|i| {
+                format!(
+                    "[White \"W{i}\"]\n[Black \"B{i}\"]\n[WhiteElo \"2000\"]\n\
+                    [Date \"2024.01.01\"]\n[Result \"*\"]\n\n1. e4 *\n\n"
+                )
+            })
+            .collect();
+        let mut db = test_db(&pgn);
+        let mut all: Vec<i32> = games::table.select(games::id).load(&mut db).unwrap();
+        all.sort();
+
+        for sort in [GameSort::Date, GameSort::WhiteElo] {
+            for direction in [SortDirection::Asc, SortDirection::Desc] {
+                let mut seen = Vec::new();
+                let mut after = None;
+                loop {
+                    let response = query_games(
+                        &mut db,
+                        GameQueryJs {
+                            options: Some(QueryOptions {
+                                skip_count: true,
+                                page_size: Some(5),
+                                sort: sort.clone(),
+                                direction: direction.clone(),
+                                pagination: Some(Pagination::Cursor),
+                                after: after.take(),
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+                    seen.extend(response.data.iter().map(|game| game.id));
+                    match response.next_cursor {
+                        Some(cursor) => after = Some(cursor),
+                        None => break,
+                    }
+                }
+
+                let mut ids = seen.clone();
+                ids.sort();
+                ids.dedup();
+                assert_eq!(seen.len(), all.len(), "{sort:?} {direction:?}");
+                assert_eq!(ids, all, "{sort:?} {direction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn filters_tournaments_by_name() {
+        let mut db = test_db(
+            r#"[Event "Tata Steel 2023"]
+[Date "2023.01.14"]
+[Result "1-0"]
+
+1. e4 e5 1-0
+
+[Event "Tata Steel 2023"]
+[Date "2023.01.29"]
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Event "Tata Steel 2022"]
+[Date "2022.01.15"]
+[Result "1/2-1/2"]
+
+1. c4 c5 1/2-1/2
+
+[Event "Candidates 2022"]
+[Date "2022.06.17"]
+[Result "1-0"]
+
+1. Nf3 Nf6 1-0
+"#,
+        );
+        let query = |name: Option<&str>, start_date: Option<&str>| TournamentQuery {
+            options: QueryOptions {
+                skip_count: false,
+                page: Some(1),
+                page_size: Some(10),
+                sort: TournamentSort::Games,
+                direction: SortDirection::Desc,
+                pagination: None,
+                after: None,
+            },
+            name: name.map(String::from),
+            start_date: start_date.map(String::from),
+            end_date: None,
+        };
+
+        let tata = query_tournaments(&mut db, &query(Some("Tata"), None)).unwrap();
+        assert_eq!(tata.count, Some(2));
+        let summary: Vec<(Option<&str>, i32)> = tata
+            .data
+            .iter()
+            .map(|t| (t.name.as_deref(), t.games))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(Some("Tata Steel 2023"), 2), (Some("Tata Steel 2022"), 1)]
+        );
+        assert_eq!(tata.data[0].date.as_deref(), Some("2023.01.14"));
+        assert_eq!(tata.data[0].end_date.as_deref(), Some("2023.01.29"));
+
+        let recent = query_tournaments(&mut db, &query(None, Some("2022.06.01"))).unwrap();
+        assert_eq!(recent.count, Some(2));
+        assert!(recent
+            .data
+            .iter()
+            .all(|t| t.name.as_deref() != Some("Tata Steel 2022")));
+    }
+
+    #[test]
+    fn builds_rating_history() {
+        let mut db = test_db(
+            r#"[Site "lichess.org"]
+[Date "2024.03.02"]
+[White "Alice"]
+[Black "Bob"]
+[WhiteElo "1510"]
+[BlackElo "1600"]
+[Result "1-0"]
+
+1. e4 e5 1-0
+
+[Site "chess.com"]
+[Date "2024.01.15"]
+[White "Bob"]
+[Black "Alice"]
+[WhiteElo "1590"]
+[BlackElo "1480"]
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Site "lichess.org"]
+[Date "2024.03.02"]
+[White "Bob"]
+[Black "Alice"]
+[WhiteElo "1605"]
+[BlackElo "1525"]
+[Result "1/2-1/2"]
+
+1. c4 c5 1/2-1/2
+
+[Date "2024.02.01"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 e5 1-0
+"#,
+        );
+        let alice: i32 = players::table
+            .filter(players::name.eq("Alice"))
+            .select(players::id)
+            .first(&mut db)
+            .unwrap();
+
+        // the game without ratings is left out, and of the two games on the
+        // same day the last one is kept
+        assert_eq!(
+            rating_history(&mut db, alice).unwrap(),
+            vec![
+                RatingPoint {
+                    date: "2024.01.15".to_string(),
+                    rating: 1480,
+                    source: "chess.com".to_string(),
+                },
+                RatingPoint {
+                    date: "2024.03.02".to_string(),
+                    rating: 1525,
+                    source: "lichess.org".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_import_matches_serial() {
+        let pgn = format!("{TEST_GAMES}\n").repeat(700);
+
+        let mut serial = test_db("");
+        for game in parse_games_sequential(&pgn) {
+            game.insert_to_db(&mut serial).unwrap();
+        }
+
+        let mut parallel = test_db("");
+        let mut progress = Vec::new();
+        let read = import_pgn_games(&mut parallel, pgn.as_bytes(), None, |stats| {
+            progress.push(stats.processed);
+            Ok(())
+        })
+        .unwrap()
+        .games as usize;
+        assert!(read > IMPORT_BATCH_SIZE);
+        assert!(progress.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(progress.last(), Some(&read));
+
+        let rows = |db: &mut SqliteConnection| {
+            let games: Vec<Game> = games::table.order(games::id).load(db).unwrap();
+            let players: Vec<Player> = players::table.order(players::id).load(db).unwrap();
+            serde_json::to_value((games, players)).unwrap()
+        };
+        assert_eq!(rows(&mut parallel), rows(&mut serial));
+    }
+
+    #[test]
+    fn import_progress_counts_skipped_games() {
+        let broken = r#"[Event "Broken"]
+[White "Grace"]
+[Black "Heidi"]
+[Result "*"]
+
+1. e4 e5 2. Ke3 Ke6 *
+"#;
+        let pgn = format!("{TEST_GAMES}\n{broken}\n").repeat(100);
+
+        let mut db = test_db("");
+        let mut progress = Vec::new();
+        let report = import_pgn_games(&mut db, pgn.as_bytes(), None, |stats| {
+            progress.push(stats.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(report.games, 400);
+        assert!(report.flagged.is_empty());
+
+        let last = progress.last().unwrap();
+        assert_eq!(last.processed, 400);
+        assert_eq!(last.skipped, 100);
+        assert_eq!(last.current_event.as_deref(), Some("Test"));
+        // not once per game
+        assert!(progress.len() < 40);
+
+        let count: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(count, 300);
+    }
+
+    #[test]
+    fn import_flags_inconsistent_clocks() {
+        let clocks = r#"[Event "Clocks"]
+[White "Ivan"]
+[Black "Judy"]
+[Result "*"]
+
+1. e4 { [%clk 0:03:00] } 1... e5 2. Nf3 { [%clk 0:02:58] } 2... Nc6 *
+"#;
+        let pgn = format!("{TEST_GAMES}\n{clocks}\n{TEST_GAMES}\n");
+
+        let mut db = test_db("");
+        let report = import_pgn_games(&mut db, pgn.as_bytes(), None, |_| Ok(())).unwrap();
+        assert_eq!(report.games, 7);
+        assert_eq!(report.flagged.len(), 1);
+        assert_eq!(report.flagged[0].index, 3);
+        assert!(matches!(
+            report.flagged[0].issues.as_slice(),
+            [ClockIssue::MissingClocks { side }] if side == "black"
+        ));
+
+        let count: i64 = games::table.count().get_result(&mut db).unwrap();
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn creating_indexes_twice_is_fine() {
+        let mut db = test_db(TEST_GAMES);
+        assert!(index_status(&mut db)
+            .unwrap()
+            .iter()
+            .all(|index| !index.exists));
+
+        db.batch_execute(INDEXES_SQL).unwrap();
+        db.batch_execute(INDEXES_SQL).unwrap();
+        let status = index_status(&mut db).unwrap();
+        let names: Vec<&str> = status.iter().map(|index| index.name.as_str()).collect();
+        for name in [
+            "games_white_idx",
+            "games_black_idx",
+            "games_event_idx",
+            "games_date_idx",
+            "games_eco_idx",
+            "games_position_idx",
+        ] {
+            assert!(names.contains(&name), "{name}");
+        }
+        assert!(status.iter().all(|index| index.exists));
+
+        // every index made is reported and dropped again
+        let existing: Vec<IndexInfo> = sql_query("SELECT name FROM pragma_index_list('Games');")
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(existing.len(), GAME_INDEXES.len());
+        db.batch_execute(DELETE_INDEXES_SQL).unwrap();
+        assert!(index_status(&mut db)
+            .unwrap()
+            .iter()
+            .all(|index| !index.exists));
+    }
+
+    #[test]
+    fn merging_skips_games_the_target_has() {
+        let mut source = test_db(
+            r#"[Event "Open A"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 { Best by test } e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[Event "Open A"]
+[White "Carol"]
+[Black "Alice"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+"#,
+        );
+        let mut target = test_db(
+            r#"[Event "Open B"]
+[White "Dave"]
+[Black "Alice"]
+[Result "1/2-1/2"]
+
+1. d4 d5 1/2-1/2
+
+[Event "Open A"]
+[White "Carol"]
+[Black "Alice"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+"#,
+        );
+
+        let report = merge_into(&mut source, &mut target, |_| {}).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                added: 1,
+                skipped: 1
+            }
+        );
+
+        let count: i64 = games::table.count().get_result(&mut target).unwrap();
+        assert_eq!(count, 3);
+        // Alice is the same player in both
+        let alices: i64 = players::table
+            .filter(players::name.eq("Alice"))
+            .count()
+            .get_result(&mut target)
+            .unwrap();
+        assert_eq!(alices, 1);
+        let matches = search_game_comments(&mut target, "best by test", 10).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].game.white, "Alice");
+
+        // merging again adds nothing
+        let report = merge_into(&mut source, &mut target, |_| {}).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                added: 0,
+                skipped: 2
+            }
+        );
+
+        for (db, version) in [(&mut source, "1.0.0"), (&mut target, "2.0.0")] {
+            diesel::insert_into(info::table)
+                .values((info::name.eq("Version"), info::value.eq(version)))
+                .execute(db)
+                .unwrap();
+        }
+        assert!(matches!(
+            merge_into(&mut source, &mut target, |_| {}),
+            Err(Error::DatabaseVersionMismatch { .. })
+        ));
+    }
 }
@@ -60,6 +60,7 @@ pub struct Game {
     pub fen: Option<String>,
     pub moves: Vec<u8>,
     pub pawn_home: i32,
+    pub termination: Option<String>,
 }
 
 #[derive(Insertable, Debug)]
@@ -83,6 +84,7 @@ pub struct NewGame<'a> {
     pub fen: Option<&'a str>,
     pub moves: &'a [u8],
     pub pawn_home: i32,
+    pub termination: Option<&'a str>,
 }
 
 #[derive(Default, Debug, Queryable, Serialize, Deserialize, Identifiable, Clone)]
@@ -115,7 +117,7 @@ pub struct Info {
     pub value: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Type, Eq, PartialEq, Hash)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Type, Eq, PartialEq, Hash)]
 pub enum Outcome {
     #[serde(rename = "1-0")]
     WhiteWin,
@@ -142,6 +144,89 @@ impl FromStr for Outcome {
     }
 }
 
+impl Outcome {
+    /// Reads the `Result` header of a game, which isn't always written the
+    /// way the PGN standard says, like `½-½` or `1 - 0`
+    pub fn from_pgn(result: &str) -> Self {
+        let result: String = result
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| if c == '–' || c == ':' { '-' } else { c })
+            .collect();
+        match result.as_str() {
+            "1-0" => Outcome::WhiteWin,
+            "0-1" => Outcome::BlackWin,
+            "1/2-1/2" | "½-½" | "0.5-0.5" | "1/2" | "=" => Outcome::Draw,
+            _ => Outcome::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::WhiteWin => "1-0",
+            Outcome::BlackWin => "0-1",
+            Outcome::Draw => "1/2-1/2",
+            Outcome::Unknown => "*",
+        }
+    }
+}
+
+/// How a game ended, from its `Termination` header
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Type, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Termination {
+    Normal,
+    TimeForfeit,
+    Abandoned,
+    Adjudication,
+    RulesInfraction,
+    Unterminated,
+}
+
+impl Termination {
+    /// Reads both the values of the PGN standard and the sentences some sites
+    /// write instead, like "Alice won on time" or "Game abandoned"
+    pub fn from_pgn(termination: &str) -> Option<Self> {
+        let termination = termination.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| termination.contains(word));
+        if has(&["unterminated"]) {
+            Some(Termination::Unterminated)
+        } else if has(&["abandon"]) {
+            Some(Termination::Abandoned)
+        } else if has(&["adjudicat"]) {
+            Some(Termination::Adjudication)
+        } else if has(&["infraction", "illegal"]) {
+            Some(Termination::RulesInfraction)
+        } else if has(&["time"]) {
+            Some(Termination::TimeForfeit)
+        } else if has(&[
+            "normal",
+            "resign",
+            "checkmate",
+            "agreement",
+            "stalemate",
+            "repetition",
+            "insufficient",
+        ]) {
+            Some(Termination::Normal)
+        } else {
+            None
+        }
+    }
+
+    /// The value of the PGN standard, which is also what databases store
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Termination::Normal => "normal",
+            Termination::TimeForfeit => "time forfeit",
+            Termination::Abandoned => "abandoned",
+            Termination::Adjudication => "adjudication",
+            Termination::RulesInfraction => "rules infraction",
+            Termination::Unterminated => "unterminated",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Type)]
 pub struct NormalizedGame {
     pub id: i32,
@@ -166,10 +251,15 @@ pub struct NormalizedGame {
     pub black_elo: Option<i32>,
     pub result: Outcome,
     #[specta(optional)]
+    pub termination: Option<Termination>,
+    #[specta(optional)]
     pub time_control: Option<String>,
     #[specta(optional)]
     pub eco: Option<String>,
     #[specta(optional)]
     pub ply_count: Option<i32>,
+    /// Name of the opening, for games from the starting position
+    #[specta(optional)]
+    pub opening: Option<String>,
     pub moves: String,
 }
@@ -63,6 +63,8 @@ diesel::table! {
         moves -> Binary,
         #[sql_name = "PawnHome"]
         pawn_home -> Integer,
+        #[sql_name = "Termination"]
+        termination -> Nullable<Text>,
     }
 }
 
@@ -1,12 +1,17 @@
-use dashmap::{mapref::entry::Entry, DashMap};
+use dashmap::DashMap;
 use diesel::prelude::*;
 use log::info;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use shakmaty::{fen::Fen, san::SanPlus, Bitboard, ByColor, Chess, FromSetup, Position, Setup};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    zobrist::{Zobrist64, ZobristHash},
+    Bitboard, ByColor, Chess, EnPassantMode, FromSetup, Position, Setup,
+};
 use specta::Type;
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Mutex,
@@ -17,11 +22,11 @@ use tauri::Emitter;
 
 use crate::{
     db::{
-        encoding::decode_move, get_db_or_create, get_material_count, get_pawn_home, models::*,
-        normalize_games, schema::*, ConnectionOptions, MaterialCount,
+        encoding::decode_move, filter_games, get_db_or_create, get_material_count, get_pawn_home,
+        models::*, normalize_games, schema::*, ConnectionOptions, MaterialCount,
     },
     error::Error,
-    AppState,
+    AppState, GameData,
 };
 
 use super::GameQueryJs;
@@ -31,6 +36,21 @@ pub struct ExactData {
     pawn_home: u16,
     material: MaterialCount,
     position: Chess,
+    hash: Zobrist64,
+}
+
+/// Zobrist hash identifying the position of a FEN regardless of the move
+/// order that reached it and the move counters, as a hex string since it
+/// doesn't fit in a JavaScript number
+#[tauri::command]
+#[specta::specta]
+pub fn position_hash(fen: &str) -> Result<String, Error> {
+    let position: Chess =
+        Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
+    Ok(format!(
+        "{:016x}",
+        position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+    ))
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -50,13 +70,19 @@ impl PositionQuery {
     pub fn exact_from_fen(fen: &str) -> Result<PositionQuery, Error> {
         let position: Chess =
             Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
+        Ok(PositionQuery::exact_from_position(position))
+    }
+
+    fn exact_from_position(position: Chess) -> PositionQuery {
         let pawn_home = get_pawn_home(position.board());
         let material = get_material_count(position.board());
-        Ok(PositionQuery::Exact(ExactData {
+        let hash = position.zobrist_hash(EnPassantMode::Legal);
+        PositionQuery::Exact(ExactData {
             pawn_home,
             material,
             position,
-        }))
+            hash,
+        })
     }
 
     pub fn partial_from_fen(fen: &str) -> Result<PositionQuery, Error> {
@@ -76,6 +102,20 @@ pub struct PositionQueryJs {
     pub type_: String,
 }
 
+/// Key of a search in the line cache. Exact positions are keyed by their hash,
+/// so that FENs only differing in the move counters share an entry.
+fn line_cache_key(query: &GameQueryJs) -> GameQueryJs {
+    let mut key = query.clone();
+    if let Some(position) = &mut key.position {
+        if position.type_ == "exact" {
+            if let Ok(hash) = position_hash(&position.fen) {
+                position.fen = hash;
+            }
+        }
+    }
+    key
+}
+
 fn convert_position_query(query: PositionQueryJs) -> Result<PositionQuery, Error> {
     match query.type_.as_str() {
         "exact" => PositionQuery::exact_from_fen(&query.fen),
@@ -87,8 +127,10 @@ fn convert_position_query(query: PositionQueryJs) -> Result<PositionQuery, Error
 impl PositionQuery {
     fn matches(&self, position: &Chess) -> bool {
         match self {
+            // the board is compared first since it's cheaper than hashing
             PositionQuery::Exact(ref data) => {
-                data.position.board() == position.board() && data.position.turn() == position.turn()
+                data.position.board() == position.board()
+                    && data.hash == position.zobrist_hash(EnPassantMode::Legal)
             }
             PositionQuery::Partial(ref data) => {
                 let query_board = &data.piece_positions.board;
@@ -191,6 +233,96 @@ fn get_move_after_match(
     Ok(None)
 }
 
+fn record_result(openings: &DashMap<String, PositionStats>, m: String, result: Option<&str>) {
+    let mut opening = openings.entry(m.clone()).or_insert_with(|| PositionStats {
+        move_: m,
+        white: 0,
+        draw: 0,
+        black: 0,
+    });
+    match result {
+        Some("1-0") => opening.white += 1,
+        Some("0-1") => opening.black += 1,
+        Some("1/2-1/2") => opening.draw += 1,
+        _ => (),
+    }
+}
+
+/// Whether the query restricts the games that are searched beyond the
+/// position. Those filters are applied by the database instead of scanning
+/// the cached games.
+fn has_game_filters(query: &GameQueryJs) -> bool {
+    query.rating.is_some()
+        || query.time_control.is_some()
+        || query.start_date.is_some()
+        || query.end_date.is_some()
+}
+
+fn load_game_data(
+    db: &mut SqliteConnection,
+    query: Option<&GameQueryJs>,
+) -> Result<Vec<GameData>, Error> {
+    let mut games = match query {
+        Some(query) => filter_games(query),
+        None => games::table.into_boxed(),
+    };
+    let position = query
+        .and_then(|query| query.position.clone())
+        .and_then(|position| convert_position_query(position).ok());
+    if let Some(PositionQuery::Exact(data)) = position {
+        // games never getting down to the material of the position can't
+        // reach it, the position index lets the database skip them
+        games = games
+            .filter(games::white_material.le(data.material.white as i32))
+            .filter(games::black_material.le(data.material.black as i32));
+    }
+    Ok(games
+        .select((
+            games::id,
+            games::white_id,
+            games::black_id,
+            games::date,
+            games::result,
+            games::moves,
+            games::fen,
+            games::pawn_home,
+            games::white_material,
+            games::black_material,
+        ))
+        .load(db)?)
+}
+
+/// The games of the database searched last without filters, so the next
+/// searches of that database don't have to load them again
+#[derive(Default)]
+pub struct GameDataCache {
+    file: Option<PathBuf>,
+    games: Vec<GameData>,
+}
+
+impl GameDataCache {
+    /// Loads the games of `file`, unless they are the ones already cached
+    fn games(&mut self, db: &mut SqliteConnection, file: &Path) -> Result<&[GameData], Error> {
+        if self.games.is_empty() || self.file.as_deref() != Some(file) {
+            self.games = load_game_data(db, None)?;
+            self.file = Some(file.to_path_buf());
+        }
+        Ok(&self.games)
+    }
+
+    pub fn clear(&mut self) {
+        self.file = None;
+        self.games = Vec::new();
+    }
+
+    /// Drops the cached games if they are the ones of `file`
+    pub fn invalidate(&mut self, file: &Path) {
+        if self.file.as_deref() == Some(file) {
+            self.clear();
+        }
+    }
+}
+
 #[derive(Clone, serde::Serialize)]
 pub struct ProgressPayload {
     pub progress: f64,
@@ -209,7 +341,8 @@ pub async fn search_position(
 ) -> Result<(Vec<PositionStats>, Vec<NormalizedGame>), Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
-    if let Some(pos) = state.line_cache.get(&(query.clone(), file.clone())) {
+    let cache_key = (line_cache_key(&query), file.clone());
+    if let Some(pos) = state.line_cache.get(&cache_key) {
         return Ok(pos.clone());
     }
 
@@ -217,27 +350,23 @@ pub async fn search_position(
     let start = Instant::now();
     info!("start loading games");
 
-    let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
-
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
+    let permit = state.new_search.acquire().await.unwrap();
+    let mut cache = state.db_cache.lock().unwrap();
+    let filtered: Vec<GameData>;
 
+    let games = if has_game_filters(&query) {
+        filtered = load_game_data(db, Some(&query))?;
+        info!(
+            "got {} filtered games: {:?}",
+            filtered.len(),
+            start.elapsed()
+        );
+        &filtered[..]
+    } else {
+        let games = cache.games(db, &file)?;
         info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
+        games
+    };
 
     let openings: DashMap<String, PositionStats> = DashMap::new();
     let sample_games: Mutex<Vec<i32>> = Mutex::new(Vec::new());
@@ -259,7 +388,7 @@ pub async fn search_position(
             white_material,
             black_material,
         )| {
-            if state.new_request.available_permits() == 0 {
+            if state.new_search.available_permits() == 0 {
                 return;
             }
             let end_material: MaterialCount = ByColor {
@@ -317,33 +446,7 @@ pub async fn search_position(
                         if sample_games.lock().unwrap().len() < 10 {
                             sample_games.lock().unwrap().push(*id);
                         }
-                        let entry = openings.entry(m);
-                        match entry {
-                            Entry::Occupied(mut e) => {
-                                let opening = e.get_mut();
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white += 1,
-                                    Some("0-1") => opening.black += 1,
-                                    Some("1/2-1/2") => opening.draw += 1,
-                                    _ => (),
-                                }
-                            }
-                            Entry::Vacant(e) => {
-                                let mut opening = PositionStats {
-                                    black: 0,
-                                    white: 0,
-                                    draw: 0,
-                                    move_: e.key().to_string(),
-                                };
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white = 1,
-                                    Some("0-1") => opening.black = 1,
-                                    Some("1/2-1/2") => opening.draw = 1,
-                                    _ => (),
-                                }
-                                e.insert(opening);
-                            }
-                        }
+                        record_result(&openings, m, result.as_deref());
                     }
                 }
             }
@@ -355,7 +458,7 @@ pub async fn search_position(
 
     info!("finished search in {:?}", start.elapsed());
 
-    if state.new_request.available_permits() == 0 {
+    if state.new_search.available_permits() == 0 {
         drop(permit);
         return Err(Error::SearchStopped);
     }
@@ -372,11 +475,113 @@ pub async fn search_position(
 
     state
         .line_cache
-        .insert((query, file), (openings.clone(), normalized_games.clone()));
+        .insert(cache_key, (openings.clone(), normalized_games.clone()));
 
     Ok((openings, normalized_games))
 }
 
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ExplorerMove {
+    pub san: String,
+    pub stats: PositionStats,
+}
+
+/// A position with the moves played from it, like `search_position`, and the
+/// results of the games reaching the position after each legal move
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ExplorerNode {
+    pub position_stats: Vec<PositionStats>,
+    pub moves: Vec<ExplorerMove>,
+}
+
+fn total_games(stats: &PositionStats) -> i32 {
+    stats.white + stats.draw + stats.black
+}
+
+/// Builds the explorer node of `fen` in one pass over the games. The
+/// positions after each move are exact queries, which compare the Zobrist
+/// hash of every position of a game, so games transposing into them are
+/// counted too.
+fn explore_games(games: &[GameData], fen: &str) -> Result<ExplorerNode, Error> {
+    let position: Chess =
+        Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
+    let query = PositionQuery::exact_from_position(position.clone());
+    let children: Vec<(String, PositionQuery)> = position
+        .legal_moves()
+        .iter()
+        .map(|m| {
+            let san = SanPlus::from_move(position.clone(), m).to_string();
+            let mut child = position.clone();
+            child.play_unchecked(m);
+            (san, PositionQuery::exact_from_position(child))
+        })
+        .collect();
+
+    let openings: DashMap<String, PositionStats> = DashMap::new();
+    let reached: DashMap<String, PositionStats> = DashMap::new();
+    games.par_iter().for_each(
+        |(_, _, _, _, result, moves, fen, end_pawn_home, white_material, black_material)| {
+            let end_material: MaterialCount = ByColor {
+                white: *white_material as u8,
+                black: *black_material as u8,
+            };
+            let end_pawn_home = *end_pawn_home as u16;
+            if query.can_reach(&end_material, end_pawn_home) {
+                if let Ok(Some(m)) = get_move_after_match(moves, fen, &query) {
+                    record_result(&openings, m, result.as_deref());
+                }
+            }
+            for (san, child) in &children {
+                if child.can_reach(&end_material, end_pawn_home)
+                    && matches!(get_move_after_match(moves, fen, child), Ok(Some(_)))
+                {
+                    record_result(&reached, san.clone(), result.as_deref());
+                }
+            }
+        },
+    );
+
+    let mut position_stats: Vec<PositionStats> = openings.into_iter().map(|(_, v)| v).collect();
+    position_stats.sort_by(|a, b| total_games(b).cmp(&total_games(a)));
+
+    let mut moves: Vec<ExplorerMove> = children
+        .into_iter()
+        .map(|(san, _)| {
+            let stats = reached
+                .remove(&san)
+                .map(|(_, stats)| stats)
+                .unwrap_or_else(|| PositionStats {
+                    move_: san.clone(),
+                    white: 0,
+                    draw: 0,
+                    black: 0,
+                });
+            ExplorerMove { san, stats }
+        })
+        .collect();
+    moves.sort_by(|a, b| total_games(&b.stats).cmp(&total_games(&a.stats)));
+
+    Ok(ExplorerNode {
+        position_stats,
+        moves,
+    })
+}
+
+/// Returns the explorer node of a position and the stats of every move out of
+/// it, so that a move tree doesn't take a search per move
+#[tauri::command]
+#[specta::specta]
+pub async fn get_opening_explorer(
+    file: PathBuf,
+    fen: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<ExplorerNode, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let mut cache = state.db_cache.lock().unwrap();
+    explore_games(cache.games(db, &file)?, &fen)
+}
+
 pub async fn is_position_in_db(
     file: PathBuf,
     query: GameQueryJs,
@@ -384,7 +589,8 @@ pub async fn is_position_in_db(
 ) -> Result<bool, Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
-    if let Some(pos) = state.line_cache.get(&(query.clone(), file.clone())) {
+    let cache_key = (line_cache_key(&query), file.clone());
+    if let Some(pos) = state.line_cache.get(&cache_key) {
         return Ok(!pos.0.is_empty());
     }
 
@@ -392,27 +598,10 @@ pub async fn is_position_in_db(
     let start = Instant::now();
     info!("start loading games");
 
-    let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
-
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
-
-        info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
+    let permit = state.new_search.acquire().await.unwrap();
+    let mut cache = state.db_cache.lock().unwrap();
+    let games = cache.games(db, &file)?;
+    info!("got {} games: {:?}", games.len(), start.elapsed());
 
     let exists = games.par_iter().any(
         |(
@@ -427,7 +616,7 @@ pub async fn is_position_in_db(
             white_material,
             black_material,
         )| {
-            if state.new_request.available_permits() == 0 {
+            if state.new_search.available_permits() == 0 {
                 return false;
             }
             let end_material: MaterialCount = ByColor {
@@ -447,13 +636,13 @@ pub async fn is_position_in_db(
         },
     );
     info!("finished search in {:?}", start.elapsed());
-    if state.new_request.available_permits() == 0 {
+    if state.new_search.available_permits() == 0 {
         drop(permit);
         return Err(Error::SearchStopped);
     }
 
     if !exists {
-        state.line_cache.insert((query, file), (vec![], vec![]));
+        state.line_cache.insert(cache_key, (vec![], vec![]));
     }
 
     drop(permit);
@@ -563,6 +752,250 @@ mod tests {
         assert_eq!(result, Some("*".to_string()));
     }
 
+    const RATED_GAMES: &str = r#"[White "A"]
+[Black "B"]
+[WhiteElo "2500"]
+[BlackElo "2450"]
+[Date "2023.05.01"]
+[Result "1/2-1/2"]
+
+1. e4 e5 1/2-1/2
+
+[White "C"]
+[Black "D"]
+[WhiteElo "1500"]
+[BlackElo "1450"]
+[Date "2023.05.01"]
+[Result "1-0"]
+
+1. e4 c5 1-0
+
+[White "E"]
+[Black "F"]
+[WhiteElo "2400"]
+[BlackElo "1600"]
+[Date "2021.01.01"]
+[Result "0-1"]
+
+1. e4 e6 0-1
+
+[White "G"]
+[Black "H"]
+[WhiteElo "2600"]
+[BlackElo "2550"]
+[Date "2023.07.01"]
+[Result "1-0"]
+
+1. e4 d5 1-0
+"#;
+
+    fn start_position_stats(games: &[GameData]) -> (i32, i32, i32) {
+        let query =
+            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR").unwrap();
+        let openings = DashMap::new();
+        for (_, _, _, _, result, moves, fen, _, _, _) in games {
+            if let Some(m) = get_move_after_match(moves, fen, &query).unwrap() {
+                record_result(&openings, m, result.as_deref());
+            }
+        }
+        let e4 = openings.get("e4").unwrap();
+        (e4.white, e4.draw, e4.black)
+    }
+
+    #[test]
+    fn deleted_games_leave_the_cache() {
+        let mut db = crate::db::tests::test_db(RATED_GAMES);
+        let state = AppState::default();
+        let file = PathBuf::from("games.db3");
+        let cached_ids = |db: &mut SqliteConnection| -> Vec<i32> {
+            let mut cache = state.db_cache.lock().unwrap();
+            cache
+                .games(db, &file)
+                .unwrap()
+                .iter()
+                .map(|g| g.0)
+                .collect()
+        };
+        let ids = cached_ids(&mut db);
+        state
+            .line_cache
+            .insert((GameQueryJs::default(), file.clone()), (vec![], vec![]));
+
+        diesel::delete(games::table.filter(games::id.eq(ids[0])))
+            .execute(&mut db)
+            .unwrap();
+        // the cache doesn't know about the delete on its own
+        assert_eq!(cached_ids(&mut db), ids);
+
+        crate::db::invalidate_caches(&state, &file);
+        assert_eq!(cached_ids(&mut db), ids[1..]);
+        assert!(state.line_cache.is_empty());
+
+        // the games of another database are loaded instead of the cached ones
+        let mut other = crate::db::tests::test_db("");
+        let mut cache = state.db_cache.lock().unwrap();
+        assert!(cache
+            .games(&mut other, Path::new("other.db3"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn transpositions_share_a_node() {
+        let mut db = crate::db::tests::test_db(
+            r#"[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[Result "0-1"]
+
+1. Nf3 e5 2. e4 Nc6 0-1
+
+[Result "1/2-1/2"]
+
+1. Nf3 e5 2. e3 Nc6 1/2-1/2
+"#,
+        );
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2";
+        let query = PositionQuery::exact_from_fen(fen).unwrap();
+
+        let openings = DashMap::new();
+        for (_, _, _, _, result, moves, fen, _, _, _) in load_game_data(&mut db, None).unwrap() {
+            if let Some(m) = get_move_after_match(&moves, &fen, &query).unwrap() {
+                record_result(&openings, m, result.as_deref());
+            }
+        }
+        assert_eq!(openings.len(), 1);
+        let nc6 = openings.get("Nc6").unwrap();
+        assert_eq!((nc6.white, nc6.draw, nc6.black), (1, 0, 1));
+
+        // the halfmove clock and move number don't change the hash
+        assert_eq!(
+            position_hash(fen).unwrap(),
+            position_hash("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 0 7")
+                .unwrap()
+        );
+        assert_ne!(
+            position_hash(fen).unwrap(),
+            position_hash("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 1 2")
+                .unwrap()
+        );
+        assert_ne!(
+            position_hash(fen).unwrap(),
+            position_hash("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b Qkq - 1 2").unwrap()
+        );
+    }
+
+    #[test]
+    fn exact_searches_are_cached_by_hash() {
+        let exact = |fen: &str| GameQueryJs {
+            position: Some(PositionQueryJs {
+                fen: fen.to_string(),
+                type_: "exact".to_string(),
+            }),
+            ..Default::default()
+        };
+        let e4_nf3 = "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2";
+        assert_eq!(
+            line_cache_key(&exact(e4_nf3)),
+            line_cache_key(&exact(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 0 7"
+            ))
+        );
+        assert_ne!(
+            line_cache_key(&exact(e4_nf3)),
+            line_cache_key(&exact(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b Qkq - 1 2"
+            ))
+        );
+
+        // partial searches don't describe a whole position
+        let partial = GameQueryJs {
+            position: Some(PositionQueryJs {
+                fen: e4_nf3.to_string(),
+                type_: "partial".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(line_cache_key(&partial), partial);
+    }
+
+    #[test]
+    fn rating_filter_changes_stats() {
+        let mut db = crate::db::tests::test_db(RATED_GAMES);
+
+        let all = load_game_data(&mut db, None).unwrap();
+        assert_eq!(start_position_stats(&all), (2, 1, 1));
+
+        let query = GameQueryJs {
+            rating: Some((2000, 3000)),
+            ..Default::default()
+        };
+        assert!(has_game_filters(&query));
+        let rated = load_game_data(&mut db, Some(&query)).unwrap();
+        assert_eq!(start_position_stats(&rated), (1, 1, 0));
+
+        let query = GameQueryJs {
+            rating: Some((2000, 3000)),
+            start_date: Some("2023.06.01".to_string()),
+            ..Default::default()
+        };
+        let recent = load_game_data(&mut db, Some(&query)).unwrap();
+        assert_eq!(start_position_stats(&recent), (1, 0, 0));
+    }
+
+    #[test]
+    fn explores_the_moves_of_a_position() {
+        let mut db = crate::db::tests::test_db(
+            r#"[Result "1-0"]
+
+1. e4 e5 1-0
+
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Result "1/2-1/2"]
+
+1. Nf3 d5 1/2-1/2
+
+[Result "1-0"]
+
+1. c4 e5 1-0
+
+[Result "1-0"]
+
+1. e4 c5 1-0
+"#,
+        );
+        let games = load_game_data(&mut db, None).unwrap();
+        let node = explore_games(
+            &games,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        )
+        .unwrap();
+
+        assert_eq!(node.moves.len(), 20);
+        let stats = |san: &str| {
+            let m = node.moves.iter().find(|m| m.san == san).unwrap();
+            (m.stats.white, m.stats.draw, m.stats.black)
+        };
+        assert_eq!(stats("e4"), (2, 0, 0));
+        assert_eq!(stats("d4"), (0, 0, 1));
+        assert_eq!(stats("Nf3"), (0, 1, 0));
+        assert_eq!(stats("c4"), (1, 0, 0));
+        assert_eq!(stats("a3"), (0, 0, 0));
+        assert_eq!(node.moves[0].san, "e4");
+
+        let e4 = node
+            .position_stats
+            .iter()
+            .find(|m| m.move_ == "e4")
+            .unwrap();
+        assert_eq!((e4.white, e4.draw, e4.black), (2, 0, 0));
+        assert_eq!(node.position_stats.len(), 4);
+    }
+
     #[test]
     fn get_move_after_partial_match_test() {
         let game = vec![12, 12]; // 1. e4 e5
@@ -18,6 +18,9 @@ pub enum Error {
     #[error(transparent)]
     XmlDeserialize(#[from] quick_xml::de::DeError),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
     #[error(transparent)]
     ParseInt(#[from] std::num::ParseIntError),
 
@@ -57,6 +60,9 @@ pub enum Error {
     #[error(transparent)]
     SystemTime(#[from] std::time::SystemTimeError),
 
+    #[error(transparent)]
+    Acquire(#[from] tokio::sync::AcquireError),
+
     #[error("No stdin")]
     NoStdin,
 
@@ -86,6 +92,81 @@ pub enum Error {
 
     #[error("Players aren't the same. They have played against each other")]
     NotDistinctPlayers,
+
+    #[error("Too many games to export at once (maximum {0}), export them to a file instead")]
+    TooManyGamesToExport(usize),
+
+    #[error("Mate length must be between 1 and {0} moves")]
+    InvalidMateLength(u32),
+
+    #[error("Engine is not running")]
+    EngineNotRunning,
+
+    #[error("No engine found at {0}")]
+    EngineNotFound(String),
+
+    #[error("{0} isn't executable")]
+    EngineNotExecutable(String),
+
+    #[error("Not a UCI engine, it didn't answer the uci command in time")]
+    NotUciEngine,
+
+    #[error("No preset named {0}")]
+    PresetNotFound(String),
+
+    #[error("Invalid preset name {0}")]
+    InvalidPresetName(String),
+
+    #[error("Analysis cancelled")]
+    AnalysisCancelled,
+
+    #[error("All the analysis slots are busy, try again once an analysis finishes")]
+    AnalysisSlotsBusy,
+
+    #[error("Engine stopped unexpectedly")]
+    EngineCrashed,
+
+    #[error("Engine has no option named {0}")]
+    UnknownEngineOption(String),
+
+    #[error("Invalid value {value} for engine option {name}")]
+    InvalidEngineOptionValue { name: String, value: String },
+
+    #[error(transparent)]
+    Xz(#[from] lzma_rs::error::Error),
+
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("No Syzygy tablebases found in {0}")]
+    InvalidTablebasePath(String),
+
+    #[error(transparent)]
+    Syzygy(#[from] shakmaty_syzygy::SyzygyError),
+
+    #[error("Not logged in to Lichess")]
+    NotAuthenticated,
+
+    #[error("Couldn't refresh the Lichess access token: {0}")]
+    TokenRefresh(String),
+
+    #[error("Download cancelled")]
+    DownloadCancelled,
+
+    #[error("This database doesn't index comments, import its games again to search them")]
+    NoCommentIndex,
+
+    #[error("Invalid page cursor")]
+    InvalidCursor,
+
+    #[error("Can't merge a version {from} database into a version {into} one")]
+    DatabaseVersionMismatch { from: String, into: String },
+
+    #[error("Compressed PGN files can't be edited")]
+    CompressedPgn,
+
+    #[error("Game {0} of the file has the same headers")]
+    ConflictingGame(usize),
 }
 
 impl serde::Serialize for Error {
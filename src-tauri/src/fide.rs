@@ -1,13 +1,16 @@
 use std::{
-    fs::{remove_file, File},
+    collections::{BTreeMap, HashMap},
+    fs::{self, remove_file, File},
     io::{BufReader, BufWriter},
+    path::Path,
 };
 
 use bincode::{config, Decode, Encode};
+use chrono::{Datelike, NaiveDate, Utc};
 use quick_xml::de::from_reader;
 use serde::{Deserialize, Deserializer, Serialize};
 use specta::Type;
-use strsim::{jaro_winkler, sorensen_dice};
+use strsim::{jaro_winkler, levenshtein};
 use tauri::{path::BaseDirectory, Manager};
 use tauri_specta::Event;
 
@@ -77,47 +80,424 @@ pub struct PlayersList {
     pub players: Vec<FidePlayer>,
 }
 
+/// Entry of a monthly standard rating list, which only has the standard
+/// rating of each player
+#[derive(Debug, Deserialize)]
+struct RatingListEntry {
+    fideid: u32,
+    name: String,
+    #[serde(default)]
+    country: String,
+    #[serde(default)]
+    sex: String,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    title: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    w_title: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    o_title: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    foa_title: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_option_u16")]
+    rating: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_option_u16")]
+    games: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_option_u16")]
+    k: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_option_u16")]
+    birthday: Option<u16>,
+    #[serde(default, deserialize_with = "empty_string_is_none")]
+    flag: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RatingList {
+    #[serde(rename = "player")]
+    players: Vec<RatingListEntry>,
+}
+
+/// Month of the last rating list applied to the local database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FideDbMeta {
+    /// As `YYYY-MM`
+    last_updated: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct FideUpdateReport {
+    /// Whether the whole player list had to be downloaded
+    pub full_download: bool,
+    pub updated: u32,
+    pub inserted: u32,
+    pub last_updated: Option<String>,
+}
+
+fn month_start(month: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+}
+
+fn next_month(month: &str) -> Option<String> {
+    let date = month_start(month)?;
+    let next = match date.month() {
+        12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)?,
+        m => NaiveDate::from_ymd_opt(date.year(), m + 1, 1)?,
+    };
+    Some(next.format("%Y-%m").to_string())
+}
+
+/// Name FIDE gives to the standard rating list of a month, like
+/// `standard_jan24frl_xml`
+fn rating_list_name(month: &str) -> Option<String> {
+    let date = month_start(month)?;
+    Some(format!(
+        "standard_{}{}frl_xml",
+        date.format("%b").to_string().to_lowercase(),
+        date.format("%y")
+    ))
+}
+
+/// Updates the standard rating and titles of known players and adds the new
+/// ones. Returns the number of updated and inserted players.
+fn apply_rating_list(players: &mut Vec<FidePlayer>, entries: Vec<RatingListEntry>) -> (u32, u32) {
+    let index: HashMap<u32, usize> = players
+        .iter()
+        .enumerate()
+        .map(|(i, player)| (player.fideid, i))
+        .collect();
+
+    let (mut updated, mut inserted) = (0, 0);
+    for entry in entries {
+        match index.get(&entry.fideid) {
+            Some(&i) => {
+                let player = &mut players[i];
+                player.name = entry.name;
+                player.title = entry.title;
+                player.w_title = entry.w_title;
+                player.o_title = entry.o_title;
+                player.foa_title = entry.foa_title;
+                player.rating = entry.rating;
+                player.games = entry.games;
+                player.k = entry.k;
+                player.flag = entry.flag;
+                updated += 1;
+            }
+            None => {
+                players.push(FidePlayer {
+                    fideid: entry.fideid,
+                    name: entry.name,
+                    country: entry.country,
+                    sex: entry.sex,
+                    title: entry.title,
+                    w_title: entry.w_title,
+                    o_title: entry.o_title,
+                    foa_title: entry.foa_title,
+                    rating: entry.rating,
+                    games: entry.games,
+                    k: entry.k,
+                    rapid_rating: None,
+                    rapid_games: None,
+                    rapid_k: None,
+                    blitz_rating: None,
+                    blitz_games: None,
+                    blitz_k: None,
+                    birthday: entry.birthday,
+                    flag: entry.flag,
+                });
+                inserted += 1;
+            }
+        }
+    }
+    (updated, inserted)
+}
+
+fn read_meta(path: &Path) -> Option<FideDbMeta> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn save_players(
+    players: &[FidePlayer],
+    fide_path: &Path,
+    meta_path: &Path,
+    month: &str,
+) -> Result<(), Error> {
+    let mut out_file = BufWriter::new(File::create(fide_path)?);
+    bincode::encode_into_std_write(players, &mut out_file, config::standard())?;
+    let meta = FideDbMeta {
+        last_updated: month.to_string(),
+    };
+    fs::write(meta_path, serde_json::to_string(&meta)?)?;
+    Ok(())
+}
+
+/// Downloads and applies the monthly rating lists published since the last
+/// update. Stops at the first list that can't be fetched, which usually
+/// means it isn't published yet.
+async fn update_fide_db(
+    players: &mut Vec<FidePlayer>,
+    mut month: String,
+    app: &tauri::AppHandle,
+) -> Result<FideUpdateReport, Error> {
+    let data_dir = app.path().app_data_dir()?;
+    let current = Utc::now().format("%Y-%m").to_string();
+    let mut report = FideUpdateReport::default();
+
+    while month < current {
+        let Some(next) = next_month(&month) else {
+            break;
+        };
+        let Some(name) = rating_list_name(&next) else {
+            break;
+        };
+        let downloaded = download_file(
+            "fide_db".to_string(),
+            format!("http://ratings.fide.com/download/{}.zip", name),
+            data_dir.clone(),
+            app.clone(),
+            None,
+            Some(false),
+            None,
+            None,
+        )
+        .await;
+        if downloaded.is_err() {
+            break;
+        }
+
+        let xml_path = data_dir.join(format!("{}.xml", name));
+        let list: RatingList = from_reader(BufReader::new(File::open(&xml_path)?))?;
+        remove_file(&xml_path)?;
+
+        let (updated, inserted) = apply_rating_list(players, list.players);
+        report.updated += updated;
+        report.inserted += inserted;
+        month = next;
+    }
+
+    report.last_updated = Some(month);
+    Ok(report)
+}
+
+/// Brings the local FIDE database up to date, applying the monthly rating
+/// lists on top of it when there is one and downloading everything
+/// otherwise
 #[tauri::command]
 #[specta::specta]
 pub async fn download_fide_db(
     state: tauri::State<'_, AppState>,
     app: tauri::AppHandle,
-) -> Result<(), Error> {
+) -> Result<FideUpdateReport, Error> {
     let fide_path = app.path().resolve("fide.bin", BaseDirectory::AppData)?;
+    let meta_path = app.path().resolve("fide.json", BaseDirectory::AppData)?;
 
-    download_file(
-        "fide_db".to_string(),
-        "http://ratings.fide.com/download/players_list_xml.zip".to_string(),
-        app.path().config_dir().unwrap(),
-        app.clone(),
-        None,
-        Some(false),
-        None,
-    )
-    .await?;
+    let local = match (read_meta(&meta_path), File::open(&fide_path)) {
+        (Some(meta), Ok(f)) => {
+            let players: Vec<FidePlayer> =
+                bincode::decode_from_reader(BufReader::new(f), config::standard())?;
+            Some((meta, players))
+        }
+        _ => None,
+    };
 
-    let xml_path = app
-        .path()
-        .resolve("players_list_xml_foa.xml", BaseDirectory::AppData)?;
+    let (players, report) = match local {
+        Some((meta, mut players)) => {
+            let report = update_fide_db(&mut players, meta.last_updated, &app).await?;
+            (players, report)
+        }
+        None => {
+            download_file(
+                "fide_db".to_string(),
+                "http://ratings.fide.com/download/players_list_xml.zip".to_string(),
+                app.path().config_dir().unwrap(),
+                app.clone(),
+                None,
+                Some(false),
+                None,
+                None,
+            )
+            .await?;
 
-    let reader = BufReader::new(File::open(&xml_path)?);
-    let players_list: PlayersList = from_reader(reader)?;
+            let xml_path = app
+                .path()
+                .resolve("players_list_xml_foa.xml", BaseDirectory::AppData)?;
 
-    let mut out_file = BufWriter::new(File::create(&fide_path)?);
-    bincode::encode_into_std_write(&players_list.players, &mut out_file, config::standard())?;
+            let reader = BufReader::new(File::open(&xml_path)?);
+            let players_list: PlayersList = from_reader(reader)?;
+            remove_file(&xml_path)?;
 
-    let mut fide_players = state.fide_players.write().await;
-    *fide_players = players_list.players;
+            let report = FideUpdateReport {
+                full_download: true,
+                inserted: players_list.players.len() as u32,
+                last_updated: Some(Utc::now().format("%Y-%m").to_string()),
+                ..Default::default()
+            };
+            (players_list.players, report)
+        }
+    };
+
+    if let Some(month) = &report.last_updated {
+        save_players(&players, &fide_path, &meta_path, month)?;
+    }
+
+    *state.fide_players.write().await = FideDb::new(players);
 
     DownloadProgress {
-        progress: 100.0,
+        progress: Some(100.0),
         id: "fide_db".to_string(),
         finished: true,
+        cancelled: false,
+        queued: false,
+        bytes_per_second: None,
+        eta_seconds: None,
     }
     .emit(&app)?;
 
-    remove_file(&xml_path)?;
+    Ok(report)
+}
+
+/// Minimum score for a player to be considered a match
+const MATCH_THRESHOLD: f64 = 0.8;
+
+/// Lowercased words of a name, sorted so that "Carlsen, Magnus" and
+/// "Magnus Carlsen" are the same
+fn name_tokens(name: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect();
+    tokens.sort();
+    tokens
+}
+
+fn token_score(query: &str, token: &str) -> f64 {
+    if query == token {
+        return 1.0;
+    }
+    if query.len() >= 3 && token.starts_with(query) {
+        return 0.9;
+    }
+    let max_len = query.chars().count().max(token.chars().count()) as f64;
+    let edit_score = 1.0 - levenshtein(query, token) as f64 / max_len;
+    jaro_winkler(query, token).max(edit_score)
+}
+
+/// How well a name matches the query, from 0 to 1. Each word of the query
+/// is matched to the closest word of the name.
+fn name_score(query: &[String], name: &[String]) -> f64 {
+    if query.is_empty() || name.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = query
+        .iter()
+        .map(|q| {
+            name.iter()
+                .map(|token| token_score(q, token))
+                .fold(0.0, f64::max)
+        })
+        .sum();
+    total / query.len() as f64
+}
+
+/// Name used to look players up, independent of case, punctuation and word
+/// order
+fn normalize_name(name: &str) -> String {
+    name_tokens(name).join(" ")
+}
+
+/// The FIDE players, indexed by id and by normalized name
+#[derive(Debug, Default)]
+pub struct FideDb {
+    players: Vec<FidePlayer>,
+    by_id: HashMap<u32, usize>,
+    by_name: BTreeMap<String, Vec<usize>>,
+}
+
+impl FideDb {
+    pub fn new(players: Vec<FidePlayer>) -> Self {
+        let mut by_id = HashMap::with_capacity(players.len());
+        let mut by_name: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, player) in players.iter().enumerate() {
+            by_id.insert(player.fideid, i);
+            by_name
+                .entry(normalize_name(&player.name))
+                .or_default()
+                .push(i);
+        }
+        Self {
+            players,
+            by_id,
+            by_name,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    pub fn get(&self, fideid: u32) -> Option<&FidePlayer> {
+        self.by_id.get(&fideid).map(|&i| &self.players[i])
+    }
 
+    fn with_name(&self, name: &str) -> &[usize] {
+        self.by_name
+            .get(&normalize_name(name))
+            .map_or(&[][..], Vec::as_slice)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct FidePlayerMatch {
+    pub player: FidePlayer,
+    pub score: f64,
+}
+
+/// Returns the indices and scores of the best matches for `name`, best first.
+/// Exact matches skip the fuzzy scoring altogether.
+fn rank_players(db: &FideDb, name: &str, limit: usize) -> Vec<(usize, f64)> {
+    let mut matches: Vec<(usize, f64)> = db.with_name(name).iter().map(|&i| (i, 1.0)).collect();
+
+    if matches.is_empty() {
+        let query = name_tokens(name);
+        // score each distinct name once, their tokens are already normalized
+        matches = db
+            .by_name
+            .iter()
+            .filter_map(|(key, indices)| {
+                let tokens: Vec<String> = key.split(' ').map(str::to_string).collect();
+                let score = name_score(&query, &tokens);
+                (score >= MATCH_THRESHOLD).then_some((indices, score))
+            })
+            .flat_map(|(indices, score)| indices.iter().map(move |&i| (i, score)))
+            .collect();
+    }
+
+    // higher rated players are more likely to be the one searched for
+    matches.sort_by(|(a, a_score), (b, b_score)| {
+        b_score
+            .total_cmp(a_score)
+            .then(db.players[*b].rating.cmp(&db.players[*a].rating))
+    });
+    matches.truncate(limit);
+    matches
+}
+
+async fn load_fide_players(
+    state: &tauri::State<'_, AppState>,
+    app: &tauri::AppHandle,
+) -> Result<(), Error> {
+    if !state.fide_players.read().await.is_empty() {
+        return Ok(());
+    }
+    let fide_path = app.path().resolve("fide.bin", BaseDirectory::AppData)?;
+    if let Ok(f) = File::open(&fide_path) {
+        let players = bincode::decode_from_reader(BufReader::new(f), config::standard())?;
+        *state.fide_players.write().await = FideDb::new(players);
+    }
     Ok(())
 }
 
@@ -128,36 +508,170 @@ pub async fn find_fide_player(
     state: tauri::State<'_, AppState>,
     app: tauri::AppHandle,
 ) -> Result<Option<FidePlayer>, Error> {
+    load_fide_players(&state, &app).await?;
+
+    let fide_players = state.fide_players.read().await;
+    match rank_players(&fide_players, &player, 1).first() {
+        Some(&(i, _)) => Ok(Some(fide_players.players[i].clone())),
+        None => Err(Error::NoMatchFound),
+    }
+}
+
+/// Ranked candidates for a name, for when the closest match might not be
+/// the right player
+#[tauri::command]
+#[specta::specta]
+pub async fn find_fide_players(
+    name: String,
+    limit: u32,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<Vec<FidePlayerMatch>, Error> {
+    load_fide_players(&state, &app).await?;
+
     let fide_players = state.fide_players.read().await;
+    Ok(rank_players(&fide_players, &name, limit as usize)
+        .into_iter()
+        .map(|(i, score)| FidePlayerMatch {
+            player: fide_players.players[i].clone(),
+            score,
+        })
+        .collect())
+}
 
-    if fide_players.is_empty() {
-        drop(fide_players);
-        let config = config::standard();
-        let fide_path = app.path().resolve("fide.bin", BaseDirectory::AppData)?;
+/// Looks a player up by FIDE id, as found in the `WhiteFideId` and
+/// `BlackFideId` headers
+#[tauri::command]
+#[specta::specta]
+pub async fn get_fide_player(
+    fideid: u32,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<Option<FidePlayer>, Error> {
+    load_fide_players(&state, &app).await?;
+    Ok(state.fide_players.read().await.get(fideid).cloned())
+}
 
-        if let Ok(f) = File::open(&fide_path) {
-            let mut fide_players = state.fide_players.write().await;
-            *fide_players = bincode::decode_from_reader(BufReader::new(f), config)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(fideid: u32, name: &str, rating: u16) -> FidePlayer {
+        FidePlayer {
+            fideid,
+            name: name.to_string(),
+            country: "NOR".to_string(),
+            sex: "M".to_string(),
+            title: None,
+            w_title: None,
+            o_title: None,
+            foa_title: None,
+            rating: Some(rating),
+            games: None,
+            k: Some(20),
+            rapid_rating: Some(2000),
+            rapid_games: None,
+            rapid_k: None,
+            blitz_rating: None,
+            blitz_games: None,
+            blitz_k: None,
+            birthday: Some(2000),
+            flag: None,
         }
     }
 
-    let fide_players = state.fide_players.read().await;
-    let mut best_match = None;
-    let mut best_match_score = 0.0;
-
-    for fide_player in (*fide_players).iter() {
-        let sorenson_score = sorensen_dice(&player, &fide_player.name);
-        let jaro_score = jaro_winkler(&player, &fide_player.name);
-        let score = sorenson_score.max(jaro_score);
-        if score > best_match_score {
-            best_match = Some(fide_player);
-            best_match_score = score;
+    #[test]
+    fn applies_monthly_rating_list() {
+        let mut players = vec![
+            player(1, "Player, One", 2300),
+            player(2, "Player, Two", 2100),
+        ];
+
+        let list: RatingList = from_reader(
+            r#"<playerslist>
+    <player><fideid>1</fideid><name>Player, One</name><country>NOR</country><sex>M</sex><title>IM</title><w_title></w_title><o_title></o_title><foa_title></foa_title><rating>2412</rating><games>9</games><k>10</k><birthday>2000</birthday><flag></flag></player>
+    <player><fideid>3</fideid><name>Player, Three</name><country>FRA</country><sex>F</sex><title></title><w_title>WFM</w_title><o_title></o_title><foa_title></foa_title><rating>2050</rating><games>0</games><k>40</k><birthday>2008</birthday><flag>w</flag></player>
+</playerslist>"#
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(apply_rating_list(&mut players, list.players), (1, 1));
+        assert_eq!(players.len(), 3);
+
+        assert_eq!(players[0].rating, Some(2412));
+        assert_eq!(players[0].title.as_deref(), Some("IM"));
+        assert_eq!(players[0].k, Some(10));
+        // the standard list doesn't touch the other ratings
+        assert_eq!(players[0].rapid_rating, Some(2000));
+
+        assert_eq!(players[1].rating, Some(2100));
+
+        assert_eq!(players[2].fideid, 3);
+        assert_eq!(players[2].w_title.as_deref(), Some("WFM"));
+        assert_eq!(players[2].rapid_rating, None);
+    }
+
+    #[test]
+    fn fuzzy_matches_names() {
+        let players = FideDb::new(vec![
+            player(1, "Carlsen, Magnus", 2830),
+            player(2, "Nepomniachtchi, Ian", 2770),
+            player(3, "Carlsen, Henrik", 1900),
+            player(4, "Nakamura, Hikaru", 2790),
+        ]);
+
+        // exact matches are returned on their own
+        assert_eq!(rank_players(&players, "Carlsen, Magnus", 5), vec![(0, 1.0)]);
+        assert_eq!(rank_players(&players, "magnus carlsen", 5), vec![(0, 1.0)]);
+
+        let matches = rank_players(&players, "Magnus Karlsen", 5);
+        assert_eq!(matches[0].0, 0);
+        assert!(matches.iter().all(|(i, _)| *i != 2));
+
+        // two edits away
+        assert_eq!(rank_players(&players, "Nakamora, Hikar", 5)[0].0, 3);
+        assert_eq!(rank_players(&players, "Nepo", 5)[0].0, 1);
+        assert!(rank_players(&players, "Kasparov, Garry", 5).is_empty());
+    }
+
+    #[test]
+    fn indexed_id_lookup_doesnt_scan() {
+        let count = 200_000;
+        let db = FideDb::new(
+            (0..count)
+                .map(|i| player(i, &format!("Player, {i}"), 2000))
+                .collect(),
+        );
+        assert_eq!(db.get(count - 1).unwrap().fideid, count - 1);
+        assert!(db.get(count).is_none());
+        assert_eq!(db.with_name("player 42"), &[42]);
+
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            let found = db.players.iter().find(|p| p.fideid == count - 1);
+            assert!(found.is_some());
         }
+        let scans = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for i in 0..1000 {
+            assert!(db.get(count - 1 - i).is_some());
+        }
+        let lookups = start.elapsed();
+
+        // a thousand lookups should be cheaper than a handful of full scans
+        assert!(lookups < scans, "{lookups:?} >= {scans:?}");
     }
 
-    if best_match_score > 0.8 {
-        Ok(best_match.cloned())
-    } else {
-        Err(Error::NoMatchFound)
+    #[test]
+    fn monthly_list_names() {
+        assert_eq!(next_month("2023-12").as_deref(), Some("2024-01"));
+        assert_eq!(next_month("2024-05").as_deref(), Some("2024-06"));
+        assert_eq!(next_month("garbage"), None);
+        assert_eq!(
+            rating_list_name("2024-01").as_deref(),
+            Some("standard_jan24frl_xml")
+        );
     }
 }
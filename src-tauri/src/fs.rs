@@ -1,26 +1,304 @@
 use std::{
-    fs::create_dir_all,
-    io::Cursor,
+    collections::VecDeque,
+    fs::{create_dir_all, File},
+    io::{self, BufReader, Cursor, Read},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+use flate2::read::GzDecoder;
+
 use log::info;
-use reqwest::{header::HeaderMap, Client};
+use reqwest::{
+    header::{HeaderMap, RANGE},
+    Client, StatusCode,
+};
+use sha2::{Digest, Sha256};
 use specta::Type;
+use tauri::Manager;
 use tauri_specta::Event;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
 use futures_util::StreamExt;
 
-use crate::error::Error;
+use crate::{error::Error, AppState};
 
 #[derive(Clone, Type, serde::Serialize, Event)]
 pub struct DownloadProgress {
-    pub progress: f32,
+    /// Percentage of the whole file, none if its size is unknown
+    pub progress: Option<f32>,
     pub id: String,
     pub finished: bool,
+    pub cancelled: bool,
+    /// Waiting for other downloads to finish before starting
+    pub queued: bool,
+    pub bytes_per_second: Option<f64>,
+    /// Seconds left, only known when the size of the file is known
+    pub eta_seconds: Option<f64>,
+}
+
+/// How far back chunks are taken into account for the download rate
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+
+/// Download rate over the last few seconds, so that it doesn't jump around
+/// with every chunk
+struct RateEstimator {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateEstimator {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, at: Instant, bytes: u64) {
+        self.samples.push_back((at, bytes));
+        // keep the last sample before the window, it marks where it starts
+        while self.samples.len() > 2 && at.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    fn bytes_per_second(&self) -> Option<f64> {
+        let (start, _) = self.samples.front()?;
+        let (end, _) = self.samples.back()?;
+        let elapsed = end.duration_since(*start).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        // the bytes of the first sample arrived before the window started
+        let bytes: u64 = self.samples.iter().skip(1).map(|(_, bytes)| bytes).sum();
+        Some(bytes as f64 / elapsed)
+    }
+}
+
+/// State of a download after a chunk arrives
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transfer {
+    /// Percentage of the whole file, none if its size is unknown
+    progress: Option<f32>,
+    bytes_per_second: Option<f64>,
+    eta_seconds: Option<f64>,
+}
+
+/// How many downloads run at once by default
+const DEFAULT_DOWNLOAD_LIMIT: usize = 2;
+
+/// How often a queued download checks whether it was cancelled
+const DOWNLOAD_QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The limit on downloads, and how many of the running ones have to give up
+/// their slot when they finish because the limit was lowered
+struct DownloadLimit {
+    limit: usize,
+    debt: usize,
+}
+
+/// Limits how many downloads run at once, the others wait for a slot
+pub struct DownloadQueue {
+    slots: Arc<Semaphore>,
+    /// Permits are only taken away or given back with this held, so that the
+    /// debt always matches the slots in use
+    limit: Arc<Mutex<DownloadLimit>>,
+}
+
+impl Default for DownloadQueue {
+    fn default() -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(DEFAULT_DOWNLOAD_LIMIT)),
+            limit: Arc::new(Mutex::new(DownloadLimit {
+                limit: DEFAULT_DOWNLOAD_LIMIT,
+                debt: 0,
+            })),
+        }
+    }
+}
+
+/// The slot of a running download, given back when it is dropped
+struct DownloadSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    limit: Arc<Mutex<DownloadLimit>>,
+}
+
+impl Drop for DownloadSlot {
+    fn drop(&mut self) {
+        let mut limit = self.limit.lock().unwrap();
+        // a permit that isn't forgotten goes back before the lock is released
+        if let Some(permit) = self.permit.take() {
+            if limit.debt > 0 {
+                limit.debt -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl DownloadQueue {
+    /// Downloads that are already running keep their slot, the ones a lower
+    /// limit takes away are given up as they finish
+    fn set_limit(&self, new_limit: usize) {
+        let new_limit = new_limit.max(1);
+        let mut limit = self.limit.lock().unwrap();
+        if new_limit > limit.limit {
+            let raise = new_limit - limit.limit;
+            let paid = raise.min(limit.debt);
+            limit.debt -= paid;
+            self.slots.add_permits(raise - paid);
+        } else {
+            let mut extra = limit.limit - new_limit;
+            while extra > 0 {
+                match self.slots.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => break,
+                }
+                extra -= 1;
+            }
+            limit.debt += extra;
+        }
+        limit.limit = new_limit;
+    }
+
+    /// Waits for a free slot, calling `on_queued` first if there is none.
+    /// Stops with `Error::DownloadCancelled` as soon as `cancelled` is set.
+    async fn acquire(
+        &self,
+        cancelled: &AtomicBool,
+        on_queued: impl FnOnce(),
+    ) -> Result<DownloadSlot, Error> {
+        let slot = |permit| DownloadSlot {
+            permit: Some(permit),
+            limit: self.limit.clone(),
+        };
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(slot(permit));
+        }
+        on_queued();
+        let permit = self.slots.clone().acquire_owned();
+        tokio::pin!(permit);
+        loop {
+            tokio::select! {
+                permit = &mut permit => return Ok(slot(permit?)),
+                _ = tokio::time::sleep(DOWNLOAD_QUEUE_POLL_INTERVAL) => {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return Err(Error::DownloadCancelled);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where a download is stored until it completes, so it can be resumed
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+async fn send_download_request(
+    client: &Client,
+    url: &str,
+    token: Option<&str>,
+    offset: u64,
+) -> Result<reqwest::Response, Error> {
+    let mut req = client.get(url);
+    // add Bearer if token is present
+    if let Some(token) = token {
+        let mut header_map = HeaderMap::new();
+        header_map.insert("Authorization", format!("Bearer {token}").parse().unwrap());
+        req = req.headers(header_map);
+    }
+    if offset > 0 {
+        req = req.header(RANGE, format!("bytes={offset}-"));
+    }
+    Ok(req.send().await?)
+}
+
+/// Downloads `url` into the `.part` file, resuming from its current size when
+/// the server supports range requests. `on_progress` gets the percentage of
+/// the whole file, including what was downloaded before, and the current
+/// rate. Stops with
+/// `Error::DownloadCancelled` as soon as `cancelled` is set.
+async fn download_to_part(
+    url: &str,
+    token: Option<&str>,
+    part: &Path,
+    total_size: Option<u64>,
+    cancelled: &AtomicBool,
+    mut on_progress: impl FnMut(Transfer) -> Result<(), Error>,
+) -> Result<(), Error> {
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(Error::DownloadCancelled);
+    }
+    let client = Client::new();
+
+    let mut offset = tokio::fs::metadata(part)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let mut res = send_download_request(&client, url, token, offset).await?;
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // the partial file doesn't match the remote one anymore
+        offset = 0;
+        res = send_download_request(&client, url, token, offset).await?;
+    }
+    let res = res.error_for_status()?;
+
+    let resumed = offset > 0 && res.status() == StatusCode::PARTIAL_CONTENT;
+    if offset > 0 && !resumed {
+        info!("Server ignored the range request, restarting the download");
+        offset = 0;
+    }
+    let total_size = total_size.or_else(|| res.content_length().map(|len| len + offset));
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)
+        .await?;
+
+    let mut downloaded = offset;
+    let mut stream = res.bytes_stream();
+    let mut rate = RateEstimator::new(RATE_WINDOW);
+    rate.record(Instant::now(), 0);
+
+    while let Some(item) = stream.next().await {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(Error::DownloadCancelled);
+        }
+        let chunk = item?;
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        rate.record(Instant::now(), chunk.len() as u64);
+
+        let bytes_per_second = rate.bytes_per_second();
+        on_progress(Transfer {
+            progress: total_size
+                .map(|total_size| ((downloaded as f32 / total_size as f32) * 100.0).min(100.0)),
+            bytes_per_second,
+            eta_seconds: total_size
+                .zip(bytes_per_second)
+                .map(|(total_size, rate)| total_size.saturating_sub(downloaded) as f64 / rate),
+        })?;
+    }
+    file.flush().await?;
+    Ok(())
 }
 
 #[tauri::command]
@@ -33,67 +311,242 @@ pub async fn download_file(
     token: Option<String>,
     finalize: Option<bool>,
     total_size: Option<u32>,
+    sha256: Option<String>,
 ) -> Result<(), Error> {
     let finalize = finalize.unwrap_or(true);
     info!("Downloading file from {}", url);
-    let client = Client::new();
 
-    let mut req = client.get(&url);
-    // add Bearer if token is present
-    if let Some(token) = token {
-        let mut header_map = HeaderMap::new();
-        header_map.insert("Authorization", format!("Bearer {token}").parse().unwrap());
-        req = req.headers(header_map);
-    }
-    let res = req.send().await?;
-    let total_size = if let Some(total_size) = total_size {
-        Some(total_size as u64)
-    } else {
-        res.content_length()
-    };
+    let path = Path::new(&path);
+    let part = part_path(path);
 
-    let mut file: Vec<u8> = Vec::new();
-    let mut downloaded: u64 = 0;
-    let mut stream = res.bytes_stream();
+    let state = app.state::<AppState>();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .download_cancellations
+        .insert(id.clone(), cancelled.clone());
 
-    while let Some(item) = stream.next().await {
-        let chunk = item?;
-        file.extend_from_slice(&chunk);
-        downloaded += chunk.len() as u64;
-        if let Some(total_size) = total_size {
-            let progress = ((downloaded as f32 / total_size as f32) * 100.0).min(100.0);
-            // println!("Downloaded {}%", progress);
-            DownloadProgress {
-                progress,
+    let queued = state
+        .download_queue
+        .acquire(&cancelled, || {
+            let _ = DownloadProgress {
+                progress: Some(0.0),
                 id: id.clone(),
                 finished: false,
+                cancelled: false,
+                queued: true,
+                bytes_per_second: None,
+                eta_seconds: None,
             }
-            .emit(&app)?;
+            .emit(&app);
+        })
+        .await;
+
+    let mut last_progress = Some(0.0);
+    // the slot is kept until the file is extracted
+    let (_slot, result) = match queued {
+        Ok(slot) => {
+            let result = download_to_part(
+                &url,
+                token.as_deref(),
+                &part,
+                total_size.map(|size| size as u64),
+                &cancelled,
+                |transfer| {
+                    last_progress = transfer.progress;
+                    DownloadProgress {
+                        progress: transfer.progress,
+                        id: id.clone(),
+                        finished: false,
+                        cancelled: false,
+                        queued: false,
+                        bytes_per_second: transfer.bytes_per_second,
+                        eta_seconds: transfer.eta_seconds,
+                    }
+                    .emit(&app)?;
+                    Ok(())
+                },
+            )
+            .await;
+            (Some(slot), result)
         }
-    }
+        Err(e) => (None, Err(e)),
+    };
+    state.download_cancellations.remove(&id);
 
-    let path = Path::new(&path);
+    if let Err(Error::DownloadCancelled) = result {
+        info!("Cancelled download of {}", url);
+        if part.exists() {
+            std::fs::remove_file(&part)?;
+        }
+        DownloadProgress {
+            progress: last_progress,
+            id,
+            finished: true,
+            cancelled: true,
+            queued: false,
+            bytes_per_second: None,
+            eta_seconds: None,
+        }
+        .emit(&app)?;
+        return Err(Error::DownloadCancelled);
+    }
+    result?;
 
     info!("Downloaded file to {}", path.display());
 
-    if url.ends_with(".zip") {
-        unzip_file(path, file).await?;
-    } else if url.ends_with(".tar") {
-        let mut archive = tar::Archive::new(Cursor::new(file));
-        archive.unpack(path)?;
+    if let Some(expected) = sha256 {
+        if let Err(e) = verify_sha256(&part, &expected) {
+            // a corrupt file can't be resumed either
+            std::fs::remove_file(&part)?;
+            return Err(e);
+        }
+    }
+
+    if let Some(kind) = archive_kind(&url, &part)? {
+        extract_archive(path, &part, kind).await?;
+        std::fs::remove_file(&part)?;
     } else {
-        std::fs::write(path, file)?
+        std::fs::rename(&part, path)?;
     }
 
     if finalize {
         DownloadProgress {
-            progress: 100.0,
+            progress: Some(100.0),
             id,
             finished: true,
+            cancelled: false,
+            queued: false,
+            bytes_per_second: None,
+            eta_seconds: None,
         }
         .emit(&app)?;
     }
-    // remove_file(&path).await;
+    Ok(())
+}
+
+/// Sets how many downloads can run at once, the others are queued
+#[tauri::command]
+#[specta::specta]
+pub async fn set_download_limit(
+    limit: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    state.download_queue.set_limit(limit as usize);
+    Ok(())
+}
+
+/// Stops a running `download_file` and deletes what was downloaded so far
+#[tauri::command]
+#[specta::specta]
+pub async fn cancel_download(id: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    if let Some(cancelled) = state.download_cancellations.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+fn sha256_file(file: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(file)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn verify_sha256(file: &Path, expected: &str) -> Result<(), Error> {
+    let actual = sha256_file(file)?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(Error::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+}
+
+/// Detects the archive type from the extension of the url or path. Names
+/// with another extension, like `games.pgn.gz`, are plain files, only names
+/// without one are recognized from the magic bytes at the start of `file`.
+fn archive_kind(url: &str, file: &Path) -> io::Result<Option<ArchiveKind>> {
+    let name = url.split(['?', '#']).next().unwrap_or(url);
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let kind = if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+        Some(ArchiveKind::TarXz)
+    } else if has_extension(name) {
+        None
+    } else {
+        return sniff_archive_kind(file);
+    };
+    Ok(kind)
+}
+
+/// Version numbers like `sf_16.1` aren't taken for an extension
+fn has_extension(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        (1..=4).contains(&extension.len())
+            && extension.chars().all(|c| c.is_ascii_alphanumeric())
+            && extension.chars().any(|c| c.is_ascii_alphabetic())
+    })
+}
+
+fn is_tar(header: &[u8]) -> bool {
+    header.get(257..262) == Some(&b"ustar"[..])
+}
+
+fn sniff_archive_kind(file: &Path) -> io::Result<Option<ArchiveKind>> {
+    let mut header = Vec::with_capacity(512);
+    File::open(file)?.take(512).read_to_end(&mut header)?;
+    let kind = if header.starts_with(b"PK\x03\x04") {
+        Some(ArchiveKind::Zip)
+    } else if header.starts_with(&[0x1f, 0x8b]) {
+        // a gzipped file that isn't a tar is kept as it is
+        let mut tar_header = Vec::with_capacity(512);
+        let decoded = GzDecoder::new(File::open(file)?)
+            .take(512)
+            .read_to_end(&mut tar_header);
+        (decoded.is_ok() && is_tar(&tar_header)).then_some(ArchiveKind::TarGz)
+    } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(ArchiveKind::TarXz)
+    } else if is_tar(&header) {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    };
+    Ok(kind)
+}
+
+async fn extract_archive(path: &Path, file: &Path, kind: ArchiveKind) -> Result<(), Error> {
+    match kind {
+        ArchiveKind::Zip => unzip_file(path, std::fs::read(file)?).await,
+        ArchiveKind::Tar => untar(path, File::open(file)?),
+        ArchiveKind::TarGz => untar(path, GzDecoder::new(File::open(file)?)),
+        ArchiveKind::TarXz => {
+            let mut tar = Vec::new();
+            lzma_rs::xz_decompress(&mut BufReader::new(File::open(file)?), &mut tar)?;
+            untar(path, Cursor::new(tar))
+        }
+    }
+}
+
+/// Unpacks a tar archive, entries escaping `path` are skipped by `tar`
+fn untar(path: &Path, reader: impl Read) -> Result<(), Error> {
+    create_dir_all(path)?;
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.unpack(path)?;
     Ok(())
 }
 
@@ -123,6 +576,11 @@ pub async fn unzip_file(path: &Path, file: Vec<u8>) -> Result<(), Error> {
             }
             let mut outfile = std::fs::File::create(&outpath)?;
             std::io::copy(&mut file, &mut outfile)?;
+
+            #[cfg(unix)]
+            if let Some(mode) = file.unix_mode() {
+                std::fs::set_permissions(&outpath, std::fs::Permissions::from_mode(mode))?;
+            }
         }
     }
     Ok(())
@@ -151,16 +609,483 @@ pub async fn file_exists(path: String) -> Result<bool, Error> {
 #[derive(Debug, Type, serde::Serialize)]
 pub struct FileMetadata {
     pub last_modified: u32,
+    /// In bytes, as a float so that files over 4GB fit in a js number
+    pub size: f64,
+    pub sha256: Option<String>,
+    pub entries: Option<Vec<ArchiveEntry>>,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn get_file_metadata(path: String) -> Result<FileMetadata, Error> {
+#[derive(Debug, Clone, PartialEq, Type, serde::Serialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub size: f64,
+    pub is_dir: bool,
+}
+
+/// Lists the entries of a zip or tar archive without extracting them
+fn archive_entries(file: &Path, kind: ArchiveKind) -> Result<Vec<ArchiveEntry>, Error> {
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(file)?))?;
+            (0..archive.len())
+                .map(|i| {
+                    let entry = archive.by_index_raw(i)?;
+                    Ok(ArchiveEntry {
+                        name: entry.name().to_string(),
+                        size: entry.size() as f64,
+                        is_dir: entry.is_dir(),
+                    })
+                })
+                .collect()
+        }
+        ArchiveKind::Tar => tar_entries(File::open(file)?),
+        ArchiveKind::TarGz => tar_entries(GzDecoder::new(File::open(file)?)),
+        ArchiveKind::TarXz => {
+            let mut tar = Vec::new();
+            lzma_rs::xz_decompress(&mut BufReader::new(File::open(file)?), &mut tar)?;
+            tar_entries(Cursor::new(tar))
+        }
+    }
+}
+
+fn tar_entries(reader: impl Read) -> Result<Vec<ArchiveEntry>, Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        entries.push(ArchiveEntry {
+            name: entry.path()?.to_string_lossy().to_string(),
+            size: entry.size() as f64,
+            is_dir: entry.header().entry_type().is_dir(),
+        });
+    }
+    Ok(entries)
+}
+
+fn file_metadata(path: &Path, checksum: bool, list_entries: bool) -> Result<FileMetadata, Error> {
     let metadata = std::fs::metadata(path)?;
     let last_modified = metadata
         .modified()?
         .duration_since(std::time::SystemTime::UNIX_EPOCH)?;
+    let sha256 = if checksum {
+        Some(sha256_file(path)?)
+    } else {
+        None
+    };
+    let entries = if list_entries && metadata.is_file() {
+        archive_kind(&path.to_string_lossy(), path)?
+            .map(|kind| archive_entries(path, kind))
+            .transpose()?
+    } else {
+        None
+    };
     Ok(FileMetadata {
         last_modified: last_modified.as_secs() as u32,
+        size: metadata.len() as f64,
+        sha256,
+        entries,
     })
 }
+
+/// Reads the metadata of a file. Hashing it and listing the contents of
+/// archives means reading the whole file, so they are only done when asked.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_file_metadata(
+    path: String,
+    checksum: Option<bool>,
+    list_entries: Option<bool>,
+) -> Result<FileMetadata, Error> {
+    file_metadata(
+        Path::new(&path),
+        checksum.unwrap_or(false),
+        list_entries.unwrap_or(false),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+
+    const CONTENT: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    /// Serves `CONTENT`, honoring range requests if `ranges` is set
+    fn serve(ranges: bool) -> SocketAddr {
+        let handler = move |headers: HeaderMap| async move {
+            let offset = headers
+                .get(header::RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("bytes="))
+                .and_then(|v| v.trim_end_matches('-').parse::<usize>().ok());
+            match offset {
+                Some(offset) if ranges => (
+                    StatusCode::PARTIAL_CONTENT,
+                    [(
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", offset, CONTENT.len() - 1, CONTENT.len()),
+                    )],
+                    CONTENT[offset..].to_vec(),
+                )
+                    .into_response(),
+                _ => (StatusCode::OK, CONTENT.to_vec()).into_response(),
+            }
+        };
+        let app = Router::new().route("/file", get(handler));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn resume(ranges: bool) -> (Vec<u8>, Vec<f32>) {
+        let addr = serve(ranges);
+        let dir = tempfile::tempdir().unwrap();
+        let part = part_path(&dir.path().join("file.bin"));
+        std::fs::write(&part, &CONTENT[..10]).unwrap();
+
+        let mut progress = Vec::new();
+        let cancelled = AtomicBool::new(false);
+        download_to_part(
+            &format!("http://{addr}/file"),
+            None,
+            &part,
+            None,
+            &cancelled,
+            |p| {
+                progress.push(p.progress.unwrap());
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        (std::fs::read(&part).unwrap(), progress)
+    }
+
+    #[tokio::test]
+    async fn resumes_partial_download() {
+        let (file, progress) = resume(true).await;
+        assert_eq!(file, CONTENT);
+        // the first chunk already counts the prefix that was downloaded before
+        assert!(progress[0] > 10.0 / CONTENT.len() as f32 * 100.0);
+        assert_eq!(progress.last(), Some(&100.0));
+    }
+
+    #[test]
+    fn checks_sha256() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.bin");
+        std::fs::write(&file, b"abc").unwrap();
+
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(verify_sha256(&file, digest).is_ok());
+        assert!(verify_sha256(&file, &digest.to_uppercase()).is_ok());
+        assert!(matches!(
+            verify_sha256(&file, &digest.replace('b', "c")),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_archive_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty");
+        std::fs::write(&empty, b"").unwrap();
+        assert_eq!(
+            archive_kind("https://x.org/sf.zip", &empty).unwrap(),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(
+            archive_kind("https://x.org/sf.tar.gz?raw=1", &empty).unwrap(),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            archive_kind("https://x.org/3-4-5.txz", &empty).unwrap(),
+            Some(ArchiveKind::TarXz)
+        );
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        builder
+            .append_data(&mut header, "stockfish", &b"binary"[..])
+            .unwrap();
+        let tar_gz = dir.path().join("tar_gz");
+        std::fs::write(&tar_gz, gzip(&builder.into_inner().unwrap())).unwrap();
+        assert_eq!(
+            archive_kind("https://x.org/sf", &tar_gz).unwrap(),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            archive_kind("https://x.org/sf_16.1", &tar_gz).unwrap(),
+            Some(ArchiveKind::TarGz)
+        );
+
+        let pgn_gz = dir.path().join("games.pgn.gz");
+        std::fs::write(&pgn_gz, gzip(b"[Event \"?\"]")).unwrap();
+        assert_eq!(
+            archive_kind(&pgn_gz.to_string_lossy(), &pgn_gz).unwrap(),
+            None
+        );
+        // without an extension, a gzipped file that isn't a tar isn't an archive
+        assert_eq!(archive_kind("https://x.org/games", &pgn_gz).unwrap(), None);
+        assert_eq!(
+            archive_kind("https://x.org/games.pgn", &tar_gz).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_file_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("games.pgn");
+        std::fs::write(&file, b"abc").unwrap();
+
+        let metadata = file_metadata(&file, false, true).unwrap();
+        assert_eq!(metadata.size, 3.0);
+        assert!(metadata.last_modified > 0);
+        assert_eq!(metadata.sha256, None);
+        assert_eq!(metadata.entries, None);
+
+        let metadata = file_metadata(&file, true, false).unwrap();
+        assert_eq!(
+            metadata.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+    }
+
+    #[test]
+    fn lists_zip_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("engine.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&file).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("stockfish/", options).unwrap();
+        zip.start_file("stockfish/stockfish", options).unwrap();
+        std::io::Write::write_all(&mut zip, b"binary").unwrap();
+        zip.finish().unwrap();
+
+        let metadata = file_metadata(&file, false, true).unwrap();
+        assert_eq!(
+            metadata.entries.unwrap(),
+            vec![
+                ArchiveEntry {
+                    name: "stockfish/".to_string(),
+                    size: 0.0,
+                    is_dir: true,
+                },
+                ArchiveEntry {
+                    name: "stockfish/stockfish".to_string(),
+                    size: 6.0,
+                    is_dir: false,
+                },
+            ]
+        );
+        // nothing was extracted next to the archive
+        assert_eq!(dir.path().read_dir().unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn extracts_tar_gz() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o755);
+        builder
+            .append_data(&mut header, "stockfish/bin/stockfish", &b"binary"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("stockfish.tar.gz.part");
+        std::fs::write(&file, archive).unwrap();
+        let out = dir.path().join("engines");
+        extract_archive(&out, &file, ArchiveKind::TarGz)
+            .await
+            .unwrap();
+
+        let binary = out.join("stockfish/bin/stockfish");
+        assert_eq!(std::fs::read(&binary).unwrap(), b"binary");
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::metadata(&binary).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+
+    #[tokio::test]
+    async fn cancellation_stops_the_download() {
+        const CHUNK: usize = 1024;
+        const CHUNKS: usize = 50;
+        // a slow server, so that the download is cancelled mid-stream
+        let handler = || async {
+            let chunks = futures::stream::unfold(0, |i| async move {
+                if i == CHUNKS {
+                    return None;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                Some((Ok::<_, std::io::Error>(vec![b'x'; CHUNK]), i + 1))
+            });
+            axum::body::StreamBody::new(chunks)
+        };
+        let app = Router::new().route("/file", get(handler));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let dir = tempfile::tempdir().unwrap();
+        let part = part_path(&dir.path().join("file.bin"));
+        let cancelled = AtomicBool::new(false);
+        let mut updates = 0;
+        let result = download_to_part(
+            &format!("http://{addr}/file"),
+            None,
+            &part,
+            Some((CHUNK * CHUNKS) as u64),
+            &cancelled,
+            |_| {
+                updates += 1;
+                cancelled.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::DownloadCancelled)));
+        assert_eq!(updates, 1);
+        assert!(std::fs::metadata(&part).unwrap().len() < (CHUNK * CHUNKS) as u64);
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_downloads() {
+        let queue = Arc::new(DownloadQueue::default());
+        queue.set_limit(2);
+        let running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let max_running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let queued = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let downloads = (0..4).map(|_| {
+            let (queue, running, max_running, queued) = (
+                queue.clone(),
+                running.clone(),
+                max_running.clone(),
+                queued.clone(),
+            );
+            tokio::spawn(async move {
+                let _slot = queue
+                    .acquire(&AtomicBool::new(false), || {
+                        queued.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .await
+                    .unwrap();
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+        for download in futures::future::join_all(downloads).await {
+            download.unwrap();
+        }
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(queued.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn changes_the_download_limit() {
+        let queue = DownloadQueue::default();
+        let not_cancelled = AtomicBool::new(false);
+        let first = queue.acquire(&not_cancelled, || {}).await.unwrap();
+        let second = queue.acquire(&not_cancelled, || {}).await.unwrap();
+
+        // both keep running, the first to finish gives its slot up
+        queue.set_limit(1);
+        assert_eq!(queue.limit.lock().unwrap().debt, 1);
+        drop(first);
+        assert_eq!(queue.slots.available_permits(), 0);
+        drop(second);
+        assert_eq!(queue.slots.available_permits(), 1);
+
+        // raising it again pays the debt back first
+        queue.set_limit(2);
+        let first = queue.acquire(&not_cancelled, || {}).await.unwrap();
+        let second = queue.acquire(&not_cancelled, || {}).await.unwrap();
+        queue.set_limit(1);
+        queue.set_limit(3);
+        assert_eq!(queue.limit.lock().unwrap().debt, 0);
+        assert_eq!(queue.slots.available_permits(), 1);
+        drop(first);
+        drop(second);
+        assert_eq!(queue.slots.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn cancels_a_queued_download() {
+        let queue = DownloadQueue::default();
+        queue.set_limit(1);
+        let not_cancelled = AtomicBool::new(false);
+        let _running = queue.acquire(&not_cancelled, || {}).await.unwrap();
+
+        let cancelled = AtomicBool::new(false);
+        let mut queued = false;
+        let (result, _) = tokio::join!(queue.acquire(&cancelled, || queued = true), async {
+            cancelled.store(true, Ordering::Relaxed)
+        });
+        assert!(queued);
+        assert!(matches!(result, Err(Error::DownloadCancelled)));
+    }
+
+    #[test]
+    fn smooths_download_rate() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        let mut rate = RateEstimator::new(Duration::from_secs(3));
+        rate.record(at(0), 0);
+        assert_eq!(rate.bytes_per_second(), None);
+
+        // 1000 bytes every 100ms
+        for i in 1..=10 {
+            rate.record(at(i * 100), 1000);
+        }
+        assert_eq!(rate.bytes_per_second(), Some(10_000.0));
+
+        // a single slow chunk barely moves the average
+        rate.record(at(2000), 1000);
+        assert_eq!(rate.bytes_per_second(), Some(5_500.0));
+
+        // old chunks leave the window
+        for i in 1..=30 {
+            rate.record(at(2000 + i * 100), 2000);
+        }
+        assert_eq!(rate.samples.len(), 31);
+        assert_eq!(rate.bytes_per_second(), Some(20_000.0));
+    }
+
+    #[tokio::test]
+    async fn restarts_without_range_support() {
+        let (file, progress) = resume(false).await;
+        assert_eq!(file, CONTENT);
+        assert_eq!(progress.last(), Some(&100.0));
+    }
+}
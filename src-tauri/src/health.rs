@@ -0,0 +1,242 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde::Serialize;
+use specta::Type;
+use sysinfo::{DiskExt, System, SystemExt};
+use tauri::{path::BaseDirectory, Manager};
+
+use crate::{chess::engine_handshake, error::Error, opening::opening_count, AppState};
+
+/// Time an engine has to answer the `uci` handshake, short so that the
+/// health check stays quick
+const ENGINE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryHealth {
+    pub total_mb: u32,
+    pub available_mb: u32,
+}
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuHealth {
+    pub threads: u32,
+    pub bmi2: bool,
+    pub avx2: bool,
+    pub popcnt: bool,
+}
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineHealth {
+    pub name: String,
+    pub path: String,
+    pub handshake_ok: bool,
+}
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DataDirHealth {
+    pub path: String,
+    pub writable: bool,
+    pub free_space_mb: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DataHealth {
+    pub openings: u32,
+    pub puzzle_databases: u32,
+    pub fide_players: u32,
+}
+
+#[derive(Debug, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemHealth {
+    pub memory: MemoryHealth,
+    pub cpu: CpuHealth,
+    pub engines: Vec<EngineHealth>,
+    pub data_dir: DataDirHealth,
+    pub sync_server: Option<String>,
+    pub data: DataHealth,
+}
+
+fn to_mb(bytes: u64) -> u32 {
+    (bytes / 1024 / 1024) as u32
+}
+
+fn memory_health() -> MemoryHealth {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    MemoryHealth {
+        total_mb: to_mb(sys.total_memory()),
+        available_mb: to_mb(sys.available_memory()),
+    }
+}
+
+fn cpu_health() -> CpuHealth {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1);
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    let (bmi2, avx2, popcnt) = (
+        is_x86_feature_detected!("bmi2"),
+        is_x86_feature_detected!("avx2"),
+        is_x86_feature_detected!("popcnt"),
+    );
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let (bmi2, avx2, popcnt) = (false, false, false);
+
+    CpuHealth {
+        threads,
+        bmi2,
+        avx2,
+        popcnt,
+    }
+}
+
+fn data_dir_health(path: &Path) -> DataDirHealth {
+    let probe = path.join(".health-check");
+    let writable = File::create(&probe).is_ok() && fs::remove_file(&probe).is_ok();
+
+    // the disk holding the directory is the one with the longest matching mount point
+    let mut sys = System::new();
+    sys.refresh_disks_list();
+    let free_space_mb = sys
+        .disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| to_mb(disk.available_space()));
+
+    DataDirHealth {
+        path: path.to_string_lossy().to_string(),
+        writable,
+        free_space_mb,
+    }
+}
+
+/// Reads the local engines from `engines.json`, as `(name, path)` pairs
+fn registered_engines(file: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(contents) = fs::read_to_string(file) else {
+        return Vec::new();
+    };
+    let Ok(serde_json::Value::Array(engines)) = serde_json::from_str(&contents) else {
+        return Vec::new();
+    };
+    engines
+        .iter()
+        .filter(|engine| engine["type"] == "local")
+        .filter_map(|engine| {
+            let path = engine["path"].as_str()?;
+            let name = engine["name"].as_str().unwrap_or(path);
+            Some((name.to_string(), PathBuf::from(path)))
+        })
+        .collect()
+}
+
+async fn engine_health(name: String, path: PathBuf) -> EngineHealth {
+    let handshake_ok = engine_handshake(path.clone(), ENGINE_CHECK_TIMEOUT)
+        .await
+        .is_ok();
+    EngineHealth {
+        name,
+        path: path.to_string_lossy().to_string(),
+        handshake_ok,
+    }
+}
+
+fn count_files(dir: &Path) -> u32 {
+    fs::read_dir(dir)
+        .map(|entries| entries.flatten().filter(|e| e.path().is_file()).count() as u32)
+        .unwrap_or(0)
+}
+
+/// Collects a snapshot of the state of the backend for the diagnostics screen
+#[tauri::command]
+#[specta::specta]
+pub async fn system_health(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<SystemHealth, Error> {
+    let data_dir = app.path().app_data_dir()?;
+    let engines_file = app
+        .path()
+        .resolve("engines/engines.json", BaseDirectory::AppData)?;
+    let puzzles_dir = app.path().resolve("puzzles", BaseDirectory::AppData)?;
+
+    let sync_server = state
+        .sync_server_addr
+        .lock()
+        .unwrap()
+        .map(|addr| addr.to_string());
+    let fide_players = state.fide_players.read().await.len() as u32;
+
+    let engines = join_all(
+        registered_engines(&engines_file)
+            .into_iter()
+            .map(|(name, path)| engine_health(name, path)),
+    )
+    .await;
+
+    Ok(SystemHealth {
+        memory: memory_health(),
+        cpu: cpu_health(),
+        engines,
+        data_dir: data_dir_health(&data_dir),
+        sync_server,
+        data: DataHealth {
+            openings: opening_count() as u32,
+            puzzle_databases: count_files(&puzzles_dir),
+            fide_players,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_is_populated() {
+        let memory = memory_health();
+        assert!(memory.total_mb > 0);
+        assert!(memory.available_mb <= memory.total_mb);
+
+        assert!(cpu_health().threads >= 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = data_dir_health(dir.path());
+        assert!(data_dir.writable);
+        assert!(!data_dir.path.is_empty());
+        assert!(!dir.path().join(".health-check").exists());
+
+        assert!(opening_count() > 0);
+    }
+
+    #[test]
+    fn reads_local_engines() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("engines.json");
+        fs::write(
+            &file,
+            r#"[
+                {"type": "local", "name": "Stockfish", "path": "/engines/stockfish", "settings": []},
+                {"type": "lichess", "name": "Lichess Cloud"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            registered_engines(&file),
+            vec![("Stockfish".to_string(), PathBuf::from("/engines/stockfish"))]
+        );
+        assert!(registered_engines(&dir.path().join("missing.json")).is_empty());
+    }
+}
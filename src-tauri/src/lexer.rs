@@ -8,7 +8,7 @@ struct Lexer {
     tokens: Vec<Token>,
 }
 
-#[derive(Serialize, Clone, Type)]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Type)]
 #[serde(tag = "type", content = "value")]
 pub enum Token {
     ParenOpen,
@@ -63,9 +63,8 @@ impl Visitor for Lexer {
     }
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn lex_pgn(pgn: String) -> Result<Vec<Token>, Error> {
+/// Tokenizes the first game of a PGN string
+pub fn lex_game(pgn: &str) -> Result<Vec<Token>, Error> {
     let mut reader = BufferedReader::new(pgn.as_bytes());
 
     let mut lexer = Lexer { tokens: Vec::new() };
@@ -74,3 +73,37 @@ pub async fn lex_pgn(pgn: String) -> Result<Vec<Token>, Error> {
 
     Ok(lexer.tokens)
 }
+
+#[tauri::command]
+#[specta::specta]
+pub async fn lex_pgn(pgn: String) -> Result<Vec<Token>, Error> {
+    lex_game(&pgn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lexes_nags() {
+        let tokens = lex_pgn("1. e4! e5?? 2. Nf3 $14 Nc6!? *".to_string())
+            .await
+            .unwrap();
+        let san = |s: &str| Token::San(s.to_string());
+        let nag = |s: &str| Token::Nag(s.to_string());
+        assert_eq!(
+            tokens,
+            vec![
+                san("e4"),
+                nag("$1"),
+                san("e5"),
+                nag("$4"),
+                san("Nf3"),
+                nag("$14"),
+                san("Nc6"),
+                nag("$5"),
+                Token::Outcome("*".to_string()),
+            ]
+        );
+    }
+}
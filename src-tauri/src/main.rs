@@ -7,36 +7,48 @@ use tauri::Emitter;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::{routing::post, Extension, Router, response::IntoResponse};
 use axum::Json;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use rand::{distributions::Alphanumeric, Rng};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use futures::stream::{SplitSink, StreamExt};
-use futures::SinkExt;
-use tokio::sync::Mutex as TokioMutex;
+use futures::{Sink, SinkExt};
+use tokio::sync::{oneshot, Mutex as TokioMutex};
+use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+mod book;
 mod chess;
 mod db;
 mod error;
 mod fide;
 mod fs;
+mod health;
 mod lexer;
 mod oauth;
 mod opening;
 mod pgn;
+mod presets;
 mod puzzle;
+mod tree;
 
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::{fs::create_dir_all, path::Path};
 
-use chess::{BestMovesPayload, EngineProcess, ReportProgress};
+use chess::{BestMovesPayload, EngineCrashed, EngineProcess, GameAnalysisResult, ReportProgress};
 use dashmap::DashMap;
-use db::{DatabaseProgress, GameQueryJs, NormalizedGame, PositionStats};
+use db::{
+    DatabaseProgress, GameDataCache, GameQueryJs, ImportProgress, NormalizedGame, PositionStats,
+};
 use derivative::Derivative;
-use fide::FidePlayer;
+use fide::FideDb;
 use log::LevelFilter;
 use oauth::AuthState;
+use specta::Type;
 use specta_typescript::{BigIntExportBehavior, Typescript};
 use sysinfo::SystemExt;
 use tauri::path::BaseDirectory;
@@ -44,34 +56,133 @@ use tauri::{Manager, Window, AppHandle};
 use tauri_plugin_log::{Target, TargetKind};
 use std::net::SocketAddr;
 
+use crate::book::probe_book;
+use crate::error::Error;
 use crate::chess::{
-    analyze_game, get_engine_config, get_engine_logs, kill_engine, kill_engines, stop_engine,
+    analyze_game, analyze_games, apply_san, cancel_analysis, get_engine_config, get_engine_logs,
+    get_engine_options, get_position_phase, kill_engine, kill_engines, legal_moves,
+    restore_analysis_settings, set_analysis_slot_timeout, set_engine_option,
+    set_max_concurrent_analyses, set_tablebase_path, solve_mate, start_analysis, stop_engine,
+    suggested_hash_mb, test_engine, validate_tablebase_path, AnalysisSlots,
 };
 use crate::db::{
-    clear_games, convert_pgn, create_indexes, delete_database, delete_db_game, delete_empty_games,
-    delete_indexes, export_to_pgn, get_player, get_players_game_info, get_tournaments,
-    search_position,
+    clear_games, convert_pgn, create_indexes, delete_database, delete_db_game, delete_db_games,
+    delete_empty_games, delete_games_matching, delete_indexes, export_games_to_string,
+    export_to_pgn, get_index_status, get_opening_explorer, get_player, get_player_rating_history,
+    get_players_game_info, get_tournaments, import_account_games, merge_databases,
+    optimize_database, search_comments, search_position,
+};
+use crate::fide::{download_fide_db, find_fide_player, find_fide_players, get_fide_player};
+use crate::fs::{
+    cancel_download, set_download_limit, set_file_as_executable, DownloadProgress, DownloadQueue,
 };
-use crate::fide::{download_fide_db, find_fide_player};
-use crate::fs::{set_file_as_executable, DownloadProgress};
+use crate::health::system_health;
 use crate::lexer::lex_pgn;
-use crate::oauth::authenticate;
-use crate::pgn::{count_pgn_games, delete_game, read_games, write_game};
-use crate::puzzle::{get_puzzle, get_puzzle_db_info};
+use crate::oauth::{authenticate, get_access_token};
+use crate::pgn::{count_pgn_games, delete_game, read_games, validate_pgn, write_game, PgnIndex};
+use crate::presets::{
+    delete_engine_preset, list_engine_presets, load_engine_preset, save_engine_preset,
+};
+use crate::puzzle::{get_puzzle, get_puzzle_db_info, record_puzzle_attempt};
+use crate::tree::{read_game_trees, write_game_analysis, write_game_tree};
 use crate::{
     chess::get_best_moves,
     db::{
         delete_duplicated_games, edit_db_info, get_db_info, get_games, get_players, merge_players,
+        position_hash,
     },
     fs::{download_file, file_exists, get_file_metadata},
     opening::{get_opening_from_fen, get_opening_from_name, search_opening_name},
 };
 use tokio::sync::{RwLock, Semaphore};
 
+// How often the server pings WebSocket clients
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// Clients that haven't sent anything for this long are dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+// Board updates of a client arriving this close together are processed once
+const BOARD_UPDATE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+// A connected WebSocket client, generic over the sink so it can be tested
+struct ClientConnection<S = SplitSink<WebSocket, Message>> {
+    sender: S,
+    last_seen: Instant,
+    // Ids of the games the client subscribed to
+    games: HashSet<String>,
+    // Dropped along with the connection, which stops its receive loop
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl<S: Sink<Message> + Unpin> ClientConnection<S> {
+    fn new(sender: S, shutdown: oneshot::Sender<()>) -> Self {
+        Self {
+            sender,
+            last_seen: Instant::now(),
+            games: HashSet::new(),
+            _shutdown: shutdown,
+        }
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), S::Error> {
+        self.sender.send(message).await
+    }
+
+    // Clients that never subscribed, like the app's own board, follow every game
+    fn follows(&self, game_id: &str) -> bool {
+        self.games.is_empty() || self.games.contains(game_id)
+    }
+}
+
+type ClientMap<S = SplitSink<WebSocket, Message>> = HashMap<usize, ClientConnection<S>>;
+
+// Coalesces the board updates of a client, since dragging a piece sends a
+// flood of them, and remembers the last FEN sent for each game
+#[derive(Default)]
+struct BoardUpdates {
+    // The latest update, and when the window of the first one ends
+    pending: Option<(BoardData, Instant)>,
+    last_fens: HashMap<String, String>,
+}
+
+impl BoardUpdates {
+    fn push(&mut self, data: BoardData, now: Instant) {
+        let deadline = self
+            .pending
+            .as_ref()
+            .map_or(now + BOARD_UPDATE_DEBOUNCE, |(_, deadline)| *deadline);
+        self.pending = Some((data, deadline));
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    // The latest update, once its window is over
+    fn take_due(&mut self, now: Instant) -> Option<BoardData> {
+        match self.pending.take() {
+            Some((data, deadline)) if deadline <= now => Some(data),
+            pending => {
+                self.pending = pending;
+                None
+            }
+        }
+    }
+
+    // Whether the FEN changed since the last one sent for its game
+    fn is_new(&mut self, fen_result: &FenResult) -> bool {
+        let last = self.last_fens.get(&fen_result.game_id);
+        if last == Some(&fen_result.fen) {
+            return false;
+        }
+        self.last_fens.insert(fen_result.game_id.clone(), fen_result.fen.clone());
+        true
+    }
+}
+
 // Define a type for the shared client state
-// Using TokioMutex for async locking and HashMap to store client senders
-// Key: Unique client ID, Value: Sender part of the WebSocket
-type Clients = Arc<TokioMutex<HashMap<usize, SplitSink<WebSocket, Message>>>>;
+// Using TokioMutex for async locking and HashMap to store client connections
+// Key: Unique client ID, Value: Sender part of the WebSocket and its liveness
+type Clients = Arc<TokioMutex<ClientMap>>;
 
 // Unique ID generator for clients
 static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
@@ -97,13 +208,26 @@ pub struct AppState {
         diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
     >,
     line_cache: DashMap<(GameQueryJs, PathBuf), (Vec<PositionStats>, Vec<NormalizedGame>)>,
-    db_cache: Mutex<Vec<GameData>>,
+    db_cache: Mutex<GameDataCache>,
+    /// Permits of the position searches, a search stops once another one
+    /// takes the last permit
     #[derivative(Default(value = "Arc::new(Semaphore::new(2))"))]
+    new_search: Arc<Semaphore>,
+    /// Permits of the engine searches, see `analysis_slots`
+    #[derivative(Default(value = "Arc::new(Semaphore::new(chess::ANALYSIS_SLOTS))"))]
     new_request: Arc<Semaphore>,
-    pgn_offsets: DashMap<String, Vec<u64>>,
-    fide_players: RwLock<Vec<FidePlayer>>,
+    analysis_slots: AnalysisSlots,
+    pgn_offsets: DashMap<String, Arc<PgnIndex>>,
+    fide_players: RwLock<FideDb>,
     engine_processes: DashMap<(String, String), Arc<tokio::sync::Mutex<EngineProcess>>>,
+    analysis_cancellations: DashMap<String, Arc<AtomicBool>>,
+    download_cancellations: DashMap<String, Arc<AtomicBool>>,
+    download_queue: DownloadQueue,
     auth: AuthState,
+    sync_server_addr: Mutex<Option<SocketAddr>>,
+    tablebase_path: Mutex<Option<PathBuf>>,
+    #[derivative(Default(value = "generate_sync_token()"))]
+    sync_token: String,
 }
 
 const REQUIRED_DIRS: &[(BaseDirectory, &str)] = &[
@@ -200,15 +324,206 @@ struct WebSocketMessage {
     extra: HashMap<String, Value>,
 }
 
+const DEFAULT_SYNC_PORT: u16 = 3030;
+// Overrides the port of the FEN sync server
+const SYNC_PORT_ENV: &str = "EN_CROISSANT_SYNC_PORT";
+// Holds the port picked in the settings, in the app data directory
+const SYNC_SETTINGS_FILE: &str = "sync.json";
+// Ports tried after the configured one when it is already taken
+const SYNC_PORT_FALLBACKS: u16 = 10;
+
+#[derive(Serialize, Debug, Clone, Type)]
+struct SyncServerStatus {
+    running: bool,
+    port: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct SyncSettings {
+    port: Option<u16>,
+}
+
+fn sync_settings_path(app_handle: &AppHandle) -> Result<PathBuf, Error> {
+    Ok(app_handle
+        .path()
+        .resolve(SYNC_SETTINGS_FILE, BaseDirectory::AppData)?)
+}
+
+fn read_sync_settings(app_handle: &AppHandle) -> SyncSettings {
+    let contents =
+        sync_settings_path(app_handle).and_then(|path| Ok(std::fs::read_to_string(path)?));
+    let Ok(contents) = contents else {
+        return SyncSettings::default();
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        log::error!("[FEN Sync] Invalid {}: {}", SYNC_SETTINGS_FILE, e);
+        SyncSettings::default()
+    })
+}
+
+// The port saved in the settings, unless the environment overrides it
+fn sync_server_port(app_handle: &AppHandle) -> u16 {
+    match std::env::var(SYNC_PORT_ENV) {
+        Ok(value) => parse_sync_port(Some(&value)),
+        Err(_) => configured_sync_port(&read_sync_settings(app_handle)),
+    }
+}
+
+fn configured_sync_port(settings: &SyncSettings) -> u16 {
+    settings.port.filter(|&port| port != 0).unwrap_or(DEFAULT_SYNC_PORT)
+}
+
+fn parse_sync_port(value: Option<&str>) -> u16 {
+    match value.map(|v| v.trim().parse::<u16>()) {
+        Some(Ok(port)) if port != 0 => port,
+        Some(_) => {
+            log::warn!("[FEN Sync] Ignoring invalid {} value {:?}", SYNC_PORT_ENV, value);
+            DEFAULT_SYNC_PORT
+        }
+        None => DEFAULT_SYNC_PORT,
+    }
+}
+
+// Binds the first free port starting from `port`
+fn bind_sync_server(port: u16) -> Option<(SocketAddr, std::net::TcpListener)> {
+    for port in (port..=u16::MAX).take(SYNC_PORT_FALLBACKS as usize + 1) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        match std::net::TcpListener::bind(addr) {
+            Ok(listener) => return Some((addr, listener)),
+            Err(e) => log::warn!("[FEN Sync] Could not bind {}: {}", addr, e),
+        }
+    }
+    log::error!("[FEN Sync] Server failed to start, no free port from {}", port);
+    None
+}
+
+fn sync_server_status(state: &AppState) -> SyncServerStatus {
+    let addr = *state.sync_server_addr.lock().unwrap();
+    SyncServerStatus {
+        running: addr.is_some(),
+        port: addr.map(|addr| addr.port()),
+    }
+}
+
+fn emit_sync_server_status(app_handle: &AppHandle) {
+    let status = sync_server_status(&app_handle.state::<AppState>());
+    if let Err(e) = app_handle.emit("sync-server-status", &status) {
+        log::error!("[FEN Sync] Failed to emit sync-server-status: {}", e);
+    }
+}
+
+// Random token required by the sync server, a new one is made on every launch
+fn generate_sync_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Clone)]
+struct SyncToken(Arc<str>);
+
+// Looks at every byte whatever the input, so that the time a rejection takes
+// doesn't tell how much of the token was right
+fn tokens_match(given: &str, expected: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), expected.as_bytes());
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Only lets through requests with the session token, either as a bearer token
+// or, since browsers can't set headers on WebSockets, as a `token` query parameter
+async fn require_sync_token<B>(
+    Extension(token): Extension<SyncToken>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = request
+        .uri()
+        .query()
+        .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+
+    if bearer.or(query).is_some_and(|given| tokens_match(given, &token.0)) {
+        Ok(next.run(request).await)
+    } else {
+        log::warn!("[FEN Sync] Rejected unauthenticated request to {}", request.uri().path());
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+fn with_sync_auth(router: Router, token: String) -> Router {
+    router
+        .route_layer(middleware::from_fn(require_sync_token))
+        .layer(Extension(SyncToken(token.into())))
+}
+
+// Token the frontend has to send to the sync server. The settings show it too,
+// for the userscript to be given by hand
+#[tauri::command]
+#[specta::specta]
+fn get_sync_token(state: tauri::State<'_, AppState>) -> String {
+    state.sync_token.clone()
+}
+
+#[tauri::command]
+#[specta::specta]
+fn get_sync_server_status(state: tauri::State<'_, AppState>) -> SyncServerStatus {
+    sync_server_status(&state)
+}
+
+// Port the sync server binds the next time the app starts, None going back
+// to the default one
+#[tauri::command]
+#[specta::specta]
+fn set_sync_port(port: Option<u16>, app: AppHandle) -> Result<(), Error> {
+    let settings = SyncSettings { port };
+    std::fs::write(sync_settings_path(&app)?, serde_json::to_string(&settings)?)?;
+    Ok(())
+}
+
+// Port the sync server binds the next time the app starts
+#[tauri::command]
+#[specta::specta]
+fn get_sync_port(app: AppHandle) -> u16 {
+    sync_server_port(&app)
+}
+
+type FenResponse = Result<Json<FenResult>, (StatusCode, Json<FenError>)>;
+
+// The FEN as the body of the response, so that clients that can't listen to
+// the app's events get it too
+fn fen_response(data: &BoardData, result: Result<FenResult, String>) -> FenResponse {
+    result.map(Json).map_err(|error| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(FenError {
+                game_id: data.game_id.clone(),
+                error,
+            }),
+        )
+    })
+}
+
 // Handler for the FEN POST request
 async fn handle_fen(
     Extension(app_handle): Extension<AppHandle>, 
     Json(data): Json<BoardData>
-) {
+) -> FenResponse {
     log::info!("[Backend FEN Handler] Received POST request with board data for game: {}", data.game_id);
     
     // Process the data to generate a FEN
-    let fen = match generate_fen_from_board_data(&data) {
+    let result = generate_fen_from_board_data(&data);
+    match &result {
         Ok(fen_result) => {
             // Emit the FEN update event to the frontend with the full result
             match app_handle.emit("fen-update", &fen_result.fen) {
@@ -221,47 +536,144 @@ async fn handle_fen(
                 Ok(_) => log::debug!("[Backend FEN Handler] Emitted board-state-update event"),
                 Err(e) => log::error!("[Backend FEN Handler] Failed to emit board-state-update: {}", e),
             }
-            
-            fen_result
         },
         Err(e) => {
             log::error!("[Backend FEN Handler] Error processing board data: {}", e);
-            FenResult {
-                fen: "".to_string(),
-                variant: data.variant,
-                game_id: data.game_id,
+            emit_fen_error(&app_handle, &data.game_id, e);
+        }
+    }
+    fen_response(&data, result)
+}
+
+#[derive(Serialize, Debug)]
+struct FenError {
+    game_id: String,
+    error: String,
+}
+
+fn emit_fen_error(app_handle: &AppHandle, game_id: &str, error: &str) {
+    let payload = FenError {
+        game_id: game_id.to_string(),
+        error: error.to_string(),
+    };
+    if let Err(e) = app_handle.emit("fen-error", &payload) {
+        log::error!("Failed to emit fen-error: {}", e);
+    }
+}
+
+// Checks the structure of a FEN: `ranks` ranks of `files` squares, one king
+// per side and well formed side to move, castling, en passant and move counter
+// fields. Runs of empty squares can take more than one digit on boards wider
+// than 9 files.
+fn validate_board_fen(fen: &str, files: usize, ranks: usize) -> Result<(), String> {
+    let fields: Vec<&str> = fen.split(' ').collect();
+    let [board, turn, castling, en_passant, halfmoves, fullmoves] = fields[..] else {
+        return Err(format!("Expected 6 fields in FEN, found {}", fields.len()));
+    };
+
+    let rows: Vec<&str> = board.split('/').collect();
+    if rows.len() != ranks {
+        return Err(format!("Expected {} ranks, found {}", ranks, rows.len()));
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let mut squares = 0;
+        let mut empty = 0;
+        for c in row.chars() {
+            match c {
+                '0'..='9' => empty = empty * 10 + (c as usize - '0' as usize),
+                'p' | 'n' | 'b' | 'r' | 'q' | 'k' | 'P' | 'N' | 'B' | 'R' | 'Q' | 'K' => {
+                    squares += empty + 1;
+                    empty = 0;
+                }
+                _ => return Err(format!("Invalid character '{}' in rank {}", c, ranks - i)),
             }
         }
+        squares += empty;
+        if squares != files {
+            return Err(format!("Rank {} has {} squares", ranks - i, squares));
+        }
+    }
+    for king in ['K', 'k'] {
+        let count = board.chars().filter(|&c| c == king).count();
+        if count != 1 {
+            return Err(format!("Expected one '{}', found {}", king, count));
+        }
+    }
+
+    if turn != "w" && turn != "b" {
+        return Err(format!("Invalid side to move '{}'", turn));
+    }
+
+    // KQkq, or Shredder-FEN files for Chess960
+    let valid_castling = castling == "-"
+        || (castling.chars().all(|c| matches!(c, 'K' | 'Q' | 'k' | 'q' | 'A'..='H' | 'a'..='h'))
+            && castling.len() <= 4
+            && castling.chars().collect::<HashSet<_>>().len() == castling.len());
+    if !valid_castling {
+        return Err(format!("Invalid castling rights '{}'", castling));
+    }
+
+    let valid_en_passant = match en_passant.as_bytes() {
+        b"-" => true,
+        [b'a'..=b'h', rank] => (turn == "w" && *rank == b'6') || (turn == "b" && *rank == b'3'),
+        _ => false,
     };
+    if !valid_en_passant {
+        return Err(format!("Invalid en passant square '{}'", en_passant));
+    }
+
+    if halfmoves.parse::<u32>().is_err() || !fullmoves.parse::<u32>().is_ok_and(|n| n > 0) {
+        return Err(format!("Invalid move counters '{} {}'", halfmoves, fullmoves));
+    }
+    Ok(())
+}
+
+// Splits a square name like "j10" into its file and its row counted from the
+// top of the board, or None if it isn't on a board of this size
+fn parse_square(name: &str, files: usize, ranks: usize) -> Option<(usize, usize)> {
+    let mut chars = name.chars();
+    let file = chars.next().filter(char::is_ascii_lowercase)?;
+    let file = (file as u8 - b'a') as usize;
+    let rank = chars.as_str();
+    if rank.is_empty() || !rank.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let rank: usize = rank.parse().ok()?;
+    if file >= files || rank == 0 || rank > ranks {
+        return None;
+    }
+    Some((file, ranks - rank))
 }
 
 // Function to generate FEN from board data
 fn generate_fen_from_board_data(data: &BoardData) -> Result<FenResult, String> {
-    // Build the 8x8 board representation
-    let mut board = vec![vec!["".to_string(); 8]; 8];
+    // Build the board representation, 8x8 unless the page says otherwise
+    let (files, ranks) = data
+        .board_layout
+        .as_ref()
+        .map_or((8, 8), |layout| (layout.files, layout.ranks));
+    let mut board = vec![vec!["".to_string(); files]; ranks];
     
     // Place pieces on the board
     for (square_name, piece_code) in &data.pieces {
-        if square_name.len() != 2 {
+        let Some((file, rank)) = parse_square(square_name, files, ranks) else {
             continue;
-        }
-        
-        let file = square_name.chars().nth(0).unwrap() as u8 - b'a';
-        let rank = 8 - (square_name.chars().nth(1).unwrap() as u8 - b'0');
+        };
         
-        if file < 8 && rank < 8 {
-            // Convert piece code (e.g., "wK" -> "K", "bP" -> "p")
-            let color = piece_code.chars().nth(0).unwrap();
-            let piece_type = piece_code.chars().nth(1).unwrap();
-            
-            let fen_char = if color == 'w' {
-                piece_type.to_uppercase().to_string()
-            } else {
-                piece_type.to_lowercase().to_string()
-            };
-            
-            board[rank as usize][file as usize] = fen_char;
+        // Convert piece code (e.g., "wK" -> "K", "bP" -> "p")
+        let Some(mut fen_char) = piece_fen_char(piece_code) else {
+            return Err(format!("Invalid piece {} on {}", piece_code, square_name));
+        };
+
+        // The pieces are what the board shows, except for a pawn that has
+        // just promoted and wasn't replaced yet
+        if fen_char.eq_ignore_ascii_case(&'p') && (rank == 0 || rank == ranks - 1) {
+            if let Some(role) = promoted_role(&data.move_list, square_name) {
+                fen_char = if fen_char == 'P' { role } else { role.to_ascii_lowercase() };
+            }
         }
+        
+        board[rank][file] = fen_char.to_string();
     }
     
     // Generate FEN piece placement section
@@ -292,41 +704,20 @@ fn generate_fen_from_board_data(data: &BoardData) -> Result<FenResult, String> {
     // Active color (determined from move list length)
     let active_color = if data.move_list.len() % 2 == 0 { "w" } else { "b" };
     
-    // Determine castling rights more accurately
-    let mut castling_rights = String::new();
-    
-    // Check if the kings and rooks are in their original positions
-    let white_king_on_e1 = data.pieces.get("e1").map_or(false, |p| p == "wK");
-    let black_king_on_e8 = data.pieces.get("e8").map_or(false, |p| p == "bK");
-    
-    // White kingside castling
-    if white_king_on_e1 && data.pieces.get("h1").map_or(false, |p| p == "wR") {
-        castling_rights.push('K');
-    }
-    
-    // White queenside castling
-    if white_king_on_e1 && data.pieces.get("a1").map_or(false, |p| p == "wR") {
-        castling_rights.push('Q');
-    }
-    
-    // Black kingside castling
-    if black_king_on_e8 && data.pieces.get("h8").map_or(false, |p| p == "bR") {
-        castling_rights.push('k');
-    }
-    
-    // Black queenside castling
-    if black_king_on_e8 && data.pieces.get("a8").map_or(false, |p| p == "bR") {
-        castling_rights.push('q');
-    }
-    
+    let castling_rights = if data.variant == "chess960" {
+        chess960_castling_rights(data)
+    } else {
+        standard_castling_rights(data)
+    };
+
     // If no castling rights, use "-"
     let castling = if castling_rights.is_empty() { "-" } else { &castling_rights };
     
     // En passant target square (determined by last move)
     let en_passant = determine_en_passant(data);
     
-    // Halfmove clock (simplified for now)
-    let halfmove_clock = "0";
+    // Halfmove clock (moves since the last capture or pawn move)
+    let halfmove_clock = halfmove_clock(&data.move_list).to_string();
     
     // Fullmove number (derived from move list length)
     let fullmove_number = (data.move_list.len() / 2 + 1).to_string();
@@ -341,6 +732,7 @@ fn generate_fen_from_board_data(data: &BoardData) -> Result<FenResult, String> {
         halfmove_clock,
         fullmove_number
     );
+    validate_board_fen(&fen, files, ranks)?;
     
     Ok(FenResult {
         fen,
@@ -349,6 +741,112 @@ fn generate_fen_from_board_data(data: &BoardData) -> Result<FenResult, String> {
     })
 }
 
+// FEN letter of a piece code like "wK" or "bP", uppercase for white
+fn piece_fen_char(piece_code: &str) -> Option<char> {
+    let mut chars = piece_code.chars();
+    let (color, role) = (chars.next()?, chars.next()?.to_ascii_uppercase());
+    if chars.next().is_some() || !"KQRBNP".contains(role) {
+        return None;
+    }
+    match color {
+        'w' => Some(role),
+        'b' => Some(role.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+// Uppercase letter of the piece the last move promoted to on `square`, if it
+// was a promotion there like "exd8=Q+"
+fn promoted_role(move_list: &[String], square: &str) -> Option<char> {
+    let last_move = move_list.last()?.trim_end_matches(['+', '#']);
+    let (target, piece) = last_move.split_once('=')?;
+    let role = piece.chars().next()?.to_ascii_uppercase();
+    (target.ends_with(square) && "QRBN".contains(role)).then_some(role)
+}
+
+// Castling rights of a standard game, in KQkq notation
+fn standard_castling_rights(data: &BoardData) -> String {
+    let mut castling_rights = String::new();
+    
+    // Check if the kings and rooks are in their original positions
+    let white_king_on_e1 = data.pieces.get("e1").map_or(false, |p| p == "wK");
+    let black_king_on_e8 = data.pieces.get("e8").map_or(false, |p| p == "bK");
+    
+    // White kingside castling
+    if white_king_on_e1 && data.pieces.get("h1").map_or(false, |p| p == "wR") {
+        castling_rights.push('K');
+    }
+    
+    // White queenside castling
+    if white_king_on_e1 && data.pieces.get("a1").map_or(false, |p| p == "wR") {
+        castling_rights.push('Q');
+    }
+    
+    // Black kingside castling
+    if black_king_on_e8 && data.pieces.get("h8").map_or(false, |p| p == "bR") {
+        castling_rights.push('k');
+    }
+    
+    // Black queenside castling
+    if black_king_on_e8 && data.pieces.get("a8").map_or(false, |p| p == "bR") {
+        castling_rights.push('q');
+    }
+
+    castling_rights
+}
+
+// Castling rights of a Chess960 game in Shredder-FEN notation, with the file
+// of each castling rook since kings and rooks can start on any file
+fn chess960_castling_rights(data: &BoardData) -> String {
+    let mut castling_rights = String::new();
+
+    for (color, rank, first_move) in [('w', '1', 0), ('b', '8', 1)] {
+        let king_moved = data
+            .move_list
+            .iter()
+            .skip(first_move)
+            .step_by(2)
+            .any(|m| m.starts_with('K') || m.starts_with('O'));
+        if king_moved {
+            continue;
+        }
+
+        let has_piece = |file: u8, piece: char| {
+            data.pieces.get(&format!("{}{}", file as char, rank)).map_or(false, |p| {
+                p.starts_with(color) && p.ends_with(piece)
+            })
+        };
+        let Some(king_file) = (b'a'..=b'h').find(|&f| has_piece(f, 'K')) else {
+            continue;
+        };
+
+        // the outermost rook on each side of the king
+        let kingside = (king_file + 1..=b'h').rev().find(|&f| has_piece(f, 'R'));
+        let queenside = (b'a'..king_file).find(|&f| has_piece(f, 'R'));
+        for file in [kingside, queenside].into_iter().flatten() {
+            let file = file as char;
+            castling_rights.push(if color == 'w' { file.to_ascii_uppercase() } else { file });
+        }
+    }
+
+    castling_rights
+}
+
+// Count the plies since the last capture or pawn move, based on the SAN move list
+fn halfmove_clock(move_list: &[String]) -> u32 {
+    let mut clock = 0;
+    for san in move_list {
+        let is_capture = san.contains('x');
+        let is_pawn_move = san.chars().next().map_or(false, |c| matches!(c, 'a'..='h'));
+        if is_capture || is_pawn_move {
+            clock = 0;
+        } else {
+            clock += 1;
+        }
+    }
+    clock
+}
+
 // Helper function to determine en passant target square
 fn determine_en_passant(data: &BoardData) -> &str {
     // Default: no en passant 
@@ -387,14 +885,37 @@ async fn handle_socket(socket: WebSocket, app_handle: AppHandle, clients: Client
     }
 
     // Add the sender to the shared state
-    clients.lock().await.insert(my_id, sender);
+    let (shutdown, mut reaped) = oneshot::channel();
+    clients.lock().await.insert(my_id, ClientConnection::new(sender, shutdown));
 
     // Main message loop
-    while let Some(result) = receiver.next().await {
+    let mut updates = BoardUpdates::default();
+    loop {
+        let deadline = updates.deadline();
+        let result = tokio::select! {
+            result = receiver.next() => result,
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                process_board_update(&mut updates, my_id, &app_handle, &clients).await;
+                continue;
+            }
+            _ = &mut reaped => {
+                log::info!("[WebSocket] Client {} dropped by the heartbeat", my_id);
+                break;
+            }
+        };
+        let Some(result) = result else {
+            break;
+        };
+
+        // Any message, including pongs, shows the client is alive
+        if let Some(client) = clients.lock().await.get_mut(&my_id) {
+            client.last_seen = Instant::now();
+        }
+
         match result {
             Ok(msg) => {
                 // Process the received message
-                process_message(msg, my_id, &app_handle, &clients).await;
+                process_message(msg, my_id, &app_handle, &clients, &mut updates).await;
             }
             Err(e) => {
                 log::error!("[WebSocket] Error receiving message from client {}: {}", my_id, e);
@@ -408,8 +929,84 @@ async fn handle_socket(socket: WebSocket, app_handle: AppHandle, clients: Client
     clients.lock().await.remove(&my_id);
 }
 
+// Sends the FEN of the latest board update of a client to the subscribers of
+// its game, unless it is the position sent last. Returns the FEN that was
+// sent, or the game and error of an invalid board, which the client is told about.
+async fn flush_board_update<S: Sink<Message> + Unpin>(
+    updates: &mut BoardUpdates,
+    now: Instant,
+    clients_map: &mut ClientMap<S>,
+    sender_id: usize,
+) -> Option<Result<FenResult, (String, String)>> {
+    let board_data = updates.take_due(now)?;
+    match generate_fen_from_board_data(&board_data) {
+        Ok(fen_result) => {
+            if !updates.is_new(&fen_result) {
+                log::debug!("[WebSocket] Skipping unchanged board from client {}", sender_id);
+                return None;
+            }
+            let broadcast_message = serde_json::json!({
+                "type": "fen_update",
+                "fen": fen_result.fen,
+                "variant": fen_result.variant,
+                "game_id": fen_result.game_id
+            });
+            broadcast_to_clients(
+                clients_map,
+                sender_id,
+                Some(&fen_result.game_id),
+                &broadcast_message.to_string(),
+            )
+            .await;
+            Some(Ok(fen_result))
+        }
+        Err(e) => {
+            log::warn!("[WebSocket] Invalid board update from client {}: {}", sender_id, e);
+            if let Some(sender) = clients_map.get_mut(&sender_id) {
+                let err_msg = serde_json::json!({ "type": "error", "message": e });
+                let _ = sender.send(Message::Text(err_msg.to_string())).await;
+            }
+            Some(Err((board_data.game_id, e)))
+        }
+    }
+}
+
+async fn process_board_update(
+    updates: &mut BoardUpdates,
+    my_id: usize,
+    app_handle: &AppHandle,
+    clients: &Clients,
+) {
+    let mut clients_map = clients.lock().await;
+    let Some(result) = flush_board_update(updates, Instant::now(), &mut clients_map, my_id).await else {
+        return;
+    };
+    drop(clients_map);
+
+    match result {
+        Ok(fen_result) => {
+            // Emit the FEN update event to the frontend
+            if let Err(e) = app_handle.emit("fen-update", &fen_result.fen) {
+                log::error!("[WebSocket] Failed to emit fen-update: {}", e);
+            }
+
+            // Also emit board state update
+            if let Err(e) = app_handle.emit("board-state-update", &fen_result) {
+                log::error!("[WebSocket] Failed to emit board-state-update: {}", e);
+            }
+        }
+        Err((game_id, e)) => emit_fen_error(app_handle, &game_id, &e),
+    }
+}
+
 // Process WebSocket messages with enhanced functionality
-async fn process_message(msg: Message, my_id: usize, app_handle: &AppHandle, clients: &Clients) {
+async fn process_message(
+    msg: Message,
+    my_id: usize,
+    app_handle: &AppHandle,
+    clients: &Clients,
+    updates: &mut BoardUpdates,
+) {
     match msg {
         Message::Text(text) => {
             log::info!("[WebSocket] Client {} sent text message", my_id);
@@ -421,35 +1018,8 @@ async fn process_message(msg: Message, my_id: usize, app_handle: &AppHandle, cli
                             if let Some(board_data) = ws_message.data {
                                 log::info!("[WebSocket] Received board update from client {}", my_id);
                                 
-                                // Process the board data to generate a FEN
-                                if let Ok(fen_result) = generate_fen_from_board_data(&board_data) {
-                                    // Emit the FEN update event to the frontend
-                                    if let Err(e) = app_handle.emit("fen-update", &fen_result.fen) {
-                                        log::error!("[WebSocket] Failed to emit fen-update: {}", e);
-                                    }
-                                    
-                                    // Also emit board state update
-                                    if let Err(e) = app_handle.emit("board-state-update", &fen_result) {
-                                        log::error!("[WebSocket] Failed to emit board-state-update: {}", e);
-                                    }
-                                    
-                                    // Broadcast to other clients
-                                    let broadcast_message = serde_json::json!({
-                                        "type": "fen_update",
-                                        "fen": fen_result.fen,
-                                        "variant": fen_result.variant,
-                                        "game_id": fen_result.game_id
-                                    });
-                                    
-                                    let mut clients_map = clients.lock().await;
-                                    for (&id, sender) in clients_map.iter_mut() {
-                                        if id != my_id { // Don't send back to original sender
-                                            if sender.send(Message::Text(broadcast_message.to_string())).await.is_err() {
-                                                log::warn!("[WebSocket] Failed to broadcast to client {}", id);
-                                            }
-                                        }
-                                    }
-                                }
+                                // Processed once no other update came for a moment
+                                updates.push(board_data, Instant::now());
                             }
                         },
                         "new_game" => {
@@ -459,6 +1029,21 @@ async fn process_message(msg: Message, my_id: usize, app_handle: &AppHandle, cli
                                 log::error!("[WebSocket] Failed to emit new-game event: {}", e);
                             }
                         },
+                        "subscribe" | "unsubscribe" => {
+                            if let Some(game_id) = ws_message.extra.get("gameId").and_then(|v| v.as_str()) {
+                                if let Some(client) = clients.lock().await.get_mut(&my_id) {
+                                    if ws_message.message_type == "subscribe" {
+                                        log::info!("[WebSocket] Client {} subscribed to game {}", my_id, game_id);
+                                        client.games.insert(game_id.to_string());
+                                    } else {
+                                        client.games.remove(game_id);
+                                    }
+                                }
+                            } else if let Some(sender) = clients.lock().await.get_mut(&my_id) {
+                                let err_msg = r#"{"type":"error","message":"Missing gameId"}"#;
+                                let _ = sender.send(Message::Text(err_msg.to_string())).await;
+                            }
+                        },
                         "ping" => {
                             // Respond to ping with pong
                             if let Some(sender) = clients.lock().await.get_mut(&my_id) {
@@ -470,15 +1055,16 @@ async fn process_message(msg: Message, my_id: usize, app_handle: &AppHandle, cli
                         },
                         // ... Add handlers for other message types ...
                         _ => {
+                            let game_id = ws_message.extra.get("gameId").and_then(|v| v.as_str());
                             // Handle existing message types for backward compatibility
                             if let Some(engine_id) = ws_message.extra.get("engineId").and_then(|v| v.as_str()) {
                                 if engine_id == "board_visualization" {
                                     log::info!("[WebSocket] Received legacy analysis message from client {}", my_id);
-                                    broadcast_message(my_id, &text, clients).await;
+                                    broadcast_message(my_id, game_id, &text, clients).await;
                                 }
                             } else if ws_message.extra.get("finalShapes").is_some() {
                                 log::info!("[WebSocket] Received finalShapes message from client {}", my_id);
-                                broadcast_message(my_id, &text, clients).await;
+                                broadcast_message(my_id, game_id, &text, clients).await;
                             } else {
                                 log::warn!("[WebSocket] Unknown message type: {}", ws_message.message_type);
                                 // Send error back to client
@@ -527,17 +1113,58 @@ async fn process_message(msg: Message, my_id: usize, app_handle: &AppHandle, cli
     }
 }
 
-// Helper to broadcast a message to all clients except the sender
-async fn broadcast_message(sender_id: usize, message: &str, clients: &Clients) {
+// Drops the clients that timed out and pings the others, returning the ids
+// of the dropped clients
+async fn heartbeat<S: Sink<Message> + Unpin>(
+    clients: &TokioMutex<ClientMap<S>>,
+    now: Instant,
+) -> Vec<usize> {
     let mut clients_map = clients.lock().await;
-    for (&id, client_sender) in clients_map.iter_mut() {
-        if id != sender_id {
-            log::debug!("[WebSocket] Broadcasting from {} to {}", sender_id, id);
-            if client_sender.send(Message::Text(message.to_string())).await.is_err() {
-                log::warn!("[WebSocket] Failed to broadcast to client {}", id);
-            }
+
+    let dead: Vec<usize> = clients_map
+        .iter()
+        .filter(|(_, client)| now.duration_since(client.last_seen) > CLIENT_TIMEOUT)
+        .map(|(&id, _)| id)
+        .collect();
+    for id in &dead {
+        if let Some(mut client) = clients_map.remove(id) {
+            log::info!("[WebSocket] Client {} timed out", id);
+            let _ = client.send(Message::Close(None)).await;
+        }
+    }
+
+    for (&id, client) in clients_map.iter_mut() {
+        if client.send(Message::Ping(Vec::new())).await.is_err() {
+            log::warn!("[WebSocket] Failed to ping client {}", id);
+        }
+    }
+    dead
+}
+
+// Sends a message to every other client. A message that belongs to a game
+// isn't sent to the clients subscribed to other games only, clients that never
+// subscribed get every game
+async fn broadcast_to_clients<S: Sink<Message> + Unpin>(
+    clients_map: &mut ClientMap<S>,
+    sender_id: usize,
+    game_id: Option<&str>,
+    message: &str,
+) {
+    for (&id, client) in clients_map.iter_mut() {
+        if id == sender_id || game_id.is_some_and(|game_id| !client.follows(game_id)) {
+            continue;
+        }
+        log::debug!("[WebSocket] Broadcasting from {} to {}", sender_id, id);
+        if client.send(Message::Text(message.to_string())).await.is_err() {
+            log::warn!("[WebSocket] Failed to broadcast to client {}", id);
         }
     }
+}
+
+// Helper to broadcast a message to all clients except the sender
+async fn broadcast_message(sender_id: usize, game_id: Option<&str>, message: &str, clients: &Clients) {
+    let mut clients_map = clients.lock().await;
+    broadcast_to_clients(&mut clients_map, sender_id, game_id, message).await;
     
     // Send confirmation to sender
     if let Some(sender) = clients_map.get_mut(&sender_id) {
@@ -553,55 +1180,105 @@ fn main() {
         .commands(tauri_specta::collect_commands!(
             close_splashscreen,
             find_fide_player,
+            find_fide_players,
+            get_fide_player,
             get_best_moves,
+            start_analysis,
             analyze_game,
+            analyze_games,
+            cancel_analysis,
             stop_engine,
+            set_engine_option,
             kill_engine,
             kill_engines,
             get_engine_logs,
+            get_engine_options,
             memory_size,
+            suggested_hash_mb,
+            system_health,
+            get_sync_server_status,
+            get_sync_token,
+            set_sync_port,
+            get_sync_port,
             get_puzzle,
+            record_puzzle_attempt,
             search_opening_name,
             get_opening_from_fen,
             get_opening_from_name,
             get_players_game_info,
+            get_index_status,
+            merge_databases,
+            get_player_rating_history,
             get_engine_config,
+            test_engine,
+            save_engine_preset,
+            load_engine_preset,
+            list_engine_presets,
+            delete_engine_preset,
+            get_position_phase,
+            apply_san,
+            legal_moves,
+            probe_book,
+            set_analysis_slot_timeout,
+            set_max_concurrent_analyses,
+            solve_mate,
+            validate_tablebase_path,
+            set_tablebase_path,
             file_exists,
             get_file_metadata,
             merge_players,
             convert_pgn,
+            import_account_games,
             get_player,
             count_pgn_games,
             read_games,
+            validate_pgn,
             lex_pgn,
             is_bmi2_compatible,
+            select_engine_binary,
             delete_game,
             delete_duplicated_games,
             delete_empty_games,
+            optimize_database,
+            search_comments,
             clear_games,
             set_file_as_executable,
             delete_indexes,
             create_indexes,
             edit_db_info,
             delete_db_game,
+            delete_db_games,
+            delete_games_matching,
             delete_database,
             export_to_pgn,
+            export_games_to_string,
             authenticate,
+            get_access_token,
             write_game,
+            read_game_trees,
+            write_game_tree,
+            write_game_analysis,
             download_fide_db,
             download_file,
+            cancel_download,
+            set_download_limit,
             get_tournaments,
             get_db_info,
             get_games,
             search_position,
+            get_opening_explorer,
+            position_hash,
             get_players,
             get_puzzle_db_info
         ))
         .events(tauri_specta::collect_events!(
             BestMovesPayload,
+            EngineCrashed,
             DatabaseProgress,
+            ImportProgress,
             DownloadProgress,
-            ReportProgress
+            ReportProgress,
+            GameAnalysisResult
         ));
 
     #[cfg(debug_assertions)]
@@ -645,30 +1322,47 @@ fn main() {
             // --- Initialize WebSocket Shared State ---
             let clients_state: Clients = Arc::new(TokioMutex::new(HashMap::new()));
 
+            // --- Start WebSocket Heartbeat ---
+            let heartbeat_clients = clients_state.clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    heartbeat(&heartbeat_clients, Instant::now()).await;
+                }
+            });
+
             // --- Start FEN Sync Server --- 
             tauri::async_runtime::spawn(async move {
+                let sync_token = app_handle.state::<AppState>().sync_token.clone();
                 let fen_sync_router = Router::new()
                     .route("/fen", post(handle_fen))
                     .route("/ws", axum::routing::get(websocket_handler)) // Use axum's built-in WebSocket handler
                     .layer(Extension(app_handle.clone())) // Provide cloned AppHandle
                     .layer(Extension(clients_state.clone())); // Provide shared client state
+                let fen_sync_router = with_sync_auth(fen_sync_router, sync_token);
 
-                let addr_str = "127.0.0.1:3030";
-                let addr: SocketAddr = match addr_str.parse() {
-                    Ok(addr) => addr,
+                let Some((addr, listener)) = bind_sync_server(sync_server_port(&app_handle)) else {
+                    emit_sync_server_status(&app_handle);
+                    return;
+                };
+                let server = match axum::Server::from_tcp(listener) {
+                    Ok(server) => server,
                     Err(e) => {
-                        log::error!("[FEN Sync] Failed to parse address '{}': {}", addr_str, e);
+                        log::error!("[FEN Sync] Server failed to start: {}", e);
+                        emit_sync_server_status(&app_handle);
                         return;
                     }
                 };
 
-                log::info!("[FEN Sync] Starting server on {}", addr);
-                if let Err(e) = axum::Server::bind(&addr)
-                    .serve(fen_sync_router.into_make_service())
-                    .await
-                {
-                    log::error!("[FEN Sync] Server failed to start: {}", e);
+                log::info!("[FEN Sync] Server listening on {}", addr);
+                *app_handle.state::<AppState>().sync_server_addr.lock().unwrap() = Some(addr);
+                emit_sync_server_status(&app_handle);
+                if let Err(e) = server.serve(fen_sync_router.into_make_service()).await {
+                    log::error!("[FEN Sync] Server failed: {}", e);
                 }
+                *app_handle.state::<AppState>().sync_server_addr.lock().unwrap() = None;
+                emit_sync_server_status(&app_handle);
             });
             // --- End FEN Sync Server ---
 
@@ -692,6 +1386,8 @@ fn main() {
                 }
             }
 
+            restore_analysis_settings(app.handle(), &app.state::<AppState>());
+
             // #[cfg(any(windows, target_os = "macos"))]
             // set_shadow(&app.get_webview_window("main").unwrap(), true).unwrap();
 
@@ -723,9 +1419,474 @@ fn is_bmi2_compatible() -> bool {
     false
 }
 
+/// Instruction sets engine binaries are built for, from the oldest to the
+/// newest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EngineBuild {
+    Generic,
+    Popcnt,
+    Avx2,
+    Bmi2,
+}
+
+impl EngineBuild {
+    /// Reads the build from the name of a binary, like
+    /// `stockfish-windows-x86-64-avx2.exe`. Builds for instruction sets that
+    /// aren't detected, like AVX-512, are `None`.
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.contains("avx512") || name.contains("vnni") {
+            None
+        } else if name.contains("bmi2") {
+            Some(EngineBuild::Bmi2)
+        } else if name.contains("avx2") {
+            Some(EngineBuild::Avx2)
+        } else if name.contains("sse4") || name.contains("popcnt") || name.contains("modern") {
+            Some(EngineBuild::Popcnt)
+        } else {
+            Some(EngineBuild::Generic)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CpuFeatures {
+    sse41: bool,
+    popcnt: bool,
+    avx2: bool,
+    bmi2: bool,
+}
+
+impl CpuFeatures {
+    fn detect() -> Self {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            Self {
+                sse41: is_x86_feature_detected!("sse4.1"),
+                popcnt: is_x86_feature_detected!("popcnt"),
+                avx2: is_x86_feature_detected!("avx2"),
+                bmi2: is_bmi2_compatible(),
+            }
+        }
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            Self::default()
+        }
+    }
+
+    fn supports(&self, build: EngineBuild) -> bool {
+        match build {
+            EngineBuild::Generic => true,
+            EngineBuild::Popcnt => self.sse41 && self.popcnt,
+            EngineBuild::Avx2 => self.avx2 && self.popcnt,
+            EngineBuild::Bmi2 => self.bmi2 && self.avx2 && self.popcnt,
+        }
+    }
+}
+
+/// The most optimized of `variants` the CPU can run
+fn best_engine_binary(variants: &[String], cpu: CpuFeatures) -> Option<String> {
+    variants
+        .iter()
+        .filter_map(|variant| Some((EngineBuild::from_name(variant)?, variant)))
+        .filter(|(build, _)| cpu.supports(*build))
+        .max_by_key(|(build, _)| *build)
+        .map(|(_, variant)| variant.clone())
+}
+
+/// Picks the binary to use among the builds of an engine, so that older
+/// CPUs don't get one with instructions they don't have
+#[tauri::command]
+#[specta::specta]
+fn select_engine_binary(available_variants: Vec<String>) -> Option<String> {
+    best_engine_binary(&available_variants, CpuFeatures::detect())
+}
+
 #[tauri::command]
 #[specta::specta]
 fn memory_size() -> u32 {
     let total_bytes = sysinfo::System::new_all().total_memory();
     (total_bytes / 1024 / 1024) as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_data(pieces: &[(&str, &str)], move_list: &[&str]) -> BoardData {
+        BoardData {
+            game_id: "test".to_string(),
+            pieces: pieces
+                .iter()
+                .map(|(square, piece)| (square.to_string(), piece.to_string()))
+                .collect(),
+            move_list: move_list.iter().map(|m| m.to_string()).collect(),
+            raw_move_text: None,
+            variant: "standard".to_string(),
+            flags: BoardFlags {
+                possible_castling: true,
+                possible_en_passant: false,
+                board_flipped: false,
+            },
+            board_orientation: "white".to_string(),
+            board_layout: None,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn halfmove_clock_reversible_moves() {
+        let moves = [
+            "Nf3", "Nf6", "Ng1", "Ng8", "Nf3", "Nf6", "Ng1", "Ng8", "Nc3", "Nc6",
+        ];
+        let data = board_data(&[("e1", "wK"), ("e8", "bK")], &moves);
+        let fen = generate_fen_from_board_data(&data).unwrap().fen;
+        assert_eq!(fen.split(' ').nth(4), Some("10"));
+        assert_eq!(fen.split(' ').nth(5), Some("6"));
+    }
+
+    #[test]
+    fn halfmove_clock_resets() {
+        let moves = ["e4", "Nf6", "Nc3", "Nxe4", "Nxe4", "Nc6"];
+        assert_eq!(halfmove_clock(&moves.map(String::from)), 1);
+        assert_eq!(halfmove_clock(&["Nf3".to_string(), "d5".to_string()]), 0);
+    }
+
+    #[test]
+    fn sync_port_from_env() {
+        assert_eq!(parse_sync_port(None), DEFAULT_SYNC_PORT);
+        assert_eq!(parse_sync_port(Some("4040")), 4040);
+        assert_eq!(parse_sync_port(Some("0")), DEFAULT_SYNC_PORT);
+        assert_eq!(parse_sync_port(Some("not a port")), DEFAULT_SYNC_PORT);
+    }
+
+    #[test]
+    fn sync_port_from_settings() {
+        let settings: SyncSettings = serde_json::from_str(r#"{"port":4040}"#).unwrap();
+        assert_eq!(configured_sync_port(&settings), 4040);
+        assert_eq!(configured_sync_port(&SyncSettings { port: Some(0) }), DEFAULT_SYNC_PORT);
+        assert_eq!(configured_sync_port(&SyncSettings::default()), DEFAULT_SYNC_PORT);
+    }
+
+    #[test]
+    fn sync_server_falls_back_to_next_port() {
+        let (taken, _listener) = bind_sync_server(39030).unwrap();
+        let (addr, _second) = bind_sync_server(taken.port()).unwrap();
+        assert!(addr.port() > taken.port());
+        assert!(addr.port() <= taken.port() + SYNC_PORT_FALLBACKS);
+    }
+
+    #[tokio::test]
+    async fn sync_server_requires_token() {
+        let router = Router::new().route("/fen", post(|| async { "ok" }));
+        let app = with_sync_auth(router, "secret".to_string());
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(app.into_make_service());
+        let url = format!("http://{}/fen", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let status = |req: reqwest::RequestBuilder| async move {
+            req.send().await.unwrap().status()
+        };
+        assert_eq!(status(client.post(&url)).await, reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(client.post(&url).bearer_auth("wrong")).await,
+            reqwest::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(client.post(&url).bearer_auth("secret")).await, reqwest::StatusCode::OK);
+        assert_eq!(
+            status(client.post(format!("{}?token=secret", url))).await,
+            reqwest::StatusCode::OK
+        );
+        assert_ne!(generate_sync_token(), generate_sync_token());
+    }
+
+    #[test]
+    fn compares_whole_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("", "secret"));
+    }
+
+    #[tokio::test]
+    async fn fen_endpoint_returns_the_fen() {
+        // handle_fen without the events, which need a running app
+        let router = Router::new().route(
+            "/fen",
+            post(|Json(data): Json<BoardData>| async move {
+                let result = generate_fen_from_board_data(&data);
+                fen_response(&data, result)
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(router.into_make_service());
+        let url = format!("http://{}/fen", server.local_addr());
+        tokio::spawn(server);
+
+        let board = |pieces: Value| {
+            serde_json::json!({
+                "gameId": "abc",
+                "pieces": pieces,
+                "moveList": ["e4"],
+                "variant": "standard",
+                "flags": {
+                    "possibleCastling": true,
+                    "possibleEnPassant": true,
+                    "boardFlipped": false
+                },
+                "boardOrientation": "white",
+                "timestamp": 0
+            })
+        };
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(&url)
+            .json(&board(serde_json::json!({"e1": "wK", "e4": "wP", "e8": "bK"})))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["fen"], "4k3/8/8/8/4P3/8/8/4K3 b - - 0 1");
+        assert_eq!(body["game_id"], "abc");
+
+        let response = client
+            .post(&url)
+            .json(&board(serde_json::json!({"e1": "wK"})))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["game_id"], "abc");
+        assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn heartbeat_drops_unresponsive_clients() {
+        use futures::channel::mpsc;
+
+        let clients: TokioMutex<ClientMap<mpsc::UnboundedSender<Message>>> =
+            TokioMutex::new(HashMap::new());
+        let (dead_tx, mut dead_rx) = mpsc::unbounded();
+        let (alive_tx, mut alive_rx) = mpsc::unbounded();
+        let (dead_shutdown, mut dead_reaped) = oneshot::channel();
+        let (alive_shutdown, mut alive_reaped) = oneshot::channel();
+
+        let now = Instant::now() + CLIENT_TIMEOUT + Duration::from_secs(1);
+        let mut alive = ClientConnection::new(alive_tx, alive_shutdown);
+        // the other client never answered since it connected
+        alive.last_seen = now;
+        clients.lock().await.insert(1, ClientConnection::new(dead_tx, dead_shutdown));
+        clients.lock().await.insert(2, alive);
+
+        assert_eq!(heartbeat(&clients, now).await, vec![1]);
+        assert!(clients.lock().await.contains_key(&2));
+        assert!(!clients.lock().await.contains_key(&1));
+
+        assert!(matches!(dead_rx.try_next(), Ok(Some(Message::Close(None)))));
+        assert!(matches!(dead_reaped.try_recv(), Err(oneshot::error::TryRecvError::Closed)));
+        assert!(matches!(alive_rx.try_next(), Ok(Some(Message::Ping(_)))));
+        assert!(matches!(alive_reaped.try_recv(), Err(oneshot::error::TryRecvError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_games_to_their_subscribers() {
+        use futures::channel::mpsc;
+
+        let mut clients: ClientMap<mpsc::UnboundedSender<Message>> = HashMap::new();
+        let mut receivers = Vec::new();
+        for (id, game) in [(1, None), (2, Some("A")), (3, Some("B")), (4, None)] {
+            let (tx, rx) = mpsc::unbounded();
+            let mut client = ClientConnection::new(tx, oneshot::channel().0);
+            client.games.extend(game.map(String::from));
+            clients.insert(id, client);
+            receivers.push(rx);
+        }
+
+        broadcast_to_clients(&mut clients, 1, Some("A"), "board A").await;
+        assert!(matches!(receivers[1].try_next(), Ok(Some(Message::Text(t))) if t == "board A"));
+        assert!(receivers[2].try_next().is_err());
+        assert!(receivers[0].try_next().is_err());
+        // like the board, which never subscribes
+        assert!(matches!(receivers[3].try_next(), Ok(Some(Message::Text(t))) if t == "board A"));
+
+        // messages without a game still reach everyone else
+        broadcast_to_clients(&mut clients, 1, None, "shapes").await;
+        assert!(receivers[1].try_next().is_ok());
+        assert!(receivers[2].try_next().is_ok());
+        assert!(receivers[3].try_next().is_ok());
+        assert!(receivers[0].try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn rapid_board_updates_are_broadcast_once() {
+        use futures::channel::mpsc;
+
+        let mut clients: ClientMap<mpsc::UnboundedSender<Message>> = HashMap::new();
+        let (tx, mut rx) = mpsc::unbounded();
+        let mut subscriber = ClientConnection::new(tx, oneshot::channel().0);
+        subscriber.games.insert("test".to_string());
+        clients.insert(2, subscriber);
+
+        // a piece dragged from e2 to e4 through e3
+        let start = Instant::now();
+        let mut updates = BoardUpdates::default();
+        for (i, square) in ["e2", "e3", "e4"].into_iter().enumerate() {
+            let data = board_data(&[("e1", "wK"), ("e8", "bK"), (square, "wP")], &[]);
+            updates.push(data, start + Duration::from_millis(10 * i as u64));
+        }
+
+        let early = start + Duration::from_millis(30);
+        assert!(flush_board_update(&mut updates, early, &mut clients, 1).await.is_none());
+        assert!(rx.try_next().is_err());
+
+        let later = start + BOARD_UPDATE_DEBOUNCE;
+        let sent = flush_board_update(&mut updates, later, &mut clients, 1).await;
+        assert!(matches!(sent, Some(Ok(fen)) if fen.fen.starts_with("4k3/8/8/8/4P3/")));
+        assert!(matches!(rx.try_next(), Ok(Some(Message::Text(_)))));
+        assert!(rx.try_next().is_err());
+        assert!(updates.deadline().is_none());
+
+        // the same position again isn't sent
+        let data = board_data(&[("e1", "wK"), ("e8", "bK"), ("e4", "wP")], &[]);
+        updates.push(data, later);
+        let again = later + BOARD_UPDATE_DEBOUNCE;
+        assert!(flush_board_update(&mut updates, again, &mut clients, 1).await.is_none());
+        assert!(rx.try_next().is_err());
+    }
+
+    #[test]
+    fn validates_fens() {
+        let validate_fen = |fen: &str| validate_board_fen(fen, 8, 8);
+        assert!(validate_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").is_ok());
+        assert!(validate_fen("bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1").is_ok());
+
+        // missing the black king
+        assert!(validate_fen("8/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        // a rank with nine squares, and one with an unknown piece
+        assert!(validate_fen("rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_err());
+        assert!(validate_fen("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/8/4K3 w - - 0 1").is_err());
+
+        assert!(validate_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/8/8/4K3 w KK - 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/8/8/4K3 w - e4 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 0").is_err());
+        assert!(validate_fen("").is_err());
+    }
+
+    #[test]
+    fn board_without_kings_is_rejected() {
+        let data = board_data(&[("e1", "wK")], &[]);
+        assert!(generate_fen_from_board_data(&data).is_err());
+    }
+
+    #[test]
+    fn chess960_start_position_round_trip() {
+        use shakmaty::{fen::Fen, CastlingMode, Chess, Position, Square};
+
+        let back_rank = ["wB", "wB", "wQ", "wN", "wN", "wR", "wK", "wR"];
+        let mut pieces = Vec::new();
+        for (file, piece) in ('a'..='h').zip(back_rank) {
+            pieces.push((format!("{}1", file), piece.to_string()));
+            pieces.push((format!("{}2", file), "wP".to_string()));
+            pieces.push((format!("{}7", file), "bP".to_string()));
+            pieces.push((format!("{}8", file), piece.replace('w', "b")));
+        }
+        let pieces: Vec<(&str, &str)> =
+            pieces.iter().map(|(s, p)| (s.as_str(), p.as_str())).collect();
+        let mut data = board_data(&pieces, &[]);
+        data.variant = "chess960".to_string();
+
+        let fen = generate_fen_from_board_data(&data).unwrap().fen;
+        assert_eq!(fen, "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1");
+
+        let position: Chess = Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Chess960)
+            .unwrap();
+        let rooks = position.castles().castling_rights();
+        for square in [Square::F1, Square::H1, Square::F8, Square::H8] {
+            assert!(rooks.contains(square));
+        }
+        assert_eq!(rooks.count(), 4);
+
+        // the king moving loses both rights
+        let data = board_data(&pieces, &["Kg2"]);
+        assert_eq!(chess960_castling_rights(&data), "hf");
+    }
+
+    #[test]
+    fn squares_of_larger_boards() {
+        assert_eq!(parse_square("j10", 10, 10), Some((9, 0)));
+        assert_eq!(parse_square("a1", 10, 10), Some((0, 9)));
+        assert_eq!(parse_square("e4", 8, 8), Some((4, 4)));
+        assert_eq!(parse_square("k1", 10, 10), None);
+        assert_eq!(parse_square("j10", 8, 8), None);
+        assert_eq!(parse_square("a0", 8, 8), None);
+        assert_eq!(parse_square("a+1", 8, 8), None);
+
+        let mut data = board_data(&[("e1", "wK"), ("j10", "bK")], &[]);
+        data.board_layout = Some(BoardLayout {
+            files: 10,
+            ranks: 10,
+            squares: Vec::new(),
+        });
+        let fen = generate_fen_from_board_data(&data).unwrap().fen;
+        assert_eq!(fen, "9k/10/10/10/10/10/10/10/10/4K5 w - - 0 1");
+    }
+
+    #[test]
+    fn picks_the_engine_build_the_cpu_runs() {
+        let variants: Vec<String> = [
+            "stockfish-ubuntu-x86-64",
+            "stockfish-ubuntu-x86-64-sse41-popcnt",
+            "stockfish-ubuntu-x86-64-avx2",
+            "stockfish-ubuntu-x86-64-bmi2",
+            "stockfish-ubuntu-x86-64-avx512",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        let best = |cpu: CpuFeatures| best_engine_binary(&variants, cpu);
+
+        let old = CpuFeatures::default();
+        assert_eq!(best(old).as_deref(), Some("stockfish-ubuntu-x86-64"));
+        let sse = CpuFeatures {
+            sse41: true,
+            popcnt: true,
+            ..old
+        };
+        assert_eq!(
+            best(sse).as_deref(),
+            Some("stockfish-ubuntu-x86-64-sse41-popcnt")
+        );
+        let avx2 = CpuFeatures { avx2: true, ..sse };
+        assert_eq!(best(avx2).as_deref(), Some("stockfish-ubuntu-x86-64-avx2"));
+        let bmi2 = CpuFeatures { bmi2: true, ..avx2 };
+        assert_eq!(best(bmi2).as_deref(), Some("stockfish-ubuntu-x86-64-bmi2"));
+
+        assert_eq!(best_engine_binary(&variants[3..], old), None);
+    }
+
+    #[test]
+    fn promoted_pieces_come_from_the_board() {
+        let data = board_data(&[("e1", "wK"), ("a8", "bK"), ("e8", "wQ")], &["e4", "d5", "e8=Q"]);
+        let fen = generate_fen_from_board_data(&data).unwrap().fen;
+        assert_eq!(fen, "k3Q3/8/8/8/8/8/8/4K3 b - - 0 2");
+
+        // a pawn left on the last rank is the piece it promoted to
+        let data = board_data(&[("e1", "wK"), ("a8", "bK"), ("d8", "wP")], &["exd8=N+"]);
+        let fen = generate_fen_from_board_data(&data).unwrap().fen;
+        assert_eq!(fen, "k2N4/8/8/8/8/8/8/4K3 b - - 0 1");
+
+        for piece in ["wX", "w", "", "xQ", "wQQ"] {
+            let data = board_data(&[("e1", "wK"), ("a8", "bK"), ("e4", piece)], &[]);
+            assert!(generate_fen_from_board_data(&data).is_err());
+        }
+    }
+}
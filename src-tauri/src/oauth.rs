@@ -1,13 +1,16 @@
 use axum::{extract::Query, response::IntoResponse, routing::get, Extension, Router};
+use chrono::Utc;
 use log::info;
 use oauth2::{
-    basic::BasicClient, reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId,
-    CsrfToken, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+    basic::{BasicClient, BasicTokenResponse},
+    reqwest::async_http_client,
+    AuthUrl, AuthorizationCode, ClientId, CsrfToken, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, RefreshToken, Scope, TokenResponse, TokenUrl,
 };
 use serde::Deserialize;
 use std::{
     net::{SocketAddr, TcpListener},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use tauri::{Emitter, Manager};
 use tauri_plugin_shell::ShellExt;
@@ -31,12 +34,42 @@ fn get_available_addr() -> SocketAddr {
     addr
 }
 
+/// Refresh tokens this long before they expire, so that they don't expire
+/// in the middle of a request
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp, in seconds. Tokens without one never expire.
+    pub expires_at: Option<i64>,
+}
+
+impl StoredToken {
+    fn from_response(token: &BasicTokenResponse, now: i64) -> Self {
+        Self {
+            access_token: token.access_token().secret().clone(),
+            refresh_token: token.refresh_token().map(|t| t.secret().clone()),
+            expires_at: token
+                .expires_in()
+                .map(|expires_in| now + expires_in.as_secs() as i64),
+        }
+    }
+
+    fn needs_refresh(&self, now: i64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| now + EXPIRY_MARGIN_SECS >= expires_at)
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthState {
     pub csrf_token: CsrfToken,
     pub pkce: Arc<(PkceCodeChallenge, String)>,
     pub client: Arc<BasicClient>,
     pub socket_addr: SocketAddr,
+    pub token: Arc<Mutex<Option<StoredToken>>>,
 }
 
 impl Default for AuthState {
@@ -52,10 +85,66 @@ impl Default for AuthState {
             )),
             client: Arc::new(create_client(RedirectUrl::new(redirect_url).unwrap())),
             socket_addr,
+            token: Arc::new(Mutex::new(None)),
         }
     }
 }
 
+/// Returns a valid access token, using the refresh token first if the
+/// current one is about to expire. `now` gives the current unix time.
+pub async fn refresh_token_if_needed(
+    auth: &AuthState,
+    app: &tauri::AppHandle,
+    now: impl Fn() -> i64,
+) -> Result<String, Error> {
+    let current = auth.token.lock().unwrap().clone();
+    let Some(current) = current else {
+        return Err(Error::NotAuthenticated);
+    };
+    if !current.needs_refresh(now()) {
+        return Ok(current.access_token);
+    }
+
+    let result = match &current.refresh_token {
+        Some(refresh_token) => auth
+            .client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.clone()))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| Error::TokenRefresh(e.to_string())),
+        None => Err(Error::TokenRefresh("the token has expired".to_string())),
+    };
+
+    match result {
+        Ok(token) => {
+            let mut refreshed = StoredToken::from_response(&token, now());
+            // the same refresh token can be used again if no new one is given
+            if refreshed.refresh_token.is_none() {
+                refreshed.refresh_token = current.refresh_token;
+            }
+            let access_token = refreshed.access_token.clone();
+            *auth.token.lock().unwrap() = Some(refreshed);
+            app.emit("access_token", &access_token)?;
+            Ok(access_token)
+        }
+        Err(e) => {
+            *auth.token.lock().unwrap() = None;
+            app.emit("token_refresh_failed", e.to_string())?;
+            Err(e)
+        }
+    }
+}
+
+/// The access token to use for a request to Lichess, refreshed if needed
+#[tauri::command]
+#[specta::specta]
+pub async fn get_access_token(
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<String, Error> {
+    refresh_token_if_needed(&state.auth, &app, || Utc::now().timestamp()).await
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn authenticate(
@@ -104,6 +193,7 @@ async fn authorize(
 
     let access_token = token.access_token().secret();
     app.emit("access_token", access_token).unwrap();
+    *auth.token.lock().unwrap() = Some(StoredToken::from_response(&token, Utc::now().timestamp()));
 
     "authorized".to_string()
 }
@@ -119,3 +209,27 @@ async fn run_server(handle: tauri::AppHandle) -> Result<(), axum::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_refreshed_before_they_expire() {
+        let token = StoredToken {
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: Some(1_000),
+        };
+        assert!(!token.needs_refresh(0));
+        assert!(!token.needs_refresh(1_000 - EXPIRY_MARGIN_SECS - 1));
+        assert!(token.needs_refresh(1_000 - EXPIRY_MARGIN_SECS));
+        assert!(token.needs_refresh(2_000));
+
+        let token = StoredToken {
+            expires_at: None,
+            ..token
+        };
+        assert!(!token.needs_refresh(i64::MAX - EXPIRY_MARGIN_SECS));
+    }
+}
@@ -1,3 +1,5 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
 use log::info;
 use serde::{Deserialize, Serialize};
 use shakmaty::{fen::Fen, san::San, Chess, EnPassantMode, Position, Setup};
@@ -45,6 +47,11 @@ struct FischerRandomRecord {
     fen: String,
 }
 
+/// Number of known openings
+pub fn opening_count() -> usize {
+    OPENINGS.len()
+}
+
 #[tauri::command]
 #[specta::specta]
 pub fn get_opening_from_fen(fen: &str) -> Result<String, Error> {
@@ -70,6 +77,57 @@ pub fn get_opening_from_setup(setup: Setup) -> Result<String, Error> {
         .ok_or_else(|| Error::NoOpeningFound)
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct OpeningClassification {
+    pub eco: String,
+    pub name: String,
+}
+
+/// Openings are matched on the position only, so that transpositions and
+/// different halfmove clocks reach the same opening
+fn opening_key(mut setup: Setup) -> Setup {
+    setup.halfmoves = 0;
+    setup.fullmoves = NonZeroU32::MIN;
+    setup
+}
+
+/// Returns the named opening of a position, if it is one
+pub fn classify_position(setup: Setup) -> Option<OpeningClassification> {
+    OPENING_INDEX
+        .get(&opening_key(setup))
+        .map(|&i| OpeningClassification {
+            eco: OPENINGS[i].eco.clone(),
+            name: OPENINGS[i].name.clone(),
+        })
+}
+
+/// No opening of the table is longer than this, so later positions don't
+/// need to be looked up
+pub fn max_opening_plies() -> usize {
+    *MAX_OPENING_PLIES
+}
+
+/// Classifies a game from the starting position by the deepest of its
+/// positions that is a known opening
+pub fn classify_game_opening(moves: &[String]) -> Option<OpeningClassification> {
+    let mut pos = Chess::default();
+    let mut opening = None;
+    for san in moves.iter().take(max_opening_plies()) {
+        let Some(m) = san
+            .parse::<San>()
+            .ok()
+            .and_then(|san| san.to_move(&pos).ok())
+        else {
+            break;
+        };
+        pos.play_unchecked(&m);
+        if let Some(found) = classify_position(pos.clone().into_setup(EnPassantMode::Legal)) {
+            opening = Some(found);
+        }
+    }
+    opening
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn search_opening_name(query: String) -> Result<Vec<OutOpening>, Error> {
@@ -155,6 +213,23 @@ lazy_static! {
         }
         positions
     };
+    /// Position of each ECO opening in `OPENINGS`, keeping the first one of
+    /// positions with several names
+    static ref OPENING_INDEX: HashMap<Setup, usize> = {
+        let mut index = HashMap::new();
+        for (i, opening) in OPENINGS.iter().enumerate() {
+            if opening.pgn.is_some() {
+                index.entry(opening_key(opening.setup.clone())).or_insert(i);
+            }
+        }
+        index
+    };
+    static ref MAX_OPENING_PLIES: usize = OPENINGS
+        .iter()
+        .filter_map(|o| o.pgn.as_ref())
+        .map(|pgn| pgn.split_whitespace().filter(|t| t.parse::<San>().is_ok()).count())
+        .max()
+        .unwrap_or(0);
 }
 
 #[cfg(test)]
@@ -168,4 +243,26 @@ mod tests {
                 .unwrap();
         assert_eq!(opening, "Bongcloud Attack");
     }
+
+    fn sans(pgn: &str) -> Vec<String> {
+        pgn.split_whitespace()
+            .filter(|t| t.parse::<San>().is_ok())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn classifies_najdorf() {
+        let moves = sans("1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6 6. a3 e5 7. Nb3");
+        let opening = classify_game_opening(&moves).unwrap();
+        assert_eq!(opening.eco, "B90");
+        assert_eq!(opening.name, "Sicilian Defense: Najdorf Variation");
+    }
+
+    #[test]
+    fn classifies_transpositions() {
+        let moves = sans("1. Nf3 c5 2. e4 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6");
+        assert_eq!(classify_game_opening(&moves).unwrap().eco, "B90");
+        assert_eq!(classify_game_opening(&[]), None);
+    }
 }
@@ -1,15 +1,156 @@
 use std::{
-    fs::{File, OpenOptions},
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
     io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
 };
 
-use crate::{error::Error, AppState};
+use dashmap::DashMap;
+use flate2::read::MultiGzDecoder;
+use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Position};
+use specta::Type;
+
+use crate::{db::invalidate_caches, error::Error, AppState};
 
 const GAME_OFFSET_FREQ: usize = 100;
 
+/// Gzip streams start with these bytes, whatever the file is called
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub(crate) fn is_gzip(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; 2];
+    let gzip = match file.read_exact(&mut magic) {
+        Ok(()) => magic == GZIP_MAGIC,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    file.rewind()?;
+    Ok(gzip)
+}
+
+/// How far back a gzipped file can seek without decompressing it again
+const GZIP_HISTORY: usize = 64 * 1024;
+
+/// A gzipped file, read as its decompressed stream. The last bytes read are
+/// kept so that the parser can step back over a line, seeking further back
+/// decompresses the file again from the start.
+struct GzipSource {
+    decoder: MultiGzDecoder<File>,
+    recent: VecDeque<u8>,
+    /// How many of the recent bytes are read again before the decoder
+    replay: usize,
+    position: u64,
+}
+
+impl GzipSource {
+    fn new(file: File) -> Self {
+        Self {
+            decoder: MultiGzDecoder::new(file),
+            recent: VecDeque::with_capacity(GZIP_HISTORY),
+            replay: 0,
+            position: 0,
+        }
+    }
+}
+
+impl Read for GzipSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = if self.replay > 0 {
+            let start = self.recent.len() - self.replay;
+            let read = buf.len().min(self.replay);
+            for (to, from) in buf.iter_mut().zip(self.recent.range(start..start + read)) {
+                *to = *from;
+            }
+            self.replay -= read;
+            read
+        } else {
+            let read = self.decoder.read(buf)?;
+            self.recent.extend(&buf[..read]);
+            let extra = self.recent.len().saturating_sub(GZIP_HISTORY);
+            self.recent.drain(..extra);
+            read
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for GzipSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "can't seek from the end of a gzip stream",
+                ))
+            }
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+
+        if target < self.position {
+            let back = (self.position - target) as usize;
+            if back <= self.recent.len() - self.replay {
+                self.replay += back;
+                self.position = target;
+                return Ok(target);
+            }
+            let mut file = self.decoder.get_ref().try_clone()?;
+            file.rewind()?;
+            *self = Self::new(file);
+        }
+        io::copy(
+            &mut self.by_ref().take(target - self.position),
+            &mut io::sink(),
+        )?;
+        Ok(self.position)
+    }
+}
+
+/// A PGN file, decompressed on the fly if it is gzipped. Positions are those
+/// of the decompressed stream, so that the offsets of the index work the
+/// same for both.
+enum PgnSource {
+    Plain(File),
+    Gzip(GzipSource),
+}
+
+impl PgnSource {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        if is_gzip(&mut file)? {
+            Ok(Self::Gzip(GzipSource::new(file)))
+        } else {
+            Ok(Self::Plain(file))
+        }
+    }
+}
+
+impl Read for PgnSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.read(buf),
+            Self::Gzip(gzip) => gzip.read(buf),
+        }
+    }
+}
+
+impl Seek for PgnSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(file) => file.seek(pos),
+            Self::Gzip(gzip) => gzip.seek(pos),
+        }
+    }
+}
+
 struct PgnParser {
-    reader: BufReader<File>,
+    reader: BufReader<PgnSource>,
     line: String,
     game: String,
     start: u64,
@@ -17,7 +158,16 @@ struct PgnParser {
 
 impl PgnParser {
     fn new(file: File) -> Self {
-        let mut reader = BufReader::new(file);
+        Self::with_source(PgnSource::Plain(file))
+    }
+
+    /// Opens a file for reading, gzipped or not
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self::with_source(PgnSource::open(path)?))
+    }
+
+    fn with_source(source: PgnSource) -> Self {
+        let mut reader = BufReader::new(source);
         let start = ignore_bom(&mut reader).unwrap_or(0);
         Self {
             reader,
@@ -31,26 +181,20 @@ impl PgnParser {
         self.reader.stream_position()
     }
 
-    fn offset_by_index(&mut self, n: usize, state: &AppState, file: &String) -> io::Result<()> {
-        let offset_index = n / GAME_OFFSET_FREQ;
-        let n_left = n % GAME_OFFSET_FREQ;
-        let pgn_offsets = state.pgn_offsets.get(file).unwrap();
-
-        if offset_index == 0 || offset_index < pgn_offsets.len() {
-            let offset = match offset_index {
-                0 => self.start,
-                _ => pgn_offsets[offset_index - 1],
-            };
+    /// Moves the reader to the start of the `n`th game, returning the number
+    /// of games that had to be skipped after seeking to the closest offset
+    fn offset_by_index(&mut self, n: usize, index: &PgnIndex) -> io::Result<usize> {
+        let offset_index = (n / GAME_OFFSET_FREQ).min(index.offsets.len());
+        let offset = match offset_index {
+            0 => self.start,
+            _ => index.offsets[offset_index - 1],
+        };
+        let n_left = n - offset_index * GAME_OFFSET_FREQ;
 
-            self.reader.seek(SeekFrom::Start(offset))?;
-
-            self.skip_games(n_left)?;
-        } else {
-            self.reader.seek(SeekFrom::Start(self.start))?;
-            self.skip_games(n)?;
-        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.skip_games(n_left)?;
 
-        Ok(())
+        Ok(n_left)
     }
 
     /// Skip n games, and return the number of bytes read
@@ -117,7 +261,7 @@ impl PgnParser {
     }
 }
 
-fn ignore_bom(reader: &mut BufReader<File>) -> io::Result<u64> {
+fn ignore_bom<R: Read + Seek>(reader: &mut BufReader<R>) -> io::Result<u64> {
     let mut bom = [0; 3];
     reader.read_exact(&mut bom)?;
     if bom != [0xEF, 0xBB, 0xBF] {
@@ -127,61 +271,283 @@ fn ignore_bom(reader: &mut BufReader<File>) -> io::Result<u64> {
     Ok(3)
 }
 
+/// Byte offsets of every `GAME_OFFSET_FREQ`th game of a PGN file, tied to
+/// the size and modification time of the file it was built from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PgnIndex {
+    size: u64,
+    modified_ns: u64,
+    count: usize,
+    offsets: Vec<u64>,
+}
+
+impl PgnIndex {
+    fn build(file: &Path, size: u64, modified_ns: u64) -> io::Result<Self> {
+        let mut parser = PgnParser::open(file)?;
+        let mut offsets = Vec::new();
+        let mut count = 0;
+
+        while parser.skip_games(1)? != 0 {
+            count += 1;
+            if count % GAME_OFFSET_FREQ == 0 {
+                offsets.push(parser.position()?);
+            }
+        }
+
+        Ok(Self {
+            size,
+            modified_ns,
+            count,
+            offsets,
+        })
+    }
+
+    fn matches(&self, size: u64, modified_ns: u64) -> bool {
+        self.size == size && self.modified_ns == modified_ns
+    }
+}
+
+/// The index is saved next to the PGN file, like its `.info` metadata
+fn index_path(file: &Path) -> PathBuf {
+    file.with_extension("index")
+}
+
+fn file_version(file: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(file)?;
+    let modified_ns = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    Ok((metadata.len(), modified_ns))
+}
+
+/// Returns the offset index of a file, from memory, from its sidecar file or
+/// by scanning the whole file, in that order. Indexes built for another
+/// version of the file are discarded.
+fn load_index(file: &Path, cache: &DashMap<String, Arc<PgnIndex>>) -> io::Result<Arc<PgnIndex>> {
+    let key = file.to_string_lossy().to_string();
+    let (size, modified_ns) = file_version(file)?;
+
+    if let Some(index) = cache.get(&key) {
+        if index.matches(size, modified_ns) {
+            return Ok(index.clone());
+        }
+    }
+
+    let sidecar = index_path(file);
+    let saved = fs::read(&sidecar)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<PgnIndex>(&data).ok())
+        .filter(|index| index.matches(size, modified_ns));
+
+    let index = match saved {
+        Some(index) => index,
+        None => {
+            let index = PgnIndex::build(file, size, modified_ns)?;
+            save_index(file, &index);
+            index
+        }
+    };
+
+    let index = Arc::new(index);
+    cache.insert(key, index.clone());
+    Ok(index)
+}
+
+fn save_index(file: &Path, index: &PgnIndex) {
+    // not being able to save the index only makes the next launch slower
+    if let Ok(data) = serde_json::to_vec(index) {
+        fs::write(index_path(file), data).ok();
+    }
+}
+
+/// Drops the index of a file that is about to be modified
+fn invalidate_index(file: &Path, cache: &DashMap<String, Arc<PgnIndex>>) {
+    cache.remove(&*file.to_string_lossy());
+    fs::remove_file(index_path(file)).ok();
+}
+
+/// Number of games in a PGN file, using the same index as `count_pgn_games`
+pub(crate) fn game_count(file: &Path, cache: &DashMap<String, Arc<PgnIndex>>) -> io::Result<usize> {
+    Ok(load_index(file, cache)?.count)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn count_pgn_games(
     file: PathBuf,
     state: tauri::State<'_, AppState>,
 ) -> Result<i32, Error> {
-    let files_string = file.to_string_lossy().to_string();
+    Ok(load_index(&file, &state.pgn_offsets)?.count as i32)
+}
 
-    let file = File::open(&file)?;
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseError {
+    pub game_index: u32,
+    pub byte_offset: u64,
+    pub message: String,
+}
 
-    let mut parser = PgnParser::new(file.try_clone()?);
+#[derive(Debug, Default, Serialize, Type)]
+pub struct ReadGamesResult {
+    pub games: Vec<String>,
+    /// Games of the requested range that were skipped
+    pub errors: Vec<ParseError>,
+}
 
-    let mut offsets = Vec::new();
+/// Plays the mainline of a game to find illegal moves and broken setups
+#[derive(Default)]
+struct GameChecker {
+    fen: Option<String>,
+    pos: Chess,
+    ply: u32,
+    error: Option<String>,
+}
 
-    let mut count = 0;
+impl Visitor for GameChecker {
+    type Result = Result<(), String>;
 
-    while let Ok(skipped) = parser.skip_games(1) {
-        if skipped == 0 {
-            break;
+    fn begin_game(&mut self) {
+        *self = GameChecker::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if key == b"FEN" {
+            self.fen = Some(value.decode_utf8_lossy().to_string());
         }
-        count += 1;
-        if count % GAME_OFFSET_FREQ as i32 == 0 {
-            let cur_pos = parser.position()?;
-            offsets.push(cur_pos);
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        if let Some(fen) = &self.fen {
+            match Fen::from_ascii(fen.as_bytes())
+                .ok()
+                .and_then(|fen| fen.into_position(CastlingMode::Chess960).ok())
+            {
+                Some(pos) => self.pos = pos,
+                None => self.error = Some(format!("Invalid FEN: {}", fen)),
+            }
         }
+        Skip(self.error.is_some())
     }
 
-    state.pgn_offsets.insert(files_string, offsets);
-    Ok(count)
-}
+    fn san(&mut self, san: SanPlus) {
+        if self.error.is_some() {
+            return;
+        }
+        self.ply += 1;
+        match san.san.to_move(&self.pos) {
+            Ok(m) => self.pos.play_unchecked(&m),
+            Err(_) => {
+                self.error = Some(format!(
+                    "Illegal move {} on move {}",
+                    san,
+                    (self.ply + 1) / 2
+                ))
+            }
+        }
+    }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn read_games(
-    file: PathBuf,
-    start: i32,
-    end: i32,
-    state: tauri::State<'_, AppState>,
-) -> Result<Vec<String>, Error> {
-    let file_r = File::open(&file)?;
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
 
-    let mut parser = PgnParser::new(file_r.try_clone()?);
+    fn end_game(&mut self) -> Self::Result {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
 
-    parser.offset_by_index(start as usize, &state, &file.to_string_lossy().to_string())?;
+/// Checks that the parentheses of the movetext are balanced, ignoring
+/// headers and comments
+fn balanced_parentheses(game: &str) -> bool {
+    let mut depth = 0i32;
+    for line in game.lines().filter(|line| !line.starts_with('[')) {
+        let mut in_comment = false;
+        for c in line.chars() {
+            match c {
+                '{' => in_comment = true,
+                '}' => in_comment = false,
+                ';' if !in_comment => break,
+                '(' if !in_comment => depth += 1,
+                ')' if !in_comment => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    depth == 0
+}
+
+/// A missing result isn't an error, games that are still being played or
+/// were saved by hand often don't have one yet
+fn check_game(game: &str) -> Result<(), String> {
+    if !balanced_parentheses(game) {
+        return Err("Mismatched parentheses".to_string());
+    }
+    let mut reader = BufferedReader::new(game.as_bytes());
+    match reader.read_game(&mut GameChecker::default()) {
+        Ok(Some(result)) => result,
+        Ok(None) => Err("Empty game".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    let mut games: Vec<String> = Vec::with_capacity((end - start) as usize);
+fn read_games_window(
+    file: &Path,
+    start: usize,
+    end: usize,
+    index: &PgnIndex,
+) -> io::Result<ReadGamesResult> {
+    let mut parser = PgnParser::open(file)?;
+    parser.offset_by_index(start, index)?;
 
-    for _ in start..=end {
+    let mut result = ReadGamesResult {
+        games: Vec::with_capacity(end.saturating_sub(start) + 1),
+        errors: Vec::new(),
+    };
+    for game_index in start..=end {
+        let byte_offset = parser.position()? - parser.line.len() as u64;
         let game = parser.read_game()?;
         if game.is_empty() {
             break;
         }
-        games.push(game);
+        match check_game(&game) {
+            Ok(()) => result.games.push(game),
+            Err(message) => result.errors.push(ParseError {
+                game_index: game_index as u32,
+                byte_offset,
+                message,
+            }),
+        }
     }
-    Ok(games)
+    Ok(result)
+}
+
+/// Reads the games `start..=end` of a file. Games that can't be parsed are
+/// left out and reported in `errors` instead.
+#[tauri::command]
+#[specta::specta]
+pub async fn read_games(
+    file: PathBuf,
+    start: i32,
+    end: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<ReadGamesResult, Error> {
+    let index = load_index(&file, &state.pgn_offsets)?;
+    Ok(read_games_window(
+        &file,
+        start.max(0) as usize,
+        end.max(0) as usize,
+        &index,
+    )?)
 }
 
 #[tauri::command]
@@ -191,11 +557,17 @@ pub async fn delete_game(
     n: i32,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), Error> {
-    let file_r = File::open(&file)?;
+    let mut file_r = File::open(&file)?;
+    if is_gzip(&mut file_r)? {
+        return Err(Error::CompressedPgn);
+    }
+
+    let index = load_index(&file, &state.pgn_offsets)?;
+    invalidate_index(&file, &state.pgn_offsets);
 
     let mut parser = PgnParser::new(file_r.try_clone()?);
 
-    parser.offset_by_index(n as usize, &state, &file.to_string_lossy().to_string())?;
+    parser.offset_by_index(n as usize, &index)?;
 
     let starting_bytes = parser.position()?;
 
@@ -209,6 +581,117 @@ pub async fn delete_game(
     Ok(())
 }
 
+/// Headers that tell games apart when looking for conflicting ones
+const IDENTITY_HEADERS: [&[u8]; 6] = [b"Event", b"Site", b"Date", b"Round", b"White", b"Black"];
+
+/// Reads the identity headers of a game, skipping its movetext
+#[derive(Default)]
+struct GameIdentity {
+    headers: [Option<String>; 6],
+}
+
+impl Visitor for GameIdentity {
+    type Result = [Option<String>; 6];
+
+    fn begin_game(&mut self) {
+        self.headers = Default::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if let Some(i) = IDENTITY_HEADERS.iter().position(|header| *header == key) {
+            self.headers[i] = Some(value.decode_utf8_lossy().to_string());
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        std::mem::take(&mut self.headers)
+    }
+}
+
+/// Returns the index of the first game of the file with the same identity
+/// headers as `pgn`, other than the one at `except`. Games without any of
+/// those headers never conflict.
+fn find_conflict(file: &Path, pgn: &str, except: Option<usize>) -> io::Result<Option<usize>> {
+    let mut visitor = GameIdentity::default();
+    let headers = match BufferedReader::new(pgn.as_bytes()).read_game(&mut visitor)? {
+        Some(headers) if headers.iter().any(Option::is_some) => headers,
+        _ => return Ok(None),
+    };
+
+    let mut reader = BufferedReader::new(File::open(file)?);
+    let mut i = 0;
+    while let Some(other) = reader.read_game(&mut visitor)? {
+        if other == headers && except != Some(i) {
+            return Ok(Some(i));
+        }
+        i += 1;
+    }
+    Ok(None)
+}
+
+/// The blank lines needed before a new game written at the end of a file
+fn separator_before_end(file: &mut File, end: u64) -> io::Result<&'static str> {
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(end.saturating_sub(4)))?;
+    file.read_to_end(&mut tail)?;
+    tail.retain(|&b| b != b'\r');
+    Ok(match tail.as_slice() {
+        [] | [.., b'\n', b'\n'] => "",
+        [.., b'\n'] => "\n",
+        _ => "\n\n",
+    })
+}
+
+/// Writes a game at the end of a file and extends the index of the file
+/// with it, instead of scanning the whole file again
+fn append_game(
+    file: &Path,
+    pgn: &str,
+    check_conflicts: bool,
+    cache: &DashMap<String, Arc<PgnIndex>>,
+) -> Result<(), Error> {
+    let index = load_index(file, cache)?;
+    if check_conflicts {
+        if let Some(i) = find_conflict(file, pgn, None)? {
+            return Err(Error::ConflictingGame(i));
+        }
+    }
+
+    let mut file_w = OpenOptions::new().read(true).append(true).open(file)?;
+    let end = file_w.metadata()?.len();
+    let separator = separator_before_end(&mut file_w, end)?;
+    file_w.write_all(separator.as_bytes())?;
+    file_w.write_all(pgn.as_bytes())?;
+    let new_end = file_w.stream_position()?;
+    drop(file_w);
+
+    let mut offsets = index.offsets.clone();
+    // an offset at the old end of the file is where the new game starts,
+    // which is after the separator
+    if let Some(last) = offsets.last_mut().filter(|last| **last == end) {
+        *last = end + separator.len() as u64;
+    }
+    let count = index.count + 1;
+    if count % GAME_OFFSET_FREQ == 0 {
+        offsets.push(new_end);
+    }
+
+    let (size, modified_ns) = file_version(file)?;
+    let index = PgnIndex {
+        size,
+        modified_ns,
+        count,
+        offsets,
+    };
+    save_index(file, &index);
+    cache.insert(file.to_string_lossy().to_string(), Arc::new(index));
+    Ok(())
+}
+
 fn write_to_end<R: Read>(reader: &mut R, writer: &mut File) -> io::Result<()> {
     io::copy(reader, writer)?;
     let end = writer.stream_position()?;
@@ -216,19 +699,43 @@ fn write_to_end<R: Read>(reader: &mut R, writer: &mut File) -> io::Result<()> {
     Ok(())
 }
 
+/// Replaces the `n`th game of a file, or adds the game at the end with
+/// `append`. With `check_conflicts`, a game with the same headers as another
+/// one of the file is rejected.
 #[tauri::command]
 #[specta::specta]
 pub async fn write_game(
     file: PathBuf,
     n: i32,
     pgn: String,
+    append: Option<bool>,
+    check_conflicts: Option<bool>,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), Error> {
     if !file.exists() {
         File::create(&file)?;
     }
 
-    let file_r = File::open(&file)?;
+    let mut file_r = File::open(&file)?;
+    if is_gzip(&mut file_r)? {
+        return Err(Error::CompressedPgn);
+    }
+
+    let check_conflicts = check_conflicts.unwrap_or(false);
+    if append.unwrap_or(false) {
+        append_game(&file, &pgn, check_conflicts, &state.pgn_offsets)?;
+        invalidate_caches(&state, &file);
+        return Ok(());
+    }
+    if check_conflicts {
+        if let Some(i) = find_conflict(&file, &pgn, Some(n as usize))? {
+            return Err(Error::ConflictingGame(i));
+        }
+    }
+
+    let index = load_index(&file, &state.pgn_offsets)?;
+    invalidate_index(&file, &state.pgn_offsets);
+
     let mut file_w = OpenOptions::new().write(true).open(&file)?;
 
     let mut tmpf = tempfile::tempfile()?;
@@ -236,7 +743,7 @@ pub async fn write_game(
 
     let mut parser = PgnParser::new(file_r.try_clone()?);
 
-    parser.offset_by_index(n as usize, &state, &file.to_string_lossy().to_string())?;
+    parser.offset_by_index(n as usize, &index)?;
 
     tmpf.seek(SeekFrom::Start(parser.position()?))?;
     tmpf.write_all(pgn.as_bytes())?;
@@ -248,6 +755,407 @@ pub async fn write_game(
     tmpf.seek(SeekFrom::Start(0))?;
 
     write_to_end(&mut tmpf, &mut file_w)?;
+    invalidate_caches(&state, &file);
 
     Ok(())
 }
+
+/// Parses a `[%clk H:MM:SS]` comment command into milliseconds
+pub(crate) fn parse_clock(comment: &str) -> Option<u32> {
+    let start = comment.find("[%clk")? + "[%clk".len();
+    let rest = &comment[start..];
+    let end = rest.find(']')?;
+    let mut seconds = 0.0;
+    for part in rest[..end].trim().split(':') {
+        seconds = seconds * 60.0 + part.trim().parse::<f64>().ok()?;
+    }
+    if seconds < 0.0 {
+        return None;
+    }
+    Some((seconds * 1000.0).round() as u32)
+}
+
+/// Returns the increment of a single period `TimeControl` header in
+/// milliseconds. Multi-period and unknown time controls return `None`.
+fn parse_increment(time_control: &str) -> Option<u32> {
+    let time_control = time_control.trim();
+    if time_control.contains(':') || time_control.contains('/') {
+        return None;
+    }
+    match time_control.split_once('+') {
+        Some((base, inc)) => {
+            base.parse::<u32>().ok()?;
+            Some(inc.parse::<u32>().ok()? * 1000)
+        }
+        None => time_control.parse::<u32>().ok().map(|_| 0),
+    }
+}
+
+/// Clocks in PGN exports are usually rounded to the second
+const CLOCK_TOLERANCE_MS: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ClockIssue {
+    /// The clock after `ply` is higher than the previous one plus the increment
+    #[serde(rename_all = "camelCase")]
+    ClockIncreased {
+        ply: u32,
+        previous_ms: u32,
+        current_ms: u32,
+    },
+    /// Only one of the players has clock times
+    MissingClocks { side: String },
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct GameClockReport {
+    pub index: u32,
+    pub issues: Vec<ClockIssue>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Type)]
+pub struct PgnValidationReport {
+    pub games: u32,
+    pub flagged: Vec<GameClockReport>,
+}
+
+/// Checks the mainline clocks of a game, given as `(ply, ms)` pairs
+fn check_clocks(clocks: &[(u32, u32)], increment: Option<u32>) -> Vec<ClockIssue> {
+    let mut issues = Vec::new();
+    if clocks.is_empty() {
+        return issues;
+    }
+
+    for (side, parity) in [("white", 1), ("black", 0)] {
+        let side_clocks: Vec<&(u32, u32)> =
+            clocks.iter().filter(|(ply, _)| ply % 2 == parity).collect();
+
+        if side_clocks.is_empty() {
+            // a one move game can't have clocks for black
+            if !(side == "black" && clocks.iter().all(|(ply, _)| *ply <= 1)) {
+                issues.push(ClockIssue::MissingClocks {
+                    side: side.to_string(),
+                });
+            }
+            continue;
+        }
+
+        if let Some(increment) = increment {
+            for pair in side_clocks.windows(2) {
+                let (_, previous_ms) = *pair[0];
+                let (ply, current_ms) = *pair[1];
+                if current_ms > previous_ms + increment + CLOCK_TOLERANCE_MS {
+                    issues.push(ClockIssue::ClockIncreased {
+                        ply,
+                        previous_ms,
+                        current_ms,
+                    });
+                }
+            }
+        }
+    }
+    issues
+}
+
+#[derive(Default)]
+struct ClockValidator {
+    increment: Option<u32>,
+    ply: u32,
+    clocks: Vec<(u32, u32)>,
+}
+
+impl Visitor for ClockValidator {
+    type Result = Vec<ClockIssue>;
+
+    fn begin_game(&mut self) {
+        *self = ClockValidator::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if key == b"TimeControl" {
+            self.increment = parse_increment(&value.decode_utf8_lossy());
+        }
+    }
+
+    fn san(&mut self, _san: SanPlus) {
+        self.ply += 1;
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if self.ply == 0 || self.clocks.last().is_some_and(|(ply, _)| *ply == self.ply) {
+            return;
+        }
+        if let Some(ms) = parse_clock(&String::from_utf8_lossy(comment.as_bytes())) {
+            self.clocks.push((self.ply, ms));
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        check_clocks(&self.clocks, self.increment)
+    }
+}
+
+/// Checks the clocks of a single game, which have no issues if it can't be
+/// read
+pub(crate) fn game_clock_issues(game: &[u8]) -> Vec<ClockIssue> {
+    BufferedReader::new(game)
+        .read_game(&mut ClockValidator::default())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Looks for games with corrupted clock data, like clocks increasing by more
+/// than the increment or clock times for only one of the players
+#[tauri::command]
+#[specta::specta]
+pub async fn validate_pgn(file: PathBuf) -> Result<PgnValidationReport, Error> {
+    let mut reader = BufReader::new(PgnSource::open(&file)?);
+    ignore_bom(&mut reader).ok();
+
+    let mut validator = ClockValidator::default();
+    let mut report = PgnValidationReport::default();
+
+    for issues in BufferedReader::new(reader).into_iter(&mut validator) {
+        let issues = issues?;
+        if !issues.is_empty() {
+            report.flagged.push(GameClockReport {
+                index: report.games,
+                issues,
+            });
+        }
+        report.games += 1;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(pgn: &str) -> Vec<ClockIssue> {
+        let mut reader = BufferedReader::new(pgn.as_bytes());
+        reader
+            .read_game(&mut ClockValidator::default())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn clock_parsing() {
+        assert_eq!(parse_clock("[%clk 0:03:00]"), Some(180_000));
+        assert_eq!(parse_clock("[%eval 0.3] [%clk 1:00:01.5]"), Some(3_601_500));
+        assert_eq!(parse_clock("no clock here"), None);
+        assert_eq!(parse_increment("180+2"), Some(2000));
+        assert_eq!(parse_increment("600"), Some(0));
+        assert_eq!(parse_increment("40/7200:3600"), None);
+        assert_eq!(parse_increment("-"), None);
+    }
+
+    #[test]
+    fn consistent_clocks() {
+        let pgn = r#"[TimeControl "180+2"]
+
+1. e4 { [%clk 0:03:00] } 1... e5 { [%clk 0:03:00] } 2. Nf3 { [%clk 0:03:01] } 2... Nc6 { [%clk 0:02:58] } *"#;
+        assert!(validate(pgn).is_empty());
+    }
+
+    #[test]
+    fn increasing_clock() {
+        let pgn = r#"[TimeControl "180+2"]
+
+1. e4 { [%clk 0:03:00] } 1... e5 { [%clk 0:03:00] } 2. Nf3 { [%clk 0:02:50] } 2... Nc6 { [%clk 0:02:58] } 3. Bb5 { [%clk 0:04:10] } *"#;
+        assert_eq!(
+            validate(pgn),
+            vec![ClockIssue::ClockIncreased {
+                ply: 5,
+                previous_ms: 170_000,
+                current_ms: 250_000,
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_clocks() {
+        let pgn = r#"[TimeControl "180+0"]
+
+1. e4 { [%clk 0:03:00] } 1... e5 2. Nf3 { [%clk 0:02:59] } 2... Nc6 *"#;
+        assert_eq!(
+            validate(pgn),
+            vec![ClockIssue::MissingClocks {
+                side: "black".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn no_clocks() {
+        assert!(validate("1. e4 e5 2. Nf3 Nc6 *").is_empty());
+    }
+
+    fn write_games(path: &Path, range: std::ops::Range<usize>) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for i in range {
+            write!(file, "[Round \"{}\"]\n\n1. e4 e5 *\n\n", i).unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_window_from_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.pgn");
+        write_games(&path, 0..2000);
+
+        let cache = DashMap::new();
+        let index = load_index(&path, &cache).unwrap();
+        assert_eq!(index.count, 2000);
+        assert!(index_path(&path).exists());
+
+        let mut parser = PgnParser::new(File::open(&path).unwrap());
+        assert_eq!(parser.offset_by_index(1000, &index).unwrap(), 0);
+        assert_eq!(parser.offset_by_index(1005, &index).unwrap(), 5);
+
+        let games = read_games_window(&path, 1000, 1010, &index).unwrap().games;
+        assert_eq!(games.len(), 11);
+        assert!(games[0].starts_with("[Round \"1000\"]"));
+        assert!(games[10].starts_with("[Round \"1010\"]"));
+
+        // a fresh cache picks the sidecar file up
+        let reloaded = load_index(&path, &DashMap::new()).unwrap();
+        assert_eq!(reloaded, index);
+    }
+
+    #[test]
+    fn appends_games_to_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.pgn");
+        write_games(&path, 0..2 * GAME_OFFSET_FREQ - 1);
+
+        let cache = DashMap::new();
+        let index = load_index(&path, &cache).unwrap();
+        assert_eq!(index.offsets.len(), 1);
+
+        append_game(&path, "[Round \"199\"]\n\n1. d4 d5 *", false, &cache).unwrap();
+        let appended = load_index(&path, &cache).unwrap();
+        assert_eq!(appended.count, index.count + 1);
+        assert_eq!(appended.offsets.len(), 2);
+        // the file wasn't scanned again, and the index is the one a scan gives
+        let (size, modified_ns) = file_version(&path).unwrap();
+        assert_eq!(
+            *appended,
+            PgnIndex::build(&path, size, modified_ns).unwrap()
+        );
+        assert!(Arc::ptr_eq(&appended, &load_index(&path, &cache).unwrap()));
+
+        // the last game has no trailing newline, so blank lines are added
+        append_game(&path, "[Round \"200\"]\n\n1. c4 *\n", true, &cache).unwrap();
+        let (size, modified_ns) = file_version(&path).unwrap();
+        let index = load_index(&path, &cache).unwrap();
+        assert_eq!(*index, PgnIndex::build(&path, size, modified_ns).unwrap());
+        let games = read_games_window(&path, 199, 200, &index).unwrap().games;
+        assert!(games[0].starts_with("[Round \"199\"]"));
+        assert!(games[1].starts_with("[Round \"200\"]"));
+
+        assert!(matches!(
+            append_game(&path, "[Round \"5\"]\n\n1. e4 *", true, &cache),
+            Err(Error::ConflictingGame(5))
+        ));
+        assert_eq!(load_index(&path, &cache).unwrap().count, 201);
+    }
+
+    #[test]
+    fn reads_gzipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("games.pgn");
+        write_games(&plain, 0..5000);
+        // named like a plain file, it is told apart by its first bytes
+        let path = dir.path().join("twic.pgn");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+        io::copy(&mut File::open(&plain).unwrap(), &mut encoder).unwrap();
+        encoder.finish().unwrap();
+        assert!(is_gzip(&mut File::open(&path).unwrap()).unwrap());
+
+        let index = load_index(&path, &DashMap::new()).unwrap();
+        assert_eq!(index.count, 5000);
+        assert_eq!(
+            index.offsets,
+            load_index(&plain, &DashMap::new()).unwrap().offsets
+        );
+
+        let games = read_games_window(&path, 2500, 2510, &index).unwrap().games;
+        assert_eq!(games.len(), 11);
+        assert!(games[0].starts_with("[Round \"2500\"]"));
+
+        // going back far enough decompresses the file from the start again
+        let mut plain_parser = PgnParser::new(File::open(&plain).unwrap());
+        let mut parser = PgnParser::open(&path).unwrap();
+        for n in [4900, 3] {
+            plain_parser.offset_by_index(n, &index).unwrap();
+            parser.offset_by_index(n, &index).unwrap();
+            assert_eq!(parser.position().unwrap(), plain_parser.position().unwrap());
+        }
+        assert!(parser.read_game().unwrap().starts_with("[Round \"3\"]"));
+    }
+
+    #[test]
+    fn index_is_rebuilt_when_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.pgn");
+        write_games(&path, 0..150);
+
+        let cache = DashMap::new();
+        assert_eq!(load_index(&path, &cache).unwrap().count, 150);
+
+        write_games(&path, 150..250);
+        let index = load_index(&path, &cache).unwrap();
+        assert_eq!(index.count, 250);
+        assert_eq!(index.offsets.len(), 2);
+    }
+
+    #[test]
+    fn skips_malformed_games() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("games.pgn");
+        let first = "[Round \"1\"]\n\n1. e4 e5 2. Nf3 Nc6 1-0\n\n";
+        let second = "[Round \"2\"]\n\n1. e4 e5 2. Ke3 Nc6 0-1\n\n";
+        let third = "[Round \"3\"]\n\n1. d4 (1. e4 e5) 1... d5 1/2-1/2\n\n";
+        fs::write(&path, format!("{first}{second}{third}")).unwrap();
+
+        let index = load_index(&path, &DashMap::new()).unwrap();
+        let result = read_games_window(&path, 0, 2, &index).unwrap();
+
+        assert_eq!(result.games, vec![first.to_string(), third.to_string()]);
+        assert_eq!(
+            result.errors,
+            vec![ParseError {
+                game_index: 1,
+                byte_offset: first.len() as u64,
+                message: "Illegal move Ke3 on move 2".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn detects_broken_games() {
+        assert!(check_game("1. e4 (1. d4 d5 e5 *").is_err());
+        assert!(check_game("1. e4 { (a comment } e5 *").is_ok());
+        assert!(check_game("[FEN \"8/8/8 w - - 0 1\"]\n\n1. e4 *").is_err());
+        // the last game of a file that is still being written
+        assert!(check_game("1. e4 e5 2. Nf3").is_ok());
+        assert!(check_game(
+            "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4 Kd7 *"
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{path::BaseDirectory, Manager};
+
+use crate::{chess::EngineOption, error::Error, AppState};
+
+/// A named set of UCI option values, like "blitz" or "deep analysis"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EnginePreset {
+    pub name: String,
+    /// Path of the engine the preset was made for
+    pub engine_id: String,
+    pub options: Vec<EngineOption>,
+}
+
+fn presets_dir(app: &tauri::AppHandle) -> Result<PathBuf, Error> {
+    Ok(app.path().resolve("presets", BaseDirectory::AppData)?)
+}
+
+/// File of a preset, names that aren't a plain file name are rejected
+fn preset_file(dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    let valid = !name.trim().is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', ':'])
+        && !name.chars().any(char::is_control);
+    if !valid {
+        return Err(Error::InvalidPresetName(name.to_string()));
+    }
+    Ok(dir.join(format!("{name}.json")))
+}
+
+fn save_preset(dir: &Path, preset: &EnginePreset) -> Result<(), Error> {
+    let file = preset_file(dir, &preset.name)?;
+    fs::write(file, serde_json::to_string_pretty(preset)?)?;
+    Ok(())
+}
+
+fn load_preset(dir: &Path, name: &str) -> Result<EnginePreset, Error> {
+    let contents = fs::read_to_string(preset_file(dir, name)?).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::PresetNotFound(name.to_string()),
+        _ => e.into(),
+    })?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Reads every preset in the directory, sorted by name. Files that aren't
+/// presets are skipped.
+fn list_presets(dir: &Path) -> Result<Vec<EnginePreset>, Error> {
+    let mut presets: Vec<EnginePreset> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| serde_json::from_str(&fs::read_to_string(path).ok()?).ok())
+        .collect();
+    presets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(presets)
+}
+
+fn delete_preset(dir: &Path, name: &str) -> Result<(), Error> {
+    fs::remove_file(preset_file(dir, name)?).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::PresetNotFound(name.to_string()),
+        _ => e.into(),
+    })
+}
+
+/// Saves the option values as a preset, replacing any preset with that name
+#[tauri::command]
+#[specta::specta]
+pub async fn save_engine_preset(
+    name: String,
+    engine_id: String,
+    options: Vec<EngineOption>,
+    app: tauri::AppHandle,
+) -> Result<(), Error> {
+    save_preset(
+        &presets_dir(&app)?,
+        &EnginePreset {
+            name,
+            engine_id,
+            options,
+        },
+    )
+}
+
+/// Reads a preset. When a tab is given, the preset's options are also set on
+/// its engine in that tab, which has to be running.
+#[tauri::command]
+#[specta::specta]
+pub async fn load_engine_preset(
+    name: String,
+    tab: Option<String>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<EnginePreset, Error> {
+    let preset = load_preset(&presets_dir(&app)?, &name)?;
+    if let Some(tab) = tab {
+        let process = state
+            .engine_processes
+            .get(&(tab, preset.engine_id.clone()))
+            .map(|p| p.clone())
+            .ok_or(Error::EngineNotRunning)?;
+        process.lock().await.apply_options(&preset.options).await?;
+    }
+    Ok(preset)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn list_engine_presets(app: tauri::AppHandle) -> Result<Vec<EnginePreset>, Error> {
+    list_presets(&presets_dir(&app)?)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_engine_preset(name: String, app: tauri::AppHandle) -> Result<(), Error> {
+    delete_preset(&presets_dir(&app)?, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, value: &str) -> EngineOption {
+        EngineOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn presets_round_trip() {
+        use crate::chess::EngineProcess;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = dir.path().join("engine");
+        fs::write(
+            &engine,
+            "#!/bin/sh\nread line\necho \"option name Hash type spin default 16 min 1 max 1024\"\necho \"option name Threads type spin default 1 min 1 max 64\"\necho uciok\nread line\necho readyok\ncat > /dev/null\n",
+        )
+        .unwrap();
+        fs::set_permissions(&engine, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let presets = dir.path().join("presets");
+        fs::create_dir(&presets).unwrap();
+        let blitz = EnginePreset {
+            name: "blitz".to_string(),
+            engine_id: engine.display().to_string(),
+            options: vec![option("Hash", "64"), option("Threads", "4")],
+        };
+        save_preset(&presets, &blitz).unwrap();
+        let deep = EnginePreset {
+            name: "deep analysis".to_string(),
+            options: vec![option("Hash", "4096")],
+            ..blitz.clone()
+        };
+        save_preset(&presets, &deep).unwrap();
+        fs::write(presets.join("notes.txt"), "not a preset").unwrap();
+
+        assert_eq!(load_preset(&presets, "blitz").unwrap(), blitz);
+        assert_eq!(
+            list_presets(&presets).unwrap(),
+            vec![blitz.clone(), deep.clone()]
+        );
+        assert!(matches!(
+            save_preset(
+                &presets,
+                &EnginePreset {
+                    name: "../escape".to_string(),
+                    ..blitz.clone()
+                }
+            ),
+            Err(Error::InvalidPresetName(_))
+        ));
+
+        // options are checked against what the engine supports
+        let (mut process, _lines) = EngineProcess::new(engine, None).await.unwrap();
+        let loaded = load_preset(&presets, "blitz").unwrap();
+        process.apply_options(&loaded.options).await.unwrap();
+        let loaded = load_preset(&presets, "deep analysis").unwrap();
+        assert!(matches!(
+            process.apply_options(&loaded.options).await,
+            Err(Error::InvalidEngineOptionValue { .. })
+        ));
+
+        delete_preset(&presets, "blitz").unwrap();
+        assert!(matches!(
+            load_preset(&presets, "blitz"),
+            Err(Error::PresetNotFound(_))
+        ));
+        assert_eq!(list_presets(&presets).unwrap(), vec![deep]);
+    }
+}
@@ -1,8 +1,15 @@
 use std::{collections::VecDeque, path::PathBuf, sync::Mutex};
 
-use diesel::{dsl::sql, sql_types::Bool, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::Utc;
+use diesel::{
+    dsl::sql,
+    sql_query,
+    sql_types::{BigInt, Bool, Double, Integer, Text},
+    Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryableByName, RunQueryDsl,
+    SqliteConnection,
+};
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use specta::Type;
 use tauri::{path::BaseDirectory, Manager};
 
@@ -11,12 +18,24 @@ use crate::{
     error::Error,
 };
 
+/// Which puzzles to pick from. A puzzle has to have all of the themes.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleFilter {
+    pub min_rating: u16,
+    pub max_rating: u16,
+    #[serde(default)]
+    pub themes: Vec<String>,
+    /// Serve puzzles due for review first, regardless of rating and themes
+    #[serde(default)]
+    pub review: bool,
+}
+
 #[derive(Debug)]
 struct PuzzleCache {
     cache: VecDeque<Puzzle>,
     counter: usize,
-    min_rating: u16,
-    max_rating: u16,
+    filter: PuzzleFilter,
 }
 
 impl PuzzleCache {
@@ -24,31 +43,34 @@ impl PuzzleCache {
         Self {
             cache: VecDeque::new(),
             counter: 0,
-            min_rating: 0,
-            max_rating: 0,
+            filter: PuzzleFilter::default(),
         }
     }
 
-    fn get_puzzles(&mut self, file: &str, min_rating: u16, max_rating: u16) -> Result<(), Error> {
-        if self.cache.is_empty()
-            || self.min_rating != min_rating
-            || self.max_rating != max_rating
-            || self.counter >= 20
-        {
+    fn get_puzzles(&mut self, file: &str, filter: &PuzzleFilter) -> Result<(), Error> {
+        if self.cache.is_empty() || self.filter != *filter || self.counter >= self.cache.len() {
             self.cache.clear();
             self.counter = 0;
 
             let mut db = diesel::SqliteConnection::establish(file).expect("open database");
-            let new_puzzles = puzzles::table
-                .filter(puzzles::rating.le(max_rating as i32))
-                .filter(puzzles::rating.ge(min_rating as i32))
+            let mut query = puzzles::table
+                .filter(puzzles::rating.le(filter.max_rating as i32))
+                .filter(puzzles::rating.ge(filter.min_rating as i32))
+                .into_boxed();
+            // themes are stored space separated, like Lichess exports them
+            for theme in &filter.themes {
+                query = query.filter(
+                    sql::<Bool>("(' ' || themes || ' ') LIKE ")
+                        .bind::<Text, _>(format!("% {theme} %")),
+                );
+            }
+            let new_puzzles = query
                 .order(sql::<Bool>("RANDOM()"))
                 .limit(20)
                 .load::<Puzzle>(&mut db)?;
 
             self.cache = new_puzzles.into_iter().collect();
-            self.min_rating = min_rating;
-            self.max_rating = max_rating;
+            self.filter = filter.clone();
         }
 
         Ok(())
@@ -64,13 +86,188 @@ impl PuzzleCache {
     }
 }
 
+/// Ease factor of a puzzle that was never reviewed
+const INITIAL_EASE: f64 = 2.5;
+const MIN_EASE: f64 = 1.3;
+/// How long to wait before retrying a failed puzzle
+const RETRY_DELAY_SECS: i64 = 10 * 60;
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// SM-2 schedule of a puzzle
+#[derive(Debug, Clone, PartialEq, QueryableByName, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PuzzleSchedule {
+    #[diesel(sql_type = Integer)]
+    pub puzzle_id: i32,
+    #[diesel(sql_type = Double)]
+    pub ease: f64,
+    #[diesel(sql_type = Integer)]
+    pub interval_days: i32,
+    #[diesel(sql_type = Integer)]
+    pub repetitions: i32,
+    /// Unix timestamp, in seconds
+    #[diesel(sql_type = BigInt)]
+    pub due: i64,
+}
+
+impl PuzzleSchedule {
+    fn new(puzzle_id: i32) -> Self {
+        Self {
+            puzzle_id,
+            ease: INITIAL_EASE,
+            interval_days: 0,
+            repetitions: 0,
+            due: 0,
+        }
+    }
+
+    /// Grades an attempt from 0 to 5, quick solves being the easiest
+    fn quality(solved: bool, time_ms: u32) -> f64 {
+        match (solved, time_ms) {
+            (false, _) => 1.0,
+            (true, 0..=10_000) => 5.0,
+            (true, 10_001..=60_000) => 4.0,
+            (true, _) => 3.0,
+        }
+    }
+
+    fn review(&self, solved: bool, time_ms: u32, now: i64) -> Self {
+        let q = Self::quality(solved, time_ms);
+        let ease = (self.ease + 0.1 - (5.0 - q) * (0.08 + (5.0 - q) * 0.02)).max(MIN_EASE);
+
+        if !solved {
+            return Self {
+                ease,
+                interval_days: 0,
+                repetitions: 0,
+                due: now + RETRY_DELAY_SECS,
+                ..*self
+            };
+        }
+
+        let interval_days = match self.repetitions {
+            0 => 1,
+            1 => 6,
+            _ => (self.interval_days as f64 * ease).round() as i32,
+        };
+        Self {
+            ease,
+            interval_days,
+            repetitions: self.repetitions + 1,
+            due: now + interval_days as i64 * DAY_SECS,
+            ..*self
+        }
+    }
+}
+
+fn create_review_tables(db: &mut SqliteConnection) -> Result<(), Error> {
+    sql_query(
+        "CREATE TABLE IF NOT EXISTS puzzle_attempts (
+            id INTEGER PRIMARY KEY,
+            puzzle_id INTEGER NOT NULL,
+            solved INTEGER NOT NULL,
+            time_ms INTEGER NOT NULL,
+            attempted_at INTEGER NOT NULL
+        )",
+    )
+    .execute(db)?;
+    sql_query(
+        "CREATE TABLE IF NOT EXISTS puzzle_schedule (
+            puzzle_id INTEGER PRIMARY KEY,
+            ease REAL NOT NULL,
+            interval_days INTEGER NOT NULL,
+            repetitions INTEGER NOT NULL,
+            due INTEGER NOT NULL
+        )",
+    )
+    .execute(db)?;
+    sql_query("CREATE INDEX IF NOT EXISTS puzzle_schedule_due_idx ON puzzle_schedule(due)")
+        .execute(db)?;
+    Ok(())
+}
+
+fn record_attempt(
+    db: &mut SqliteConnection,
+    puzzle_id: i32,
+    solved: bool,
+    time_ms: u32,
+    now: i64,
+) -> Result<PuzzleSchedule, Error> {
+    create_review_tables(db)?;
+
+    db.transaction::<_, Error, _>(|db| {
+        sql_query(
+            "INSERT INTO puzzle_attempts (puzzle_id, solved, time_ms, attempted_at)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind::<Integer, _>(puzzle_id)
+        .bind::<Bool, _>(solved)
+        .bind::<Integer, _>(time_ms as i32)
+        .bind::<BigInt, _>(now)
+        .execute(db)?;
+
+        let schedule = sql_query("SELECT * FROM puzzle_schedule WHERE puzzle_id = ?")
+            .bind::<Integer, _>(puzzle_id)
+            .get_result::<PuzzleSchedule>(db)
+            .optional()?
+            .unwrap_or_else(|| PuzzleSchedule::new(puzzle_id))
+            .review(solved, time_ms, now);
+
+        sql_query("INSERT OR REPLACE INTO puzzle_schedule VALUES (?, ?, ?, ?, ?)")
+            .bind::<Integer, _>(schedule.puzzle_id)
+            .bind::<Double, _>(schedule.ease)
+            .bind::<Integer, _>(schedule.interval_days)
+            .bind::<Integer, _>(schedule.repetitions)
+            .bind::<BigInt, _>(schedule.due)
+            .execute(db)?;
+        Ok(schedule)
+    })
+}
+
+/// The puzzle that has been due for the longest, if any
+fn next_due_puzzle(db: &mut SqliteConnection, now: i64) -> Result<Option<Puzzle>, Error> {
+    create_review_tables(db)?;
+
+    let schedule = sql_query("SELECT * FROM puzzle_schedule WHERE due <= ? ORDER BY due LIMIT 1")
+        .bind::<BigInt, _>(now)
+        .get_result::<PuzzleSchedule>(db)
+        .optional()?;
+    match schedule {
+        Some(schedule) => Ok(puzzles::table
+            .find(schedule.puzzle_id)
+            .first::<Puzzle>(db)
+            .optional()?),
+        None => Ok(None),
+    }
+}
+
+/// Records a puzzle attempt and reschedules the puzzle for review
+#[tauri::command]
+#[specta::specta]
+pub fn record_puzzle_attempt(
+    file: String,
+    puzzle_id: i32,
+    solved: bool,
+    time_ms: u32,
+) -> Result<PuzzleSchedule, Error> {
+    let mut db = diesel::SqliteConnection::establish(&file).expect("open database");
+    record_attempt(&mut db, puzzle_id, solved, time_ms, Utc::now().timestamp())
+}
+
 #[tauri::command]
 #[specta::specta]
-pub fn get_puzzle(file: String, min_rating: u16, max_rating: u16) -> Result<Puzzle, Error> {
+pub fn get_puzzle(file: String, filter: PuzzleFilter) -> Result<Puzzle, Error> {
     static PUZZLE_CACHE: Lazy<Mutex<PuzzleCache>> = Lazy::new(|| Mutex::new(PuzzleCache::new()));
 
+    if filter.review {
+        let mut db = diesel::SqliteConnection::establish(&file).expect("open database");
+        if let Some(puzzle) = next_due_puzzle(&mut db, Utc::now().timestamp())? {
+            return Ok(puzzle);
+        }
+    }
+
     let mut cache = PUZZLE_CACHE.lock().unwrap();
-    cache.get_puzzles(&file, min_rating, max_rating)?;
+    cache.get_puzzles(&file, &filter)?;
     cache.get_next_puzzle().ok_or(Error::NoPuzzles)
 }
 
@@ -82,6 +279,45 @@ pub struct PuzzleDatabaseInfo {
     puzzle_count: i32,
     storage_size: i32,
     path: String,
+    themes: Vec<(String, i32)>,
+}
+
+#[derive(QueryableByName)]
+struct ColumnName {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct ThemeCount {
+    #[diesel(sql_type = Text)]
+    theme: String,
+    #[diesel(sql_type = Integer)]
+    count: i32,
+}
+
+/// How many puzzles have each theme, most common first
+fn theme_distribution(db: &mut SqliteConnection) -> Result<Vec<(String, i32)>, Error> {
+    // databases downloaded before themes were added don't have the column
+    let columns: Vec<ColumnName> =
+        sql_query("SELECT name FROM pragma_table_info('puzzles')").load(db)?;
+    if !columns.iter().any(|column| column.name == "themes") {
+        return Ok(Vec::new());
+    }
+
+    // themes are stored space separated, so they are split one at a time
+    let counts: Vec<ThemeCount> = sql_query(
+        "WITH RECURSIVE split(theme, rest) AS (
+            SELECT '', themes || ' ' FROM puzzles WHERE themes IS NOT NULL
+            UNION ALL
+            SELECT substr(rest, 1, instr(rest, ' ') - 1), substr(rest, instr(rest, ' ') + 1)
+            FROM split WHERE rest <> ''
+        )
+        SELECT theme, COUNT(*) AS count FROM split WHERE theme <> ''
+        GROUP BY theme ORDER BY count DESC, theme",
+    )
+    .load(db)?;
+    Ok(counts.into_iter().map(|c| (c.theme, c.count)).collect())
 }
 
 #[tauri::command]
@@ -98,6 +334,7 @@ pub async fn get_puzzle_db_info(
         diesel::SqliteConnection::establish(&path.to_string_lossy()).expect("open database");
 
     let puzzle_count = puzzles::table.count().get_result::<i64>(&mut db)? as i32;
+    let themes = theme_distribution(&mut db)?;
 
     let storage_size = path.metadata()?.len() as i32;
     let filename = path.file_name().expect("get filename").to_string_lossy();
@@ -108,5 +345,102 @@ pub async fn get_puzzle_db_info(
         puzzle_count,
         storage_size,
         path: path.to_string_lossy().to_string(),
+        themes,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle_db(dir: &tempfile::TempDir) -> String {
+        let file = dir.path().join("puzzles.db3").to_string_lossy().to_string();
+        let mut db = SqliteConnection::establish(&file).unwrap();
+        sql_query(
+            "CREATE TABLE puzzles (id INTEGER PRIMARY KEY, fen TEXT NOT NULL, moves TEXT NOT NULL,
+                rating INTEGER NOT NULL, rating_deviation INTEGER NOT NULL,
+                popularity INTEGER NOT NULL, nb_plays INTEGER NOT NULL, themes TEXT)",
+        )
+        .execute(&mut db)
+        .unwrap();
+        sql_query(
+            "INSERT INTO puzzles VALUES
+                (1, '8/8/8/8/8/8/8/8 w - - 0 1', 'e2e4', 1600, 80, 90, 100, 'endgame rookEndgame'),
+                (2, '8/8/8/8/8/8/8/8 w - - 0 1', 'e2e4', 1700, 80, 90, 100, 'fork middlegame'),
+                (3, '8/8/8/8/8/8/8/8 w - - 0 1', 'e2e4', 2100, 80, 90, 100, 'endgame pin'),
+                (4, '8/8/8/8/8/8/8/8 w - - 0 1', 'e2e4', 1750, 80, 90, 100, 'endgame mateIn2')",
+        )
+        .execute(&mut db)
+        .unwrap();
+        file
+    }
+
+    #[test]
+    fn filters_puzzles_by_theme_and_rating() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = puzzle_db(&dir);
+
+        let mut cache = PuzzleCache::new();
+        let filter = PuzzleFilter {
+            min_rating: 1500,
+            max_rating: 1800,
+            themes: vec!["endgame".to_string()],
+            review: false,
+        };
+        cache.get_puzzles(&file, &filter).unwrap();
+        let mut ids: Vec<i32> = cache.cache.iter().map(|p| p.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 4]);
+
+        // the cache is refilled once every puzzle in it was served
+        for _ in 0..2 {
+            assert!(cache.get_next_puzzle().is_some());
+        }
+        assert!(cache.get_next_puzzle().is_none());
+        cache.get_puzzles(&file, &filter).unwrap();
+        assert!(cache.get_next_puzzle().is_some());
+
+        // "mate" is not a theme on its own, only part of "mateIn2"
+        let filter = PuzzleFilter {
+            themes: vec!["mate".to_string()],
+            ..filter
+        };
+        cache.get_puzzles(&file, &filter).unwrap();
+        assert!(cache.get_next_puzzle().is_none());
+
+        let mut db = SqliteConnection::establish(&file).unwrap();
+        let themes = theme_distribution(&mut db).unwrap();
+        assert_eq!(themes[0], ("endgame".to_string(), 3));
+        assert_eq!(themes.len(), 6);
+
+        sql_query("ALTER TABLE puzzles DROP COLUMN themes")
+            .execute(&mut db)
+            .unwrap();
+        assert!(theme_distribution(&mut db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_puzzles_are_due_sooner() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = puzzle_db(&dir);
+        let mut db = SqliteConnection::establish(&file).unwrap();
+        let now = 1_700_000_000;
+
+        assert!(next_due_puzzle(&mut db, now).unwrap().is_none());
+
+        let failed = record_attempt(&mut db, 1, false, 30_000, now).unwrap();
+        let solved = record_attempt(&mut db, 2, true, 30_000, now).unwrap();
+        assert!(failed.due < solved.due);
+        assert!(failed.ease < solved.ease);
+
+        assert!(next_due_puzzle(&mut db, now).unwrap().is_none());
+        let due = next_due_puzzle(&mut db, now + RETRY_DELAY_SECS).unwrap();
+        assert_eq!(due.map(|p| p.id), Some(1));
+
+        // the interval grows with each successful review
+        let solved = record_attempt(&mut db, 2, true, 5_000, solved.due).unwrap();
+        assert_eq!(solved.interval_days, 6);
+        let solved = record_attempt(&mut db, 2, true, 5_000, solved.due).unwrap();
+        assert!(solved.interval_days > 6);
+    }
+}
@@ -0,0 +1,463 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use crate::{
+    chess::MoveClassification,
+    error::Error,
+    lexer::{lex_game, Token},
+    pgn::{parse_clock, read_games, write_game},
+    AppState,
+};
+
+/// Engine evaluation from a `[%eval]` comment command, from White's point of
+/// view
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Eval {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Eval {
+    fn parse(value: &str) -> Option<Self> {
+        // some sites append the search depth, as in `[%eval 0.34,18]`
+        let value = value.split(',').next()?.trim();
+        match value.strip_prefix('#') {
+            Some(mate) => mate.parse().ok().map(Eval::Mate),
+            None => {
+                let pawns = value.parse::<f64>().ok()?;
+                pawns
+                    .is_finite()
+                    .then(|| Eval::Cp((pawns * 100.0).round() as i32))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Eval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Eval::Cp(cp) => write!(f, "{:.2}", *cp as f64 / 100.0),
+            Eval::Mate(moves) => write!(f, "#{}", moves),
+        }
+    }
+}
+
+fn format_clock(ms: u32) -> String {
+    let seconds = ms / 1000;
+    let mut clock = format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if ms % 1000 != 0 {
+        clock.push('.');
+        clock.push_str(format!("{:03}", ms % 1000).trim_end_matches('0'));
+    }
+    clock
+}
+
+/// Takes the `[%clk]` and `[%eval]` commands out of a comment, leaving the
+/// remaining text and any other command untouched
+pub(crate) fn extract_annotations(comment: &str) -> (String, Option<u32>, Option<Eval>) {
+    let mut text = String::new();
+    let mut clock = None;
+    let mut eval = None;
+    let mut rest = comment;
+
+    while let Some(start) = rest.find("[%") {
+        let Some(len) = rest[start..].find(']') else {
+            break;
+        };
+        let command = &rest[start..=start + len];
+        let body = command[2..command.len() - 1].trim();
+        let (name, value) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        text.push_str(&rest[..start]);
+        match name {
+            "clk" if parse_clock(command).is_some() => clock = parse_clock(command),
+            "eval" if Eval::parse(value).is_some() => eval = Eval::parse(value),
+            _ => text.push_str(command),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    text.push_str(rest);
+
+    if clock.is_none() && eval.is_none() {
+        return (comment.to_string(), None, None);
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (text, clock, eval)
+}
+
+/// A move with its annotations and the variations that can be played
+/// instead of it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveNode {
+    pub san: String,
+    pub nags: Vec<String>,
+    /// Comments placed before the move, only possible at the start of a
+    /// line or after a variation
+    pub comments_before: Vec<String>,
+    pub comments: Vec<String>,
+    /// Remaining time in milliseconds, from a `[%clk]` command
+    pub clock_ms: Option<u32>,
+    pub eval: Option<Eval>,
+    pub variations: Vec<Vec<MoveNode>>,
+}
+
+impl MoveNode {
+    fn add_comment(&mut self, comment: &str) {
+        let (text, clock, eval) = extract_annotations(comment);
+        self.clock_ms = clock.or(self.clock_ms);
+        self.eval = eval.or(self.eval);
+        if !text.is_empty() {
+            self.comments.push(text);
+        }
+    }
+}
+
+/// What the analysis of a game says about one of its moves
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Type)]
+pub struct MoveAnnotation {
+    pub eval: Option<Eval>,
+    pub classification: Option<MoveClassification>,
+}
+
+/// NAGs judging the move itself, from `$1` (good move) to `$6` (dubious
+/// move)
+fn is_move_nag(nag: &str) -> bool {
+    matches!(nag, "$1" | "$2" | "$3" | "$4" | "$5" | "$6")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameTree {
+    pub headers: Vec<(String, String)>,
+    pub mainline: Vec<MoveNode>,
+    /// Comments of a game without moves
+    pub comments: Vec<String>,
+    pub outcome: String,
+}
+
+/// Builds a line from the tokens until the matching `ParenClose`, returning
+/// the line and the comments that weren't followed by a move
+fn parse_line(tokens: &[Token], i: &mut usize) -> (Vec<MoveNode>, Vec<String>) {
+    let mut line: Vec<MoveNode> = Vec::new();
+    let mut pending = Vec::new();
+    // whether a comment belongs to the last move or to the next one
+    let mut after_move = false;
+
+    while *i < tokens.len() {
+        let token = &tokens[*i];
+        *i += 1;
+        match token {
+            Token::San(san) => {
+                line.push(MoveNode {
+                    san: san.clone(),
+                    comments_before: std::mem::take(&mut pending),
+                    ..Default::default()
+                });
+                after_move = true;
+            }
+            Token::Nag(nag) => {
+                if let Some(last) = line.last_mut() {
+                    last.nags.push(nag.clone());
+                }
+            }
+            Token::Comment(comment) => match line.last_mut() {
+                Some(last) if after_move => last.add_comment(comment),
+                _ => pending.push(comment.clone()),
+            },
+            Token::ParenOpen => {
+                let (variation, _) = parse_line(tokens, i);
+                if let Some(last) = line.last_mut() {
+                    last.variations.push(variation);
+                }
+                after_move = false;
+            }
+            Token::ParenClose => break,
+            Token::Header { .. } | Token::Outcome(_) => {}
+        }
+    }
+
+    if let Some(last) = line.last_mut() {
+        last.comments.append(&mut pending);
+    }
+    (line, pending)
+}
+
+impl GameTree {
+    pub fn from_tokens(tokens: &[Token]) -> Self {
+        let headers = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Header { tag, value } => Some((tag.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+        let outcome = tokens
+            .iter()
+            .find_map(|token| match token {
+                Token::Outcome(outcome) => Some(outcome.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| "*".to_string());
+
+        let mut i = 0;
+        let (mainline, comments) = parse_line(tokens, &mut i);
+        Self {
+            headers,
+            mainline,
+            comments,
+            outcome,
+        }
+    }
+
+    pub fn from_pgn(pgn: &str) -> Result<Self, Error> {
+        Ok(Self::from_tokens(&lex_game(pgn)?))
+    }
+
+    /// Sets the evaluation of each move of the mainline and marks the bad
+    /// ones with their NAG. Comments are kept, and so are the NAGs judging a
+    /// move that was already annotated.
+    pub fn annotate(&mut self, annotations: &[MoveAnnotation]) {
+        for (node, annotation) in self.mainline.iter_mut().zip(annotations) {
+            node.eval = annotation.eval.or(node.eval);
+            let nag = annotation.classification.and_then(MoveClassification::nag);
+            if let Some(nag) = nag {
+                if !node.nags.iter().any(|n| is_move_nag(n)) {
+                    node.nags.push(nag.to_string());
+                }
+            }
+        }
+    }
+
+    /// Ply of the first move, taken from the `FEN` header if there is one
+    fn first_ply(&self) -> u32 {
+        let Some((_, fen)) = self.headers.iter().find(|(tag, _)| tag == "FEN") else {
+            return 0;
+        };
+        let mut fields = fen.split_whitespace().skip(1);
+        let black = fields.next() == Some("b");
+        let fullmoves = fields
+            .nth(3)
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(1);
+        fullmoves.saturating_sub(1) * 2 + black as u32
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        // header values are kept escaped as they were read
+        for (tag, value) in &self.headers {
+            pgn.push_str(&format!("[{} \"{}\"]\n", tag, value));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        for comment in &self.comments {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        write_line(&self.mainline, self.first_ply(), &mut tokens);
+        tokens.push(self.outcome.clone());
+        pgn.push_str(&tokens.join(" "));
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+fn write_line(line: &[MoveNode], first_ply: u32, tokens: &mut Vec<String>) {
+    // black moves need their number after anything interrupting the line
+    let mut needs_number = true;
+    for (k, node) in line.iter().enumerate() {
+        let ply = first_ply + k as u32;
+        for comment in &node.comments_before {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        let number = ply / 2 + 1;
+        if ply % 2 == 0 {
+            tokens.push(format!("{}. {}", number, node.san));
+        } else if needs_number {
+            tokens.push(format!("{}... {}", number, node.san));
+        } else {
+            tokens.push(node.san.clone());
+        }
+        needs_number = false;
+
+        tokens.extend(node.nags.iter().cloned());
+        let annotations: Vec<String> = node
+            .eval
+            .map(|eval| format!("[%eval {}]", eval))
+            .into_iter()
+            .chain(
+                node.clock_ms
+                    .map(|ms| format!("[%clk {}]", format_clock(ms))),
+            )
+            .collect();
+        // the commands go in front of the first comment, as they were read
+        let mut comments: Vec<String> = node.comments.clone();
+        if !annotations.is_empty() {
+            match comments.first_mut() {
+                Some(first) => *first = format!("{} {}", annotations.join(" "), first),
+                None => comments.push(annotations.join(" ")),
+            }
+        }
+        for comment in &comments {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        for variation in &node.variations {
+            let mut inner = Vec::new();
+            write_line(variation, ply, &mut inner);
+            tokens.push(format!("({})", inner.join(" ")));
+            needs_number = true;
+        }
+    }
+}
+
+/// Like `read_games`, with each game parsed into a tree of moves
+#[tauri::command]
+#[specta::specta]
+pub async fn read_game_trees(
+    file: PathBuf,
+    start: i32,
+    end: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<GameTree>, Error> {
+    read_games(file, start, end, state)
+        .await?
+        .games
+        .iter()
+        .map(|pgn| GameTree::from_pgn(pgn))
+        .collect()
+}
+
+/// Writes a game tree as the `n`th game of a PGN file
+#[tauri::command]
+#[specta::specta]
+pub async fn write_game_tree(
+    file: PathBuf,
+    n: i32,
+    tree: GameTree,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    write_game(file, n, tree.to_pgn(), None, None, state).await
+}
+
+/// Writes an analysis into the `n`th game of a PGN file as `[%eval]`
+/// comments and NAGs. `annotations` has one entry per move of the mainline,
+/// like the `annotations` of the analysis `analyze_game` returns.
+#[tauri::command]
+#[specta::specta]
+pub async fn write_game_analysis(
+    file: PathBuf,
+    n: i32,
+    annotations: Vec<MoveAnnotation>,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let games = read_games(file.clone(), n, n, state.clone()).await?.games;
+    let Some(pgn) = games.first() else {
+        return Err(Error::NoMatchFound);
+    };
+    let mut tree = GameTree::from_pgn(pgn)?;
+    tree.annotate(&annotations);
+    write_game(file, n, tree.to_pgn(), None, None, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(pgn: &str) -> String {
+        pgn.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn round_trip_nested_variations() {
+        let pgn = r#"[Event "Test"]
+[White "Alice"]
+[Black "Bob"]
+
+{Opening comment} 1. e4 {King's pawn} e5 2. Nf3 (2. f4 exf4 (2... d5 {Falkbeer} 3. exd5) 3. Nf3 $1)
+2... Nc6 {Developing} 3. Bb5 $14 a6 1-0
+
+"#;
+        let tree = GameTree::from_pgn(pgn).unwrap();
+
+        assert_eq!(tree.mainline.len(), 6);
+        assert_eq!(tree.mainline[0].comments_before, vec!["Opening comment"]);
+        assert_eq!(tree.mainline[0].comments, vec!["King's pawn"]);
+        let f4 = &tree.mainline[2].variations[0];
+        assert_eq!(f4[0].san, "f4");
+        assert_eq!(f4[1].variations[0][0].comments, vec!["Falkbeer"]);
+        assert_eq!(f4[2].nags, vec!["$1"]);
+        assert_eq!(tree.outcome, "1-0");
+
+        assert_eq!(normalize(&tree.to_pgn()), normalize(pgn));
+        assert_eq!(GameTree::from_pgn(&tree.to_pgn()).unwrap(), tree);
+    }
+
+    #[test]
+    fn extracts_clock_and_eval() {
+        let pgn =
+            "1. e4 { [%eval 0.34] [%clk 0:03:21] } 1... e5 { [%eval -1.2,20] [%clk 0:02:59.5] } \
+            2. Qh5 { [%eval #-3] } 2... Nc6 { [%eval #2] Good [%csl Gd4] } *";
+        let tree = GameTree::from_pgn(pgn).unwrap();
+        let moves = &tree.mainline;
+
+        assert_eq!(moves[0].clock_ms, Some(201_000));
+        assert_eq!(moves[0].eval, Some(Eval::Cp(34)));
+        assert!(moves[0].comments.is_empty());
+        assert_eq!(moves[1].clock_ms, Some(179_500));
+        assert_eq!(moves[1].eval, Some(Eval::Cp(-120)));
+        assert_eq!(moves[2].clock_ms, None);
+        assert_eq!(moves[2].eval, Some(Eval::Mate(-3)));
+        assert_eq!(moves[3].eval, Some(Eval::Mate(2)));
+        assert_eq!(moves[3].comments, vec!["Good [%csl Gd4]"]);
+
+        let written = tree.to_pgn();
+        assert!(written.contains(
+            "1. e4 {[%eval 0.34] [%clk 0:03:21]} 1... e5 {[%eval -1.20] [%clk 0:02:59.5]}"
+        ));
+        assert!(written.contains("2. Qh5 {[%eval #-3]} 2... Nc6 {[%eval #2] Good [%csl Gd4]}"));
+        assert_eq!(GameTree::from_pgn(&written).unwrap(), tree);
+    }
+
+    #[test]
+    fn annotates_analyzed_moves() {
+        let pgn = "1. e4 {Best by test} e5 $5 2. Qh5 Ke7 3. Qxe5# 1-0";
+        let mut tree = GameTree::from_pgn(pgn).unwrap();
+        let annotation = |eval, classification| MoveAnnotation {
+            eval: Some(eval),
+            classification: Some(classification),
+        };
+        tree.annotate(&[
+            annotation(Eval::Cp(30), MoveClassification::Best),
+            annotation(Eval::Cp(25), MoveClassification::Inaccuracy),
+            annotation(Eval::Cp(-40), MoveClassification::Mistake),
+            annotation(Eval::Mate(1), MoveClassification::Blunder),
+        ]);
+
+        let written = tree.to_pgn();
+        assert!(written.contains(
+            "1. e4 {[%eval 0.30] Best by test} 1... e5 $5 {[%eval 0.25]} \
+            2. Qh5 $2 {[%eval -0.40]} 2... Ke7 $4 {[%eval #1]} 3. Qxe5# 1-0"
+        ));
+        assert_eq!(GameTree::from_pgn(&written).unwrap(), tree);
+    }
+
+    #[test]
+    fn numbers_moves_from_fen() {
+        let pgn = r#"[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12"]
+
+12... Kd7 13. e4 *"#;
+        let tree = GameTree::from_pgn(pgn).unwrap();
+        assert_eq!(tree.first_ply(), 23);
+        assert!(tree.to_pgn().ends_with("12... Kd7 13. e4 *\n\n"));
+    }
+}
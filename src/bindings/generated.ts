@@ -21,9 +21,9 @@ async findFidePlayer(player: string) : Promise<Result<FidePlayer | null, string>
     else return { status: "error", error: e  as any };
 }
 },
-async getBestMoves(id: string, engine: string, tab: string, goMode: GoMode, options: EngineOptions) : Promise<Result<[number, BestMoves[]] | null, string>> {
+async getBestMoves(id: string, engine: string, tab: string, goMode: GoMode, options: EngineOptions, multipv: number | null) : Promise<Result<[number, BestMoves[]] | null, string>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("get_best_moves", { id, engine, tab, goMode, options }) };
+    return { status: "ok", data: await TAURI_INVOKE("get_best_moves", { id, engine, tab, goMode, options, multipv }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -61,9 +61,9 @@ async killEngines(tab: string) : Promise<Result<null, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async getEngineLogs(engine: string, tab: string) : Promise<Result<EngineLog[], string>> {
+async getEngineLogs(engine: string, tab: string, filter: EngineLogFilter | null) : Promise<Result<EngineLog[], string>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("get_engine_logs", { engine, tab }) };
+    return { status: "ok", data: await TAURI_INVOKE("get_engine_logs", { engine, tab, filter }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -72,6 +72,23 @@ async getEngineLogs(engine: string, tab: string) : Promise<Result<EngineLog[], s
 async memorySize() : Promise<number> {
     return await TAURI_INVOKE("memory_size");
 },
+async getSyncServerStatus() : Promise<SyncServerStatus> {
+    return await TAURI_INVOKE("get_sync_server_status");
+},
+async getSyncToken() : Promise<string> {
+    return await TAURI_INVOKE("get_sync_token");
+},
+async setSyncPort(port: number | null) : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("set_sync_port", { port }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getSyncPort() : Promise<number> {
+    return await TAURI_INVOKE("get_sync_port");
+},
 async getPuzzle(file: string, minRating: number, maxRating: number) : Promise<Result<Puzzle, string>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("get_puzzle", { file, minRating, maxRating }) };
@@ -128,9 +145,9 @@ async fileExists(path: string) : Promise<Result<boolean, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async getFileMetadata(path: string) : Promise<Result<FileMetadata, string>> {
+async getFileMetadata(path: string, checksum: boolean | null, listEntries: boolean | null) : Promise<Result<FileMetadata, string>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("get_file_metadata", { path }) };
+    return { status: "ok", data: await TAURI_INVOKE("get_file_metadata", { path, checksum, listEntries }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -144,7 +161,7 @@ async mergePlayers(file: string, player1: number, player2: number) : Promise<Res
     else return { status: "error", error: e  as any };
 }
 },
-async convertPgn(file: string, dbPath: string, timestamp: number | null, title: string, description: string | null) : Promise<Result<null, string>> {
+async convertPgn(file: string, dbPath: string, timestamp: number | null, title: string, description: string | null) : Promise<Result<PgnValidationReport, string>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("convert_pgn", { file, dbPath, timestamp, title, description }) };
 } catch (e) {
@@ -168,7 +185,7 @@ async countPgnGames(file: string) : Promise<Result<number, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async readGames(file: string, start: number, end: number) : Promise<Result<string[], string>> {
+async readGames(file: string, start: number, end: number) : Promise<Result<ReadGamesResult, string>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("read_games", { file, start, end }) };
 } catch (e) {
@@ -176,6 +193,14 @@ async readGames(file: string, start: number, end: number) : Promise<Result<strin
     else return { status: "error", error: e  as any };
 }
 },
+async validatePgn(file: string) : Promise<Result<PgnValidationReport, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("validate_pgn", { file }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
 async lexPgn(pgn: string) : Promise<Result<Token[], string>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("lex_pgn", { pgn }) };
@@ -262,9 +287,9 @@ async deleteDatabase(file: string) : Promise<Result<null, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async exportToPgn(file: string, destFile: string) : Promise<Result<null, string>> {
+async exportToPgn(file: string, destFile: string, query: GameQueryJs | null, options: PgnExportOptions) : Promise<Result<number, string>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("export_to_pgn", { file, destFile }) };
+    return { status: "ok", data: await TAURI_INVOKE("export_to_pgn", { file, destFile, query, options }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -294,9 +319,25 @@ async downloadFideDb() : Promise<Result<null, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async downloadFile(id: string, url: string, path: string, token: string | null, finalize: boolean | null, totalSize: number | null) : Promise<Result<null, string>> {
+async downloadFile(id: string, url: string, path: string, token: string | null, finalize: boolean | null, totalSize: number | null, sha256: string | null) : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("download_file", { id, url, path, token, finalize, totalSize, sha256 }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async cancelDownload(id: string) : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("cancel_download", { id }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async setDownloadLimit(limit: number) : Promise<Result<null, string>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("download_file", { id, url, path, token, finalize, totalSize }) };
+    return { status: "ok", data: await TAURI_INVOKE("set_download_limit", { limit }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -374,26 +415,101 @@ reportProgress: "report-progress"
 /** user-defined types **/
 
 export type AnalysisOptions = { fen: string; moves: string[]; annotateNovelties: boolean; referenceDb: string | null; reversed: boolean }
+export type ArchiveEntry = { name: string; size: number; is_dir: boolean }
 export type BestMoves = { nodes: number; depth: number; score: Score; uciMoves: string[]; sanMoves: string[]; multipv: number; nps: number }
 export type BestMovesPayload = { bestLines: BestMoves[]; engine: string; tab: string; fen: string; moves: string[]; progress: number }
+export type ClockIssue = 
+/**
+ * The clock after `ply` is higher than the previous one plus the increment
+ */
+{ type: "clockIncreased"; ply: number; previousMs: number; currentMs: number } | 
+/**
+ * Only one of the players has clock times
+ */
+{ type: "missingClocks"; side: string }
 export type DatabaseInfo = { title: string; description: string; player_count: number; event_count: number; game_count: number; storage_size: number; filename: string; indexed: boolean }
 export type DatabaseProgress = { id: string; progress: number }
-export type DownloadProgress = { progress: number; id: string; finished: boolean }
+export type DownloadProgress = { 
+/**
+ * Percentage of the whole file, none if its size is unknown
+ */
+progress: number | null; id: string; finished: boolean; cancelled: boolean; 
+/**
+ * Waiting for other downloads to finish before starting
+ */
+queued: boolean; bytes_per_second: number | null; 
+/**
+ * Seconds left, only known when the size of the file is known
+ */
+eta_seconds: number | null }
 export type EngineConfig = { name: string; options: UciOptionConfig[] }
-export type EngineLog = { type: "gui"; value: string } | { type: "engine"; value: string }
+export type EngineLog = { type: LogDirection; value: string; 
+/**
+ * Milliseconds since the engine was started
+ */
+elapsedMs: number }
+export type EngineLogFilter = { direction: LogDirection | null; contains: string | null }
 export type EngineOption = { name: string; value: string }
 export type EngineOptions = { fen: string; moves: string[]; extraOptions: EngineOption[] }
 export type Event = { id: number; name: string | null }
 export type FidePlayer = { fideid: number; name: string; country: string; sex: string; title: string | null; w_title: string | null; o_title: string | null; foa_title: string | null; rating: number | null; games: number | null; k: number | null; rapid_rating: number | null; rapid_games: number | null; rapid_k: number | null; blitz_rating: number | null; blitz_games: number | null; blitz_k: number | null; birthday: number | null; flag: string | null }
-export type FileMetadata = { last_modified: number }
-export type GameQueryJs = { options?: QueryOptions<GameSort> | null; player1?: number | null; player2?: number | null; tournament_id?: number | null; start_date?: string | null; end_date?: string | null; range1?: [number, number] | null; range2?: [number, number] | null; sides?: Sides | null; outcome?: string | null; position?: PositionQueryJs | null }
+export type FileMetadata = { last_modified: number; 
+/**
+ * In bytes, as a float so that files over 4GB fit in a js number
+ */
+size: number; sha256: string | null; entries: ArchiveEntry[] | null }
+export type GameClockReport = { index: number; issues: ClockIssue[] }
+export type GameQueryJs = { options?: QueryOptions<GameSort> | null; player1?: number | null; player2?: number | null; tournament_id?: number | null; start_date?: string | null; end_date?: string | null; range1?: [number, number] | null; range2?: [number, number] | null; sides?: Sides | null; outcome?: Outcome | null; termination?: Termination | null; 
+/**
+ * Inclusive range of ECO codes, like `("B90", "B99")`
+ */
+eco_range?: [string, string] | null; 
+/**
+ * Range both players' ratings have to be in
+ */
+rating?: [number, number] | null; time_control?: string | null; position?: PositionQueryJs | null }
 export type GameSort = "id" | "date" | "whiteElo" | "blackElo" | "ply_count"
 export type GoMode = { t: "PlayersTime"; c: PlayersTime } | { t: "Depth"; c: number } | { t: "Time"; c: number } | { t: "Nodes"; c: number } | { t: "Infinite" }
+export type LogDirection = 
+/**
+ * Sent to the engine
+ */
+"gui" | 
+/**
+ * Received from the engine
+ */
+"engine"
 export type MonthData = { count: number; avg_elo: number }
 export type MoveAnalysis = { best: BestMoves[]; novelty: boolean; is_sacrifice: boolean }
-export type NormalizedGame = { id: number; fen: string; event: string; event_id: number; site: string; site_id: number; date?: string | null; time?: string | null; round?: string | null; white: string; white_id: number; white_elo?: number | null; black: string; black_id: number; black_elo?: number | null; result: Outcome; time_control?: string | null; eco?: string | null; ply_count?: number | null; moves: string }
+export type NormalizedGame = { id: number; fen: string; event: string; event_id: number; site: string; site_id: number; date?: string | null; time?: string | null; round?: string | null; white: string; white_id: number; white_elo?: number | null; black: string; black_id: number; black_elo?: number | null; result: Outcome; termination?: Termination | null; time_control?: string | null; eco?: string | null; ply_count?: number | null; 
+/**
+ * Name of the opening, for games from the starting position
+ */
+opening?: string | null; moves: string }
 export type OutOpening = { name: string; fen: string }
 export type Outcome = "1-0" | "0-1" | "1/2-1/2" | "*"
+export type Pagination = 
+/**
+ * Skips `page - 1` pages, which gets slower the further the page is
+ */
+"offset" | 
+/**
+ * Continues after the last game of the previous page, which is as fast
+ * for every page
+ */
+"cursor"
+export type ParseError = { gameIndex: number; byteOffset: bigint; message: string }
+/**
+ * Databases only store the mainline of each game, so exports never contain
+ * variations
+ */
+export type PgnExportOptions = { minimalHeaders: boolean; 
+/**
+ * Writes the comments kept in the full text index of the database,
+ * without their clocks and evals
+ */
+includeComments: boolean }
+export type PgnValidationReport = { games: number; flagged: GameClockReport[] }
 export type Player = { id: number; name: string | null; elo: number | null }
 export type PlayerGameInfo = { won: number; lost: number; draw: number; data_per_month: ([string, MonthData])[]; white_openings: ([string, Results])[]; black_openings: ([string, Results])[] }
 export type PlayerQuery = { options: QueryOptions<PlayerSort>; name?: string | null; range?: [number, number] | null }
@@ -403,8 +519,26 @@ export type PositionQueryJs = { fen: string; type_: string }
 export type PositionStats = { move: string; white: number; draw: number; black: number }
 export type Puzzle = { id: number; fen: string; moves: string; rating: number; rating_deviation: number; popularity: number; nb_plays: number }
 export type PuzzleDatabaseInfo = { title: string; description: string; puzzleCount: number; storageSize: number; path: string }
-export type QueryOptions<SortT> = { skipCount: boolean; page?: number | null; pageSize?: number | null; sort: SortT; direction: SortDirection }
-export type QueryResponse<T> = { data: T; count: number | null }
+export type QueryOptions<SortT> = { skipCount: boolean; page?: number | null; pageSize?: number | null; sort: SortT; direction: SortDirection; 
+/**
+ * Pages by cursor instead of `page` when set, only supported by
+ * `get_games`
+ */
+pagination?: Pagination | null; 
+/**
+ * `next_cursor` of the previous page, to get the one after it
+ */
+after?: string | null }
+export type QueryResponse<T> = { data: T; count: number | null; 
+/**
+ * Cursor of the next page, when paging by cursor and there are more
+ */
+next_cursor: string | null }
+export type ReadGamesResult = { games: string[]; 
+/**
+ * Games of the requested range that were skipped
+ */
+errors: ParseError[] }
 export type ReportProgress = { progress: number; id: string; finished: boolean }
 export type Results = { won: number; lost: number; draw: number }
 export type Score = { value: ScoreValue; 
@@ -423,8 +557,17 @@ export type ScoreValue =
 { type: "mate"; value: number }
 export type Sides = "BlackWhite" | "WhiteBlack" | "Any"
 export type SortDirection = "asc" | "desc"
+export type SyncServerStatus = { running: boolean; port: number | null }
+/**
+ * How a game ended, from its `Termination` header
+ */
+export type Termination = "normal" | "timeForfeit" | "abandoned" | "adjudication" | "rulesInfraction" | "unterminated"
 export type Token = { type: "ParenOpen" } | { type: "ParenClose" } | { type: "Comment"; value: string } | { type: "San"; value: string } | { type: "Header"; value: { tag: string; value: string } } | { type: "Nag"; value: string } | { type: "Outcome"; value: string }
-export type TournamentQuery = { options: QueryOptions<TournamentSort>; name: string | null }
+export type TournamentQuery = { options: QueryOptions<TournamentSort>; name: string | null; 
+/**
+ * Keeps the tournaments with games between these dates, as `YYYY.MM.DD`
+ */
+start_date?: string | null; end_date?: string | null }
 export type TournamentSort = "id" | "name"
 /**
  * Represents a UCI option definition.
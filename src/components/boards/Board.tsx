@@ -1,3 +1,4 @@
+import { type SyncServerStatus, commands } from "@/bindings";
 import { Chessground } from "@/chessground/Chessground";
 import {
   autoPromoteAtom,
@@ -54,6 +55,7 @@ import {
   IconTarget,
   IconZoomCheck,
 } from "@tabler/icons-react";
+import { listen } from "@tauri-apps/api/event";
 import { documentDir } from "@tauri-apps/api/path";
 import { save } from "@tauri-apps/plugin-dialog";
 import { writeFile } from "@tauri-apps/plugin-fs";
@@ -225,14 +227,34 @@ function Board({
   const wsRef = useRef<WebSocket | null>(null);
   
   useEffect(() => {
-    const connectWebSocket = () => {
-      if (wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+    let unmounted = false;
+
+    const connectWebSocket = async () => {
+      if (
+        wsRef.current &&
+        (wsRef.current.readyState === WebSocket.OPEN ||
+          wsRef.current.readyState === WebSocket.CONNECTING)
+      ) {
           console.log("[Board WS] Already connected.");
           return; // Already connected
       }
       
+      // The sync server falls back to another port when its own is taken, and
+      // rejects connections without the token of this session
+      const [status, token] = await Promise.all([
+        commands.getSyncServerStatus(),
+        commands.getSyncToken(),
+      ]);
+      if (unmounted) return;
+      if (!status.running || status.port === null) {
+          console.log("[Board WS] Sync server isn't running yet.");
+          return;
+      }
+
       console.log("[Board WS] Attempting to connect...");
-      wsRef.current = new WebSocket('ws://localhost:3030/ws');
+      wsRef.current = new WebSocket(
+        `ws://localhost:${status.port}/ws?token=${encodeURIComponent(token)}`,
+      );
 
       wsRef.current.onopen = () => {
           console.log("[Board WS] Connection established.");
@@ -266,9 +288,16 @@ function Board({
     };
     
     connectWebSocket(); // Initial connection attempt
+
+    // The server may start after the board, or on another port after a restart
+    const unlisten = listen<SyncServerStatus>("sync-server-status", (event) => {
+      if (event.payload.running) connectWebSocket();
+    });
     
     // Cleanup function: close WebSocket on component unmount
     return () => {
+      unmounted = true;
+      unlisten.then((f) => f());
       if (wsRef.current) {
         console.log("[Board WS] Closing WebSocket connection.");
         wsRef.current.close();
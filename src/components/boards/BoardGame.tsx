@@ -386,6 +386,7 @@ function BoardGame() {
                 value: s.value?.toString() ?? "",
               })),
           },
+          null,
         );
       }
     }
@@ -9,7 +9,8 @@ import * as classes from "./ProgressButton.css";
 
 type Payload = {
   id: string;
-  progress: number;
+  /** Unknown when the size of a download isn't */
+  progress: number | null;
   finished: boolean;
 };
 
@@ -43,7 +44,7 @@ function ProgressButton<T extends Payload>({
   inProgress,
   setInProgress,
 }: Props<T>) {
-  const [progress, setProgress] = useState(0);
+  const [progress, setProgress] = useState<number | null>(0);
   const [completed, setCompleted] = useState(initInstalled);
 
   useEffect(() => {
@@ -89,7 +90,9 @@ function ProgressButton<T extends Payload>({
           <Progress
             pos="absolute"
             h="100%"
-            value={progress}
+            value={progress ?? 100}
+            striped={progress === null}
+            animated={progress === null}
             className={classes.progress}
             radius="sm"
           />
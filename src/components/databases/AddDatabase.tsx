@@ -23,6 +23,7 @@ import {
   TextInput,
 } from "@mantine/core";
 import { useForm } from "@mantine/form";
+import { notifications } from "@mantine/notifications";
 import { IconAlertCircle } from "@tabler/icons-react";
 import { appDataDir, resolve } from "@tauri-apps/api/path";
 import { open } from "@tauri-apps/plugin-dialog";
@@ -51,9 +52,18 @@ function AddDatabase({
   async function convertDB(path: string, title: string, description?: string) {
     setLoading(true);
     const dbPath = await resolve(await appDataDir(), "db", `${title}.db3`);
-    unwrap(
+    const report = unwrap(
       await commands.convertPgn(path, dbPath, null, title, description ?? null),
     );
+    if (report.flagged.length > 0) {
+      notifications.show({
+        title: t("Databases.ClockIssues"),
+        message: t("Databases.ClockIssues.Desc", {
+          count: report.flagged.length,
+        }),
+        color: "yellow",
+      });
+    }
     setDatabases(await getDatabases());
     setLoading(false);
   }
@@ -202,7 +212,15 @@ function DatabaseCard({
   async function downloadDatabase(id: number, url: string, name: string) {
     setInProgress(true);
     const path = await resolve(await appDataDir(), "db", `${name}.db3`);
-    await commands.downloadFile(`db_${id}`, url, path, null, null, null);
+    await commands.downloadFile(
+      `db_${id}`,
+      url,
+      path,
+      null,
+      null,
+      null,
+      null,
+    );
     setDatabases(await getDatabases());
   }
 
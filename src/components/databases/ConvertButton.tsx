@@ -1,15 +1,10 @@
+import { type ImportProgress, events } from "@/bindings";
 import * as classes from "@/components/common/GenericCard.css";
 import { Box, Loader, Stack, Text } from "@mantine/core";
 import { IconPlus } from "@tabler/icons-react";
-import { listen } from "@tauri-apps/api/event";
 import { useEffect, useState } from "react";
 import { useTranslation } from "react-i18next";
 
-type Progress = {
-  total: number;
-  elapsed: number;
-};
-
 function ConvertButton({
   setOpen,
   loading,
@@ -18,16 +13,15 @@ function ConvertButton({
   loading: boolean;
 }) {
   const { t } = useTranslation();
-  const [progress, setProgress] = useState<Progress | null>(null);
+  const [progress, setProgress] = useState<ImportProgress | null>(null);
 
   useEffect(() => {
-    async function getProgress() {
-      await listen<number[]>("convert_progress", (event) => {
-        const progress = event.payload;
-        setProgress({ total: progress[0], elapsed: progress[1] / 1000 });
-      });
-    }
-    getProgress();
+    const unlisten = events.importProgress.listen(({ payload }) => {
+      setProgress(payload);
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
   }, []);
   return (
     <Box
@@ -48,12 +42,26 @@ function ConvertButton({
 
         {progress && loading && (
           <Box style={{ display: "flex", justifyContent: "space-around" }}>
-            <Text fz="xs">{progress.total} games</Text>
+            <Text fz="xs">
+              {progress.games_total
+                ? `${progress.games_processed} / ${progress.games_total}`
+                : progress.games_processed}{" "}
+              games
+            </Text>
             <Text fz="xs" mb={10}>
-              {(progress.total / progress.elapsed).toFixed(1)} games/s
+              {(
+                progress.games_processed /
+                (progress.elapsed_ms / 1000)
+              ).toFixed(1)}{" "}
+              games/s
             </Text>
           </Box>
         )}
+        {progress && loading && progress.games_skipped > 0 && (
+          <Text fz="xs" c="dimmed">
+            {progress.games_skipped} skipped
+          </Text>
+        )}
       </Stack>
     </Box>
   );
@@ -24,6 +24,7 @@ import {
   Tooltip,
 } from "@mantine/core";
 import { useDebouncedValue, useToggle } from "@mantine/hooks";
+import { notifications } from "@mantine/notifications";
 import { IconArrowRight, IconDatabase, IconPlus } from "@tabler/icons-react";
 import { Link, useNavigate } from "@tanstack/react-router";
 import { open as openDialog, save } from "@tauri-apps/plugin-dialog";
@@ -311,13 +312,25 @@ export default function DatabasesPage() {
                           });
                           if (!file || typeof file !== "string") return;
                           setConvertLoading(true);
-                          await commands.convertPgn(
+                          const result = await commands.convertPgn(
                             file,
                             selectedDatabase.file,
                             null,
                             "",
                             null,
                           );
+                          if (
+                            result.status === "ok" &&
+                            result.data.flagged.length > 0
+                          ) {
+                            notifications.show({
+                              title: t("Databases.ClockIssues"),
+                              message: t("Databases.ClockIssues.Desc", {
+                                count: result.data.flagged.length,
+                              }),
+                              color: "yellow",
+                            });
+                          }
                           mutate();
                           setConvertLoading(false);
                         }}
@@ -337,6 +350,8 @@ export default function DatabasesPage() {
                           await commands.exportToPgn(
                             selectedDatabase.file,
                             destFile,
+                            null,
+                            { minimalHeaders: false, includeComments: true },
                           );
                           setExportLoading(false);
                         }}
@@ -491,7 +506,7 @@ function DuplicateRemover({
           onClick={async () => {
             setLoading(true);
             commands
-              .deleteDuplicatedGames(selectedDatabase.file)
+              .deleteDuplicatedGames(selectedDatabase.file, null)
               .then(() => {
                 setLoading(false);
                 reload();
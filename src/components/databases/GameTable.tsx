@@ -10,6 +10,7 @@ import { query_games } from "@/utils/db";
 import { createTab } from "@/utils/tabs";
 import {
   ActionIcon,
+  Badge,
   Box,
   Center,
   Collapse,
@@ -284,7 +285,16 @@ function GameTable({ database }: { database: DatabaseInfo }) {
               { accessor: "date", sortable: true },
               {
                 accessor: "result",
-                render: ({ result }) => result?.replaceAll("1/2", "½"),
+                render: ({ result, termination }) => (
+                  <Group gap={4} wrap="nowrap">
+                    {result?.replaceAll("1/2", "½")}
+                    {termination === "timeForfeit" && (
+                      <Badge size="xs" variant="light">
+                        Time
+                      </Badge>
+                    )}
+                  </Group>
+                ),
               },
               { accessor: "ply_count", title: "Plies", sortable: true },
               { accessor: "event" },
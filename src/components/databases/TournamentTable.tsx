@@ -1,6 +1,6 @@
 import {
   type DatabaseInfo,
-  type Event,
+  type Tournament,
   type TournamentSort,
   commands,
 } from "@/bindings";
@@ -16,14 +16,14 @@ import * as classes from "./styles.css";
 
 function TournamentTable({ database }: { database: DatabaseInfo }) {
   const file = database.file;
-  const [tournaments, setTournaments] = useState<Event[]>([]);
+  const [tournaments, setTournaments] = useState<Tournament[]>([]);
   const [count, setCount] = useState(0);
   const [name, setName] = useState("");
   const [loading, setLoading] = useState(false);
   const [limit, setLimit] = useState(25);
   const [activePage, setActivePage] = useState(1);
   const [selected, setSelected] = useState<number | null>(null);
-  const [sort, setSort] = useState<DataTableSortStatus<Event>>({
+  const [sort, setSort] = useState<DataTableSortStatus<Tournament>>({
     columnAccessor: "id",
     direction: "asc",
   });
@@ -110,7 +110,7 @@ function TournamentTable({ database }: { database: DatabaseInfo }) {
         </Flex>
       }
       table={
-        <DataTable<Event>
+        <DataTable<Tournament>
           withTableBorder
           highlightOnHover
           records={tournaments}
@@ -118,6 +118,8 @@ function TournamentTable({ database }: { database: DatabaseInfo }) {
           columns={[
             { accessor: "id", sortable: true },
             { accessor: "name", sortable: true },
+            { accessor: "date", sortable: true },
+            { accessor: "games", sortable: true },
           ]}
           rowClassName={(_, i) => (i === selected ? classes.selected : "")}
           noRecordsText="No tournaments found"
@@ -210,10 +210,18 @@ function EngineCard({
         "engines",
         `${url.slice(url.lastIndexOf("/") + 1)}`,
       );
-      if (url.endsWith(".zip") || url.endsWith(".tar")) {
+      if (/\.(zip|tar|tar\.gz|tgz|tar\.xz|txz)$/.test(url)) {
         path = await resolve(await appDataDir(), "engines");
       }
-      await commands.downloadFile(`engine_${id}`, url, path, null, null, null);
+      await commands.downloadFile(
+        `engine_${id}`,
+        url,
+        path,
+        null,
+        null,
+        null,
+        null,
+      );
       let appDataDirPath = await appDataDir();
       if (appDataDirPath.endsWith("/") || appDataDirPath.endsWith("\\")) {
         appDataDirPath = appDataDirPath.slice(0, -1);
@@ -1,8 +1,7 @@
-import { commands } from "@/bindings";
 import { activeTabAtom, deckAtomFamily, tabsAtom } from "@/state/atoms";
+import { readGame } from "@/utils/files";
 import { capitalize } from "@/utils/format";
 import { createTab } from "@/utils/tabs";
-import { unwrap } from "@/utils/unwrap";
 import { Badge, Box, Group } from "@mantine/core";
 import {
   IconChevronRight,
@@ -203,7 +202,7 @@ function Table({
 
   const openFile = useCallback(
     async (record: FileMetadata) => {
-      const pgn = unwrap(await commands.readGames(record.path, 0, 0));
+      const pgn = await readGame(record.path, 0);
       createTab({
         tab: {
           name: record?.name || "Untitled",
@@ -211,7 +210,7 @@ function Table({
         },
         setTabs,
         setActiveTab,
-        pgn: pgn[0] || "",
+        pgn,
         fileInfo: record,
         gameNumber: 0,
       });
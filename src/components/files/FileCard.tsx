@@ -1,8 +1,7 @@
-import { commands } from "@/bindings";
 import { activeTabAtom, tabsAtom } from "@/state/atoms";
+import { readGame } from "@/utils/files";
 import { capitalize } from "@/utils/format";
 import { createTab } from "@/utils/tabs";
-import { unwrap } from "@/utils/unwrap";
 import {
   ActionIcon,
   Badge,
@@ -47,9 +46,7 @@ function FileCard({
 
   useEffect(() => {
     async function loadGames() {
-      const data = unwrap(await commands.readGames(selected.path, page, page));
-
-      setSelectedGame(data[0]);
+      setSelectedGame(await readGame(selected.path, page));
     }
     loadGames();
   }, [selected, page]);
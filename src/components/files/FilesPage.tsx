@@ -172,6 +172,9 @@ function FilesPage() {
               onConfirm={async () => {
                 await remove(selected.path);
                 await remove(selected.path.replace(".pgn", ".info"));
+                await remove(selected.path.replace(".pgn", ".index")).catch(
+                  () => {},
+                );
                 mutate(files?.filter((file) => file.name !== selected.name));
                 toggleDeleteModal();
                 setSelected(null);
@@ -58,7 +58,9 @@ async function readFileMetadata(path: string): Promise<FileMetadata | null> {
     };
     await writeTextFile(metadataPath, JSON.stringify(metadata));
   }
-  const fileMetadata = unwrap(await commands.getFileMetadata(path));
+  const fileMetadata = unwrap(
+    await commands.getFileMetadata(path, null, null),
+  );
   const numGames = unwrap(await commands.countPgnGames(path));
   return {
     type: "file",
@@ -121,6 +121,10 @@ export function AccountCard({
       id: `${type}_${title}`,
       progress: 100,
       finished: true,
+      cancelled: false,
+      queued: false,
+      bytes_per_second: null,
+      eta_seconds: null,
     });
   }
 
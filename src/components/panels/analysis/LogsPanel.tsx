@@ -35,7 +35,7 @@ export default function LogsPanel() {
     ["logs", engine?.path, activeTab],
     async () => {
       return engine
-        ? unwrap(await commands.getEngineLogs(engine.path, activeTab!))
+        ? unwrap(await commands.getEngineLogs(engine.path, activeTab!, null))
         : undefined;
     },
   );
@@ -105,7 +105,7 @@ function ReportModal({
       )
       .then((analysis) => {
         const analysisData = unwrap(analysis);
-        addAnalysis(analysisData);
+        addAnalysis(analysisData.moves);
       })
       .finally(() => setInProgress(false));
   }
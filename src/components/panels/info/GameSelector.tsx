@@ -41,9 +41,14 @@ export default function GameSelector({
         await commands.readGames(path, startIndex, stopIndex),
       );
       const newGames = new Map(games);
-      data.forEach(async (game, index) => {
+      const skipped = new Set(data.errors.map((e) => e.gameIndex));
+      const indices: number[] = [];
+      for (let i = startIndex; i <= stopIndex; i++) {
+        if (!skipped.has(i)) indices.push(i);
+      }
+      data.games.forEach(async (game, index) => {
         const { headers } = await parsePGN(game);
-        newGames.set(startIndex + index, getGameName(headers));
+        newGames.set(indices[index], getGameName(headers));
       });
       setGames(newGames);
     },
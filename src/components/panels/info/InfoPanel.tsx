@@ -5,10 +5,10 @@ import ConfirmChangesModal from "@/components/tabs/ConfirmChangesModal";
 import { currentTabAtom, missingMovesAtom } from "@/state/atoms";
 import { keyMapAtom } from "@/state/keybinds";
 import { parsePGN } from "@/utils/chess";
+import { readGame } from "@/utils/files";
 import { formatNumber } from "@/utils/format";
 import { getTreeStats } from "@/utils/repertoire";
 import { getNodeAtPath } from "@/utils/treeReducer";
-import { unwrap } from "@/utils/unwrap";
 import { Accordion, Box, Group, ScrollArea, Stack, Text } from "@mantine/core";
 import { useToggle } from "@mantine/hooks";
 import { useAtom, useAtomValue, useSetAtom } from "jotai";
@@ -102,10 +102,8 @@ function GameSelectorAccordion({
 
     if (!currentTab?.file) return;
 
-    const data = unwrap(
-      await commands.readGames(currentTab.file.path, page, page),
-    );
-    const tree = await parsePGN(data[0]);
+    const game = await readGame(currentTab.file.path, page);
+    const tree = await parsePGN(game);
     setState(tree);
 
     setCurrentTab((prev) => {
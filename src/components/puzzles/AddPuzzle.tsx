@@ -83,7 +83,15 @@ function PuzzleDbCard({
   async function downloadDatabase(id: number, url: string, name: string) {
     setInProgress(true);
     const path = await resolve(await appDataDir(), "puzzles", `${name}.db3`);
-    await commands.downloadFile(`puzzle_db_${id}`, url, path, null, null, null);
+    await commands.downloadFile(
+      `puzzle_db_${id}`,
+      url,
+      path,
+      null,
+      null,
+      null,
+      null,
+    );
     setPuzzleDbs(await getPuzzleDatabases());
   }
 
@@ -1,3 +1,4 @@
+import { commands } from "@/bindings";
 import {
   autoPromoteAtom,
   autoSaveAtom,
@@ -20,10 +21,14 @@ import {
   storedDocumentDirAtom,
 } from "@/state/atoms";
 import { keyMapAtom } from "@/state/keybinds";
+import { unwrap } from "@/utils/unwrap";
 import {
   ActionIcon,
+  Button,
   Card,
+  CopyButton,
   Group,
+  NumberInput,
   ScrollArea,
   Select,
   Stack,
@@ -40,6 +45,7 @@ import {
   IconFolder,
   IconKeyboard,
   IconMouse,
+  IconPlugConnected,
   IconReload,
   IconVolume,
 } from "@tabler/icons-react";
@@ -48,6 +54,7 @@ import { open } from "@tauri-apps/plugin-dialog";
 import { useAtom } from "jotai";
 import { RESET } from "jotai/utils";
 import { useTranslation } from "react-i18next";
+import useSWR from "swr";
 import FileInput from "../common/FileInput";
 import BoardSelect from "./BoardSelect";
 import ColorControl from "./ColorControl";
@@ -76,6 +83,15 @@ export default function Page() {
 
   const [moveMethod, setMoveMethod] = useAtom(moveMethodAtom);
   const [moveNotationType, setMoveNotationType] = useAtom(moveNotationTypeAtom);
+  const { data: syncToken } = useSWR("sync-token", () =>
+    commands.getSyncToken(),
+  );
+  const { data: syncStatus } = useSWR("sync-server-status", () =>
+    commands.getSyncServerStatus(),
+  );
+  const { data: syncPort, mutate: setSyncPort } = useSWR("sync-port", () =>
+    commands.getSyncPort(),
+  );
 
   return (
     <Tabs defaultValue="board" orientation="vertical" h="100%">
@@ -104,6 +120,12 @@ export default function Page() {
         <Tabs.Tab value="directories" leftSection={<IconFolder size="1rem" />}>
           {t("Settings.Directories")}
         </Tabs.Tab>
+        <Tabs.Tab
+          value="sync"
+          leftSection={<IconPlugConnected size="1rem" />}
+        >
+          {t("Settings.BoardSync")}
+        </Tabs.Tab>
       </Tabs.List>
       <Stack flex={1} px="md" pt="md">
         <ScrollArea>
@@ -689,6 +711,67 @@ export default function Page() {
                 />
               </Group>
             </Tabs.Panel>
+
+            <Tabs.Panel value="sync">
+              <Text size="lg" fw={500} className={classes.title}>
+                {t("Settings.BoardSync")}
+              </Text>
+              <Text size="xs" c="dimmed" mt={3} mb="lg">
+                {t("Settings.BoardSync.Desc")}
+              </Text>
+              <Group
+                justify="space-between"
+                wrap="nowrap"
+                gap="xl"
+                className={classes.item}
+              >
+                <div>
+                  <Text>{t("Settings.BoardSync.Token")}</Text>
+                  <Text size="xs" c="dimmed">
+                    {t("Settings.BoardSync.Token.Desc")}
+                  </Text>
+                </div>
+                <CopyButton value={syncToken ?? ""}>
+                  {({ copied, copy }) => (
+                    <Button
+                      color={copied ? "teal" : undefined}
+                      onClick={copy}
+                      disabled={!syncToken}
+                    >
+                      {copied ? t("Common.Copied") : t("Common.Copy")}
+                    </Button>
+                  )}
+                </CopyButton>
+              </Group>
+              <Group
+                justify="space-between"
+                wrap="nowrap"
+                gap="xl"
+                className={classes.item}
+              >
+                <div>
+                  <Text>{t("Settings.BoardSync.Port")}</Text>
+                  <Text size="xs" c="dimmed">
+                    {syncStatus?.running
+                      ? t("Settings.BoardSync.Port.Running", {
+                          port: syncStatus.port,
+                        })
+                      : t("Settings.BoardSync.Port.Stopped")}
+                  </Text>
+                </div>
+                <NumberInput
+                  value={syncPort ?? ""}
+                  onChange={async (value) => {
+                    if (typeof value !== "number") return;
+                    unwrap(await commands.setSyncPort(value));
+                    setSyncPort(value);
+                  }}
+                  min={1}
+                  max={65535}
+                  allowDecimal={false}
+                />
+              </Group>
+            </Tabs.Panel>
           </Card>
         </ScrollArea>
         <Text size="xs" c="dimmed" ta="right">
@@ -3,7 +3,7 @@ import { currentTabAtom } from "@/state/atoms";
 import { parsePGN } from "@/utils/chess";
 import { getChesscomGame } from "@/utils/chess.com/api";
 import { chessopsError } from "@/utils/chessops";
-import { createFile } from "@/utils/files";
+import { createFile, readGame } from "@/utils/files";
 import { getLichessGame } from "@/utils/lichess/api";
 import { defaultTree, getGameName } from "@/utils/treeReducer";
 import { unwrap } from "@/utils/unwrap";
@@ -69,7 +69,7 @@ export default function ImportModal({
         let input = pgn;
         if (file) {
           const count = unwrap(await commands.countPgnGames(file));
-          input = unwrap(await commands.readGames(file, 0, 0))[0];
+          input = await readGame(file, 0);
           if (save) {
             const newFile = await createFile({
               filename,
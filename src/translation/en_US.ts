@@ -38,6 +38,8 @@ export const en_US = {
     "Common.Next": "Next",
     "Common.Skip": "Skip",
     "Common.Reset": "Reset",
+    "Common.Copy": "Copy",
+    "Common.Copied": "Copied",
     "Common.Incorrect": "Incorrect",
     "Common.Run": "Run",
     "Common.Stop": "Stop",
@@ -241,6 +243,9 @@ export const en_US = {
       "Failed to fetch the database's info from the server.",
     "Databases.Add.ClickToSelectPGN": "Click to select the PGN file",
     "Databases.Add.Convert": "Convert",
+    "Databases.ClockIssues": "Inconsistent clocks",
+    "Databases.ClockIssues.Desc":
+      "{{count}} of the imported games have clock times that don't add up",
     "Databases.Settings.ReferenceDatabase": "Reference Database",
     "Databases.Settings.Indexed": "Indexed",
     "Databases.Settings.Indexed.Desc":
@@ -315,6 +320,7 @@ export const en_US = {
     "Settings.Sound": "Sound",
     "Settings.Keybinds": "Keybinds",
     "Settings.Directories": "Directories",
+    "Settings.BoardSync": "Board Sync",
 
     "Settings.Board.Desc": "Customize the analysis board and game controls",
     "Settings.PieceDest": "Piece Destinations",
@@ -389,6 +395,16 @@ export const en_US = {
     "Settings.Directories.Files": "Files directory",
     "Settings.Directories.Files.Desc":
       "This is where your games in the Files page are stored",
+    "Settings.BoardSync.Desc":
+      "Follow the games you play in the browser with the userscript",
+    "Settings.BoardSync.Token": "Sync token",
+    "Settings.BoardSync.Token.Desc":
+      "Paste it in the userscript with its Set Token button. A new one is made every time the app starts",
+    "Settings.BoardSync.Port": "Port",
+    "Settings.BoardSync.Port.Running":
+      "Listening on port {{port}}. A new port is used after restarting the app",
+    "Settings.BoardSync.Port.Stopped":
+      "The sync server isn't running. A new port is used after restarting the app",
 
     "PgnInput.Comments": "Comments",
     "PgnInput.Glyphs": "Glyphs",
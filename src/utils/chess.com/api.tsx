@@ -167,12 +167,20 @@ export async function downloadChessCom(
       id: `chesscom_${player}`,
       progress:
         (filteredArchives.indexOf(archive) / filteredArchives.length) * 100,
+      cancelled: false,
+      queued: false,
+      bytes_per_second: null,
+      eta_seconds: null,
     });
   }
   events.downloadProgress.emit({
     finished: false,
     id: `chesscom_${player}`,
     progress: 100,
+    cancelled: false,
+    queued: false,
+    bytes_per_second: null,
+    eta_seconds: null,
   });
 }
 
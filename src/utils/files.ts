@@ -18,13 +18,26 @@ export function usePlatform() {
   return { os: r.data, ...r };
 }
 
+// Reads the game `n` of a file. A game the parser skipped fails with the
+// reason instead of opening as an empty game.
+export async function readGame(file: string, n: number): Promise<string> {
+  const { games, errors } = unwrap(await commands.readGames(file, n, n));
+  if (errors.length > 0) {
+    return unwrap<string>({
+      status: "error",
+      error: `Game ${n + 1} can't be read: ${errors[0].message}`,
+    });
+  }
+  return games[0];
+}
+
 export async function openFile(
   file: string,
   setTabs: React.Dispatch<React.SetStateAction<Tab[]>>,
   setActiveTab: React.Dispatch<React.SetStateAction<string | null>>,
 ) {
   const count = unwrap(await commands.countPgnGames(file));
-  const input = unwrap(await commands.readGames(file, 0, 0))[0];
+  const input = await readGame(file, 0);
 
   const fileInfo = {
     type: "file" as const,
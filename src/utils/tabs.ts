@@ -137,6 +137,8 @@ export async function saveToFile({
       glyphs: true,
       variations: true,
     })}\n\n`,
+    null,
+    null,
   );
   store.getState().save();
 }